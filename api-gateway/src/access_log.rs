@@ -0,0 +1,341 @@
+use chrono::{DateTime, Utc};
+use reqwest::Client;
+use serde::Serialize;
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use syslog::{Facility, Formatter3164, Logger, LoggerBackend};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncWriteExt, BufWriter, Stdout},
+    sync::mpsc,
+};
+use tracing::{debug, info, warn};
+
+use crate::config::{AccessLogSinkConfig, LoggingConfig, SyslogProtocol};
+
+/// Entries waiting for the writer task. Beyond this, entries are dropped rather than
+/// slowing down requests.
+const QUEUE_CAPACITY: usize = 10_000;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const HTTP_SINK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessLogEntry {
+    pub timestamp: DateTime<Utc>,
+    pub request_id: String,
+    pub client_ip: Option<IpAddr>,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub duration_ms: f64,
+    pub user_agent: Option<String>,
+    pub route: Option<String>,
+    pub backend: Option<String>,
+    pub request_bytes: Option<usize>,
+    pub response_bytes: Option<usize>,
+}
+
+/// Hands access log entries to a background task that writes them to the configured
+/// sinks, so a slow disk or collector never holds up a request.
+pub struct AccessLogger {
+    entries: mpsc::Sender<AccessLogEntry>,
+}
+
+impl AccessLogger {
+    pub async fn new(config: &LoggingConfig) -> anyhow::Result<Self> {
+        let mut sinks = Vec::with_capacity(config.access_log.len());
+        for sink_config in &config.access_log {
+            sinks.push(Sink::open(sink_config).await?);
+        }
+
+        let (entries, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        tokio::spawn(write_entries(sinks, receiver));
+        info!("Access log enabled with {} sink(s)", config.access_log.len());
+
+        Ok(Self { entries })
+    }
+
+    pub fn log(&self, entry: AccessLogEntry) {
+        if self.entries.try_send(entry).is_err() {
+            debug!("Access log queue full, dropping entry");
+        }
+    }
+}
+
+async fn write_entries(mut sinks: Vec<Sink>, mut entries: mpsc::Receiver<AccessLogEntry>) {
+    let mut flush = tokio::time::interval(FLUSH_INTERVAL);
+
+    loop {
+        tokio::select! {
+            entry = entries.recv() => match entry {
+                Some(entry) => {
+                    let line = match serde_json::to_string(&entry) {
+                        Ok(line) => line,
+                        Err(_) => continue,
+                    };
+                    for sink in &mut sinks {
+                        if let Err(e) = sink.write(&entry, &line).await {
+                            warn!("Failed to write {} access log: {}", sink.name(), e);
+                        }
+                    }
+                }
+                None => break,
+            },
+            _ = flush.tick() => {
+                for sink in &mut sinks {
+                    if let Err(e) = sink.flush(false).await {
+                        warn!("Failed to flush {} access log: {}", sink.name(), e);
+                    }
+                }
+            }
+        }
+    }
+
+    for sink in &mut sinks {
+        let _ = sink.flush(true).await;
+    }
+}
+
+enum Sink {
+    Stdout(Stdout),
+    File(RotatingFile),
+    Syslog(Logger<LoggerBackend, Formatter3164>),
+    Http(HttpSink),
+}
+
+impl Sink {
+    async fn open(config: &AccessLogSinkConfig) -> anyhow::Result<Self> {
+        Ok(match config {
+            AccessLogSinkConfig::Stdout => Sink::Stdout(tokio::io::stdout()),
+            AccessLogSinkConfig::File {
+                path,
+                max_size_bytes,
+                rotate_interval_seconds,
+                max_files,
+            } => Sink::File(
+                RotatingFile::open(
+                    PathBuf::from(path),
+                    *max_size_bytes,
+                    rotate_interval_seconds.map(Duration::from_secs),
+                    *max_files,
+                )
+                .await?,
+            ),
+            AccessLogSinkConfig::Syslog {
+                address,
+                protocol,
+                facility,
+            } => {
+                let formatter = Formatter3164 {
+                    facility: facility
+                        .parse::<Facility>()
+                        .map_err(|_| anyhow::anyhow!("Unknown syslog facility '{}'", facility))?,
+                    hostname: None,
+                    process: "api-gateway".to_string(),
+                    pid: std::process::id(),
+                };
+                // syslog's errors aren't Sync, so they can't go through `?` into anyhow
+                let logger = match (address, protocol) {
+                    (None, _) => syslog::unix(formatter),
+                    (Some(address), SyslogProtocol::Udp) => syslog::udp(formatter, "0.0.0.0:0", address.as_str()),
+                    (Some(address), SyslogProtocol::Tcp) => syslog::tcp(formatter, address.as_str()),
+                }
+                .map_err(|e| anyhow::anyhow!("Failed to connect to syslog: {}", e))?;
+                Sink::Syslog(logger)
+            }
+            AccessLogSinkConfig::Http {
+                url,
+                batch_size,
+                flush_interval_ms,
+            } => Sink::Http(HttpSink {
+                client: Client::builder().timeout(HTTP_SINK_TIMEOUT).build()?,
+                url: url.clone(),
+                batch_size: (*batch_size).max(1),
+                flush_interval: Duration::from_millis(*flush_interval_ms),
+                batch: Vec::new(),
+                last_flush: Instant::now(),
+            }),
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Sink::Stdout(_) => "stdout",
+            Sink::File(_) => "file",
+            Sink::Syslog(_) => "syslog",
+            Sink::Http(_) => "HTTP",
+        }
+    }
+
+    async fn write(&mut self, entry: &AccessLogEntry, line: &str) -> anyhow::Result<()> {
+        match self {
+            Sink::Stdout(stdout) => stdout.write_all(format!("{}\n", line).as_bytes()).await?,
+            Sink::File(file) => file.write(line).await?,
+            Sink::Syslog(logger) => logger
+                .info(line)
+                .map_err(|e| anyhow::anyhow!("{}", e))?,
+            Sink::Http(http) => {
+                http.batch.push(entry.clone());
+                if http.batch.len() >= http.batch_size {
+                    http.send().await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Periodic flush; `force` sends a partial HTTP batch regardless of its interval.
+    async fn flush(&mut self, force: bool) -> anyhow::Result<()> {
+        match self {
+            Sink::Stdout(stdout) => stdout.flush().await?,
+            Sink::File(file) => file.writer.flush().await?,
+            Sink::Syslog(_) => {}
+            Sink::Http(http) => {
+                if force || http.last_flush.elapsed() >= http.flush_interval {
+                    http.send().await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    max_size_bytes: u64,
+    rotate_interval: Option<Duration>,
+    max_files: usize,
+    writer: BufWriter<File>,
+    size: u64,
+    opened_at: Instant,
+}
+
+impl RotatingFile {
+    async fn open(
+        path: PathBuf,
+        max_size_bytes: u64,
+        rotate_interval: Option<Duration>,
+        max_files: usize,
+    ) -> anyhow::Result<Self> {
+        let (writer, size) = open_append(&path).await?;
+        Ok(Self {
+            path,
+            max_size_bytes,
+            rotate_interval,
+            max_files,
+            writer,
+            size,
+            opened_at: Instant::now(),
+        })
+    }
+
+    async fn write(&mut self, line: &str) -> anyhow::Result<()> {
+        let len = line.len() as u64 + 1;
+        let too_big = self.size > 0 && self.size + len > self.max_size_bytes;
+        let too_old = self
+            .rotate_interval
+            .map_or(false, |interval| self.opened_at.elapsed() >= interval);
+        if too_big || too_old {
+            self.rotate().await?;
+        }
+
+        self.writer.write_all(line.as_bytes()).await?;
+        self.writer.write_all(b"\n").await?;
+        self.size += len;
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> anyhow::Result<()> {
+        self.writer.flush().await?;
+
+        if self.max_files == 0 {
+            fs::remove_file(&self.path).await?;
+        } else {
+            // Shift <path>.N to <path>.N+1; the oldest is overwritten
+            for index in (1..self.max_files).rev() {
+                let from = rotated_path(&self.path, index);
+                if fs::try_exists(&from).await? {
+                    fs::rename(&from, rotated_path(&self.path, index + 1)).await?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1)).await?;
+        }
+
+        let (writer, size) = open_append(&self.path).await?;
+        self.writer = writer;
+        self.size = size;
+        self.opened_at = Instant::now();
+        Ok(())
+    }
+}
+
+async fn open_append(path: &Path) -> anyhow::Result<(BufWriter<File>, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path).await?;
+    let size = file.metadata().await?.len();
+    Ok((BufWriter::new(file), size))
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    rotated.into()
+}
+
+struct HttpSink {
+    client: Client,
+    url: String,
+    batch_size: usize,
+    flush_interval: Duration,
+    batch: Vec<AccessLogEntry>,
+    last_flush: Instant,
+}
+
+impl HttpSink {
+    /// A batch the collector doesn't accept is dropped, so an outage can't grow it
+    /// without bound.
+    async fn send(&mut self) -> anyhow::Result<()> {
+        self.last_flush = Instant::now();
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+
+        let batch = std::mem::take(&mut self.batch);
+        let response = self.client.post(&self.url).json(&batch).send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!(
+                "collector returned {}, dropped {} entries",
+                response.status(),
+                batch.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_rotation_by_size() {
+        let dir = std::env::temp_dir().join(format!("access-log-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+
+        let mut file = RotatingFile::open(path.clone(), 20, None, 2).await.unwrap();
+        for line in ["first entry", "second entry", "third entry", "fourth entry"] {
+            file.write(line).await.unwrap();
+        }
+        file.writer.flush().await.unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "fourth entry\n");
+        assert_eq!(std::fs::read_to_string(rotated_path(&path, 1)).unwrap(), "third entry\n");
+        assert_eq!(std::fs::read_to_string(rotated_path(&path, 2)).unwrap(), "second entry\n");
+        assert!(!rotated_path(&path, 3).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+use axum::http::Method;
+
+use crate::config::{AdminAuthConfig, AdminRole};
+
+/// The status dashboard. The page itself holds no data, so it loads without admin
+/// credentials; the endpoints it polls still need them.
+pub const DASHBOARD_PATH: &str = "/admin/ui";
+
+/// Endpoints that take a POST body but don't change anything.
+const READ_ONLY_POSTS: &[&str] = &["/admin/routes/match"];
+
+pub struct AdminAuth {
+    config: AdminAuthConfig,
+}
+
+impl AdminAuth {
+    pub fn new(config: &AdminAuthConfig) -> Self {
+        Self { config: config.clone() }
+    }
+
+    pub fn is_admin_path(path: &str) -> bool {
+        path == "/admin" || path.starts_with("/admin/")
+    }
+
+    /// Reads only need the read-only role; anything that changes gateway state needs
+    /// read-write.
+    pub fn required_role(method: &Method, path: &str) -> AdminRole {
+        if method == Method::GET || method == Method::HEAD || READ_ONLY_POSTS.contains(&path) {
+            AdminRole::ReadOnly
+        } else {
+            AdminRole::ReadWrite
+        }
+    }
+
+    /// The name and role of the dedicated admin token `token` matches.
+    pub fn token_role(&self, token: &str) -> Option<(&str, AdminRole)> {
+        let digest = hex(&openssl::sha::sha256(token.as_bytes()));
+        self.config
+            .tokens
+            .iter()
+            .find(|admin_token| {
+                admin_token.token_sha256.len() == digest.len()
+                    && openssl::memcmp::eq(admin_token.token_sha256.to_ascii_lowercase().as_bytes(), digest.as_bytes())
+            })
+            .map(|admin_token| (admin_token.name.as_str(), admin_token.role))
+    }
+
+    /// The strongest admin role the scopes of a regular identity grant.
+    pub fn scope_role(&self, scopes: &[String]) -> Option<AdminRole> {
+        if scopes.contains(&self.config.read_write_scope) {
+            Some(AdminRole::ReadWrite)
+        } else if scopes.contains(&self.config.read_only_scope) {
+            Some(AdminRole::ReadOnly)
+        } else {
+            None
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_roles() {
+        let reader_digest = hex(&openssl::sha::sha256(b"reader-token"));
+        let admin_auth = AdminAuth::new(
+            &serde_json::from_value(json!({
+                "tokens": [
+                    { "name": "dashboard", "token_sha256": reader_digest.to_uppercase(), "role": "read_only" },
+                    { "name": "deployer", "token_sha256": hex(&openssl::sha::sha256(b"deploy-token")), "role": "read_write" }
+                ]
+            }))
+            .unwrap(),
+        );
+
+        assert_eq!(admin_auth.token_role("reader-token"), Some(("dashboard", AdminRole::ReadOnly)));
+        assert_eq!(admin_auth.token_role("deploy-token"), Some(("deployer", AdminRole::ReadWrite)));
+        assert_eq!(admin_auth.token_role(&reader_digest), None);
+
+        let scopes = |scopes: &[&str]| scopes.iter().map(|scope| scope.to_string()).collect::<Vec<_>>();
+        assert_eq!(admin_auth.scope_role(&scopes(&["read", "admin:read"])), Some(AdminRole::ReadOnly));
+        assert_eq!(admin_auth.scope_role(&scopes(&["admin:read", "admin"])), Some(AdminRole::ReadWrite));
+        assert_eq!(admin_auth.scope_role(&scopes(&["read", "write"])), None);
+
+        assert!(AdminRole::ReadWrite >= AdminAuth::required_role(&Method::DELETE, "/admin/api-keys/1"));
+        assert!(AdminRole::ReadOnly < AdminAuth::required_role(&Method::POST, "/admin/api-keys"));
+        assert!(AdminRole::ReadOnly >= AdminAuth::required_role(&Method::GET, "/admin/routes"));
+        assert!(AdminRole::ReadOnly >= AdminAuth::required_role(&Method::POST, "/admin/routes/match"));
+        assert!(AdminAuth::is_admin_path("/admin/config") && !AdminAuth::is_admin_path("/administrator"));
+    }
+}
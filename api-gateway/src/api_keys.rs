@@ -0,0 +1,678 @@
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use lru::LruCache;
+use rand::Rng;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgPoolOptions, PgPool, Row};
+use std::{
+    num::NonZeroUsize,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info, warn};
+
+use crate::auth::ApiKeyInfo;
+use crate::config::{ApiKeyCacheConfig, DatabaseConfig};
+
+/// Bounds each Redis round trip; a slow Redis falls through to Postgres.
+const REDIS_OPERATION_TIMEOUT: Duration = Duration::from_millis(200);
+/// Channel on which revocations and updates are announced to every instance.
+const INVALIDATION_CHANNEL: &str = "api_key_invalidations";
+/// Unknown keys remembered in-process, so a client retrying a bad key doesn't reach
+/// Postgres each time. They're kept apart from known keys, which random keys from one
+/// client therefore can't evict.
+const MISS_CACHE_ENTRIES: usize = 1024;
+
+/// New keys look like `ak_live_<lookup prefix>_<secret>`. Only the lookup prefix is
+/// stored in the clear; the key itself is kept as a salted SHA-256 hash.
+const KEY_PREFIX: &str = "ak_live_";
+/// Length of the lookup prefix taken from keys that predate the `ak_live_` format.
+const LEGACY_PREFIX_LEN: usize = 12;
+
+/// An API key as shown to admins. The secret itself is only returned once, on creation.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKeyRecord {
+    pub name: String,
+    pub key_prefix: Option<String>,
+    #[serde(flatten)]
+    pub info: ApiKeyInfo,
+    pub created_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CreatedApiKey {
+    pub api_key: String,
+    #[serde(flatten)]
+    pub record: ApiKeyRecord,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateApiKey {
+    pub name: String,
+    pub user_id: Option<i32>,
+    #[serde(default)]
+    pub permissions: Vec<String>,
+    pub rate_limit: Option<u32>,
+    pub plan: Option<String>,
+    /// Tenant realm the key belongs to; keys without one only work outside tenant realms
+    pub tenant: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Fields left out of an update are kept as they are.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct UpdateApiKey {
+    pub name: Option<String>,
+    pub permissions: Option<Vec<String>>,
+    pub rate_limit: Option<u32>,
+    pub plan: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub is_active: Option<bool>,
+}
+
+struct CachedKey {
+    info: ApiKeyInfo,
+    fetched_at: Instant,
+}
+
+/// API keys stored in the `api_keys` table. Lookups go through an in-process LRU cache,
+/// then an optional Redis cache shared by all instances, before reaching Postgres. Caches
+/// are keyed by a digest of the key so plaintext keys aren't held onto either.
+pub struct ApiKeyStore {
+    pool: PgPool,
+    cache: Mutex<LruCache<String, CachedKey>>,
+    /// When each unknown key was looked up
+    misses: Mutex<LruCache<String, Instant>>,
+    cache_ttl: Duration,
+    redis: Option<redis::Client>,
+}
+
+impl ApiKeyStore {
+    /// Connections are opened on first use, so the gateway can start while Postgres is down.
+    pub fn new(database: &DatabaseConfig, cache: &ApiKeyCacheConfig, redis_url: &str) -> anyhow::Result<Self> {
+        let pool = PgPoolOptions::new()
+            .max_connections(database.max_connections)
+            .acquire_timeout(Duration::from_secs(5))
+            .connect_lazy(&database.url)?;
+
+        let redis = if cache.shared {
+            Some(redis::Client::open(redis_url)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            pool,
+            cache: Mutex::new(LruCache::new(
+                NonZeroUsize::new(cache.max_entries).unwrap_or(NonZeroUsize::MIN),
+            )),
+            misses: Mutex::new(LruCache::new(NonZeroUsize::new(MISS_CACHE_ENTRIES).unwrap())),
+            cache_ttl: Duration::from_secs(cache.ttl_seconds),
+            redis,
+        })
+    }
+
+    /// Creates the table on databases that weren't set up with `database/init.sql`, and
+    /// replaces any plaintext keys left from before keys were hashed.
+    pub async fn ensure_schema(&self) -> anyhow::Result<()> {
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS api_keys (
+                id SERIAL PRIMARY KEY,
+                key_name VARCHAR(100) NOT NULL,
+                api_key VARCHAR(255) UNIQUE,
+                key_prefix VARCHAR(32),
+                key_salt VARCHAR(64),
+                key_hash VARCHAR(64),
+                user_id INTEGER,
+                permissions JSONB DEFAULT '[]',
+                rate_limit INTEGER DEFAULT 1000,
+                plan VARCHAR(50),
+                tenant VARCHAR(100),
+                is_active BOOLEAN DEFAULT true,
+                expires_at TIMESTAMP WITH TIME ZONE,
+                created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
+                last_used_at TIMESTAMP WITH TIME ZONE
+            )",
+        )
+        .execute(&self.pool)
+        .await?;
+
+        for statement in [
+            "ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS plan VARCHAR(50)",
+            "ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS tenant VARCHAR(100)",
+            "ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS key_prefix VARCHAR(32)",
+            "ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS key_salt VARCHAR(64)",
+            "ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS key_hash VARCHAR(64)",
+            "ALTER TABLE api_keys ALTER COLUMN api_key DROP NOT NULL",
+            "CREATE INDEX IF NOT EXISTS idx_api_keys_prefix ON api_keys(key_prefix)",
+        ] {
+            sqlx::query(statement).execute(&self.pool).await?;
+        }
+
+        self.hash_plaintext_keys().await
+    }
+
+    async fn hash_plaintext_keys(&self) -> anyhow::Result<()> {
+        let rows = sqlx::query("SELECT id, api_key FROM api_keys WHERE api_key IS NOT NULL")
+            .fetch_all(&self.pool)
+            .await?;
+
+        for row in &rows {
+            let id: i32 = row.try_get("id")?;
+            let api_key: String = row.try_get("api_key")?;
+            let salt = random_hex(16);
+
+            sqlx::query("UPDATE api_keys SET key_prefix = $2, key_salt = $3, key_hash = $4, api_key = NULL WHERE id = $1")
+                .bind(id)
+                .bind(lookup_prefix(&api_key))
+                .bind(&salt)
+                .bind(hash_key(&salt, &api_key))
+                .execute(&self.pool)
+                .await?;
+        }
+
+        if !rows.is_empty() {
+            info!("Hashed {} plaintext API keys", rows.len());
+        }
+        Ok(())
+    }
+
+    /// Looks up a key, including revoked and expired ones; callers decide whether it's usable.
+    pub async fn lookup(&self, api_key: &str) -> anyhow::Result<Option<ApiKeyInfo>> {
+        let digest = cache_key(api_key);
+        if let Some(info) = self.cached_locally(&digest) {
+            return Ok(info);
+        }
+
+        if let Some(info) = self.shared_get(&digest).await {
+            self.cache_locally(digest, info.clone());
+            return Ok(info);
+        }
+
+        // Prefixes aren't unique, so every candidate's hash is checked
+        let rows = sqlx::query(&format!("SELECT {} FROM api_keys WHERE key_prefix = $1", KEY_COLUMNS))
+            .bind(lookup_prefix(api_key))
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut info = None;
+        for row in &rows {
+            let salt: Option<String> = row.try_get("key_salt")?;
+            let hash: Option<String> = row.try_get("key_hash")?;
+            if let (Some(salt), Some(hash)) = (salt, hash) {
+                if hashes_match(&hash_key(&salt, api_key), &hash) {
+                    info = Some(record_from_row(row)?.info);
+                    break;
+                }
+            }
+        }
+
+        self.shared_put(&digest, &info).await;
+        self.cache_locally(digest, info.clone());
+
+        Ok(info)
+    }
+
+    /// `Some(None)` for a key remembered as unknown. Expired entries are dropped as
+    /// they're found.
+    fn cached_locally(&self, digest: &str) -> Option<Option<ApiKeyInfo>> {
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(cached) = cache.get(digest) {
+            if cached.fetched_at.elapsed() < self.cache_ttl {
+                return Some(Some(cached.info.clone()));
+            }
+            cache.pop(digest);
+        }
+        drop(cache);
+
+        let mut misses = self.misses.lock().unwrap();
+        let fetched_at = *misses.get(digest)?;
+        if fetched_at.elapsed() < self.cache_ttl {
+            return Some(None);
+        }
+        misses.pop(digest);
+        None
+    }
+
+    fn cache_locally(&self, digest: String, info: Option<ApiKeyInfo>) {
+        let fetched_at = Instant::now();
+        match info {
+            Some(info) => {
+                self.misses.lock().unwrap().pop(&digest);
+                self.cache.lock().unwrap().put(digest, CachedKey { info, fetched_at });
+            }
+            None => {
+                self.cache.lock().unwrap().pop(&digest);
+                self.misses.lock().unwrap().put(digest, fetched_at);
+            }
+        }
+    }
+
+    /// `None` on a miss or when Redis can't be reached; `Some(None)` caches an unknown key.
+    async fn shared_get(&self, digest: &str) -> Option<Option<ApiKeyInfo>> {
+        let client = self.redis.as_ref()?;
+        let lookup = async {
+            let mut conn = client.get_async_connection().await?;
+            conn.get::<_, Option<String>>(format!("api_key_cache:{}", digest)).await
+        };
+
+        match tokio::time::timeout(REDIS_OPERATION_TIMEOUT, lookup).await {
+            Ok(Ok(Some(cached))) => serde_json::from_str(&cached).ok(),
+            Ok(Ok(None)) => None,
+            Ok(Err(e)) => {
+                debug!("API key cache read failed: {}", e);
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    async fn shared_put(&self, digest: &str, info: &Option<ApiKeyInfo>) {
+        let client = match &self.redis {
+            Some(client) => client,
+            None => return,
+        };
+        let value = match serde_json::to_string(info) {
+            Ok(value) => value,
+            Err(_) => return,
+        };
+        let ttl = self.cache_ttl.as_secs().max(1) as i64;
+
+        let store = async {
+            let mut conn = client.get_async_connection().await?;
+            let mut pipe = redis::pipe();
+            pipe.set_ex(format!("api_key_cache:{}", digest), value, ttl as u64).ignore();
+            // Indexed by key id so a revocation can find every cached copy
+            if let Some(info) = info {
+                let index = format!("api_key_cache_index:{}", info.key_id);
+                pipe.sadd(&index, digest).ignore().expire(&index, ttl).ignore();
+            }
+            pipe.query_async::<_, ()>(&mut conn).await
+        };
+
+        if let Ok(Err(e)) = tokio::time::timeout(REDIS_OPERATION_TIMEOUT, store).await {
+            debug!("API key cache write failed: {}", e);
+        }
+    }
+
+    /// Drops a key from this instance's cache and the shared one, and tells the other
+    /// instances to drop it too.
+    async fn invalidate(&self, key_id: i32) {
+        let key_id = key_id.to_string();
+        self.evict_local(&key_id);
+
+        let client = match &self.redis {
+            Some(client) => client,
+            None => return,
+        };
+        let invalidate = async {
+            let mut conn = client.get_async_connection().await?;
+            let index = format!("api_key_cache_index:{}", key_id);
+            let digests: Vec<String> = conn.smembers(&index).await?;
+
+            let mut pipe = redis::pipe();
+            for digest in &digests {
+                pipe.del(format!("api_key_cache:{}", digest)).ignore();
+            }
+            pipe.del(&index).ignore();
+            pipe.publish(INVALIDATION_CHANNEL, &key_id).ignore();
+            pipe.query_async::<_, ()>(&mut conn).await
+        };
+
+        match tokio::time::timeout(REDIS_OPERATION_TIMEOUT, invalidate).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("Failed to invalidate cached API key {}: {}", key_id, e),
+            Err(_) => warn!("Timed out invalidating cached API key {}", key_id),
+        }
+    }
+
+    fn evict_local(&self, key_id: &str) {
+        let mut cache = self.cache.lock().unwrap();
+        let evicted: Vec<String> = cache
+            .iter()
+            .filter(|(_, cached)| cached.info.key_id == key_id)
+            .map(|(digest, _)| digest.clone())
+            .collect();
+        for digest in evicted {
+            cache.pop(&digest);
+        }
+    }
+
+    /// Evicts keys other instances announce as changed. Runs for the life of the gateway,
+    /// reconnecting when Redis goes away.
+    pub async fn watch_invalidations(&self) {
+        let client = match &self.redis {
+            Some(client) => client,
+            None => return,
+        };
+
+        loop {
+            let subscribed = async {
+                let mut pubsub = client.get_async_connection().await?.into_pubsub();
+                pubsub.subscribe(INVALIDATION_CHANNEL).await?;
+                Ok::<_, redis::RedisError>(pubsub)
+            };
+
+            match subscribed.await {
+                Ok(mut pubsub) => {
+                    // Announcements may have been missed while disconnected
+                    self.cache.lock().unwrap().clear();
+                    self.misses.lock().unwrap().clear();
+
+                    let mut messages = pubsub.on_message();
+                    while let Some(message) = messages.next().await {
+                        if let Ok(key_id) = message.get_payload::<String>() {
+                            self.evict_local(&key_id);
+                        }
+                    }
+                    warn!("API key invalidation subscription closed, reconnecting");
+                }
+                Err(e) => warn!("Failed to subscribe to API key invalidations: {}", e),
+            }
+
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+    }
+
+    pub async fn list(&self) -> anyhow::Result<Vec<ApiKeyRecord>> {
+        let rows = sqlx::query(&format!("SELECT {} FROM api_keys ORDER BY id", KEY_COLUMNS))
+            .fetch_all(&self.pool)
+            .await?;
+
+        rows.iter().map(record_from_row).collect()
+    }
+
+    pub async fn create(&self, request: CreateApiKey) -> anyhow::Result<CreatedApiKey> {
+        let GeneratedApiKey { api_key, key_prefix, key_salt, key_hash } = generate_api_key();
+
+        let row = sqlx::query(&format!(
+            "INSERT INTO api_keys (key_name, key_prefix, key_salt, key_hash, user_id, permissions, rate_limit, plan, tenant, expires_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+             RETURNING {}",
+            KEY_COLUMNS
+        ))
+        .bind(&request.name)
+        .bind(&key_prefix)
+        .bind(&key_salt)
+        .bind(&key_hash)
+        .bind(request.user_id)
+        .bind(serde_json::json!(request.permissions))
+        .bind(request.rate_limit.unwrap_or(1000) as i32)
+        .bind(&request.plan)
+        .bind(&request.tenant)
+        .bind(request.expires_at)
+        .fetch_one(&self.pool)
+        .await?;
+        let record = record_from_row(&row)?;
+
+        info!("Created API key {} ({}, prefix {:?})", record.info.key_id, record.name, record.key_prefix);
+        Ok(CreatedApiKey { api_key, record })
+    }
+
+    /// Returns `None` when no key has that id.
+    pub async fn update(&self, key_id: i32, update: UpdateApiKey) -> anyhow::Result<Option<ApiKeyRecord>> {
+        let row = sqlx::query(&format!(
+            "UPDATE api_keys SET
+                key_name = COALESCE($2, key_name),
+                permissions = COALESCE($3, permissions),
+                rate_limit = COALESCE($4, rate_limit),
+                plan = COALESCE($5, plan),
+                expires_at = COALESCE($6, expires_at),
+                is_active = COALESCE($7, is_active)
+             WHERE id = $1
+             RETURNING {}",
+            KEY_COLUMNS
+        ))
+        .bind(key_id)
+        .bind(&update.name)
+        .bind(update.permissions.map(|permissions| serde_json::json!(permissions)))
+        .bind(update.rate_limit.map(|limit| limit as i32))
+        .bind(&update.plan)
+        .bind(update.expires_at)
+        .bind(update.is_active)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        self.invalidate(key_id).await;
+        row.map(|row| record_from_row(&row)).transpose()
+    }
+
+    /// Deactivates a key. Returns whether a key with that id existed.
+    pub async fn revoke(&self, key_id: i32) -> anyhow::Result<bool> {
+        let result = sqlx::query("UPDATE api_keys SET is_active = false WHERE id = $1")
+            .bind(key_id)
+            .execute(&self.pool)
+            .await?;
+
+        self.invalidate(key_id).await;
+        if result.rows_affected() > 0 {
+            info!("Revoked API key {}", key_id);
+        }
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+const KEY_COLUMNS: &str = "id, key_name, key_prefix, key_salt, key_hash, user_id, permissions, rate_limit, plan, tenant, is_active, expires_at, created_at, last_used_at";
+
+fn record_from_row(row: &sqlx::postgres::PgRow) -> anyhow::Result<ApiKeyRecord> {
+    let id: i32 = row.try_get("id")?;
+    let user_id: Option<i32> = row.try_get("user_id")?;
+    let permissions: Option<serde_json::Value> = row.try_get("permissions")?;
+    let rate_limit: Option<i32> = row.try_get("rate_limit")?;
+    let expires_at: Option<DateTime<Utc>> = row.try_get("expires_at")?;
+
+    Ok(ApiKeyRecord {
+        name: row.try_get("key_name")?,
+        key_prefix: row.try_get("key_prefix")?,
+        info: ApiKeyInfo {
+            key_id: id.to_string(),
+            user_id: user_id.map(|id| id.to_string()),
+            permissions: permissions.as_ref().map(parse_permissions).unwrap_or_default(),
+            rate_limit: rate_limit.unwrap_or(0).max(0) as u32,
+            expires_at: expires_at.map(|at| at.timestamp().max(0) as u64),
+            is_active: row.try_get::<Option<bool>, _>("is_active")?.unwrap_or(false),
+            plan: row.try_get("plan")?,
+            tenant: row.try_get("tenant")?,
+        },
+        created_at: row.try_get("created_at")?,
+        last_used_at: row.try_get("last_used_at")?,
+    })
+}
+
+/// Permissions are a JSON list of names. Rows written by the original seed script use an
+/// object of `"name": true` flags instead, so that form is still accepted.
+fn parse_permissions(value: &serde_json::Value) -> Vec<String> {
+    match value {
+        serde_json::Value::Array(items) => items
+            .iter()
+            .filter_map(|item| item.as_str().map(String::from))
+            .collect(),
+        serde_json::Value::Object(flags) => flags
+            .iter()
+            .filter(|(_, enabled)| enabled.as_bool() == Some(true))
+            .map(|(name, _)| name.clone())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// A fresh key and the columns stored for it. The key itself is only ever shown once.
+#[derive(Debug, Serialize)]
+pub struct GeneratedApiKey {
+    pub api_key: String,
+    pub key_prefix: String,
+    pub key_salt: String,
+    pub key_hash: String,
+}
+
+pub fn generate_api_key() -> GeneratedApiKey {
+    let api_key = generate_key();
+    let key_salt = random_hex(16);
+    GeneratedApiKey {
+        key_prefix: lookup_prefix(&api_key),
+        key_hash: hash_key(&key_salt, &api_key),
+        key_salt,
+        api_key,
+    }
+}
+
+fn generate_key() -> String {
+    format!("{}{}_{}", KEY_PREFIX, random_hex(4), random_hex(24))
+}
+
+/// The part of a key used to find its row: `ak_live_<prefix>` for current keys, or the
+/// first few characters of a legacy key.
+pub fn lookup_prefix(api_key: &str) -> String {
+    if let Some(rest) = api_key.strip_prefix(KEY_PREFIX) {
+        if let Some((prefix, _)) = rest.split_once('_') {
+            return format!("{}{}", KEY_PREFIX, prefix);
+        }
+    }
+    api_key.chars().take(LEGACY_PREFIX_LEN).collect()
+}
+
+/// Stable, non-reversible identifier for a key, safe to log or use as a rate limit key.
+pub fn fingerprint(api_key: &str) -> String {
+    hex(&openssl::sha::sha256(api_key.as_bytes())[..8])
+}
+
+/// Full digest used to key the lookup caches; unlike `fingerprint` it's not truncated,
+/// so two keys can't share a cache entry.
+fn cache_key(api_key: &str) -> String {
+    hex(&openssl::sha::sha256(api_key.as_bytes()))
+}
+
+fn hash_key(salt: &str, api_key: &str) -> String {
+    let mut hasher = openssl::sha::Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(api_key.as_bytes());
+    hex(&hasher.finish())
+}
+
+fn hashes_match(computed: &str, stored: &str) -> bool {
+    computed.len() == stored.len() && openssl::memcmp::eq(computed.as_bytes(), stored.as_bytes())
+}
+
+fn random_hex(len: usize) -> String {
+    let bytes: Vec<u8> = (0..len).map(|_| rand::thread_rng().gen()).collect();
+    hex(&bytes)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// A store whose cache is pre-filled, for tests that don't have a database.
+#[cfg(test)]
+pub fn test_store(keys: Vec<(&str, ApiKeyInfo)>) -> ApiKeyStore {
+    let config = crate::config::Config::default_config();
+    let cache = ApiKeyCacheConfig {
+        shared: false,
+        ..Default::default()
+    };
+    let store = ApiKeyStore::new(&config.database, &cache, &config.redis.url).unwrap();
+    for (api_key, info) in keys {
+        store.cache_locally(cache_key(api_key), Some(info));
+    }
+    store
+}
+
+#[cfg(test)]
+impl ApiKeyStore {
+    /// Caches `api_key` as unknown, as if Postgres had no row for it.
+    pub fn remember_unknown(&self, api_key: &str) {
+        self.cache_locally(cache_key(api_key), None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_permissions() {
+        assert_eq!(
+            parse_permissions(&serde_json::json!(["read", "write"])),
+            vec!["read".to_string(), "write".to_string()]
+        );
+        assert_eq!(
+            parse_permissions(&serde_json::json!({ "read": true, "write": false })),
+            vec!["read".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_generated_keys_are_unique() {
+        let key = generate_key();
+        assert!(key.starts_with(KEY_PREFIX));
+        assert_ne!(key, generate_key());
+    }
+
+    #[test]
+    fn test_lookup_prefix() {
+        assert_eq!(lookup_prefix("ak_live_1a2b3c4d_secret"), "ak_live_1a2b3c4d");
+        assert_eq!(lookup_prefix("ak_admin_12345678901234567890"), "ak_admin_123");
+        assert_eq!(lookup_prefix(&generate_key()).len(), KEY_PREFIX.len() + 8);
+    }
+
+    #[tokio::test]
+    async fn test_invalidation_evicts_only_that_key() {
+        let info = |key_id: &str| ApiKeyInfo {
+            key_id: key_id.to_string(),
+            user_id: None,
+            permissions: Vec::new(),
+            rate_limit: 1000,
+            expires_at: None,
+            is_active: true,
+            plan: None,
+            tenant: None,
+        };
+        let store = test_store(vec![("ak_one", info("1")), ("ak_two", info("2"))]);
+
+        store.invalidate(1).await;
+        assert!(!store.cache.lock().unwrap().contains(&cache_key("ak_one")));
+        assert!(store.cache.lock().unwrap().contains(&cache_key("ak_two")));
+    }
+
+    #[tokio::test]
+    async fn test_local_cache_is_bounded_and_drops_expired_keys() {
+        let info = ApiKeyInfo {
+            key_id: "1".to_string(),
+            user_id: None,
+            permissions: Vec::new(),
+            rate_limit: 1000,
+            expires_at: None,
+            is_active: true,
+            plan: None,
+            tenant: None,
+        };
+        let store = test_store(vec![("ak_one", info)]);
+
+        // A client sending random keys fills only the miss cache
+        for attempt in 0..MISS_CACHE_ENTRIES * 2 {
+            store.cache_locally(cache_key(&format!("ak_random_{}", attempt)), None);
+        }
+        assert_eq!(store.misses.lock().unwrap().len(), MISS_CACHE_ENTRIES);
+        assert!(store.cached_locally(&cache_key("ak_random_0")).is_none());
+        let last = cache_key(&format!("ak_random_{}", MISS_CACHE_ENTRIES * 2 - 1));
+        assert!(matches!(store.cached_locally(&last), Some(None)));
+        assert!(store.cached_locally(&cache_key("ak_one")).unwrap().is_some());
+
+        let expired = Instant::now() - store.cache_ttl;
+        store.cache.lock().unwrap().get_mut(&cache_key("ak_one")).unwrap().fetched_at = expired;
+        assert!(store.cached_locally(&cache_key("ak_one")).is_none());
+        assert!(store.cache.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_hash_is_salted() {
+        let key = generate_key();
+        let hash = hash_key("salt-a", &key);
+
+        assert!(hashes_match(&hash_key("salt-a", &key), &hash));
+        assert!(!hashes_match(&hash_key("salt-b", &key), &hash));
+        assert!(!hashes_match(&hash_key("salt-a", "ak_live_other_key"), &hash));
+    }
+}
@@ -1,7 +1,12 @@
-use jsonwebtoken::{decode, DecodingKey, Validation, Algorithm};
+use jsonwebtoken::{decode, DecodingKey, Validation};
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 
+use crate::api_keys::ApiKeyStore;
+use crate::config::{RateLimitingConfig, RouteConfig};
+use crate::jwks::JwtVerifier;
+use crate::rate_limiter::EffectiveLimits;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
@@ -16,7 +21,14 @@ pub enum AuthError {
     InvalidToken,
     ExpiredToken,
     InvalidApiKey,
+    InvalidCredentials,
     MissingCredentials,
+    InsufficientScope,
+    InvalidSignature,
+    ReplayedRequest,
+    RevokedToken,
+    CsrfTokenMismatch,
+    StoreUnavailable,
 }
 
 impl std::fmt::Display for AuthError {
@@ -25,41 +37,149 @@ impl std::fmt::Display for AuthError {
             AuthError::InvalidToken => write!(f, "Invalid JWT token"),
             AuthError::ExpiredToken => write!(f, "JWT token has expired"),
             AuthError::InvalidApiKey => write!(f, "Invalid API key"),
+            AuthError::InvalidCredentials => write!(f, "Invalid username or password"),
             AuthError::MissingCredentials => write!(f, "Missing authentication credentials"),
+            AuthError::InsufficientScope => write!(f, "Credentials lack a required scope"),
+            AuthError::InvalidSignature => write!(f, "Invalid request signature"),
+            AuthError::ReplayedRequest => write!(f, "Request nonce has already been used"),
+            AuthError::RevokedToken => write!(f, "JWT token has been revoked"),
+            AuthError::CsrfTokenMismatch => write!(f, "Missing or invalid CSRF token"),
+            AuthError::StoreUnavailable => write!(f, "Credential store unavailable"),
         }
     }
 }
 
 impl std::error::Error for AuthError {}
 
+impl AuthError {
+    /// Short label for metrics.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            AuthError::InvalidToken => "invalid_token",
+            AuthError::ExpiredToken => "expired_token",
+            AuthError::InvalidApiKey => "invalid_api_key",
+            AuthError::InvalidCredentials => "invalid_credentials",
+            AuthError::MissingCredentials => "missing_credentials",
+            AuthError::InsufficientScope => "insufficient_scope",
+            AuthError::InvalidSignature => "invalid_signature",
+            AuthError::ReplayedRequest => "replayed_request",
+            AuthError::RevokedToken => "revoked_token",
+            AuthError::CsrfTokenMismatch => "csrf_mismatch",
+            AuthError::StoreUnavailable => "store_unavailable",
+        }
+    }
+}
+
 pub struct AuthService;
 
 impl AuthService {
-    pub fn validate_jwt_token(token: &str, secret: &str) -> Result<Claims, AuthError> {
-        let decoding_key = DecodingKey::from_secret(secret.as_ref());
-        let validation = Validation::new(Algorithm::HS256);
-        
-        match decode::<Claims>(token, &decoding_key, &validation) {
+    /// Validates a token's signature and standard claims. `validation.algorithms` is the
+    /// allow-list: tokens whose header names any other algorithm are rejected.
+    pub fn validate_jwt_token(token: &str, key: &DecodingKey, validation: &Validation) -> Result<Claims, AuthError> {
+        match decode::<Claims>(token, key, validation) {
+            Ok(token_data) => Ok(token_data.claims),
+            Err(err) => Err(map_jwt_error(err)),
+        }
+    }
+
+    /// Like `validate_jwt_token`, but returns every claim so callers can read custom ones.
+    pub fn validate_jwt_claims(
+        token: &str,
+        key: &DecodingKey,
+        validation: &Validation,
+    ) -> Result<serde_json::Map<String, serde_json::Value>, AuthError> {
+        match decode::<serde_json::Map<String, serde_json::Value>>(token, key, validation) {
             Ok(token_data) => Ok(token_data.claims),
-            Err(err) => {
-                match err.kind() {
-                    jsonwebtoken::errors::ErrorKind::ExpiredSignature => Err(AuthError::ExpiredToken),
-                    _ => Err(AuthError::InvalidToken),
+            Err(err) => Err(map_jwt_error(err)),
+        }
+    }
+
+    /// Rejects unknown, revoked and expired keys.
+    pub async fn validate_api_key(store: &ApiKeyStore, api_key: &str) -> Result<ApiKeyInfo, AuthError> {
+        let key_info = match store.lookup(api_key).await {
+            Ok(Some(key_info)) => key_info,
+            Ok(None) => return Err(AuthError::InvalidApiKey),
+            Err(e) => {
+                tracing::error!("API key lookup failed: {}", e);
+                return Err(AuthError::StoreUnavailable);
+            }
+        };
+
+        let now = chrono::Utc::now().timestamp().max(0) as u64;
+        if !key_info.is_active || key_info.expires_at.map_or(false, |expires_at| expires_at <= now) {
+            return Err(AuthError::InvalidApiKey);
+        }
+
+        Ok(key_info)
+    }
+
+    /// Resolves the limits for an API key from its plan, falling back to the defaults when
+    /// the key has no plan or the plan isn't configured.
+    pub fn resolve_limits(key_info: &ApiKeyInfo, config: &RateLimitingConfig) -> EffectiveLimits {
+        match key_info.plan.as_ref().and_then(|plan| config.plans.get(plan).map(|limits| (plan, limits))) {
+            Some((plan, limits)) => EffectiveLimits {
+                plan: Some(plan.clone()),
+                requests_per_minute: limits.requests_per_minute,
+                burst_size: limits.burst_size,
+                daily_quota: limits.daily_quota,
+                max_concurrent_requests: limits.max_concurrent_requests,
+            },
+            None => EffectiveLimits::defaults(config),
+        }
+    }
+
+    /// Authenticates with a bearer token, falling back to an API key, and checks the
+    /// credentials grant every scope the route requires.
+    pub async fn authorize(
+        verifier: &JwtVerifier,
+        api_keys: &ApiKeyStore,
+        route: Option<&RouteConfig>,
+        bearer_token: Option<&str>,
+        api_key: Option<&str>,
+    ) -> Result<Identity, AuthError> {
+        let mut identity = None;
+
+        if let Some(token) = bearer_token {
+            if let Ok(claims) = verifier.verify(token, route).await {
+                identity = Some(Identity {
+                    user_id: claims.get("sub").and_then(|sub| sub.as_str()).map(String::from),
+                    key_id: None,
+                    scopes: scopes_from_claims(&claims),
+                    claims,
+                });
+            }
+        }
+
+        if identity.is_none() {
+            if let Some(api_key) = api_key {
+                match Self::validate_api_key(api_keys, api_key).await {
+                    Ok(key_info) => identity = Some(Identity::from_api_key(key_info)),
+                    Err(AuthError::StoreUnavailable) => return Err(AuthError::StoreUnavailable),
+                    Err(_) => {}
                 }
             }
         }
+
+        let identity = match identity {
+            Some(identity) => identity,
+            None if api_key.is_some() => return Err(AuthError::InvalidApiKey),
+            None if bearer_token.is_some() => return Err(AuthError::InvalidToken),
+            None => return Err(AuthError::MissingCredentials),
+        };
+
+        Self::check_scopes(route, &identity.scopes)?;
+        Ok(identity)
     }
 
-    pub async fn validate_api_key(api_key: &str) -> Result<ApiKeyInfo, AuthError> {
-        // In a real implementation, this would query a database or cache
-        // For demo purposes, we'll use a hardcoded set of valid API keys
-        let valid_keys = get_valid_api_keys();
-        
-        if let Some(key_info) = valid_keys.get(api_key) {
-            Ok(key_info.clone())
-        } else {
-            Err(AuthError::InvalidApiKey)
+    pub fn check_scopes(route: Option<&RouteConfig>, granted: &[String]) -> Result<(), AuthError> {
+        let required: Vec<&str> = route
+            .map(|r| r.required_scopes.iter().map(|s| s.as_str()).collect())
+            .unwrap_or_default();
+        if !Self::validate_permissions(&required, granted) {
+            return Err(AuthError::InsufficientScope);
         }
+
+        Ok(())
     }
 
     pub fn extract_bearer_token(auth_header: &str) -> Option<&str> {
@@ -77,6 +197,57 @@ impl AuthService {
     }
 }
 
+fn map_jwt_error(err: jsonwebtoken::errors::Error) -> AuthError {
+    match err.kind() {
+        jsonwebtoken::errors::ErrorKind::ExpiredSignature => AuthError::ExpiredToken,
+        _ => AuthError::InvalidToken,
+    }
+}
+
+/// Scopes granted by a token, from a space-separated `scope` claim or a `scp` list.
+pub fn scopes_from_claims(claims: &serde_json::Map<String, serde_json::Value>) -> Vec<String> {
+    if let Some(scope) = claims.get("scope").and_then(|v| v.as_str()) {
+        return scope.split_whitespace().map(String::from).collect();
+    }
+
+    claims
+        .get("scp")
+        .and_then(|v| v.as_array())
+        .map(|scopes| scopes.iter().filter_map(|s| s.as_str().map(String::from)).collect())
+        .unwrap_or_default()
+}
+
+/// Who a request was authenticated as, forwarded to backends as headers.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Identity {
+    pub user_id: Option<String>,
+    pub key_id: Option<String>,
+    pub scopes: Vec<String>,
+    /// Token claims, or the equivalent fields for other credentials
+    pub claims: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Identity {
+    pub fn from_api_key(key_info: ApiKeyInfo) -> Self {
+        let mut claims = serde_json::Map::new();
+        if let Some(user_id) = &key_info.user_id {
+            claims.insert("sub".to_string(), user_id.clone().into());
+        }
+        claims.insert("key_id".to_string(), key_info.key_id.clone().into());
+        claims.insert("scope".to_string(), key_info.permissions.join(" ").into());
+        if let Some(tenant) = &key_info.tenant {
+            claims.insert("tenant".to_string(), tenant.clone().into());
+        }
+
+        Self {
+            user_id: key_info.user_id,
+            key_id: Some(key_info.key_id),
+            scopes: key_info.permissions,
+            claims,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiKeyInfo {
     pub key_id: String,
@@ -85,66 +256,16 @@ pub struct ApiKeyInfo {
     pub rate_limit: u32,
     pub expires_at: Option<u64>,
     pub is_active: bool,
-}
-
-// In a real implementation, this would be loaded from a database
-fn get_valid_api_keys() -> std::collections::HashMap<String, ApiKeyInfo> {
-    let mut keys = std::collections::HashMap::new();
-    
-    keys.insert(
-        "ak_admin_12345678901234567890".to_string(),
-        ApiKeyInfo {
-            key_id: "admin_key".to_string(),
-            user_id: Some("admin".to_string()),
-            permissions: vec![
-                "admin".to_string(),
-                "read".to_string(),
-                "write".to_string(),
-                "delete".to_string(),
-            ],
-            rate_limit: 10000,
-            expires_at: None,
-            is_active: true,
-        },
-    );
-    
-    keys.insert(
-        "ak_user_09876543210987654321".to_string(),
-        ApiKeyInfo {
-            key_id: "user_key".to_string(),
-            user_id: Some("user".to_string()),
-            permissions: vec![
-                "read".to_string(),
-            ],
-            rate_limit: 1000,
-            expires_at: None,
-            is_active: true,
-        },
-    );
-    
-    keys.insert(
-        "ak_service_11111111111111111111".to_string(),
-        ApiKeyInfo {
-            key_id: "service_key".to_string(),
-            user_id: None,
-            permissions: vec![
-                "service".to_string(),
-                "read".to_string(),
-                "write".to_string(),
-            ],
-            rate_limit: 5000,
-            expires_at: None,
-            is_active: true,
-        },
-    );
-    
-    keys
+    pub plan: Option<String>,
+    pub tenant: Option<String>,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use jsonwebtoken::{encode, EncodingKey, Header};
+    use crate::api_keys::test_store;
+    use crate::config::Config;
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
 
     #[test]
     fn test_valid_jwt_token() {
@@ -163,7 +284,8 @@ mod tests {
             &EncodingKey::from_secret(secret.as_ref()),
         ).unwrap();
         
-        let result = AuthService::validate_jwt_token(&token, secret);
+        let key = DecodingKey::from_secret(secret.as_ref());
+        let result = AuthService::validate_jwt_token(&token, &key, &Validation::new(Algorithm::HS256));
         assert!(result.is_ok());
         
         let decoded_claims = result.unwrap();
@@ -175,27 +297,78 @@ mod tests {
         let secret = "test_secret";
         let invalid_token = "invalid.token.here";
         
-        let result = AuthService::validate_jwt_token(invalid_token, secret);
+        let key = DecodingKey::from_secret(secret.as_ref());
+        let result = AuthService::validate_jwt_token(invalid_token, &key, &Validation::new(Algorithm::HS256));
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_disallowed_algorithm_rejected() {
+        let secret = "test_secret";
+        let claims = Claims {
+            sub: "test_user".to_string(),
+            exp: (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp() as usize,
+            iat: chrono::Utc::now().timestamp() as usize,
+            iss: None,
+            aud: None,
+        };
+
+        let token = encode(
+            &Header::new(Algorithm::HS512),
+            &claims,
+            &EncodingKey::from_secret(secret.as_ref()),
+        ).unwrap();
+
+        let key = DecodingKey::from_secret(secret.as_ref());
+        let result = AuthService::validate_jwt_token(&token, &key, &Validation::new(Algorithm::HS256));
+        assert!(result.is_err());
+    }
+
+    fn key_info(key_id: &str, permissions: &[&str]) -> ApiKeyInfo {
+        ApiKeyInfo {
+            key_id: key_id.to_string(),
+            user_id: None,
+            permissions: permissions.iter().map(|p| p.to_string()).collect(),
+            rate_limit: 1000,
+            expires_at: None,
+            is_active: true,
+            plan: None,
+            tenant: None,
+        }
+    }
+
     #[tokio::test]
     async fn test_valid_api_key() {
-        let api_key = "ak_admin_12345678901234567890";
-        let result = AuthService::validate_api_key(api_key).await;
+        let store = test_store(vec![("ak_admin", key_info("1", &["admin", "read"]))]);
+        let result = AuthService::validate_api_key(&store, "ak_admin").await;
         
         assert!(result.is_ok());
         let key_info = result.unwrap();
-        assert_eq!(key_info.key_id, "admin_key");
+        assert_eq!(key_info.key_id, "1");
         assert!(key_info.permissions.contains(&"admin".to_string()));
     }
 
     #[tokio::test]
     async fn test_invalid_api_key() {
-        let api_key = "invalid_key";
-        let result = AuthService::validate_api_key(api_key).await;
-        
-        assert!(result.is_err());
+        let store = test_store(vec![]);
+        store.remember_unknown("invalid_key");
+
+        assert!(matches!(
+            AuthService::validate_api_key(&store, "invalid_key").await,
+            Err(AuthError::InvalidApiKey)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_revoked_and_expired_api_keys_rejected() {
+        let mut revoked = key_info("1", &["read"]);
+        revoked.is_active = false;
+        let mut expired = key_info("2", &["read"]);
+        expired.expires_at = Some(1);
+        let store = test_store(vec![("ak_revoked", revoked), ("ak_expired", expired)]);
+
+        assert!(AuthService::validate_api_key(&store, "ak_revoked").await.is_err());
+        assert!(AuthService::validate_api_key(&store, "ak_expired").await.is_err());
     }
 
     #[test]
@@ -216,4 +389,104 @@ mod tests {
         let insufficient_permissions = vec!["read".to_string()];
         assert!(!AuthService::validate_permissions(&required, &insufficient_permissions));
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_scopes_from_claims() {
+        let claims = serde_json::json!({ "scope": "read:users write:users" });
+        assert_eq!(
+            scopes_from_claims(claims.as_object().unwrap()),
+            vec!["read:users".to_string(), "write:users".to_string()]
+        );
+
+        let claims = serde_json::json!({ "scp": ["read:users"] });
+        assert_eq!(scopes_from_claims(claims.as_object().unwrap()), vec!["read:users".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_authorize_checks_required_scopes() {
+        let config = Config::default_config();
+        let verifier = JwtVerifier::new(&config.auth).unwrap();
+        let store = test_store(vec![("ak_user", key_info("2", &["read"]))]);
+        let mut route = config.routes[0].clone();
+        let user_key = Some("ak_user");
+
+        route.required_scopes = vec!["read".to_string()];
+        let identity = AuthService::authorize(&verifier, &store, Some(&route), None, user_key).await.unwrap();
+        assert_eq!(identity.key_id.as_deref(), Some("2"));
+        assert_eq!(identity.scopes, vec!["read".to_string()]);
+
+        route.required_scopes = vec!["write".to_string()];
+        assert!(matches!(
+            AuthService::authorize(&verifier, &store, Some(&route), None, user_key).await,
+            Err(AuthError::InsufficientScope)
+        ));
+        assert!(matches!(
+            AuthService::authorize(&verifier, &store, Some(&route), None, None).await,
+            Err(AuthError::MissingCredentials)
+        ));
+    }
+
+    #[test]
+    fn test_required_permissions_alias() {
+        let mut route = serde_json::to_value(&Config::default_config().routes[0]).unwrap();
+        let route = route.as_object_mut().unwrap();
+        route.remove("required_scopes");
+        route.insert("required_permissions".to_string(), serde_json::json!(["write", "admin"]));
+
+        let route: RouteConfig = serde_json::from_value(serde_json::Value::Object(route.clone())).unwrap();
+        assert_eq!(route.required_scopes, vec!["write".to_string(), "admin".to_string()]);
+        assert!(matches!(
+            AuthService::check_scopes(Some(&route), &["write".to_string()]),
+            Err(AuthError::InsufficientScope)
+        ));
+    }
+
+    #[test]
+    fn test_tenant_selection() {
+        let mut config = Config::default_config();
+        config.auth.tenants = serde_json::from_value(serde_json::json!([
+            { "name": "acme", "hosts": ["acme.example.com"], "bypass_paths": ["/status"] },
+            { "name": "globex", "path_prefix": "/t/globex" }
+        ]))
+        .unwrap();
+
+        let tenant = |host: Option<&str>, path: &str| config.find_tenant(host, path).map(|t| t.name.as_str());
+        assert_eq!(tenant(Some("ACME.example.com:8443"), "/api/v1/users"), Some("acme"));
+        assert_eq!(tenant(None, "/t/globex/orders"), Some("globex"));
+        assert_eq!(tenant(None, "/t/globexcorp/orders"), None);
+        assert_eq!(tenant(Some("other.example.com"), "/api/v1/users"), None);
+
+        // A tenant's bypass paths replace the global ones
+        let acme = config.find_tenant(Some("acme.example.com"), "/status");
+        assert!(!config.requires_auth_for(acme, "/status"));
+        assert!(config.requires_auth_for(acme, "/health"));
+    }
+
+    #[tokio::test]
+    async fn test_issuer_and_audience_validation() {
+        let mut config = Config::default_config();
+        config.auth.expected_issuers = vec!["https://auth.example.com/".to_string()];
+        config.auth.expected_audiences = vec!["gateway".to_string()];
+        let verifier = JwtVerifier::new(&config.auth).unwrap();
+
+        let token = |iss: &str, aud: &str| {
+            let claims = Claims {
+                sub: "test_user".to_string(),
+                exp: (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp() as usize,
+                iat: chrono::Utc::now().timestamp() as usize,
+                iss: Some(iss.to_string()),
+                aud: Some(aud.to_string()),
+            };
+            encode(&Header::default(), &claims, &EncodingKey::from_secret(config.auth.jwt_secret.as_ref())).unwrap()
+        };
+
+        assert!(verifier.verify(&token("https://auth.example.com/", "gateway"), None).await.is_ok());
+        assert!(verifier.verify(&token("https://evil.example.com/", "gateway"), None).await.is_err());
+        assert!(verifier.verify(&token("https://auth.example.com/", "billing"), None).await.is_err());
+
+        // Route overrides replace the global expectations
+        let mut route = config.routes[0].clone();
+        route.expected_audiences = Some(vec!["billing".to_string()]);
+        assert!(verifier.verify(&token("https://auth.example.com/", "billing"), Some(&route)).await.is_ok());
+    }
+}
@@ -0,0 +1,107 @@
+use axum::http::HeaderMap;
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::api_keys::ApiKeyStore;
+use crate::auth::{AuthError, AuthService, Identity};
+use crate::config::{path_matches, BasicAuthConfig};
+use crate::ldap::LdapAuthenticator;
+
+pub struct BasicAuthenticator {
+    config: BasicAuthConfig,
+    ldap: Option<LdapAuthenticator>,
+}
+
+impl BasicAuthenticator {
+    pub fn new(config: &BasicAuthConfig) -> Self {
+        Self {
+            config: config.clone(),
+            ldap: config.ldap.as_ref().map(LdapAuthenticator::new),
+        }
+    }
+
+    pub fn applies_to(&self, path: &str) -> bool {
+        self.config.paths.iter().any(|pattern| path_matches(pattern, path))
+    }
+
+    /// Value for the `WWW-Authenticate` header on 401s, so browsers and tools prompt.
+    pub fn challenge(&self) -> String {
+        format!("Basic realm=\"{}\"", self.config.realm)
+    }
+
+    pub fn credentials(headers: &HeaderMap) -> Option<(String, String)> {
+        let encoded = headers
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok())?
+            .strip_prefix("Basic ")?;
+        let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+        let (username, password) = decoded.split_once(':')?;
+
+        Some((username.to_string(), password.to_string()))
+    }
+
+    pub async fn authenticate(
+        &self,
+        api_keys: &ApiKeyStore,
+        username: &str,
+        password: &str,
+    ) -> Result<Identity, AuthError> {
+        if let Some(user) = self.config.users.iter().find(|user| user.username == username) {
+            // bcrypt is deliberately slow, keep it off the async workers
+            let password = password.to_string();
+            let hash = user.password_hash.clone();
+            let valid = tokio::task::spawn_blocking(move || bcrypt::verify(password, &hash).unwrap_or(false))
+                .await
+                .unwrap_or(false);
+
+            return if valid {
+                let mut claims = serde_json::Map::new();
+                claims.insert("sub".to_string(), user.username.clone().into());
+                claims.insert("scope".to_string(), user.scopes.join(" ").into());
+
+                Ok(Identity {
+                    user_id: Some(user.username.clone()),
+                    key_id: None,
+                    scopes: user.scopes.clone(),
+                    claims,
+                })
+            } else {
+                Err(AuthError::InvalidCredentials)
+            };
+        }
+
+        if let Some(ldap) = &self.ldap {
+            match ldap.authenticate(username, password).await {
+                Err(AuthError::InvalidCredentials) if self.config.api_key_passwords => {}
+                result => return result,
+            }
+        }
+
+        if self.config.api_key_passwords {
+            return match AuthService::validate_api_key(api_keys, password).await {
+                Ok(key_info) => Ok(Identity::from_api_key(key_info)),
+                Err(AuthError::StoreUnavailable) => Err(AuthError::StoreUnavailable),
+                Err(_) => Err(AuthError::InvalidCredentials),
+            };
+        }
+
+        Err(AuthError::InvalidCredentials)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_credentials_parsed() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", format!("Basic {}", STANDARD.encode("ops:s3cr:et")).parse().unwrap());
+        assert_eq!(
+            BasicAuthenticator::credentials(&headers),
+            Some(("ops".to_string(), "s3cr:et".to_string()))
+        );
+
+        headers.insert("Authorization", "Bearer token".parse().unwrap());
+        assert_eq!(BasicAuthenticator::credentials(&headers), None);
+    }
+}
@@ -0,0 +1,281 @@
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+    routing::post,
+    Extension, Json, Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+    sync::Arc,
+};
+use tower::ServiceExt;
+use tracing::debug;
+
+use crate::config::BatchConfig;
+use crate::tls::ClientCertificate;
+use crate::transform;
+
+pub const BATCH_PATH: &str = "/batch";
+
+/// Batch request headers that describe the batch itself rather than its sub-requests.
+/// `Accept-Encoding` is dropped so sub-responses aren't compressed inside the JSON.
+const NOT_INHERITED: [HeaderName; 4] = [
+    header::CONTENT_LENGTH,
+    header::CONTENT_TYPE,
+    header::TRANSFER_ENCODING,
+    header::ACCEPT_ENCODING,
+];
+
+#[derive(Debug, Deserialize)]
+pub struct SubRequest {
+    #[serde(default = "default_method")]
+    pub method: String,
+    /// Path and query, e.g. `/api/users?page=2`
+    pub path: String,
+    /// Added to, or replacing, the batch request's headers
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Strings are sent as they are, anything else as JSON
+    #[serde(default)]
+    pub body: Option<Value>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubResponse {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    /// JSON bodies are embedded, other text as a string and binary bodies in base64
+    pub body: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body_encoding: Option<&'static str>,
+}
+
+impl SubResponse {
+    fn error(status: StatusCode, message: String) -> Self {
+        Self {
+            status: status.as_u16(),
+            headers: BTreeMap::new(),
+            body: serde_json::json!({ "error": message }),
+            body_encoding: None,
+        }
+    }
+}
+
+struct Batch {
+    /// The gateway's router, which doesn't include the batch endpoint, so batches can't nest
+    router: Router,
+    config: BatchConfig,
+}
+
+/// Adds the batch endpoint in front of the fully layered gateway router.
+pub fn mount(router: Router, config: BatchConfig) -> Router {
+    let batch = Arc::new(Batch {
+        router: router.clone(),
+        config,
+    });
+    Router::new()
+        .route(BATCH_PATH, post(handle))
+        .with_state(batch)
+        .merge(router)
+}
+
+async fn handle(
+    State(batch): State<Arc<Batch>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    client_cert: Option<Extension<ClientCertificate>>,
+    headers: HeaderMap,
+    Json(requests): Json<Vec<SubRequest>>,
+) -> Response {
+    if requests.len() > batch.config.max_requests {
+        return (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(serde_json::json!({
+                "error": format!("Batches are limited to {} requests", batch.config.max_requests)
+            })),
+        )
+            .into_response();
+    }
+
+    let mut inherited = headers;
+    for name in NOT_INHERITED {
+        inherited.remove(name);
+    }
+
+    // Sub-responses come back in the order of the requests
+    let responses: Vec<SubResponse> = futures::stream::iter(requests)
+        .map(|request| batch.dispatch(request, &inherited, connect_info, client_cert.as_ref()))
+        .buffered(batch.config.max_concurrency.max(1))
+        .collect()
+        .await;
+
+    Json(responses).into_response()
+}
+
+impl Batch {
+    async fn dispatch(
+        &self,
+        sub_request: SubRequest,
+        inherited: &HeaderMap,
+        connect_info: Option<ConnectInfo<SocketAddr>>,
+        client_cert: Option<&Extension<ClientCertificate>>,
+    ) -> SubResponse {
+        let mut request = match build_request(sub_request, inherited) {
+            Ok(request) => request,
+            Err(message) => return SubResponse::error(StatusCode::BAD_REQUEST, message),
+        };
+        if let Some(connect_info) = connect_info {
+            request.extensions_mut().insert(connect_info);
+        }
+        if let Some(Extension(client_cert)) = client_cert {
+            request.extensions_mut().insert(client_cert.clone());
+        }
+
+        debug!("Dispatching batched {} {}", request.method(), request.uri());
+        let response = match self.router.clone().oneshot(request).await {
+            Ok(response) => response,
+            Err(never) => match never {},
+        };
+        self.sub_response(response).await
+    }
+
+    async fn sub_response(&self, response: Response) -> SubResponse {
+        let (parts, body) = response.into_parts();
+        let body = match axum::body::to_bytes(body, self.config.max_response_bytes).await {
+            Ok(body) => body,
+            Err(_) => {
+                return SubResponse::error(
+                    StatusCode::BAD_GATEWAY,
+                    format!("Response body exceeds {} bytes", self.config.max_response_bytes),
+                )
+            }
+        };
+
+        let json = if transform::is_json(&parts.headers) {
+            serde_json::from_slice(&body).ok()
+        } else {
+            None
+        };
+        let (body, body_encoding) = match (json, String::from_utf8(body.to_vec())) {
+            (Some(json), _) => (json, None),
+            (None, Ok(text)) => (Value::String(text), None),
+            (None, Err(_)) => (Value::String(STANDARD.encode(&body)), Some("base64")),
+        };
+
+        let mut headers = BTreeMap::new();
+        for (name, value) in &parts.headers {
+            if let Ok(value) = value.to_str() {
+                headers
+                    .entry(name.as_str().to_string())
+                    .and_modify(|existing: &mut String| {
+                        existing.push_str(", ");
+                        existing.push_str(value);
+                    })
+                    .or_insert_with(|| value.to_string());
+            }
+        }
+
+        SubResponse {
+            status: parts.status.as_u16(),
+            headers,
+            body,
+            body_encoding,
+        }
+    }
+}
+
+fn build_request(sub_request: SubRequest, inherited: &HeaderMap) -> Result<Request, String> {
+    if !sub_request.path.starts_with('/') || sub_request.path.starts_with("//") {
+        return Err(format!("Sub-request path '{}' must start with a single '/'", sub_request.path));
+    }
+    let method = Method::from_bytes(sub_request.method.to_uppercase().as_bytes())
+        .map_err(|_| format!("Invalid method '{}'", sub_request.method))?;
+
+    let mut headers = inherited.clone();
+    for (name, value) in &sub_request.headers {
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(|_| format!("Invalid header name '{}'", name))?;
+        let value = HeaderValue::from_str(value).map_err(|_| format!("Invalid value for header '{}'", name))?;
+        headers.insert(name, value);
+    }
+
+    let body = match sub_request.body {
+        None | Some(Value::Null) => Body::empty(),
+        Some(Value::String(text)) => Body::from(text),
+        Some(json) => {
+            if !headers.contains_key(header::CONTENT_TYPE) {
+                headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            }
+            Body::from(json.to_string())
+        }
+    };
+
+    let mut request = Request::builder()
+        .method(method)
+        .uri(&sub_request.path)
+        .body(body)
+        .map_err(|e| format!("Invalid sub-request: {}", e))?;
+    *request.headers_mut() = headers;
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use serde_json::json;
+
+    fn batch() -> Arc<Batch> {
+        let router = Router::new()
+            .route("/json", get(|headers: HeaderMap| async move {
+                Json(json!({ "user": headers.get("x-user").and_then(|v| v.to_str().ok()) }))
+            }))
+            .route("/echo", post(|body: String| async move { body }))
+            .route("/binary", get(|| async { vec![0xffu8, 0xfe] }));
+        Arc::new(Batch {
+            router,
+            config: serde_json::from_value(json!({ "max_requests": 3 })).unwrap(),
+        })
+    }
+
+    fn sub_request(request: Value) -> SubRequest {
+        serde_json::from_value(request).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_dispatch() {
+        let batch = batch();
+        let mut inherited = HeaderMap::new();
+        inherited.insert("x-user", HeaderValue::from_static("batch"));
+
+        let response = batch.dispatch(sub_request(json!({ "path": "/json" })), &inherited, None, None).await;
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, json!({ "user": "batch" }));
+
+        let overridden = sub_request(json!({ "path": "/json", "headers": { "X-User": "sub" } }));
+        let response = batch.dispatch(overridden, &inherited, None, None).await;
+        assert_eq!(response.body, json!({ "user": "sub" }));
+
+        let echo = sub_request(json!({ "method": "post", "path": "/echo", "body": { "a": 1 } }));
+        let response = batch.dispatch(echo, &inherited, None, None).await;
+        assert_eq!(response.body, json!(r#"{"a":1}"#));
+
+        let response = batch.dispatch(sub_request(json!({ "path": "/binary" })), &inherited, None, None).await;
+        assert_eq!((response.body, response.body_encoding), (json!("//4="), Some("base64")));
+
+        let response = batch.dispatch(sub_request(json!({ "path": "/missing" })), &inherited, None, None).await;
+        assert_eq!(response.status, 404);
+
+        let response = batch.dispatch(sub_request(json!({ "path": "http://internal/" })), &inherited, None, None).await;
+        assert_eq!(response.status, 400);
+    }
+}
@@ -0,0 +1,368 @@
+use axum::{
+    body::Bytes,
+    http::{header, HeaderMap},
+};
+use dashmap::DashMap;
+use regex::Regex;
+use serde_json::{Map, Value};
+use tracing::{debug, warn};
+
+use crate::config::{RewriteRule, RouteConfig};
+use crate::transform;
+
+/// Applies routes' rewrite rules to bodies, compiling each regex once.
+pub struct BodyRewriter {
+    /// `None` for patterns that failed to compile, so the warning is only logged once
+    regexes: DashMap<String, Option<Regex>>,
+}
+
+impl BodyRewriter {
+    pub fn new() -> Self {
+        Self {
+            regexes: DashMap::new(),
+        }
+    }
+
+    pub fn request_body(&self, route: &RouteConfig, headers: &HeaderMap, body: Bytes) -> Bytes {
+        match &route.rewrite {
+            Some(rewrite) if !rewrite.request.is_empty() => {
+                self.rewrite(&rewrite.request, rewrite.max_body_bytes, headers, body)
+            }
+            _ => body,
+        }
+    }
+
+    /// Drops the response's `Content-Length` when the body changes.
+    pub fn response_body(&self, route: &RouteConfig, headers: &mut HeaderMap, body: Bytes) -> Bytes {
+        match &route.rewrite {
+            Some(rewrite) if !rewrite.response.is_empty() => {
+                let rewritten = self.rewrite(&rewrite.response, rewrite.max_body_bytes, headers, body.clone());
+                if rewritten != body {
+                    headers.remove(header::CONTENT_LENGTH);
+                }
+                rewritten
+            }
+            _ => body,
+        }
+    }
+
+    fn rewrite(&self, rules: &[RewriteRule], max_body_bytes: usize, headers: &HeaderMap, body: Bytes) -> Bytes {
+        if body.len() > max_body_bytes {
+            debug!("Not rewriting {} byte body, over the {} byte limit", body.len(), max_body_bytes);
+            return body;
+        }
+
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.split(';').next().unwrap_or("").trim().to_ascii_lowercase())
+            .unwrap_or_default();
+        let json = transform::is_json(headers);
+
+        // JSON stays parsed across consecutive JSONPath rules
+        let mut body = Body::Raw(body);
+        for rule in rules {
+            match rule {
+                RewriteRule::JsonSet { path, value } if json => {
+                    if let (Some(path), Some(json)) = (parse_path(path), body.json()) {
+                        set(json, &path, value);
+                    }
+                }
+                RewriteRule::JsonDelete { path } if json => {
+                    if let (Some(path), Some(json)) = (parse_path(path), body.json()) {
+                        delete(json, &path);
+                    }
+                }
+                RewriteRule::Regex {
+                    pattern,
+                    replacement,
+                    content_types,
+                } if applies_to(content_types, &content_type) => {
+                    let regex = match self.regex(pattern) {
+                        Some(regex) => regex,
+                        None => continue,
+                    };
+                    let raw = body.into_bytes();
+                    body = match std::str::from_utf8(&raw) {
+                        Ok(text) => Body::Raw(Bytes::from(regex.replace_all(text, replacement.as_str()).into_owned())),
+                        Err(_) => Body::Raw(raw),
+                    };
+                }
+                _ => {}
+            }
+        }
+        body.into_bytes()
+    }
+
+    fn regex(&self, pattern: &str) -> Option<Regex> {
+        if let Some(regex) = self.regexes.get(pattern) {
+            return regex.clone();
+        }
+
+        let regex = match Regex::new(pattern) {
+            Ok(regex) => Some(regex),
+            Err(e) => {
+                warn!("Skipping rewrite rule with invalid pattern '{}': {}", pattern, e);
+                None
+            }
+        };
+        self.regexes.insert(pattern.to_string(), regex.clone());
+        regex
+    }
+}
+
+impl Default for BodyRewriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+enum Body {
+    Raw(Bytes),
+    Json(Value),
+    /// Not valid JSON despite the content type
+    Invalid(Bytes),
+}
+
+impl Body {
+    fn json(&mut self) -> Option<&mut Value> {
+        if let Body::Raw(bytes) = self {
+            let bytes = std::mem::take(bytes);
+            *self = match serde_json::from_slice(&bytes) {
+                Ok(value) => Body::Json(value),
+                Err(_) => Body::Invalid(bytes),
+            };
+        }
+        match self {
+            Body::Json(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn into_bytes(self) -> Bytes {
+        match self {
+            Body::Raw(bytes) | Body::Invalid(bytes) => bytes,
+            Body::Json(value) => serde_json::to_vec(&value).map(Bytes::from).unwrap_or_default(),
+        }
+    }
+}
+
+/// Text-like types unless the rule lists its own
+fn applies_to(content_types: &[String], content_type: &str) -> bool {
+    if !content_types.is_empty() {
+        return content_types.iter().any(|allowed| allowed.eq_ignore_ascii_case(content_type));
+    }
+    content_type.starts_with("text/")
+        || content_type.ends_with("+json")
+        || content_type.ends_with("+xml")
+        || matches!(
+            content_type,
+            "application/json"
+                | "application/xml"
+                | "application/javascript"
+                | "application/x-www-form-urlencoded"
+        )
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+    Wildcard,
+}
+
+/// The supported JSONPath subset: `$`, `.key`, `['key']`, `[0]`, `[*]` and `.*`.
+fn parse_path(path: &str) -> Option<Vec<Segment>> {
+    let parsed = parse_segments(path);
+    if parsed.is_none() {
+        warn!("Skipping rewrite rule with unsupported JSONPath '{}'", path);
+    }
+    parsed
+}
+
+fn parse_segments(path: &str) -> Option<Vec<Segment>> {
+    let mut rest = path.trim().strip_prefix('$')?;
+    let mut segments = Vec::new();
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            let key = &after[..end];
+            segments.push(match key {
+                "" => return None,
+                "*" => Segment::Wildcard,
+                key => Segment::Key(key.to_string()),
+            });
+            rest = &after[end..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after.find(']')?;
+            let inner = after[..end].trim();
+            segments.push(if inner == "*" {
+                Segment::Wildcard
+            } else if let Some(key) = inner
+                .strip_prefix('\'')
+                .and_then(|key| key.strip_suffix('\''))
+                .or_else(|| inner.strip_prefix('"').and_then(|key| key.strip_suffix('"')))
+            {
+                Segment::Key(key.to_string())
+            } else {
+                Segment::Index(inner.parse().ok()?)
+            });
+            rest = &after[end + 1..];
+        } else {
+            return None;
+        }
+    }
+
+    Some(segments)
+}
+
+fn set(value: &mut Value, path: &[Segment], new: &Value) {
+    let (segment, rest) = match path.split_first() {
+        Some(split) => split,
+        None => {
+            *value = new.clone();
+            return;
+        }
+    };
+
+    match (segment, value) {
+        (Segment::Key(key), Value::Object(object)) => {
+            let child = object.entry(key.clone()).or_insert_with(|| {
+                if rest.is_empty() {
+                    Value::Null
+                } else {
+                    Value::Object(Map::new())
+                }
+            });
+            set(child, rest, new);
+        }
+        (Segment::Index(index), Value::Array(values)) => {
+            if let Some(child) = values.get_mut(*index) {
+                set(child, rest, new);
+            }
+        }
+        (Segment::Wildcard, Value::Object(object)) => object.values_mut().for_each(|child| set(child, rest, new)),
+        (Segment::Wildcard, Value::Array(values)) => values.iter_mut().for_each(|child| set(child, rest, new)),
+        _ => {}
+    }
+}
+
+fn delete(value: &mut Value, path: &[Segment]) {
+    let (segment, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+
+    if rest.is_empty() {
+        match (segment, value) {
+            (Segment::Key(key), Value::Object(object)) => {
+                object.remove(key);
+            }
+            (Segment::Index(index), Value::Array(values)) if *index < values.len() => {
+                values.remove(*index);
+            }
+            (Segment::Wildcard, Value::Object(object)) => object.clear(),
+            (Segment::Wildcard, Value::Array(values)) => values.clear(),
+            _ => {}
+        }
+        return;
+    }
+
+    match (segment, value) {
+        (Segment::Key(key), Value::Object(object)) => {
+            if let Some(child) = object.get_mut(key) {
+                delete(child, rest);
+            }
+        }
+        (Segment::Index(index), Value::Array(values)) => {
+            if let Some(child) = values.get_mut(*index) {
+                delete(child, rest);
+            }
+        }
+        (Segment::Wildcard, Value::Object(object)) => object.values_mut().for_each(|child| delete(child, rest)),
+        (Segment::Wildcard, Value::Array(values)) => values.iter_mut().for_each(|child| delete(child, rest)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn headers(content_type: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+        headers
+    }
+
+    fn rules(rules: Value) -> Vec<RewriteRule> {
+        serde_json::from_value(rules).unwrap()
+    }
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(
+            parse_segments("$.items[*]['unit price'][0].*").unwrap(),
+            vec![
+                Segment::Key("items".to_string()),
+                Segment::Wildcard,
+                Segment::Key("unit price".to_string()),
+                Segment::Index(0),
+                Segment::Wildcard,
+            ]
+        );
+        assert_eq!(parse_segments("$").unwrap(), Vec::new());
+        assert!(parse_segments("items").is_none());
+        assert!(parse_segments("$..items").is_none());
+        assert!(parse_segments("$.items[first]").is_none());
+    }
+
+    #[test]
+    fn test_json_rules() {
+        let rewriter = BodyRewriter::new();
+        let rules = rules(json!([
+            { "type": "json_set", "path": "$.items[*].currency", "value": "EUR" },
+            { "type": "json_set", "path": "$.meta.source", "value": "gateway" },
+            { "type": "json_delete", "path": "$.items[*].cost" },
+            { "type": "json_delete", "path": "$.items[1]" }
+        ]));
+        let body = json!({ "items": [{ "price": 1, "cost": 0.5 }, { "price": 2, "cost": 1 }] });
+
+        let rewritten = rewriter.rewrite(&rules, 1024, &headers("application/json"), Bytes::from(body.to_string()));
+        assert_eq!(
+            serde_json::from_slice::<Value>(&rewritten).unwrap(),
+            json!({ "items": [{ "price": 1, "currency": "EUR" }], "meta": { "source": "gateway" } })
+        );
+
+        // Content-type guard and size limit
+        let text = Bytes::from(body.to_string());
+        assert_eq!(rewriter.rewrite(&rules, 1024, &headers("text/plain"), text.clone()), text);
+        assert_eq!(rewriter.rewrite(&rules, 8, &headers("application/json"), text.clone()), text);
+    }
+
+    #[test]
+    fn test_regex_rules() {
+        let rewriter = BodyRewriter::new();
+        let rules = rules(json!([
+            { "type": "regex", "pattern": "http://internal\\.svc/(?P<path>[a-z/]+)", "replacement": "https://api.example.com/$path" },
+            { "type": "regex", "pattern": "secret", "replacement": "***", "content_types": ["text/csv"] },
+            { "type": "regex", "pattern": "(unclosed", "replacement": "" }
+        ]));
+
+        let rewritten = rewriter.rewrite(
+            &rules,
+            1024,
+            &headers("text/html; charset=utf-8"),
+            Bytes::from("<a href=\"http://internal.svc/docs/intro\">secret</a>"),
+        );
+        assert_eq!(rewritten, Bytes::from("<a href=\"https://api.example.com/docs/intro\">secret</a>"));
+
+        let csv = rewriter.rewrite(&rules, 1024, &headers("text/csv"), Bytes::from("id,secret"));
+        assert_eq!(csv, Bytes::from("id,***"));
+
+        let binary = Bytes::from_static(b"\x89PNG http://internal.svc/x");
+        assert_eq!(rewriter.rewrite(&rules, 1024, &headers("image/png"), binary.clone()), binary);
+    }
+}
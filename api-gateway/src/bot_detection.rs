@@ -0,0 +1,303 @@
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use dashmap::DashMap;
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+use rand::Rng;
+use std::{
+    net::IpAddr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::config::{BotAction, BotDetectionConfig, Config, RouteConfig};
+
+/// Carries the challenge in responses asking for one.
+pub const CHALLENGE_HEADER: &str = "x-bot-challenge";
+/// Carries `<challenge>:<solution>` in requests answering one.
+pub const CHALLENGE_RESPONSE_HEADER: &str = "x-bot-challenge-response";
+
+/// Client IPs tracked for request rates before stale windows are dropped.
+const MAX_TRACKED_CLIENTS: usize = 100_000;
+
+#[derive(Debug, Default)]
+pub struct Verdict {
+    pub score: u32,
+    pub reasons: Vec<&'static str>,
+}
+
+pub struct BotDetector {
+    config: BotDetectionConfig,
+    user_agents: Vec<String>,
+    allowed_user_agents: Vec<String>,
+    /// Client IP to its current rate window and the requests seen in it
+    request_counts: DashMap<IpAddr, (u64, u32)>,
+}
+
+impl BotDetector {
+    /// Builds the detector when `bot_detection` is configured. Routes with their own
+    /// `bot_protection` need it, and the `challenge` action needs a `challenge_secret`.
+    pub fn load(config: &Config) -> anyhow::Result<Option<Self>> {
+        let bot_config = match &config.bot_detection {
+            Some(bot_config) => bot_config,
+            None => {
+                if let Some(route) = config.routes.iter().find(|route| route.bot_protection.is_some()) {
+                    return Err(anyhow::anyhow!(
+                        "Route {} has bot protection but bot_detection isn't configured",
+                        route.path
+                    ));
+                }
+                return Ok(None);
+            }
+        };
+
+        let challenges = bot_config.default_action == BotAction::Challenge
+            || config
+                .routes
+                .iter()
+                .any(|route| route.bot_protection.as_ref().map_or(false, |bot| bot.action == BotAction::Challenge));
+        if challenges && bot_config.challenge_secret.is_none() {
+            return Err(anyhow::anyhow!("The challenge bot action requires bot_detection.challenge_secret"));
+        }
+
+        Ok(Some(Self::new(bot_config)))
+    }
+
+    fn new(config: &BotDetectionConfig) -> Self {
+        let lowercase = |agents: &[String]| agents.iter().map(|agent| agent.to_lowercase()).collect();
+        Self {
+            user_agents: lowercase(&config.user_agents),
+            allowed_user_agents: lowercase(&config.allowed_user_agents),
+            config: config.clone(),
+            request_counts: DashMap::new(),
+        }
+    }
+
+    pub fn action(&self, route: Option<&RouteConfig>) -> BotAction {
+        route
+            .and_then(|route| route.bot_protection.as_ref())
+            .map_or(self.config.default_action, |bot| bot.action)
+    }
+
+    pub fn is_bot(&self, verdict: &Verdict) -> bool {
+        verdict.score >= self.config.threshold
+    }
+
+    pub fn tarpit_ms(&self) -> u64 {
+        self.config.tarpit_ms
+    }
+
+    /// Scores the request, counting it towards the client's request rate.
+    pub fn classify(&self, headers: &HeaderMap, client_ip: Option<IpAddr>) -> Verdict {
+        let user_agent = headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_lowercase);
+        if let Some(user_agent) = &user_agent {
+            if self.allowed_user_agents.iter().any(|allowed| user_agent.contains(allowed.as_str())) {
+                return Verdict::default();
+            }
+        }
+
+        let mut verdict = Verdict::default();
+        let mut add = |points: u32, reason: &'static str| {
+            verdict.score += points;
+            verdict.reasons.push(reason);
+        };
+
+        match &user_agent {
+            Some(user_agent) if self.user_agents.iter().any(|agent| user_agent.contains(agent.as_str())) => {
+                add(50, "automation_user_agent")
+            }
+            Some(_) => {}
+            None => add(40, "missing_user_agent"),
+        }
+        if !headers.contains_key(header::ACCEPT) {
+            add(15, "missing_accept");
+        }
+        if !headers.contains_key(header::ACCEPT_LANGUAGE) {
+            add(15, "missing_accept_language");
+        }
+        if !headers.contains_key(header::ACCEPT_ENCODING) {
+            add(10, "missing_accept_encoding");
+        }
+        if let Some(ip) = client_ip {
+            if self.count_request(ip) > self.config.rate_threshold {
+                add(40, "request_rate");
+            }
+        }
+        verdict
+    }
+
+    /// Requests from the client in the current fixed window, including this one.
+    fn count_request(&self, ip: IpAddr) -> u32 {
+        let window = now() / self.config.rate_window_seconds.max(1);
+        if self.request_counts.len() >= MAX_TRACKED_CLIENTS {
+            self.request_counts.retain(|_, (client_window, _)| *client_window == window);
+        }
+
+        let mut entry = self.request_counts.entry(ip).or_insert((window, 0));
+        if entry.0 != window {
+            *entry = (window, 0);
+        }
+        entry.1 += 1;
+        entry.1
+    }
+
+    /// A fresh challenge: its expiry, a random nonce, and a signature over both.
+    pub fn challenge(&self) -> anyhow::Result<String> {
+        let nonce: [u8; 16] = rand::thread_rng().gen();
+        let payload = format!("{}.{}", now() + self.config.challenge_ttl_seconds, hex(&nonce));
+        Ok(format!("{}.{}", payload, self.sign(&payload)?))
+    }
+
+    /// Whether the request carries an unexpired challenge issued by the gateway with a
+    /// solution of the required difficulty.
+    pub fn solved(&self, headers: &HeaderMap) -> bool {
+        let answer = match headers.get(CHALLENGE_RESPONSE_HEADER).and_then(|value| value.to_str().ok()) {
+            Some(answer) => answer,
+            None => return false,
+        };
+        let (challenge, _) = match answer.rsplit_once(':') {
+            Some(parts) => parts,
+            None => return false,
+        };
+        let (payload, signature) = match challenge.rsplit_once('.') {
+            Some(parts) => parts,
+            None => return false,
+        };
+        let expires_at = payload.split('.').next().and_then(|expires_at| expires_at.parse::<u64>().ok());
+
+        let signed = self
+            .sign(payload)
+            .map_or(false, |expected| openssl::memcmp::eq(expected.as_bytes(), signature.as_bytes()));
+        signed
+            && expires_at.map_or(false, |expires_at| expires_at > now())
+            && leading_zero_bits(&openssl::sha::sha256(answer.as_bytes())) >= self.config.challenge_difficulty
+    }
+
+    /// Asks the client to solve a challenge: find a solution such that the SHA-256 of
+    /// `<challenge>:<solution>` starts with `difficulty` zero bits, then send that string
+    /// in `X-Bot-Challenge-Response`.
+    pub fn challenge_response(&self, request_id: &str) -> Response {
+        let challenge = match self.challenge() {
+            Ok(challenge) => challenge,
+            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        };
+        let mut response = (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({
+                "error": "Challenge required",
+                "challenge": challenge,
+                "algorithm": "sha256",
+                "difficulty": self.config.challenge_difficulty,
+                "request_id": request_id,
+            })),
+        )
+            .into_response();
+        if let Ok(value) = HeaderValue::from_str(&challenge) {
+            response.headers_mut().insert(CHALLENGE_HEADER, value);
+        }
+        response
+    }
+
+    fn sign(&self, payload: &str) -> anyhow::Result<String> {
+        let secret = self.config.challenge_secret.as_deref().unwrap_or_default();
+        let key = PKey::hmac(secret.as_bytes())?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+        signer.update(payload.as_bytes())?;
+        Ok(hex(&signer.sign_to_vec()?))
+    }
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn detector(config: serde_json::Value) -> BotDetector {
+        BotDetector::new(&serde_json::from_value(config).unwrap())
+    }
+
+    fn browser_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::USER_AGENT, HeaderValue::from_static("Mozilla/5.0 (X11; Linux x86_64) Firefox/128.0"));
+        headers.insert(header::ACCEPT, HeaderValue::from_static("text/html"));
+        headers.insert(header::ACCEPT_LANGUAGE, HeaderValue::from_static("en-US"));
+        headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_static("gzip, br"));
+        headers
+    }
+
+    #[test]
+    fn test_classify() {
+        let detector = detector(json!({ "rate_threshold": 2, "allowed_user_agents": ["Googlebot"] }));
+        let ip: IpAddr = "198.51.100.7".parse().unwrap();
+
+        let browser = detector.classify(&browser_headers(), Some(ip));
+        assert!(!detector.is_bot(&browser));
+
+        let mut scraper = browser_headers();
+        scraper.insert(header::USER_AGENT, HeaderValue::from_static("python-requests/2.32"));
+        let verdict = detector.classify(&scraper, Some(ip));
+        assert_eq!(verdict.reasons, vec!["automation_user_agent"]);
+        assert!(detector.is_bot(&verdict));
+
+        // The third request in the window exceeds the rate threshold
+        let verdict = detector.classify(&browser_headers(), Some(ip));
+        assert_eq!(verdict.reasons, vec!["request_rate"]);
+        assert!(!detector.is_bot(&verdict));
+
+        let verdict = detector.classify(&HeaderMap::new(), None);
+        assert_eq!(verdict.score, 80);
+
+        let mut crawler = HeaderMap::new();
+        crawler.insert(header::USER_AGENT, HeaderValue::from_static("Mozilla/5.0 (compatible; Googlebot/2.1)"));
+        assert_eq!(detector.classify(&crawler, None).score, 0);
+    }
+
+    #[test]
+    fn test_challenge() {
+        let detector = detector(json!({ "challenge_secret": "s3cret", "challenge_difficulty": 8 }));
+        let challenge = detector.challenge().unwrap();
+
+        let solution = (0u64..)
+            .map(|candidate| format!("{}:{}", challenge, candidate))
+            .find(|answer| leading_zero_bits(&openssl::sha::sha256(answer.as_bytes())) >= 8)
+            .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(CHALLENGE_RESPONSE_HEADER, HeaderValue::from_str(&solution).unwrap());
+        assert!(detector.solved(&headers));
+
+        // Changing the expiry invalidates the signature
+        let (expires_at, rest) = solution.split_once('.').unwrap();
+        let tampered = format!("{}.{}", expires_at.parse::<u64>().unwrap() + 1, rest);
+        headers.insert(CHALLENGE_RESPONSE_HEADER, HeaderValue::from_str(&tampered).unwrap());
+        assert!(!detector.solved(&headers));
+
+        assert!(!detector.solved(&HeaderMap::new()));
+        assert_eq!(leading_zero_bits(&[0, 0x1f, 0xff]), 11);
+    }
+}
@@ -0,0 +1,296 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::Instant,
+};
+
+use crate::config::Config;
+
+const DEFAULT_MIN_REQUESTS: u64 = 100;
+const DEFAULT_MAX_ERROR_RATE_INCREASE: f64 = 5.0;
+
+/// The top-level config sections a canary may change. Middleware reads everything else
+/// from the config the gateway started with, which neither a canary nor its promotion
+/// replaces.
+const ROUTING_SECTIONS: [&str; 2] = ["routes", "backends"];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CanaryRequest {
+    pub config: Config,
+    pub percentage: f64,
+    /// Requests the candidate must serve before it is judged
+    pub min_requests: Option<u64>,
+    /// Percentage points the candidate's error rate may exceed the active table's
+    pub max_error_rate_increase: Option<f64>,
+    /// Promote automatically once healthy for this long; manual promotion otherwise
+    pub auto_promote_after_seconds: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CanaryStatus {
+    pub percentage: f64,
+    pub running_seconds: u64,
+    pub active: TableStatus,
+    pub candidate: TableStatus,
+    pub min_requests: u64,
+    pub max_error_rate_increase: f64,
+    pub auto_promote_after_seconds: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TableStatus {
+    pub requests: u64,
+    pub errors: u64,
+    pub error_rate: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CanaryDecision {
+    Continue,
+    Promote,
+    Revert,
+}
+
+#[derive(Default)]
+struct TableStats {
+    requests: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl TableStats {
+    fn record(&self, error: bool) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        if error {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn status(&self) -> TableStatus {
+        let requests = self.requests.load(Ordering::Relaxed);
+        let errors = self.errors.load(Ordering::Relaxed);
+        let error_rate = if requests > 0 {
+            (errors as f64 / requests as f64) * 100.0
+        } else {
+            0.0
+        };
+
+        TableStatus {
+            requests,
+            errors,
+            error_rate,
+        }
+    }
+}
+
+struct CanaryRollout {
+    candidate: Arc<Config>,
+    percentage: f64,
+    min_requests: u64,
+    max_error_rate_increase: f64,
+    auto_promote_after_seconds: Option<u64>,
+    started_at: Instant,
+    active_stats: TableStats,
+    candidate_stats: TableStats,
+}
+
+/// Runs a candidate routing table alongside the active one, sending a share of traffic
+/// through it and comparing error rates to decide on promotion or revert.
+///
+/// Only the proxy routes by the candidate: a candidate may change `routes` and `backends`
+/// and nothing else. Route settings enforced before proxying, like `auth_required`,
+/// `rate_limit` and `required_scopes`, keep their startup values even after promotion.
+pub struct ConfigCanary {
+    rollout: RwLock<Option<CanaryRollout>>,
+}
+
+impl ConfigCanary {
+    pub fn new() -> Self {
+        Self {
+            rollout: RwLock::new(None),
+        }
+    }
+
+    pub fn start(&self, request: CanaryRequest, active: &Config) -> anyhow::Result<()> {
+        if !(0.0..=100.0).contains(&request.percentage) {
+            return Err(anyhow::anyhow!("percentage must be between 0 and 100"));
+        }
+        check_routing_only(active, &request.config)?;
+
+        let mut rollout = self.rollout.write().unwrap();
+        if rollout.is_some() {
+            return Err(anyhow::anyhow!("A config canary is already running"));
+        }
+
+        *rollout = Some(CanaryRollout {
+            candidate: Arc::new(request.config),
+            percentage: request.percentage,
+            min_requests: request.min_requests.unwrap_or(DEFAULT_MIN_REQUESTS),
+            max_error_rate_increase: request.max_error_rate_increase.unwrap_or(DEFAULT_MAX_ERROR_RATE_INCREASE),
+            auto_promote_after_seconds: request.auto_promote_after_seconds,
+            started_at: Instant::now(),
+            active_stats: TableStats::default(),
+            candidate_stats: TableStats::default(),
+        });
+
+        Ok(())
+    }
+
+    /// Returns the candidate config if this request should be routed through it.
+    pub fn select(&self) -> Option<Arc<Config>> {
+        let rollout = self.rollout.read().unwrap();
+        rollout
+            .as_ref()
+            .filter(|rollout| rand::random::<f64>() * 100.0 < rollout.percentage)
+            .map(|rollout| rollout.candidate.clone())
+    }
+
+    /// Records a request outcome and evaluates the rollout.
+    pub fn record(&self, candidate: bool, error: bool) -> CanaryDecision {
+        let rollout = self.rollout.read().unwrap();
+        let rollout = match rollout.as_ref() {
+            Some(rollout) => rollout,
+            None => return CanaryDecision::Continue,
+        };
+
+        if candidate {
+            rollout.candidate_stats.record(error);
+        } else {
+            rollout.active_stats.record(error);
+        }
+
+        let candidate_status = rollout.candidate_stats.status();
+        if candidate_status.requests < rollout.min_requests {
+            return CanaryDecision::Continue;
+        }
+
+        let active_status = rollout.active_stats.status();
+        if candidate_status.error_rate > active_status.error_rate + rollout.max_error_rate_increase {
+            return CanaryDecision::Revert;
+        }
+
+        match rollout.auto_promote_after_seconds {
+            Some(secs) if rollout.started_at.elapsed().as_secs() >= secs => CanaryDecision::Promote,
+            _ => CanaryDecision::Continue,
+        }
+    }
+
+    pub fn status(&self) -> Option<CanaryStatus> {
+        let rollout = self.rollout.read().unwrap();
+        rollout.as_ref().map(|rollout| CanaryStatus {
+            percentage: rollout.percentage,
+            running_seconds: rollout.started_at.elapsed().as_secs(),
+            active: rollout.active_stats.status(),
+            candidate: rollout.candidate_stats.status(),
+            min_requests: rollout.min_requests,
+            max_error_rate_increase: rollout.max_error_rate_increase,
+            auto_promote_after_seconds: rollout.auto_promote_after_seconds,
+        })
+    }
+
+    /// Ends the rollout, returning the candidate config.
+    pub fn finish(&self) -> Option<Arc<Config>> {
+        self.rollout.write().unwrap().take().map(|rollout| rollout.candidate)
+    }
+}
+
+/// Rejects a candidate that changes config sections outside `ROUTING_SECTIONS`.
+fn check_routing_only(active: &Config, candidate: &Config) -> anyhow::Result<()> {
+    let (active, candidate) = match (serde_json::to_value(active)?, serde_json::to_value(candidate)?) {
+        (serde_json::Value::Object(active), serde_json::Value::Object(candidate)) => (active, candidate),
+        _ => return Ok(()),
+    };
+
+    let mut changed: Vec<&str> = active
+        .keys()
+        .chain(candidate.keys())
+        .map(String::as_str)
+        .filter(|section| !ROUTING_SECTIONS.contains(section))
+        .filter(|section| active.get(*section) != candidate.get(*section))
+        .collect();
+    changed.sort_unstable();
+    changed.dedup();
+
+    if changed.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "A config canary can only change routes and backends, but the candidate also changes: {}",
+            changed.join(", ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(config: Config, percentage: f64) -> CanaryRequest {
+        CanaryRequest {
+            config,
+            percentage,
+            min_requests: Some(10),
+            max_error_rate_increase: Some(5.0),
+            auto_promote_after_seconds: None,
+        }
+    }
+
+    fn candidate() -> Config {
+        let mut config = Config::default_config();
+        config.routes[0].backend = "kong_gateway".to_string();
+        config
+    }
+
+    #[test]
+    fn test_percentage_splits_traffic() {
+        let active = Config::default_config();
+        for (percentage, expected) in [(0.0, 0..=0), (100.0, 1000..=1000), (50.0, 350..=650)] {
+            let canary = ConfigCanary::new();
+            canary.start(request(candidate(), percentage), &active).unwrap();
+            let selected = (0..1000).filter(|_| canary.select().is_some()).count();
+            assert!(expected.contains(&selected), "{}% selected {} of 1000", percentage, selected);
+        }
+    }
+
+    #[test]
+    fn test_reverts_on_errors_and_promotes_when_healthy() {
+        let active = Config::default_config();
+
+        let canary = ConfigCanary::new();
+        canary.start(request(candidate(), 50.0), &active).unwrap();
+        for _ in 0..9 {
+            assert_eq!(canary.record(true, true), CanaryDecision::Continue);
+        }
+        assert_eq!(canary.record(true, true), CanaryDecision::Revert);
+
+        let canary = ConfigCanary::new();
+        let mut healthy = request(candidate(), 50.0);
+        healthy.auto_promote_after_seconds = Some(0);
+        canary.start(healthy, &active).unwrap();
+        for _ in 0..9 {
+            canary.record(false, false);
+            assert_eq!(canary.record(true, false), CanaryDecision::Continue);
+        }
+        assert_eq!(canary.record(true, false), CanaryDecision::Promote);
+
+        assert_eq!(canary.finish().unwrap().routes[0].backend, "kong_gateway");
+        assert!(canary.status().is_none());
+        assert!(canary.select().is_none());
+    }
+
+    #[test]
+    fn test_candidate_may_only_change_routing() {
+        let active = Config::default_config();
+        let canary = ConfigCanary::new();
+
+        let mut changes_auth = candidate();
+        changes_auth.auth.enabled = !active.auth.enabled;
+        let error = canary.start(request(changes_auth, 10.0), &active).unwrap_err();
+        assert!(error.to_string().ends_with("changes: auth"), "{}", error);
+        assert!(canary.status().is_none());
+
+        assert!(canary.start(request(candidate(), 10.0), &active).is_ok());
+    }
+}
@@ -0,0 +1,119 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use api_gateway::api_keys;
+use api_gateway::config::Config;
+
+#[derive(Debug, Parser)]
+#[command(name = "gateway", about = "API Gateway")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Same as `validate-config` on the configuration from the environment
+    #[arg(long, hide = true)]
+    pub validate: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the gateway (the default)
+    Serve,
+    /// Check a configuration and run its route assertions, without serving
+    ValidateConfig {
+        /// JSON or YAML file; defaults to `GATEWAY_CONFIG` or the built-in configuration
+        file: Option<PathBuf>,
+    },
+    /// Print the built-in configuration as JSON, as a starting point for your own
+    PrintDefaultConfig,
+    /// Show which route and backend a request would be sent to
+    TestRoute {
+        method: String,
+        path: String,
+        /// JSON or YAML file; defaults to `GATEWAY_CONFIG` or the built-in configuration
+        #[arg(long)]
+        config: Option<PathBuf>,
+    },
+    /// Generate an API key and the prefix, salt and hash to store for it
+    GenerateApiKey,
+    /// Probe every route of an already running gateway, e.g. as a post-deploy gate
+    Smoke {
+        /// Defaults to `http://localhost:<server.port>`
+        base_url: Option<String>,
+    },
+}
+
+impl Command {
+    /// The config file the command was pointed at, if any.
+    pub fn config_file(&self) -> Option<&PathBuf> {
+        match self {
+            Command::ValidateConfig { file } => file.as_ref(),
+            Command::TestRoute { config, .. } => config.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+pub fn print_default_config() -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(&Config::default_config())?);
+    Ok(())
+}
+
+/// Prints the route a request would match and where it would be sent. Matching is by
+/// path, as in the proxy; a method the route doesn't declare is pointed out.
+pub fn test_route(config: &Config, method: &str, path: &str) -> anyhow::Result<()> {
+    let route = config
+        .find_route(path)
+        .ok_or_else(|| anyhow::anyhow!("No route matches {} {}", method, path))?;
+
+    println!("route:        {}", route.name.as_deref().unwrap_or(&route.path));
+    println!("path:         {}", route.path);
+    if let Some(route_method) = route.method.as_deref() {
+        if !route_method.eq_ignore_ascii_case(method) {
+            println!("method:       {} (the route is declared for {})", method.to_uppercase(), route_method);
+        }
+    }
+
+    if let Some(mock) = &route.mock {
+        println!("backend:      none, mocked with status {}", mock.status);
+    } else if let Some(composite) = &route.composite {
+        let backends: Vec<&str> = composite.parts.iter().map(|part| part.backend.as_str()).collect();
+        println!("backend:      composite of {}", backends.join(", "));
+    } else {
+        let backend = config.backends.get(&route.backend);
+        println!(
+            "backend:      {}{}",
+            route.backend,
+            if backend.is_none() { " (not configured)" } else { "" }
+        );
+        if let Some(backend) = backend {
+            println!("servers:      {} ({:?})", backend.servers.join(", "), route.load_balancing);
+        }
+        if let Some(geo) = &route.geo {
+            for (country, backend) in &geo.backends {
+                println!("  from {}:    {}", country, backend);
+            }
+        }
+    }
+
+    if config.requires_auth(path) {
+        println!("auth:         required ({:?})", route.auth_mode);
+    } else {
+        println!("auth:         not required");
+    }
+    if !route.required_scopes.is_empty() {
+        println!("scopes:       {}", route.required_scopes.join(", "));
+    }
+    match route.rate_limit {
+        Some(rate_limit) => println!("rate limit:   {} per minute", rate_limit),
+        None => println!("rate limit:   global"),
+    }
+    if let Some(timeout_ms) = route.timeout_ms {
+        println!("timeout:      {} ms", timeout_ms);
+    }
+    Ok(())
+}
+
+pub fn generate_api_key() -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(&api_keys::generate_api_key())?);
+    Ok(())
+}
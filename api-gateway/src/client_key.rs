@@ -0,0 +1,233 @@
+use axum::extract::{ConnectInfo, Request};
+use ipnet::IpNet;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use crate::{
+    api_keys::fingerprint,
+    auth::AuthService,
+    config::{Config, RateLimitKeyStrategy},
+    forwarded,
+    jwks::JwtVerifier,
+    rate_limiter::ClientIdentity,
+};
+
+/// Derives client identifiers from requests for rate limiting and related per-client limits.
+pub struct ClientKeyExtractor {
+    trusted_proxies: Vec<IpNet>,
+    api_key_header: String,
+    jwt_verifier: Arc<JwtVerifier>,
+}
+
+impl ClientKeyExtractor {
+    pub fn new(config: &Config, jwt_verifier: Arc<JwtVerifier>) -> anyhow::Result<Self> {
+        let trusted_proxies = config
+            .server
+            .trusted_proxies
+            .iter()
+            .map(|range| {
+                range
+                    .parse::<IpNet>()
+                    .or_else(|_| range.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| anyhow::anyhow!("Invalid trusted proxy range: {}", range))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            trusted_proxies,
+            api_key_header: config.auth.api_key_header.clone(),
+            jwt_verifier,
+        })
+    }
+
+    /// Resolves the originating client IP. X-Forwarded-For (or Forwarded) is only
+    /// honoured when the direct peer is a trusted proxy, and is walked right-to-left past
+    /// any further trusted hops so clients can't spoof their address by prepending entries.
+    pub fn client_ip(&self, request: &Request) -> Option<IpAddr> {
+        let peer_ip = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())?;
+
+        if !self.is_trusted(&peer_ip) {
+            return Some(peer_ip);
+        }
+
+        let mut client_ip = peer_ip;
+        for hop in forwarded::chain(request.headers()).iter().rev() {
+            match hop.parse::<IpAddr>() {
+                Ok(ip) => {
+                    client_ip = ip;
+                    if !self.is_trusted(&ip) {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+
+        Some(client_ip)
+    }
+
+    pub fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|range| range.contains(ip))
+    }
+
+    pub fn api_key<'a>(&self, request: &'a Request) -> Option<&'a str> {
+        request
+            .headers()
+            .get(&self.api_key_header)
+            .and_then(|value| value.to_str().ok())
+    }
+
+    fn bearer_claims(&self, request: &Request) -> Option<serde_json::Map<String, serde_json::Value>> {
+        request
+            .headers()
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(AuthService::extract_bearer_token)
+            .and_then(|token| self.jwt_verifier.verify_cached(token).ok())
+    }
+
+    /// Attributes checked against rate limit exemption lists.
+    pub fn identity(&self, request: &Request) -> ClientIdentity {
+        // Only trust the subject of a token that actually validates
+        let jwt_subject = self
+            .bearer_claims(request)
+            .and_then(|claims| claims.get("sub").and_then(|sub| sub.as_str()).map(|sub| sub.to_string()));
+
+        ClientIdentity {
+            api_key: self.api_key(request).map(|key| key.to_string()),
+            ip: self.client_ip(request),
+            jwt_subject,
+        }
+    }
+
+    /// Builds the rate limit key for a request. Without a configured strategy the API key
+    /// is used when present, otherwise the client IP.
+    pub fn rate_limit_key(&self, request: &Request, strategy: Option<&RateLimitKeyStrategy>) -> String {
+        let key = match strategy {
+            Some(strategy) => self.key_for_strategy(request, strategy),
+            None => self
+                .key_for_strategy(request, &RateLimitKeyStrategy::ApiKey)
+                .or_else(|| self.key_for_strategy(request, &RateLimitKeyStrategy::ClientIp)),
+        };
+
+        key.unwrap_or_else(|| "unknown".to_string())
+    }
+
+    /// Names the kind of key `rate_limit_key` produces, for metric labels.
+    pub fn rate_limit_key_type(&self, request: &Request, strategy: Option<&RateLimitKeyStrategy>) -> &'static str {
+        match strategy {
+            Some(RateLimitKeyStrategy::ClientIp) => "client_ip",
+            Some(RateLimitKeyStrategy::ApiKey) => "api_key",
+            Some(RateLimitKeyStrategy::Header { .. }) => "header",
+            Some(RateLimitKeyStrategy::JwtClaim { .. }) => "jwt_claim",
+            Some(RateLimitKeyStrategy::Composite { .. }) => "composite",
+            None if self.api_key(request).is_some() => "api_key",
+            None if self.client_ip(request).is_some() => "client_ip",
+            None => "unknown",
+        }
+    }
+
+    fn key_for_strategy(&self, request: &Request, strategy: &RateLimitKeyStrategy) -> Option<String> {
+        match strategy {
+            RateLimitKeyStrategy::ClientIp => self.client_ip(request).map(|ip| format!("ip:{}", ip)),
+            // Fingerprinted so the key never shows up in logs or Redis
+            RateLimitKeyStrategy::ApiKey => self.api_key(request).map(|key| format!("api_key:{}", fingerprint(key))),
+            RateLimitKeyStrategy::Header { name } => request
+                .headers()
+                .get(name.as_str())
+                .and_then(|value| value.to_str().ok())
+                .map(|value| format!("header:{}:{}", name.to_lowercase(), value)),
+            RateLimitKeyStrategy::JwtClaim { claim } => self
+                .bearer_claims(request)
+                .and_then(|claims| claims.get(claim).cloned())
+                .map(|value| match value {
+                    serde_json::Value::String(s) => s,
+                    other => other.to_string(),
+                })
+                .map(|value| format!("claim:{}:{}", claim, value)),
+            RateLimitKeyStrategy::Composite { parts } => {
+                let parts: Vec<String> = parts
+                    .iter()
+                    .map(|part| self.key_for_strategy(request, part).unwrap_or_else(|| "-".to_string()))
+                    .collect();
+                Some(parts.join("|"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+
+    fn extractor(trusted_proxies: &[&str]) -> ClientKeyExtractor {
+        let mut config = Config::default_config();
+        config.server.trusted_proxies = trusted_proxies.iter().map(|range| range.to_string()).collect();
+        let jwt_verifier = Arc::new(JwtVerifier::new(&config.auth).unwrap());
+        ClientKeyExtractor::new(&config, jwt_verifier).unwrap()
+    }
+
+    fn request(peer: &str, forwarded_for: Option<&str>) -> Request {
+        let mut request = Request::builder().uri("/");
+        if let Some(forwarded_for) = forwarded_for {
+            request = request.header("X-Forwarded-For", forwarded_for);
+        }
+        let mut request = request.body(Body::empty()).unwrap();
+        request.extensions_mut().insert(ConnectInfo(peer.parse::<SocketAddr>().unwrap()));
+        request
+    }
+
+    #[test]
+    fn test_exemption_identity_ignores_forwarded_for_from_untrusted_peers() {
+        let extractor = extractor(&["10.0.0.0/8"]);
+
+        let identity = extractor.identity(&request("203.0.113.9:4000", Some("127.0.0.1")));
+        assert_eq!(identity.ip, Some("203.0.113.9".parse().unwrap()));
+
+        let identity = extractor.identity(&request("10.0.0.2:4000", Some("127.0.0.1")));
+        assert_eq!(identity.ip, Some("127.0.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_spoofed_leftmost_entry_is_skipped() {
+        let extractor = extractor(&["10.0.0.0/8"]);
+
+        // The client prepended 1.1.1.1; the trusted hop appended the address it saw
+        let request = request("10.0.0.2:4000", Some("1.1.1.1, 198.51.100.7"));
+        assert_eq!(extractor.client_ip(&request), Some("198.51.100.7".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_all_hops_trusted_resolves_to_the_leftmost() {
+        let extractor = extractor(&["10.0.0.0/8"]);
+
+        let request = request("10.0.0.2:4000", Some("10.1.1.1, 10.2.2.2"));
+        assert_eq!(extractor.client_ip(&request), Some("10.1.1.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_non_ip_entry_stops_the_walk() {
+        let extractor = extractor(&["10.0.0.0/8"]);
+
+        let request = request("10.0.0.2:4000", Some("198.51.100.7, unknown, 10.3.3.3"));
+        assert_eq!(extractor.client_ip(&request), Some("10.3.3.3".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_untrusted_peer_is_the_client() {
+        let spoofed = request("203.0.113.9:4000", Some("198.51.100.7"));
+        let trusting = extractor(&["10.0.0.0/8"]);
+        assert_eq!(trusting.client_ip(&spoofed), Some("203.0.113.9".parse().unwrap()));
+        assert_eq!(trusting.rate_limit_key(&spoofed, Some(&RateLimitKeyStrategy::ClientIp)), "ip:203.0.113.9");
+
+        // Without trusted proxies nobody's forwarding headers count
+        let from_proxy = request("10.0.0.2:4000", Some("198.51.100.7"));
+        assert_eq!(extractor(&[]).client_ip(&from_proxy), Some("10.0.0.2".parse().unwrap()));
+    }
+}
@@ -0,0 +1,90 @@
+use axum::{
+    body::Bytes,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::{Map, Value};
+use tracing::warn;
+
+use crate::config::CompositePart;
+use crate::proxy::add_degradation;
+
+/// Added to `X-Gateway-Degraded` when optional parts are missing from the response.
+pub const PARTIAL_FLAG: &str = "composite-partial";
+
+/// Merges the parts' responses, given in the order of `parts`, into one JSON object.
+/// Bodies that aren't JSON are included as strings. Fails if a required part failed.
+pub fn merge(
+    parts: &[CompositePart],
+    results: Vec<anyhow::Result<Bytes>>,
+    request_id: &str,
+) -> anyhow::Result<Response> {
+    let mut merged = Map::new();
+    let mut partial = false;
+
+    for (part, result) in parts.iter().zip(results) {
+        let value = match result {
+            Ok(body) => serde_json::from_slice(&body)
+                .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&body).into_owned())),
+            Err(e) if part.required => {
+                return Err(anyhow::anyhow!("Composite part '{}' failed: {}", part.key, e));
+            }
+            Err(e) => {
+                warn!("Optional composite part '{}' failed: {} (request_id: {})", part.key, e, request_id);
+                partial = true;
+                Value::Null
+            }
+        };
+        merged.insert(part.key.clone(), value);
+    }
+
+    let mut response = Json(Value::Object(merged)).into_response();
+    if partial {
+        add_degradation(response.headers_mut(), PARTIAL_FLAG);
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parts() -> Vec<CompositePart> {
+        serde_json::from_value(json!([
+            { "key": "user", "backend": "users", "path": "/me" },
+            { "key": "orders", "backend": "orders", "path": "/orders" },
+            { "key": "ads", "backend": "ads", "path": "/ads", "required": false }
+        ]))
+        .unwrap()
+    }
+
+    async fn body(response: Response) -> Value {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_merge() {
+        let response = merge(
+            &parts(),
+            vec![
+                Ok(Bytes::from(r#"{"id":7}"#)),
+                Ok(Bytes::from("not json")),
+                Err(anyhow::anyhow!("timed out")),
+            ],
+            "req-1",
+        )
+        .unwrap();
+
+        assert_eq!(response.headers()["X-Gateway-Degraded"], PARTIAL_FLAG);
+        assert_eq!(body(response).await, json!({ "user": { "id": 7 }, "orders": "not json", "ads": null }));
+
+        let failed = merge(
+            &parts(),
+            vec![Ok(Bytes::from("{}")), Err(anyhow::anyhow!("backend returned 500")), Ok(Bytes::from("[]"))],
+            "req-2",
+        );
+        assert!(failed.unwrap_err().to_string().contains("'orders'"));
+    }
+}
@@ -0,0 +1,153 @@
+use dashmap::DashMap;
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::debug;
+
+#[derive(Clone)]
+pub struct ConcurrencyLimiter {
+    semaphores: Arc<DashMap<String, Arc<Semaphore>>>,
+}
+
+#[derive(Debug)]
+pub enum ConcurrencyLimitError {
+    Exceeded,
+}
+
+impl std::fmt::Display for ConcurrencyLimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConcurrencyLimitError::Exceeded => write!(f, "Concurrent request limit exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for ConcurrencyLimitError {}
+
+/// Held for the lifetime of an in-flight request; releases the slot on drop.
+pub struct ConcurrencyPermit {
+    key: String,
+    max: u32,
+    semaphores: Arc<DashMap<String, Arc<Semaphore>>>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        // Release the slot first so the idle check below sees it
+        self.permit.take();
+
+        // Drop idle entries so the map doesn't grow with every client ever seen. This runs
+        // under the same shard lock `try_acquire` holds, so no acquire can slip in between
+        self.semaphores
+            .remove_if(&self.key, |_, semaphore| semaphore.available_permits() == self.max as usize);
+    }
+}
+
+impl ConcurrencyLimiter {
+    pub fn new() -> Self {
+        Self {
+            semaphores: Arc::new(DashMap::new()),
+        }
+    }
+
+    pub fn try_acquire(
+        &self,
+        route_path: &str,
+        client_id: &str,
+        max_concurrent: u32,
+    ) -> Result<ConcurrencyPermit, ConcurrencyLimitError> {
+        // Keyed by the limit too, so a reload that changes it starts a fresh semaphore; the
+        // old one is removed once its in-flight requests finish
+        let key = format!("{}:{}:{}", route_path, max_concurrent, client_id);
+
+        // Acquire while holding the entry so a permit being dropped can't evict the
+        // semaphore between lookup and acquire, leaving the next request a fresh one
+        let permit = self
+            .semaphores
+            .entry(key.clone())
+            .or_insert_with(|| Arc::new(Semaphore::new(max_concurrent as usize)))
+            .clone()
+            .try_acquire_owned();
+
+        match permit {
+            Ok(permit) => Ok(ConcurrencyPermit {
+                key,
+                max: max_concurrent,
+                semaphores: self.semaphores.clone(),
+                permit: Some(permit),
+            }),
+            Err(_) => {
+                debug!("Concurrency limit exceeded for client: {} (route: {})", client_id, route_path);
+                Err(ConcurrencyLimitError::Exceeded)
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_limit_holds_and_permits_are_released() {
+        let limiter = ConcurrencyLimiter::new();
+
+        let first = limiter.try_acquire("/api", "client", 2).unwrap();
+        let second = limiter.try_acquire("/api", "client", 2).unwrap();
+        assert!(limiter.try_acquire("/api", "client", 2).is_err());
+        assert!(limiter.try_acquire("/api", "other", 2).is_ok());
+
+        drop(first);
+        let third = limiter.try_acquire("/api", "client", 2).unwrap();
+        drop(second);
+        drop(third);
+        assert!(limiter.semaphores.is_empty());
+    }
+
+    #[test]
+    fn test_changed_limit_applies_at_once() {
+        let limiter = ConcurrencyLimiter::new();
+
+        let old = limiter.try_acquire("/api", "client", 1).unwrap();
+        assert!(limiter.try_acquire("/api", "client", 1).is_err());
+
+        // A reload raised the limit while a request under the old one is in flight
+        let first = limiter.try_acquire("/api", "client", 2).unwrap();
+        let second = limiter.try_acquire("/api", "client", 2).unwrap();
+        assert!(limiter.try_acquire("/api", "client", 2).is_err());
+
+        drop((old, first, second));
+        assert!(limiter.semaphores.is_empty());
+    }
+
+    #[test]
+    fn test_limit_holds_under_contention() {
+        let limiter = ConcurrencyLimiter::new();
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let (limiter, in_flight, peak) = (limiter.clone(), in_flight.clone(), peak.clone());
+                std::thread::spawn(move || {
+                    for _ in 0..2000 {
+                        if let Ok(permit) = limiter.try_acquire("/api", "client", 2) {
+                            let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                            peak.fetch_max(now, Ordering::SeqCst);
+                            in_flight.fetch_sub(1, Ordering::SeqCst);
+                            drop(permit);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+        assert!(limiter.semaphores.is_empty());
+    }
+}
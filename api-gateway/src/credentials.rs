@@ -0,0 +1,365 @@
+use reqwest::{Client, Identity};
+use serde::Serialize;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::RwLock,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::time::interval;
+use tracing::{error, info};
+
+use crate::config::{BackendConfig, BackendCredentialsConfig, Config};
+use crate::proxy::build_backend_client;
+use crate::upstream_resolver::DnsCache;
+
+const DEFAULT_OVERLAP_SECONDS: u64 = 300;
+
+/// Credentials attached to upstream requests for a backend.
+#[derive(Clone)]
+pub struct UpstreamCredential {
+    pub header: Option<(String, String)>,
+    /// Client carrying the mTLS identity, when one is configured
+    pub client: Option<Client>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CredentialStatus {
+    pub backend: String,
+    pub fingerprint: String,
+    pub loaded_at: u64,
+    pub previous_fingerprint: Option<String>,
+    pub previous_valid_for_seconds: Option<u64>,
+}
+
+struct CredentialVersion {
+    credential: UpstreamCredential,
+    fingerprint: u64,
+    loaded_at: u64,
+}
+
+struct BackendCredentials {
+    config: BackendCredentialsConfig,
+    backend: BackendConfig,
+    current: CredentialVersion,
+    previous: Option<(CredentialVersion, Instant)>,
+}
+
+/// Holds upstream credentials and rotates them at runtime. After a rotation the previous
+/// version stays usable for the configured overlap, so backends can be switched over
+/// to the new secret without a window where requests fail. Header secrets and mTLS
+/// identities are the only upstream credentials the gateway has; it neither signs
+/// upstream requests nor fetches client-credentials tokens.
+pub struct CredentialStore {
+    backends: RwLock<HashMap<String, BackendCredentials>>,
+    dns_cache: Option<DnsCache>,
+}
+
+impl CredentialStore {
+    pub fn new(config: &Config, dns_cache: Option<DnsCache>) -> anyhow::Result<Self> {
+        let mut backends = HashMap::new();
+
+        for (name, backend) in &config.backends {
+            if let Some(credentials_config) = &backend.credentials {
+                let current = load_version(backend, credentials_config, dns_cache.as_ref())?;
+                backends.insert(
+                    name.clone(),
+                    BackendCredentials {
+                        config: credentials_config.clone(),
+                        backend: backend.clone(),
+                        current,
+                        previous: None,
+                    },
+                );
+            }
+        }
+
+        Ok(Self {
+            backends: RwLock::new(backends),
+            dns_cache,
+        })
+    }
+
+    pub fn current(&self, backend: &str) -> Option<UpstreamCredential> {
+        let backends = self.backends.read().unwrap();
+        backends.get(backend).map(|creds| creds.current.credential.clone())
+    }
+
+    /// The credential replaced by the last rotation, while its overlap has not expired.
+    pub fn previous(&self, backend: &str) -> Option<UpstreamCredential> {
+        let backends = self.backends.read().unwrap();
+        backends
+            .get(backend)
+            .and_then(|creds| creds.previous.as_ref())
+            .filter(|(_, valid_until)| Instant::now() < *valid_until)
+            .map(|(version, _)| version.credential.clone())
+    }
+
+    /// Re-reads a backend's credential files. Returns whether anything changed.
+    pub fn reload(&self, backend: &str) -> anyhow::Result<bool> {
+        let (backend_config, credentials_config) = {
+            let backends = self.backends.read().unwrap();
+            let creds = backends
+                .get(backend)
+                .ok_or_else(|| anyhow::anyhow!("No credentials configured for backend: {}", backend))?;
+            (creds.backend.clone(), creds.config.clone())
+        };
+
+        let version = load_version(&backend_config, &credentials_config, self.dns_cache.as_ref())?;
+
+        let mut backends = self.backends.write().unwrap();
+        let creds = backends
+            .get_mut(backend)
+            .ok_or_else(|| anyhow::anyhow!("No credentials configured for backend: {}", backend))?;
+
+        if version.fingerprint == creds.current.fingerprint {
+            return Ok(false);
+        }
+
+        let overlap = Duration::from_secs(creds.config.overlap_seconds.unwrap_or(DEFAULT_OVERLAP_SECONDS));
+        let previous = std::mem::replace(&mut creds.current, version);
+        creds.previous = Some((previous, Instant::now() + overlap));
+
+        info!(
+            "Rotated credentials for backend {} (previous valid for {}s)",
+            backend,
+            overlap.as_secs()
+        );
+
+        Ok(true)
+    }
+
+    pub fn status(&self) -> Vec<CredentialStatus> {
+        let backends = self.backends.read().unwrap();
+        let now = Instant::now();
+
+        backends
+            .iter()
+            .map(|(name, creds)| {
+                let previous = creds
+                    .previous
+                    .as_ref()
+                    .filter(|(_, valid_until)| now < *valid_until);
+
+                CredentialStatus {
+                    backend: name.clone(),
+                    fingerprint: format!("{:016x}", creds.current.fingerprint),
+                    loaded_at: creds.current.loaded_at,
+                    previous_fingerprint: previous.map(|(version, _)| format!("{:016x}", version.fingerprint)),
+                    previous_valid_for_seconds: previous.map(|(_, valid_until)| (*valid_until - now).as_secs()),
+                }
+            })
+            .collect()
+    }
+
+    /// Polls credential files for backends with `watch_interval_seconds` set.
+    pub async fn watch(&self) {
+        let watched: Vec<(String, u64)> = {
+            let backends = self.backends.read().unwrap();
+            backends
+                .iter()
+                .filter_map(|(name, creds)| creds.config.watch_interval_seconds.map(|secs| (name.clone(), secs)))
+                .collect()
+        };
+
+        let tick = match watched.iter().map(|(_, secs)| *secs).min() {
+            Some(tick) => tick,
+            None => return,
+        };
+
+        info!("Watching credential files for {} backend(s)", watched.len());
+
+        let mut interval = interval(Duration::from_secs(tick.max(1)));
+        loop {
+            interval.tick().await;
+            for (backend, _) in &watched {
+                if let Err(e) = self.reload(backend) {
+                    error!("Failed to reload credentials for backend {}: {}", backend, e);
+                }
+            }
+        }
+    }
+}
+
+fn load_version(
+    backend: &BackendConfig,
+    config: &BackendCredentialsConfig,
+    dns_cache: Option<&DnsCache>,
+) -> anyhow::Result<CredentialVersion> {
+    let mut hasher = DefaultHasher::new();
+
+    let header = match (&config.header_name, &config.header_value_file) {
+        (Some(name), Some(path)) => {
+            let value = std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path, e))?
+                .trim()
+                .to_string();
+            value.hash(&mut hasher);
+            Some((name.clone(), value))
+        }
+        _ => None,
+    };
+
+    let client = match (&config.client_cert_file, &config.client_key_file) {
+        (Some(cert_path), Some(key_path)) => {
+            let cert = std::fs::read(cert_path)
+                .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", cert_path, e))?;
+            let key = std::fs::read(key_path)
+                .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", key_path, e))?;
+            cert.hash(&mut hasher);
+            key.hash(&mut hasher);
+
+            let identity = Identity::from_pkcs8_pem(&cert, &key)?;
+            Some(build_backend_client(backend, Some(identity), dns_cache)?)
+        }
+        _ => None,
+    };
+
+    let loaded_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    Ok(CredentialVersion {
+        credential: UpstreamCredential { header, client },
+        fingerprint: hasher.finish(),
+        loaded_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::Gateway;
+    use axum::{
+        body::Body,
+        extract::Request,
+        http::{HeaderMap, Method, StatusCode},
+        routing::any,
+        Router,
+    };
+    use std::{
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    };
+    use tower::ServiceExt;
+
+    fn secret_file(value: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("credentials-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("secret");
+        std::fs::write(&path, value).unwrap();
+        path
+    }
+
+    fn backend(header_name: &str, secret: &Path, overlap_seconds: u64) -> BackendConfig {
+        let mut backend = Config::default_config().backends["backend_api"].clone();
+        backend.credentials = Some(BackendCredentialsConfig {
+            header_name: Some(header_name.to_string()),
+            header_value_file: Some(secret.display().to_string()),
+            client_cert_file: None,
+            client_key_file: None,
+            overlap_seconds: Some(overlap_seconds),
+            watch_interval_seconds: None,
+        });
+        backend
+    }
+
+    fn header_value(credential: Option<UpstreamCredential>) -> Option<String> {
+        credential.and_then(|credential| credential.header).map(|(_, value)| value)
+    }
+
+    #[test]
+    fn test_reload_keeps_the_previous_secret_for_the_overlap() {
+        let secret = secret_file("first\n");
+        let mut config = Config::default_config();
+        config.backends.insert("backend_api".to_string(), backend("X-Backend-Token", &secret, 300));
+        let store = CredentialStore::new(&config, None).unwrap();
+
+        assert_eq!(header_value(store.current("backend_api")).as_deref(), Some("first"));
+        assert!(store.previous("backend_api").is_none());
+        assert!(!store.reload("backend_api").unwrap());
+
+        std::fs::write(&secret, "second").unwrap();
+        assert!(store.reload("backend_api").unwrap());
+        assert_eq!(header_value(store.current("backend_api")).as_deref(), Some("second"));
+        assert_eq!(header_value(store.previous("backend_api")).as_deref(), Some("first"));
+        assert!(store.status()[0].previous_fingerprint.is_some());
+
+        assert!(store.reload("backend_unknown").is_err());
+    }
+
+    #[test]
+    fn test_zero_overlap_retires_the_previous_secret_at_once() {
+        let secret = secret_file("first");
+        let mut config = Config::default_config();
+        config.backends.insert("backend_api".to_string(), backend("X-Backend-Token", &secret, 0));
+        let store = CredentialStore::new(&config, None).unwrap();
+
+        std::fs::write(&secret, "second").unwrap();
+        assert!(store.reload("backend_api").unwrap());
+        assert!(store.previous("backend_api").is_none());
+        assert!(store.status()[0].previous_fingerprint.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_credential_replaces_the_callers_and_only_idempotent_requests_retry() {
+        // The backend hasn't picked up the rotated secret yet
+        let seen: Arc<Mutex<Vec<Vec<String>>>> = Arc::default();
+        let recorded = seen.clone();
+        let upstream = Router::new().route(
+            "/*path",
+            any(move |headers: HeaderMap| {
+                let values: Vec<String> = headers
+                    .get_all("authorization")
+                    .iter()
+                    .map(|value| value.to_str().unwrap().to_string())
+                    .collect();
+                let accepted = values == ["Bearer old"];
+                recorded.lock().unwrap().push(values);
+                async move { if accepted { StatusCode::OK } else { StatusCode::UNAUTHORIZED } }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, upstream).await });
+
+        let secret = secret_file("Bearer old");
+        let mut backend = backend("Authorization", &secret, 300);
+        backend.servers = vec![format!("http://{}", addr)];
+        backend.name = "creds".to_string();
+        let mut config = Config::default_config();
+        config.routes.clear();
+        config.auth.enabled = false;
+        config.rate_limiting.enabled = false;
+        let route = serde_json::from_value(serde_json::json!({
+            "path": "/upstream/*",
+            "backend": "creds",
+            "load_balancing": "round_robin",
+            "auth_required": false
+        }))
+        .unwrap();
+        let gateway = Gateway::builder().config(config).backend("creds", backend).route(route).build().await.unwrap();
+
+        std::fs::write(&secret, "Bearer new").unwrap();
+        assert!(gateway.state().credentials.reload("creds").unwrap());
+
+        let send = |method: Method| {
+            let request = Request::builder()
+                .method(method)
+                .uri("/upstream/orders")
+                .header("Authorization", "Bearer caller")
+                .body(Body::empty())
+                .unwrap();
+            let router = gateway.router();
+            async move { router.oneshot(request).await.unwrap().status() }
+        };
+
+        assert_eq!(send(Method::GET).await, StatusCode::OK);
+        assert_eq!(*seen.lock().unwrap(), vec![vec!["Bearer new".to_string()], vec!["Bearer old".to_string()]]);
+
+        seen.lock().unwrap().clear();
+        assert_eq!(send(Method::POST).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(*seen.lock().unwrap(), vec![vec!["Bearer new".to_string()]]);
+    }
+}
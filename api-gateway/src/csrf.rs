@@ -0,0 +1,83 @@
+use axum::http::{HeaderMap, Method};
+
+use crate::config::{path_matches, CsrfConfig};
+
+pub struct CsrfProtection {
+    config: CsrfConfig,
+}
+
+impl CsrfProtection {
+    pub fn new(config: &CsrfConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+
+    /// Safe methods don't change state, so they never need a token.
+    pub fn applies_to(&self, method: &Method, path: &str) -> bool {
+        let unsafe_method = !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE);
+
+        unsafe_method
+            && (self.config.paths.is_empty() || self.config.paths.iter().any(|pattern| path_matches(pattern, path)))
+    }
+
+    /// Whether the request echoes the session's token back in the CSRF header.
+    pub fn verify(&self, headers: &HeaderMap, session_token: &str) -> bool {
+        let presented = match headers.get(&self.config.header_name).and_then(|value| value.to_str().ok()) {
+            Some(presented) => presented,
+            None => return false,
+        };
+
+        // Sessions created before CSRF protection was enabled have no token
+        !session_token.is_empty()
+            && presented.len() == session_token.len()
+            && openssl::memcmp::eq(presented.as_bytes(), session_token.as_bytes())
+    }
+
+    /// Cookie handing the token to the page. Unlike the session cookie it's readable by
+    /// scripts, which is what lets them copy it into the header.
+    pub fn cookie(&self, token: &str, max_age: u64, secure: bool) -> String {
+        let mut cookie = format!(
+            "{}={}; Path=/; SameSite=Strict; Max-Age={}",
+            self.config.cookie_name, token, max_age
+        );
+        if secure {
+            cookie.push_str("; Secure");
+        }
+        cookie
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn protection(paths: Vec<String>) -> CsrfProtection {
+        CsrfProtection::new(&CsrfConfig {
+            paths,
+            header_name: "X-CSRF-Token".to_string(),
+            cookie_name: "gateway_csrf".to_string(),
+        })
+    }
+
+    #[test]
+    fn test_only_unsafe_methods_on_selected_paths() {
+        let csrf = protection(vec!["/api/v1/*".to_string()]);
+        assert!(csrf.applies_to(&Method::POST, "/api/v1/orders"));
+        assert!(csrf.applies_to(&Method::DELETE, "/api/v1/orders/1"));
+        assert!(!csrf.applies_to(&Method::GET, "/api/v1/orders"));
+        assert!(!csrf.applies_to(&Method::POST, "/public/form"));
+    }
+
+    #[test]
+    fn test_verify_token() {
+        let csrf = protection(Vec::new());
+        let mut headers = HeaderMap::new();
+        assert!(!csrf.verify(&headers, "abc"));
+
+        headers.insert("X-CSRF-Token", "abc".parse().unwrap());
+        assert!(csrf.verify(&headers, "abc"));
+        assert!(!csrf.verify(&headers, "abd"));
+        assert!(!csrf.verify(&headers, ""));
+    }
+}
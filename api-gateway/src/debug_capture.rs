@@ -0,0 +1,268 @@
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, Method, StatusCode, Uri},
+};
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Mutex,
+};
+use tracing::info;
+
+use crate::config::{Config, DebugCaptureConfig, RouteConfig};
+
+pub const DEBUG_CAPTURE_HEADER: &str = "X-Debug-Capture";
+
+/// Signed headers can't enable capture for longer than this, so a leaked one is only
+/// useful briefly.
+const MAX_HEADER_LIFETIME_SECONDS: u64 = 60 * 60;
+const REDACTED: &str = "[REDACTED]";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CapturedExchange {
+    pub request_id: String,
+    pub captured_at: u64,
+    pub route: String,
+    pub method: String,
+    pub uri: String,
+    pub request_headers: BTreeMap<String, String>,
+    pub request_body: CapturedBody,
+    pub status: u16,
+    pub response_headers: BTreeMap<String, String>,
+    /// Absent for streamed responses, which are never buffered
+    pub response_body: Option<CapturedBody>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CapturedBody {
+    pub content: String,
+    pub size: usize,
+    pub truncated: bool,
+}
+
+/// Ring buffer of the most recent captured exchanges.
+pub struct DebugCapture {
+    config: Option<DebugCaptureConfig>,
+    redact_headers: Vec<String>,
+    exchanges: Mutex<VecDeque<CapturedExchange>>,
+}
+
+/// Lowercased names of headers that carry credentials: the standard ones, the API key
+/// header, backends' injected credential headers and any `debug_capture.redact_headers`.
+pub fn sensitive_headers(config: &Config) -> Vec<String> {
+    let mut headers: Vec<String> = ["authorization", "proxy-authorization", "cookie", "set-cookie"]
+        .iter()
+        .map(|name| name.to_string())
+        .collect();
+    headers.push(config.auth.api_key_header.to_ascii_lowercase());
+    headers.extend(
+        config
+            .backends
+            .values()
+            .filter_map(|backend| backend.credentials.as_ref()?.header_name.as_ref())
+            .map(|name| name.to_ascii_lowercase()),
+    );
+    if let Some(capture) = &config.debug_capture {
+        headers.extend(capture.redact_headers.iter().map(|name| name.to_ascii_lowercase()));
+    }
+    headers
+}
+
+impl DebugCapture {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            config: config.debug_capture.clone(),
+            redact_headers: sensitive_headers(config),
+            exchanges: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Whether bodies of this request should be captured: the route asks for it, or the
+    /// request carries a valid signed header.
+    pub fn enabled_for(&self, route: &RouteConfig, headers: &HeaderMap) -> bool {
+        let config = match &self.config {
+            Some(config) => config,
+            None => return false,
+        };
+
+        route.debug_capture
+            || match (&config.header_secret, headers.get(DEBUG_CAPTURE_HEADER).and_then(|value| value.to_str().ok())) {
+                (Some(secret), Some(value)) => verify_header(secret, value, now()),
+                _ => false,
+            }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn record(
+        &self,
+        request_id: &str,
+        route: &RouteConfig,
+        method: &Method,
+        uri: &Uri,
+        request_headers: &HeaderMap,
+        request_body: &Bytes,
+        status: StatusCode,
+        response_headers: &HeaderMap,
+        response_body: Option<&Bytes>,
+    ) {
+        let config = match &self.config {
+            Some(config) => config,
+            None => return,
+        };
+
+        let exchange = CapturedExchange {
+            request_id: request_id.to_string(),
+            captured_at: now(),
+            route: route.path.clone(),
+            method: method.to_string(),
+            uri: uri.to_string(),
+            request_headers: self.headers(request_headers),
+            request_body: capture_body(request_body, config),
+            status: status.as_u16(),
+            response_headers: self.headers(response_headers),
+            response_body: response_body.map(|body| capture_body(body, config)),
+        };
+
+        if config.log {
+            info!(
+                "Debug capture (request_id: {}): {}",
+                request_id,
+                serde_json::to_string(&exchange).unwrap_or_default()
+            );
+        }
+
+        let mut exchanges = self.exchanges.lock().unwrap();
+        exchanges.push_front(exchange);
+        exchanges.truncate(config.buffer_size);
+    }
+
+    /// Newest first
+    pub fn recent(&self) -> Vec<CapturedExchange> {
+        self.exchanges.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn headers(&self, headers: &HeaderMap) -> BTreeMap<String, String> {
+        headers
+            .iter()
+            .filter(|(name, _)| !name.as_str().eq_ignore_ascii_case(DEBUG_CAPTURE_HEADER))
+            .map(|(name, value)| {
+                let value = if self.redact_headers.iter().any(|redacted| redacted == name.as_str()) {
+                    REDACTED.to_string()
+                } else {
+                    String::from_utf8_lossy(value.as_bytes()).into_owned()
+                };
+                (name.as_str().to_string(), value)
+            })
+            .collect()
+    }
+}
+
+/// Redacts JSON bodies field by field, then cuts the result down to `max_body_bytes`.
+fn capture_body(body: &Bytes, config: &DebugCaptureConfig) -> CapturedBody {
+    let content = match serde_json::from_slice::<Value>(body) {
+        Ok(mut json) => {
+            redact_fields(&mut json, &config.redact_fields);
+            json.to_string()
+        }
+        Err(_) => String::from_utf8_lossy(body).into_owned(),
+    };
+
+    let truncated = content.len() > config.max_body_bytes;
+    let mut end = content.len().min(config.max_body_bytes);
+    while !content.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    CapturedBody {
+        content: content[..end].to_string(),
+        size: body.len(),
+        truncated,
+    }
+}
+
+fn redact_fields(value: &mut Value, fields: &[String]) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                if fields.iter().any(|field| field.eq_ignore_ascii_case(key)) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact_fields(value, fields);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(|value| redact_fields(value, fields)),
+        _ => {}
+    }
+}
+
+/// `<expires_at>.<hex HMAC-SHA256 of expires_at>`
+fn verify_header(secret: &str, value: &str, now: u64) -> bool {
+    let (expires_at, signature) = match value.split_once('.') {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let expires_at_secs = match expires_at.parse::<u64>() {
+        Ok(expires_at) => expires_at,
+        Err(_) => return false,
+    };
+    if expires_at_secs <= now || expires_at_secs > now + MAX_HEADER_LIFETIME_SECONDS {
+        return false;
+    }
+
+    match sign(secret, expires_at) {
+        Ok(expected) => {
+            expected.len() == signature.len()
+                && openssl::memcmp::eq(expected.as_bytes(), signature.to_ascii_lowercase().as_bytes())
+        }
+        Err(_) => false,
+    }
+}
+
+fn sign(secret: &str, message: &str) -> anyhow::Result<String> {
+    let key = PKey::hmac(secret.as_bytes())?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(message.as_bytes())?;
+    Ok(signer.sign_to_vec()?.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+fn now() -> u64 {
+    chrono::Utc::now().timestamp().max(0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_body_redacts_and_truncates() {
+        let config: DebugCaptureConfig = serde_json::from_value(serde_json::json!({ "max_body_bytes": 40 })).unwrap();
+
+        let body = Bytes::from(r#"{"user":{"name":"jdoe","password":"hunter2"}}"#);
+        let captured = capture_body(&body, &config);
+        assert_eq!(captured.content, r#"{"user":{"name":"jdoe","password":"[REDA"#);
+        assert!(captured.truncated);
+        assert_eq!(captured.size, body.len());
+
+        let plain = capture_body(&Bytes::from("plain text"), &config);
+        assert_eq!(plain.content, "plain text");
+        assert!(!plain.truncated);
+    }
+
+    #[test]
+    fn test_signed_header() {
+        let now = 1_700_000_000;
+        let expires_at = (now + 600).to_string();
+        let header = format!("{}.{}", expires_at, sign("secret", &expires_at).unwrap());
+
+        assert!(verify_header("secret", &header, now));
+        assert!(!verify_header("other-secret", &header, now));
+        assert!(!verify_header("secret", &header, now + 601));
+
+        let far_future = (now + MAX_HEADER_LIFETIME_SECONDS + 1).to_string();
+        let header = format!("{}.{}", far_future, sign("secret", &far_future).unwrap());
+        assert!(!verify_header("secret", &header, now));
+    }
+}
@@ -0,0 +1,126 @@
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+use tokio::sync::broadcast;
+
+/// Recent events kept so a controller that reconnects with `Last-Event-ID` can catch up.
+const REPLAY_CAPACITY: usize = 256;
+
+lazy_static! {
+    pub static ref LIFECYCLE_EVENTS: EventBus = EventBus::new();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LifecycleEventKind {
+    ConfigCanaryStarted,
+    ConfigApplied,
+    ConfigCanaryReverted,
+    BackendAdded,
+    ServerEjected,
+    ServerReadmitted,
+    CircuitOpened,
+    CircuitClosed,
+}
+
+impl LifecycleEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LifecycleEventKind::ConfigCanaryStarted => "config_canary_started",
+            LifecycleEventKind::ConfigApplied => "config_applied",
+            LifecycleEventKind::ConfigCanaryReverted => "config_canary_reverted",
+            LifecycleEventKind::BackendAdded => "backend_added",
+            LifecycleEventKind::ServerEjected => "server_ejected",
+            LifecycleEventKind::ServerReadmitted => "server_readmitted",
+            LifecycleEventKind::CircuitOpened => "circuit_opened",
+            LifecycleEventKind::CircuitClosed => "circuit_closed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LifecycleEvent {
+    pub id: u64,
+    pub kind: LifecycleEventKind,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub details: serde_json::Value,
+}
+
+/// Fans gateway state changes out to `/admin/events` subscribers.
+pub struct EventBus {
+    sender: broadcast::Sender<LifecycleEvent>,
+    recent: Mutex<VecDeque<LifecycleEvent>>,
+    next_id: AtomicU64,
+}
+
+impl EventBus {
+    fn new() -> Self {
+        let (sender, _) = broadcast::channel(REPLAY_CAPACITY);
+        Self {
+            sender,
+            recent: Mutex::new(VecDeque::with_capacity(REPLAY_CAPACITY)),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    pub fn emit(&self, kind: LifecycleEventKind, details: serde_json::Value) {
+        let mut recent = self.recent.lock().unwrap();
+        let event = LifecycleEvent {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            kind,
+            timestamp: chrono::Utc::now(),
+            details,
+        };
+
+        if recent.len() == REPLAY_CAPACITY {
+            recent.pop_front();
+        }
+        recent.push_back(event.clone());
+
+        // No subscribers is fine
+        let _ = self.sender.send(event);
+    }
+
+    /// Events after `last_id` still in the replay buffer, plus a receiver for everything
+    /// emitted from now on. Sent under the same lock, so nothing falls in between.
+    pub fn subscribe(&self, last_id: Option<u64>) -> (Vec<LifecycleEvent>, broadcast::Receiver<LifecycleEvent>) {
+        let recent = self.recent.lock().unwrap();
+        let receiver = self.sender.subscribe();
+
+        let backlog = match last_id {
+            Some(last_id) => recent.iter().filter(|event| event.id > last_id).cloned().collect(),
+            None => Vec::new(),
+        };
+
+        (backlog, receiver)
+    }
+}
+
+pub fn emit(kind: LifecycleEventKind, details: serde_json::Value) {
+    LIFECYCLE_EVENTS.emit(kind, details);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_replay_after_last_event_id() {
+        let bus = EventBus::new();
+        bus.emit(LifecycleEventKind::ServerEjected, serde_json::json!({ "server": "a" }));
+        bus.emit(LifecycleEventKind::ServerReadmitted, serde_json::json!({ "server": "a" }));
+
+        let (backlog, mut receiver) = bus.subscribe(Some(1));
+        assert_eq!(backlog.len(), 1);
+        assert_eq!(backlog[0].kind, LifecycleEventKind::ServerReadmitted);
+
+        bus.emit(LifecycleEventKind::CircuitOpened, serde_json::json!({}));
+        assert_eq!(receiver.recv().await.unwrap().id, 3);
+    }
+}
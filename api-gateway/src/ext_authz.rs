@@ -0,0 +1,490 @@
+use axum::http::{uri::PathAndQuery, HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri};
+use reqwest::Client;
+use serde::Serialize;
+use std::{collections::HashMap, net::IpAddr, time::Duration};
+use tonic::{codec::ProstCodec, transport::Channel};
+use tracing::{error, warn};
+
+use crate::config::{path_matches, ExtAuthzConfig, ExtAuthzProtocol};
+use crate::ext_proc::header_value;
+
+/// The subset of the `envoy.service.auth.v3` messages the gateway uses, with their upstream tags.
+pub mod proto {
+    use std::collections::HashMap;
+
+    pub use crate::ext_proc::proto::{HeaderValue, HeaderValueOption, HttpStatus};
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CheckRequest {
+        #[prost(message, optional, tag = "1")]
+        pub attributes: Option<AttributeContext>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct AttributeContext {
+        #[prost(message, optional, tag = "1")]
+        pub source: Option<Peer>,
+        #[prost(message, optional, tag = "4")]
+        pub request: Option<Request>,
+    }
+
+    /// `AttributeContext.Peer`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Peer {
+        #[prost(message, optional, tag = "1")]
+        pub address: Option<Address>,
+    }
+
+    /// `envoy.config.core.v3.Address`, of which only the socket address is sent
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Address {
+        #[prost(message, optional, tag = "1")]
+        pub socket_address: Option<SocketAddress>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SocketAddress {
+        #[prost(string, tag = "2")]
+        pub address: String,
+    }
+
+    /// `AttributeContext.Request`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Request {
+        #[prost(message, optional, tag = "2")]
+        pub http: Option<HttpRequest>,
+    }
+
+    /// `AttributeContext.HttpRequest`; `path` carries the query string too
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HttpRequest {
+        #[prost(string, tag = "2")]
+        pub method: String,
+        #[prost(map = "string, string", tag = "3")]
+        pub headers: HashMap<String, String>,
+        #[prost(string, tag = "4")]
+        pub path: String,
+        #[prost(string, tag = "5")]
+        pub host: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CheckResponse {
+        #[prost(message, optional, tag = "1")]
+        pub status: Option<Status>,
+        #[prost(oneof = "check_response::HttpResponse", tags = "2, 3")]
+        pub http_response: Option<check_response::HttpResponse>,
+    }
+
+    pub mod check_response {
+        #[derive(Clone, PartialEq, prost::Oneof)]
+        pub enum HttpResponse {
+            #[prost(message, tag = "2")]
+            DeniedResponse(super::DeniedHttpResponse),
+            #[prost(message, tag = "3")]
+            OkResponse(super::OkHttpResponse),
+        }
+    }
+
+    /// `google.rpc.Status`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Status {
+        #[prost(int32, tag = "1")]
+        pub code: i32,
+        #[prost(string, tag = "2")]
+        pub message: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct DeniedHttpResponse {
+        #[prost(message, optional, tag = "1")]
+        pub status: Option<HttpStatus>,
+        #[prost(message, repeated, tag = "2")]
+        pub headers: Vec<HeaderValueOption>,
+        #[prost(string, tag = "3")]
+        pub body: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct OkHttpResponse {
+        #[prost(message, repeated, tag = "2")]
+        pub headers: Vec<HeaderValueOption>,
+    }
+}
+
+const CHECK_PATH: &str = "/envoy.service.auth.v3.Authorization/Check";
+
+#[derive(Debug)]
+pub enum ExtAuthzDecision {
+    /// Headers to add to the upstream request
+    Allow(Vec<(HeaderName, HeaderValue)>),
+    Deny(StatusCode),
+}
+
+#[derive(Debug, Serialize)]
+struct CheckRequest<'a> {
+    method: &'a str,
+    path: &'a str,
+    query: Option<&'a str>,
+    headers: HashMap<String, String>,
+    client_ip: Option<IpAddr>,
+}
+
+enum Transport {
+    Http(Client),
+    Grpc(Channel),
+}
+
+pub struct ExtAuthzClient {
+    config: ExtAuthzConfig,
+    transport: Transport,
+}
+
+impl ExtAuthzClient {
+    pub fn new(config: &ExtAuthzConfig) -> anyhow::Result<Self> {
+        let timeout = Duration::from_millis(config.timeout_ms);
+        let transport = match config.protocol {
+            ExtAuthzProtocol::Http => Transport::Http(Client::builder().timeout(timeout).build()?),
+            ExtAuthzProtocol::Grpc => Transport::Grpc(
+                Channel::from_shared(config.url.clone())
+                    .map_err(|e| anyhow::anyhow!("Invalid ext_authz URL {}: {}", config.url, e))?
+                    .connect_timeout(timeout)
+                    .connect_lazy(),
+            ),
+        };
+
+        Ok(Self {
+            config: config.clone(),
+            transport,
+        })
+    }
+
+    pub fn applies_to(&self, path: &str) -> bool {
+        self.config.paths.is_empty() || self.config.paths.iter().any(|pattern| path_matches(pattern, path))
+    }
+
+    pub async fn check(&self, method: &Method, uri: &Uri, headers: &HeaderMap, client_ip: Option<IpAddr>) -> ExtAuthzDecision {
+        let decision = match &self.transport {
+            Transport::Http(http) => self.check_http(http, method, uri, headers, client_ip).await,
+            Transport::Grpc(channel) => self.check_grpc(channel.clone(), method, uri, headers, client_ip).await,
+        };
+
+        match decision {
+            Ok(ExtAuthzDecision::Deny(status)) => {
+                warn!("External authorization denied {} {} ({})", method, uri.path(), status);
+                ExtAuthzDecision::Deny(status)
+            }
+            Ok(allow) => allow,
+            Err(e) => {
+                error!("External authorization service unreachable: {}", e);
+                if self.config.failure_mode_allow {
+                    ExtAuthzDecision::Allow(Vec::new())
+                } else {
+                    ExtAuthzDecision::Deny(StatusCode::SERVICE_UNAVAILABLE)
+                }
+            }
+        }
+    }
+
+    async fn check_http(
+        &self,
+        http: &Client,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        client_ip: Option<IpAddr>,
+    ) -> anyhow::Result<ExtAuthzDecision> {
+        let request = CheckRequest {
+            method: method.as_str(),
+            path: uri.path(),
+            query: uri.query(),
+            headers: self.forwarded_headers(headers),
+            client_ip,
+        };
+
+        let response = http.post(&self.config.url).json(&request).send().await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Ok(ExtAuthzDecision::Deny(denial_status(status.as_u16())));
+        }
+
+        let copied = self
+            .config
+            .copy_headers
+            .iter()
+            .filter_map(|name| {
+                let value = response.headers().get(name.as_str())?;
+                Some((
+                    HeaderName::from_bytes(name.as_bytes()).ok()?,
+                    HeaderValue::from_bytes(value.as_bytes()).ok()?,
+                ))
+            })
+            .collect();
+
+        Ok(ExtAuthzDecision::Allow(copied))
+    }
+
+    async fn check_grpc(
+        &self,
+        channel: Channel,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        client_ip: Option<IpAddr>,
+    ) -> anyhow::Result<ExtAuthzDecision> {
+        let host = headers
+            .get("host")
+            .and_then(|host| host.to_str().ok())
+            .or(uri.host())
+            .unwrap_or_default();
+        let request = proto::CheckRequest {
+            attributes: Some(proto::AttributeContext {
+                source: client_ip.map(|ip| proto::Peer {
+                    address: Some(proto::Address {
+                        socket_address: Some(proto::SocketAddress { address: ip.to_string() }),
+                    }),
+                }),
+                request: Some(proto::Request {
+                    http: Some(proto::HttpRequest {
+                        method: method.to_string(),
+                        headers: self.forwarded_headers(headers),
+                        path: uri.path_and_query().map_or(uri.path(), |pq| pq.as_str()).to_string(),
+                        host: host.to_string(),
+                    }),
+                }),
+            }),
+        };
+
+        let mut grpc = tonic::client::Grpc::new(channel);
+        let codec = ProstCodec::<proto::CheckRequest, proto::CheckResponse>::default();
+        let call = async move {
+            grpc.ready().await.map_err(|e| anyhow::anyhow!("{}", e))?;
+            grpc.unary(tonic::Request::new(request), PathAndQuery::from_static(CHECK_PATH), codec)
+                .await
+                .map_err(|status| anyhow::anyhow!("{}", status))
+        };
+        let response = tokio::time::timeout(Duration::from_millis(self.config.timeout_ms), call)
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out"))??
+            .into_inner();
+
+        use proto::check_response::HttpResponse;
+        let allowed = response.status.map_or(0, |status| status.code) == 0;
+        Ok(match (allowed, response.http_response) {
+            (true, Some(HttpResponse::OkResponse(ok))) => ExtAuthzDecision::Allow(
+                ok.headers
+                    .iter()
+                    .filter_map(|option| {
+                        let header = option.header.as_ref()?;
+                        Some((
+                            HeaderName::from_bytes(header.key.as_bytes()).ok()?,
+                            HeaderValue::from_str(&header_value(header)).ok()?,
+                        ))
+                    })
+                    .collect(),
+            ),
+            (true, _) => ExtAuthzDecision::Allow(Vec::new()),
+            (false, Some(HttpResponse::DeniedResponse(denied))) => ExtAuthzDecision::Deny(denial_status(
+                denied.status.map_or(403, |status| status.code).try_into().unwrap_or(403),
+            )),
+            (false, _) => ExtAuthzDecision::Deny(StatusCode::FORBIDDEN),
+        })
+    }
+
+    fn forwarded_headers(&self, headers: &HeaderMap) -> HashMap<String, String> {
+        headers
+            .iter()
+            .filter(|(name, _)| {
+                self.config.include_headers.is_empty()
+                    || self
+                        .config
+                        .include_headers
+                        .iter()
+                        .any(|included| included.eq_ignore_ascii_case(name.as_str()))
+            })
+            .filter_map(|(name, value)| Some((name.as_str().to_string(), value.to_str().ok()?.to_string())))
+            .collect()
+    }
+}
+
+/// Passes through the service's 401 so clients see why; anything else is a plain denial.
+fn denial_status(status: u16) -> StatusCode {
+    match status {
+        401 => StatusCode::UNAUTHORIZED,
+        _ => StatusCode::FORBIDDEN,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use axum::{extract::Request, response::IntoResponse, routing::post, Json, Router};
+    use tokio::{net::TcpListener, sync::watch};
+
+    fn config(url: String, protocol: ExtAuthzProtocol) -> ExtAuthzConfig {
+        ExtAuthzConfig {
+            url,
+            protocol,
+            timeout_ms: 1000,
+            paths: Vec::new(),
+            include_headers: vec!["authorization".to_string()],
+            copy_headers: vec!["x-user".to_string()],
+            failure_mode_allow: false,
+        }
+    }
+
+    async fn check(client: &ExtAuthzClient, authorization: &str) -> ExtAuthzDecision {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", authorization.parse().unwrap());
+        headers.insert("cookie", "session=secret".parse().unwrap());
+        let uri: Uri = "/orders?page=2".parse().unwrap();
+        client.check(&Method::GET, &uri, &headers, Some([203, 0, 113, 7].into())).await
+    }
+
+    fn assert_allowed_as_alice(decision: ExtAuthzDecision) {
+        match decision {
+            ExtAuthzDecision::Allow(headers) => {
+                assert_eq!(headers, vec![(HeaderName::from_static("x-user"), HeaderValue::from_static("alice"))])
+            }
+            ExtAuthzDecision::Deny(status) => panic!("denied with {}", status),
+        }
+    }
+
+    fn assert_denied(decision: ExtAuthzDecision, expected: StatusCode) {
+        match decision {
+            ExtAuthzDecision::Deny(status) => assert_eq!(status, expected),
+            ExtAuthzDecision::Allow(_) => panic!("allowed, expected {}", expected),
+        }
+    }
+
+    /// Allows `Bearer good`, answers 401 for `Bearer expired` and 500 otherwise.
+    async fn http_service() -> String {
+        let app = Router::new().route(
+            "/check",
+            post(|Json(request): Json<serde_json::Value>| async move {
+                // Only the included headers are sent
+                assert!(request["headers"].get("cookie").is_none());
+                assert_eq!(request["path"], "/orders");
+                assert_eq!(request["client_ip"], "203.0.113.7");
+                match request["headers"]["authorization"].as_str() {
+                    Some("Bearer good") => {
+                        (StatusCode::OK, [("x-user", "alice"), ("x-internal", "not copied")]).into_response()
+                    }
+                    Some("Bearer expired") => StatusCode::UNAUTHORIZED.into_response(),
+                    _ => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+                }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/check", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        url
+    }
+
+    struct Authorizer;
+
+    impl tonic::server::UnaryService<proto::CheckRequest> for Authorizer {
+        type Response = proto::CheckResponse;
+        type Future = std::future::Ready<Result<tonic::Response<proto::CheckResponse>, tonic::Status>>;
+
+        /// Allows `Bearer good` and denies everything else with the status the token names.
+        fn call(&mut self, request: tonic::Request<proto::CheckRequest>) -> Self::Future {
+            let attributes = request.into_inner().attributes.unwrap();
+            let http = attributes.request.unwrap().http.unwrap();
+            assert!(!http.headers.contains_key("cookie"));
+            assert_eq!(http.path, "/orders?page=2");
+            assert_eq!(attributes.source.unwrap().address.unwrap().socket_address.unwrap().address, "203.0.113.7");
+
+            use proto::check_response::HttpResponse;
+            let (code, http_response) = match http.headers.get("authorization").map(String::as_str) {
+                Some("Bearer good") => (
+                    0,
+                    HttpResponse::OkResponse(proto::OkHttpResponse {
+                        headers: vec![proto::HeaderValueOption {
+                            header: Some(proto::HeaderValue {
+                                key: "x-user".to_string(),
+                                value: String::new(),
+                                raw_value: b"alice".to_vec(),
+                            }),
+                            append: None,
+                            append_action: 0,
+                        }],
+                    }),
+                ),
+                authorization => (
+                    7,
+                    HttpResponse::DeniedResponse(proto::DeniedHttpResponse {
+                        status: Some(proto::HttpStatus {
+                            code: if authorization == Some("Bearer expired") { 401 } else { 403 },
+                        }),
+                        headers: Vec::new(),
+                        body: String::new(),
+                    }),
+                ),
+            };
+
+            std::future::ready(Ok(tonic::Response::new(proto::CheckResponse {
+                status: Some(proto::Status { code, message: String::new() }),
+                http_response: Some(http_response),
+            })))
+        }
+    }
+
+    async fn grpc_service() -> String {
+        let app = Router::new().route(
+            CHECK_PATH,
+            post(|request: Request| async move {
+                let codec = ProstCodec::<proto::CheckResponse, proto::CheckRequest>::default();
+                tonic::server::Grpc::new(codec).unary(Authorizer, request).await.map(axum::body::Body::new)
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (shutdown_tx, shutdown) = watch::channel(false);
+        tokio::spawn(async move {
+            let _shutdown_tx = shutdown_tx;
+            crate::server::serve(listener, app, &Config::default_config().server, shutdown).await
+        });
+        url
+    }
+
+    /// A URL nothing is listening on.
+    async fn unreachable() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        format!("http://{}/check", listener.local_addr().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_http_allow_copies_configured_headers_and_deny_keeps_401() {
+        let client = ExtAuthzClient::new(&config(http_service().await, ExtAuthzProtocol::Http)).unwrap();
+
+        assert_allowed_as_alice(check(&client, "Bearer good").await);
+        assert_denied(check(&client, "Bearer expired").await, StatusCode::UNAUTHORIZED);
+        assert_denied(check(&client, "Bearer broken").await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_grpc_allow_adds_ok_response_headers_and_deny_keeps_401() {
+        let client = ExtAuthzClient::new(&config(grpc_service().await, ExtAuthzProtocol::Grpc)).unwrap();
+
+        assert_allowed_as_alice(check(&client, "Bearer good").await);
+        assert_denied(check(&client, "Bearer expired").await, StatusCode::UNAUTHORIZED);
+        assert_denied(check(&client, "Bearer other").await, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_failure_mode_allow() {
+        for protocol in [ExtAuthzProtocol::Http, ExtAuthzProtocol::Grpc] {
+            let mut config = config(unreachable().await, protocol);
+            let client = ExtAuthzClient::new(&config).unwrap();
+            assert_denied(check(&client, "Bearer good").await, StatusCode::SERVICE_UNAVAILABLE);
+
+            config.failure_mode_allow = true;
+            let client = ExtAuthzClient::new(&config).unwrap();
+            let decision = check(&client, "Bearer good").await;
+            assert!(matches!(decision, ExtAuthzDecision::Allow(headers) if headers.is_empty()));
+        }
+    }
+}
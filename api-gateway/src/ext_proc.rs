@@ -0,0 +1,611 @@
+//! Streams requests to an external processor speaking Envoy's ext_proc protocol
+//! (`envoy.service.ext_proc.v3.ExternalProcessor/Process`). Each request gets its own
+//! gRPC stream, kept open from the request phase to the response phase, over which the
+//! processor is sent the parts its `processing_mode` asks for. It answers each with
+//! header and body mutations, or an immediate response that replaces the backend's.
+//!
+//! Bodies are sent whole (Envoy's `BUFFERED` mode); trailers aren't sent.
+
+use axum::{
+    body::{Body, Bytes},
+    extract::Request,
+    http::{header, uri::PathAndQuery, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{codec::ProstCodec, transport::Channel, Streaming};
+use tracing::{error, warn};
+
+use crate::config::ExtProcConfig;
+use crate::filters::{
+    apply_request_pairs, apply_response_pairs, header_map, request_pairs, response_pairs, FilterAction, FilterContext,
+    FilterFuture, HeaderPairs, RequestFilter, ResponseFilter,
+};
+
+/// The subset of the ext_proc v3 messages the gateway uses, with their upstream tags.
+pub mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ProcessingRequest {
+        #[prost(oneof = "processing_request::Request", tags = "2, 3, 4, 5")]
+        pub request: Option<processing_request::Request>,
+    }
+
+    pub mod processing_request {
+        #[derive(Clone, PartialEq, prost::Oneof)]
+        pub enum Request {
+            #[prost(message, tag = "2")]
+            RequestHeaders(super::HttpHeaders),
+            #[prost(message, tag = "3")]
+            ResponseHeaders(super::HttpHeaders),
+            #[prost(message, tag = "4")]
+            RequestBody(super::HttpBody),
+            #[prost(message, tag = "5")]
+            ResponseBody(super::HttpBody),
+        }
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ProcessingResponse {
+        #[prost(oneof = "processing_response::Response", tags = "1, 2, 3, 4, 7")]
+        pub response: Option<processing_response::Response>,
+    }
+
+    pub mod processing_response {
+        #[derive(Clone, PartialEq, prost::Oneof)]
+        pub enum Response {
+            #[prost(message, tag = "1")]
+            RequestHeaders(super::HeadersResponse),
+            #[prost(message, tag = "2")]
+            ResponseHeaders(super::HeadersResponse),
+            #[prost(message, tag = "3")]
+            RequestBody(super::BodyResponse),
+            #[prost(message, tag = "4")]
+            ResponseBody(super::BodyResponse),
+            #[prost(message, tag = "7")]
+            ImmediateResponse(super::ImmediateResponse),
+        }
+    }
+
+    /// `envoy.config.core.v3.HeaderMap`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HeaderMap {
+        #[prost(message, repeated, tag = "1")]
+        pub headers: Vec<HeaderValue>,
+    }
+
+    /// `envoy.config.core.v3.HeaderValue`; Envoy sends values in `raw_value`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HeaderValue {
+        #[prost(string, tag = "1")]
+        pub key: String,
+        #[prost(string, tag = "2")]
+        pub value: String,
+        #[prost(bytes = "vec", tag = "3")]
+        pub raw_value: Vec<u8>,
+    }
+
+    /// `envoy.config.core.v3.HeaderValueOption`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HeaderValueOption {
+        #[prost(message, optional, tag = "1")]
+        pub header: Option<HeaderValue>,
+        /// Deprecated upstream in favour of `append_action`, but still honoured first
+        #[prost(message, optional, tag = "2")]
+        pub append: Option<bool>,
+        #[prost(int32, tag = "3")]
+        pub append_action: i32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HttpHeaders {
+        #[prost(message, optional, tag = "1")]
+        pub headers: Option<HeaderMap>,
+        #[prost(bool, tag = "3")]
+        pub end_of_stream: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HttpBody {
+        #[prost(bytes = "vec", tag = "1")]
+        pub body: Vec<u8>,
+        #[prost(bool, tag = "2")]
+        pub end_of_stream: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HeadersResponse {
+        #[prost(message, optional, tag = "1")]
+        pub response: Option<CommonResponse>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct BodyResponse {
+        #[prost(message, optional, tag = "1")]
+        pub response: Option<CommonResponse>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct CommonResponse {
+        /// `CONTINUE` (0) or `CONTINUE_AND_REPLACE` (1)
+        #[prost(int32, tag = "1")]
+        pub status: i32,
+        #[prost(message, optional, tag = "2")]
+        pub header_mutation: Option<HeaderMutation>,
+        #[prost(message, optional, tag = "3")]
+        pub body_mutation: Option<BodyMutation>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HeaderMutation {
+        #[prost(message, repeated, tag = "1")]
+        pub set_headers: Vec<HeaderValueOption>,
+        #[prost(string, repeated, tag = "2")]
+        pub remove_headers: Vec<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct BodyMutation {
+        #[prost(oneof = "body_mutation::Mutation", tags = "1, 2")]
+        pub mutation: Option<body_mutation::Mutation>,
+    }
+
+    pub mod body_mutation {
+        #[derive(Clone, PartialEq, prost::Oneof)]
+        pub enum Mutation {
+            #[prost(bytes = "vec", tag = "1")]
+            Body(Vec<u8>),
+            #[prost(bool, tag = "2")]
+            ClearBody(bool),
+        }
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ImmediateResponse {
+        #[prost(message, optional, tag = "1")]
+        pub status: Option<HttpStatus>,
+        #[prost(message, optional, tag = "2")]
+        pub headers: Option<HeaderMutation>,
+        #[prost(bytes = "vec", tag = "3")]
+        pub body: Vec<u8>,
+        #[prost(string, tag = "5")]
+        pub details: String,
+    }
+
+    /// `envoy.type.v3.HttpStatus`
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct HttpStatus {
+        #[prost(int32, tag = "1")]
+        pub code: i32,
+    }
+}
+
+use proto::{processing_request, processing_response};
+
+const PROCESS_PATH: &str = "/envoy.service.ext_proc.v3.ExternalProcessor/Process";
+
+const ADD_IF_ABSENT: i32 = 1;
+const OVERWRITE_IF_EXISTS_OR_ADD: i32 = 2;
+const OVERWRITE_IF_EXISTS: i32 = 3;
+
+/// Streams whose response never came back are closed after this long.
+const SESSION_TTL: Duration = Duration::from_secs(300);
+
+/// One request's stream to the processor.
+struct Session {
+    outbound: mpsc::Sender<proto::ProcessingRequest>,
+    inbound: Streaming<proto::ProcessingResponse>,
+    started: Instant,
+}
+
+impl Session {
+    async fn open(channel: Channel, first: proto::ProcessingRequest, timeout: Duration) -> anyhow::Result<Self> {
+        let (outbound, receiver) = mpsc::channel(4);
+        // Queued before the call, as processors may hold their response headers until
+        // they've seen a message
+        outbound.send(first).await?;
+
+        let mut grpc = tonic::client::Grpc::new(channel);
+        grpc.ready().await.map_err(|e| anyhow::anyhow!("Processor unavailable: {}", e))?;
+        let codec = ProstCodec::<proto::ProcessingRequest, proto::ProcessingResponse>::default();
+        let call = grpc.streaming(
+            tonic::Request::new(ReceiverStream::new(receiver)),
+            PathAndQuery::from_static(PROCESS_PATH),
+            codec,
+        );
+        let inbound = tokio::time::timeout(timeout, call)
+            .await
+            .map_err(|_| anyhow::anyhow!("Processor timed out"))??
+            .into_inner();
+        Ok(Self {
+            outbound,
+            inbound,
+            started: Instant::now(),
+        })
+    }
+
+    async fn send(&mut self, message: proto::ProcessingRequest) -> anyhow::Result<()> {
+        self.outbound
+            .send(message)
+            .await
+            .map_err(|_| anyhow::anyhow!("Processor closed the stream"))
+    }
+
+    async fn receive(&mut self, timeout: Duration) -> anyhow::Result<processing_response::Response> {
+        let message = tokio::time::timeout(timeout, self.inbound.message())
+            .await
+            .map_err(|_| anyhow::anyhow!("Processor timed out"))??
+            .ok_or_else(|| anyhow::anyhow!("Processor closed the stream"))?;
+        message
+            .response
+            .ok_or_else(|| anyhow::anyhow!("Processor sent an empty response"))
+    }
+}
+
+/// What the processor said about one part of the exchange.
+enum Verdict {
+    Continue(proto::CommonResponse),
+    Respond(Response),
+}
+
+/// A configured processor, usable as both a request and a response filter.
+pub struct ExtProcFilter {
+    config: ExtProcConfig,
+    channel: Channel,
+    /// Keyed by request ID
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+impl ExtProcFilter {
+    pub fn new(config: &ExtProcConfig) -> anyhow::Result<Self> {
+        let channel = Channel::from_shared(config.url.clone())
+            .map_err(|e| anyhow::anyhow!("Invalid ext_proc URL {}: {}", config.url, e))?
+            .connect_timeout(Duration::from_millis(config.timeout_ms))
+            .connect_lazy();
+        Ok(Self {
+            config: config.clone(),
+            channel,
+            sessions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.config.timeout_ms)
+    }
+
+    /// Sends `message` on the request's session, opening it first if needed, and waits
+    /// for the processor's answer.
+    async fn exchange(
+        &self,
+        session: &mut Option<Session>,
+        message: proto::ProcessingRequest,
+    ) -> anyhow::Result<Verdict> {
+        match session {
+            Some(session) => session.send(message).await?,
+            None => *session = Some(Session::open(self.channel.clone(), message, self.timeout()).await?),
+        }
+        let response = session.as_mut().unwrap().receive(self.timeout()).await?;
+        use processing_response::Response as Answer;
+        let common = match response {
+            Answer::ImmediateResponse(immediate) => return Ok(Verdict::Respond(immediate_response(immediate))),
+            Answer::RequestHeaders(answer) | Answer::ResponseHeaders(answer) => answer.response,
+            Answer::RequestBody(answer) | Answer::ResponseBody(answer) => answer.response,
+        };
+        Ok(Verdict::Continue(common.unwrap_or_default()))
+    }
+
+    fn failed(&self, error: anyhow::Error, request_id: &str) -> Option<Response> {
+        error!("External processor {} failed: {} (request_id: {})", self.config.name, error, request_id);
+        if self.config.failure_mode_allow {
+            None
+        } else {
+            Some(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+
+    async fn process_request(&self, request_id: &str, request: &mut Request) -> anyhow::Result<Option<Response>> {
+        let mode = &self.config.processing_mode;
+        let mut session = None;
+
+        if mode.request_headers {
+            let message = headers_message(request_pairs(request), !mode.request_body, true);
+            match self.exchange(&mut session, message).await? {
+                Verdict::Respond(response) => return Ok(Some(response)),
+                Verdict::Continue(common) => {
+                    let mut pairs = request_pairs(request);
+                    apply_header_mutation(&mut pairs, common.header_mutation.as_ref());
+                    apply_request_pairs(request, &pairs);
+                }
+            }
+        }
+        if mode.request_body {
+            let body = read_body(request.body_mut(), self.config.max_body_bytes).await?;
+            let message = body_message(body.to_vec(), true);
+            match self.exchange(&mut session, message).await? {
+                Verdict::Respond(response) => return Ok(Some(response)),
+                Verdict::Continue(common) => {
+                    let mut pairs = request_pairs(request);
+                    apply_header_mutation(&mut pairs, common.header_mutation.as_ref());
+                    apply_request_pairs(request, &pairs);
+                    *request.body_mut() = mutated_body(body, common.body_mutation, request.headers_mut());
+                }
+            }
+        }
+
+        if let Some(session) = session {
+            if (mode.response_headers || mode.response_body) && !request_id.is_empty() {
+                let mut sessions = self.sessions.lock().unwrap();
+                sessions.retain(|_, session| session.started.elapsed() < SESSION_TTL);
+                sessions.insert(request_id.to_string(), session);
+            }
+        }
+        Ok(None)
+    }
+
+    async fn process_response(&self, request_id: &str, response: &mut Response) -> anyhow::Result<()> {
+        let mode = &self.config.processing_mode;
+        let mut session = self.sessions.lock().unwrap().remove(request_id);
+
+        if mode.response_headers {
+            let message = headers_message(response_pairs(response), !mode.response_body, false);
+            match self.exchange(&mut session, message).await? {
+                Verdict::Respond(replacement) => {
+                    *response = replacement;
+                    return Ok(());
+                }
+                Verdict::Continue(common) => {
+                    let mut pairs = response_pairs(response);
+                    apply_header_mutation(&mut pairs, common.header_mutation.as_ref());
+                    apply_response_pairs(response, &pairs);
+                }
+            }
+        }
+        if mode.response_body {
+            let body = read_body(response.body_mut(), self.config.max_body_bytes).await?;
+            let message = body_message(body.to_vec(), false);
+            match self.exchange(&mut session, message).await? {
+                Verdict::Respond(replacement) => *response = replacement,
+                Verdict::Continue(common) => {
+                    let mut pairs = response_pairs(response);
+                    apply_header_mutation(&mut pairs, common.header_mutation.as_ref());
+                    apply_response_pairs(response, &pairs);
+                    *response.body_mut() = mutated_body(body, common.body_mutation, response.headers_mut());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RequestFilter for ExtProcFilter {
+    fn on_request<'a>(
+        &'a self,
+        context: &'a FilterContext<'a>,
+        request: &'a mut Request,
+    ) -> FilterFuture<'a, FilterAction> {
+        Box::pin(async move {
+            let mode = &self.config.processing_mode;
+            if !mode.request_headers && !mode.request_body {
+                return FilterAction::Continue;
+            }
+            let response = match self.process_request(context.request_id, request).await {
+                Ok(response) => response,
+                Err(e) => self.failed(e, context.request_id),
+            };
+            response.map_or(FilterAction::Continue, FilterAction::Respond)
+        })
+    }
+}
+
+impl ResponseFilter for ExtProcFilter {
+    fn on_response<'a>(
+        &'a self,
+        context: &'a FilterContext<'a>,
+        response: &'a mut Response,
+    ) -> FilterFuture<'a, ()> {
+        Box::pin(async move {
+            let mode = &self.config.processing_mode;
+            if !mode.response_headers && !mode.response_body {
+                return;
+            }
+            if let Err(e) = self.process_response(context.request_id, response).await {
+                if let Some(failure) = self.failed(e, context.request_id) {
+                    *response = failure;
+                }
+            }
+        })
+    }
+}
+
+fn headers_message(pairs: HeaderPairs, end_of_stream: bool, request: bool) -> proto::ProcessingRequest {
+    let headers = proto::HttpHeaders {
+        headers: Some(proto::HeaderMap {
+            headers: pairs
+                .into_iter()
+                .map(|(key, value)| proto::HeaderValue {
+                    key,
+                    value: String::new(),
+                    raw_value: value.into_bytes(),
+                })
+                .collect(),
+        }),
+        end_of_stream,
+    };
+    proto::ProcessingRequest {
+        request: Some(if request {
+            processing_request::Request::RequestHeaders(headers)
+        } else {
+            processing_request::Request::ResponseHeaders(headers)
+        }),
+    }
+}
+
+fn body_message(body: Vec<u8>, request: bool) -> proto::ProcessingRequest {
+    let body = proto::HttpBody {
+        body,
+        end_of_stream: true,
+    };
+    proto::ProcessingRequest {
+        request: Some(if request {
+            processing_request::Request::RequestBody(body)
+        } else {
+            processing_request::Request::ResponseBody(body)
+        }),
+    }
+}
+
+async fn read_body(body: &mut Body, max_body_bytes: usize) -> anyhow::Result<Bytes> {
+    axum::body::to_bytes(std::mem::take(body), max_body_bytes)
+        .await
+        .map_err(|_| anyhow::anyhow!("Body is larger than {} bytes", max_body_bytes))
+}
+
+pub(crate) fn header_value(header: &proto::HeaderValue) -> String {
+    if header.raw_value.is_empty() {
+        header.value.clone()
+    } else {
+        String::from_utf8_lossy(&header.raw_value).into_owned()
+    }
+}
+
+/// Applies the processor's header changes the way Envoy does. Pseudo-headers can be
+/// set but not removed.
+fn apply_header_mutation(pairs: &mut HeaderPairs, mutation: Option<&proto::HeaderMutation>) {
+    let mutation = match mutation {
+        Some(mutation) => mutation,
+        None => return,
+    };
+    for name in mutation.remove_headers.iter().filter(|name| !name.starts_with(':')) {
+        pairs.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+    for option in &mutation.set_headers {
+        let header = match &option.header {
+            Some(header) => header,
+            None => continue,
+        };
+        let key = header.key.to_ascii_lowercase();
+        let value = header_value(header);
+        let exists = pairs.iter().any(|(name, _)| *name == key);
+        let (append, add) = match (option.append, option.append_action) {
+            (Some(append), _) => (append, true),
+            (None, ADD_IF_ABSENT) => (false, !exists),
+            (None, OVERWRITE_IF_EXISTS_OR_ADD) => (false, true),
+            (None, OVERWRITE_IF_EXISTS) => (false, exists),
+            (None, _) => (true, true),
+        };
+        if !add {
+            continue;
+        }
+        if !append || key.starts_with(':') {
+            pairs.retain(|(name, _)| *name != key);
+        }
+        pairs.push((key, value));
+    }
+}
+
+/// The body after the processor's mutation, if it made one.
+fn mutated_body(
+    original: Bytes,
+    mutation: Option<proto::BodyMutation>,
+    headers: &mut axum::http::HeaderMap,
+) -> Body {
+    let body = match mutation.and_then(|mutation| mutation.mutation) {
+        Some(proto::body_mutation::Mutation::Body(body)) => Bytes::from(body),
+        Some(proto::body_mutation::Mutation::ClearBody(true)) => Bytes::new(),
+        _ => return Body::from(original),
+    };
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(body.len()));
+    Body::from(body)
+}
+
+fn immediate_response(immediate: proto::ImmediateResponse) -> Response {
+    let status = immediate
+        .status
+        .and_then(|status| u16::try_from(status.code).ok())
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .unwrap_or(StatusCode::OK);
+    let mut pairs = HeaderPairs::new();
+    apply_header_mutation(&mut pairs, immediate.headers.as_ref());
+    if !immediate.details.is_empty() {
+        warn!("External processor answered {}: {}", status, immediate.details);
+    }
+    (status, header_map(&pairs), immediate.body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+
+    fn set(key: &str, value: &str, append: Option<bool>, append_action: i32) -> proto::HeaderValueOption {
+        proto::HeaderValueOption {
+            header: Some(proto::HeaderValue {
+                key: key.to_string(),
+                value: String::new(),
+                raw_value: value.as_bytes().to_vec(),
+            }),
+            append,
+            append_action,
+        }
+    }
+
+    #[test]
+    fn test_header_mutation() {
+        let mut pairs = vec![
+            (":path".to_string(), "/orders".to_string()),
+            ("x-card".to_string(), "4111".to_string()),
+            ("x-keep".to_string(), "1".to_string()),
+        ];
+        let mutation = proto::HeaderMutation {
+            set_headers: vec![
+                set(":path", "/orders/redacted", None, 0),
+                set("X-Keep", "2", None, ADD_IF_ABSENT),
+                set("x-new", "a", None, OVERWRITE_IF_EXISTS),
+                set("x-scan", "clean", Some(false), 0),
+                set("x-scan", "v2", Some(true), 0),
+            ],
+            remove_headers: vec!["x-card".to_string(), ":path".to_string()],
+        };
+        apply_header_mutation(&mut pairs, Some(&mutation));
+        assert_eq!(
+            pairs,
+            vec![
+                ("x-keep".to_string(), "1".to_string()),
+                (":path".to_string(), "/orders/redacted".to_string()),
+                ("x-scan".to_string(), "clean".to_string()),
+                ("x-scan".to_string(), "v2".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_immediate_response_round_trip() {
+        let message = proto::ProcessingResponse {
+            response: Some(processing_response::Response::ImmediateResponse(proto::ImmediateResponse {
+                status: Some(proto::HttpStatus { code: 403 }),
+                headers: Some(proto::HeaderMutation {
+                    set_headers: vec![set("content-type", "text/plain", None, 0)],
+                    remove_headers: Vec::new(),
+                }),
+                body: b"card number found".to_vec(),
+                details: "dlp".to_string(),
+            })),
+        };
+        let decoded = proto::ProcessingResponse::decode(message.encode_to_vec().as_slice()).unwrap();
+        let immediate = match decoded.response {
+            Some(processing_response::Response::ImmediateResponse(immediate)) => immediate,
+            _ => panic!("expected an immediate response"),
+        };
+
+        let response = immediate_response(immediate);
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(response.headers()["content-type"], "text/plain");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, "card number found");
+    }
+}
@@ -0,0 +1,279 @@
+use axum::{
+    http::{HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::RwLock;
+use tracing::{debug, info};
+
+use crate::config::{FaultAbort, FaultDelay, FaultInjectionConfig, RouteConfig};
+
+pub const FAULT_HEADER: &str = "x-gateway-fault";
+
+/// Runtime overrides of the routes' `fault_injection` config, keyed by route path.
+#[derive(Clone, Default)]
+pub struct FaultInjector {
+    overrides: Arc<RwLock<HashMap<String, FaultOverride>>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FaultOverride {
+    pub route: String,
+    #[serde(flatten)]
+    pub faults: FaultInjectionConfig,
+    /// Unset when the override lasts until it's cleared
+    pub expires_at: Option<u64>,
+}
+
+/// Faults left out fall back to the route's configured ones.
+#[derive(Debug, Clone, Deserialize)]
+pub struct FaultRequest {
+    pub route: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub delay: Option<FaultDelay>,
+    pub abort: Option<FaultAbort>,
+    pub duration_seconds: Option<u64>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FaultStatus {
+    pub route: String,
+    #[serde(flatten)]
+    pub faults: FaultInjectionConfig,
+    /// Whether the faults were set through the admin API rather than the config
+    pub overridden: bool,
+    pub expires_at: Option<u64>,
+}
+
+impl FaultInjector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn set(&self, route: &RouteConfig, request: FaultRequest) -> anyhow::Result<FaultOverride> {
+        let configured = route.fault_injection.clone().unwrap_or_default();
+        let faults = FaultInjectionConfig {
+            enabled: request.enabled,
+            delay: request.delay.or(configured.delay),
+            abort: request.abort.or(configured.abort),
+        };
+        validate(&faults)?;
+
+        let fault_override = FaultOverride {
+            route: request.route.clone(),
+            faults,
+            expires_at: request.duration_seconds.map(|duration| now() + duration),
+        };
+        info!(
+            "Fault injection {} for route {} through the admin API",
+            if request.enabled { "enabled" } else { "disabled" },
+            request.route
+        );
+        self.overrides.write().await.insert(request.route, fault_override.clone());
+        Ok(fault_override)
+    }
+
+    /// Removes the override, leaving the route's configured faults in effect.
+    pub async fn clear(&self, route: &str) -> Option<FaultOverride> {
+        let fault_override = self.overrides.write().await.remove(route);
+        if fault_override.is_some() {
+            info!("Fault injection override cleared for route {}", route);
+        }
+        fault_override
+    }
+
+    /// Routes with faults in effect, whether configured or overridden.
+    pub async fn list(&self, routes: &[RouteConfig]) -> Vec<FaultStatus> {
+        self.purge_expired().await;
+        let overrides = self.overrides.read().await;
+
+        let mut statuses: Vec<FaultStatus> = overrides
+            .values()
+            .filter(|fault_override| fault_override.faults.enabled)
+            .map(|fault_override| FaultStatus {
+                route: fault_override.route.clone(),
+                faults: fault_override.faults.clone(),
+                overridden: true,
+                expires_at: fault_override.expires_at,
+            })
+            .collect();
+        statuses.extend(
+            routes
+                .iter()
+                .filter(|route| !overrides.contains_key(&route.path))
+                .filter_map(|route| Some((route, route.fault_injection.as_ref().filter(|faults| faults.enabled)?)))
+                .map(|(route, faults)| FaultStatus {
+                    route: route.path.clone(),
+                    faults: faults.clone(),
+                    overridden: false,
+                    expires_at: None,
+                }),
+        );
+        statuses
+    }
+
+    /// Delays the request and returns the response to fail it with, if it's selected for
+    /// either fault.
+    pub async fn inject(&self, route: &RouteConfig, request_id: &str) -> Option<Response> {
+        let faults = self.effective(route).await?;
+
+        if let Some(delay) = faults.delay.as_ref().filter(|delay| selected(delay.percentage)) {
+            let jitter = if delay.jitter_ms > 0 {
+                rand::thread_rng().gen_range(0..=delay.jitter_ms)
+            } else {
+                0
+            };
+            debug!(
+                "Injecting {}ms delay on route {} (request_id: {})",
+                delay.delay_ms + jitter,
+                route.path,
+                request_id
+            );
+            tokio::time::sleep(Duration::from_millis(delay.delay_ms + jitter)).await;
+        }
+
+        let abort = faults.abort.as_ref().filter(|abort| selected(abort.percentage))?;
+        debug!("Injecting {} on route {} (request_id: {})", abort.status, route.path, request_id);
+        Some(abort_response(abort))
+    }
+
+    async fn effective(&self, route: &RouteConfig) -> Option<FaultInjectionConfig> {
+        let fault_override = self.overrides.read().await.get(&route.path).cloned();
+        let faults = match fault_override {
+            Some(fault_override) if fault_override.expires_at.map_or(true, |expires_at| expires_at > now()) => {
+                fault_override.faults
+            }
+            Some(_) => {
+                self.purge_expired().await;
+                route.fault_injection.clone()?
+            }
+            None => route.fault_injection.clone()?,
+        };
+        faults.enabled.then_some(faults)
+    }
+
+    async fn purge_expired(&self) {
+        let now = now();
+        self.overrides.write().await.retain(|route, fault_override| {
+            let active = fault_override.expires_at.map_or(true, |expires_at| expires_at > now);
+            if !active {
+                info!("Fault injection override expired for route {}", route);
+            }
+            active
+        });
+    }
+}
+
+fn validate(faults: &FaultInjectionConfig) -> anyhow::Result<()> {
+    let percentages = [
+        faults.delay.as_ref().map(|delay| delay.percentage),
+        faults.abort.as_ref().map(|abort| abort.percentage),
+    ];
+    if percentages.into_iter().flatten().any(|percentage| !(0.0..=100.0).contains(&percentage)) {
+        return Err(anyhow::anyhow!("percentage must be between 0 and 100"));
+    }
+    if let Some(abort) = &faults.abort {
+        if StatusCode::from_u16(abort.status).is_err() {
+            return Err(anyhow::anyhow!("Invalid abort status: {}", abort.status));
+        }
+    }
+    Ok(())
+}
+
+fn selected(percentage: f64) -> bool {
+    rand::random::<f64>() * 100.0 < percentage
+}
+
+fn abort_response(abort: &FaultAbort) -> Response {
+    let status = StatusCode::from_u16(abort.status).unwrap_or(StatusCode::SERVICE_UNAVAILABLE);
+    let mut response = (status, Json(serde_json::json!({ "error": "Injected fault" }))).into_response();
+    response.headers_mut().insert(FAULT_HEADER, HeaderValue::from_static("abort"));
+    response
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn route(fault_injection: serde_json::Value) -> RouteConfig {
+        serde_json::from_value(json!({
+            "path": "/api/orders",
+            "backend": "orders",
+            "load_balancing": "round_robin",
+            "auth_required": false,
+            "fault_injection": fault_injection
+        }))
+        .unwrap()
+    }
+
+    fn request(request: serde_json::Value) -> FaultRequest {
+        serde_json::from_value(request).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_configured_faults() {
+        let injector = FaultInjector::new();
+
+        let disabled = route(json!({ "abort": { "percentage": 100.0 } }));
+        assert!(injector.inject(&disabled, "req-1").await.is_none());
+        assert!(injector.list(std::slice::from_ref(&disabled)).await.is_empty());
+
+        let enabled = route(json!({
+            "enabled": true,
+            "delay": { "percentage": 100.0, "delay_ms": 20 },
+            "abort": { "percentage": 100.0, "status": 504 }
+        }));
+        let start = std::time::Instant::now();
+        let response = injector.inject(&enabled, "req-2").await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(response.headers()[FAULT_HEADER], "abort");
+    }
+
+    #[tokio::test]
+    async fn test_admin_overrides() {
+        let injector = FaultInjector::new();
+        let route = route(json!({ "abort": { "percentage": 100.0 } }));
+
+        // Enabling without faults uses the configured ones
+        let enabled = injector.set(&route, request(json!({ "route": "/api/orders" }))).await.unwrap();
+        assert_eq!(enabled.faults.abort.as_ref().unwrap().status, 503);
+        assert_eq!(injector.inject(&route, "req-1").await.unwrap().status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let statuses = injector.list(std::slice::from_ref(&route)).await;
+        assert_eq!(statuses.len(), 1);
+        assert!(statuses[0].overridden);
+
+        injector
+            .set(&route, request(json!({ "route": "/api/orders", "abort": { "percentage": 0.0 } })))
+            .await
+            .unwrap();
+        assert!(injector.inject(&route, "req-2").await.is_none());
+
+        assert!(injector.clear("/api/orders").await.is_some());
+        assert!(injector.inject(&route, "req-3").await.is_none());
+
+        let invalid = request(json!({ "route": "/api/orders", "delay": { "percentage": 150.0, "delay_ms": 10 } }));
+        assert!(injector.set(&route, invalid).await.is_err());
+    }
+}
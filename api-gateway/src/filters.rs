@@ -0,0 +1,276 @@
+use axum::{
+    extract::Request,
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::Response,
+};
+use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc};
+use tracing::warn;
+
+use crate::config::{Config, RouteConfig, RouteFilterConfig};
+
+pub type FilterFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Header names and values in order, including pseudo-headers.
+pub(crate) type HeaderPairs = Vec<(String, String)>;
+
+/// What a filter is told about the request it's handling.
+pub struct FilterContext<'a> {
+    pub route: &'a RouteConfig,
+    pub request_id: &'a str,
+    /// The `settings` of this filter's entry in the route's chain
+    pub settings: &'a serde_json::Value,
+}
+
+/// Set on the request by a request filter to send it to a different backend than the
+/// route's.
+#[derive(Debug, Clone)]
+pub struct BackendOverride(pub String);
+
+pub enum FilterAction {
+    Continue,
+    /// Answers the request; later filters and the backend never see it
+    Respond(Response),
+}
+
+/// Custom handling of requests on routes that list the filter. Runs after the gateway's
+/// own middleware, so the request has been authenticated and rate limited by then.
+pub trait RequestFilter: Send + Sync {
+    fn on_request<'a>(
+        &'a self,
+        context: &'a FilterContext<'a>,
+        request: &'a mut Request,
+    ) -> FilterFuture<'a, FilterAction>;
+}
+
+/// Custom handling of responses on routes that list the filter, including responses
+/// from request filters.
+pub trait ResponseFilter: Send + Sync {
+    fn on_response<'a>(
+        &'a self,
+        context: &'a FilterContext<'a>,
+        response: &'a mut Response,
+    ) -> FilterFuture<'a, ()>;
+}
+
+/// The filters routes can name in their `filters` chain.
+#[derive(Default, Clone)]
+pub struct FilterRegistry {
+    request: HashMap<String, Arc<dyn RequestFilter>>,
+    response: HashMap<String, Arc<dyn ResponseFilter>>,
+}
+
+impl FilterRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register_request(&mut self, name: impl Into<String>, filter: Arc<dyn RequestFilter>) {
+        self.request.insert(name.into(), filter);
+    }
+
+    pub fn register_response(&mut self, name: impl Into<String>, filter: Arc<dyn ResponseFilter>) {
+        self.response.insert(name.into(), filter);
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.request.contains_key(name) || self.response.contains_key(name)
+    }
+
+    /// Fails on the first route naming a filter that isn't registered.
+    pub fn validate(&self, config: &Config) -> anyhow::Result<()> {
+        for route in &config.routes {
+            for entry in &route.filters {
+                if !self.contains(&entry.name) {
+                    return Err(anyhow::anyhow!("Route {} uses unknown filter {}", route.path, entry.name));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the route's request filters until one answers the request.
+    pub async fn run_request(&self, route: &RouteConfig, request_id: &str, request: &mut Request) -> Option<Response> {
+        for entry in chain(route) {
+            let filter = match self.request.get(&entry.name) {
+                Some(filter) => filter,
+                None => continue,
+            };
+            let context = FilterContext { route, request_id, settings: &entry.settings };
+            if let FilterAction::Respond(response) = filter.on_request(&context, request).await {
+                return Some(response);
+            }
+        }
+        None
+    }
+
+    pub async fn run_response(&self, route: &RouteConfig, request_id: &str, response: &mut Response) {
+        for entry in chain(route).into_iter().rev() {
+            if let Some(filter) = self.response.get(&entry.name) {
+                let context = FilterContext { route, request_id, settings: &entry.settings };
+                filter.on_response(&context, response).await;
+            }
+        }
+    }
+}
+
+fn chain(route: &RouteConfig) -> Vec<&RouteFilterConfig> {
+    let mut chain: Vec<&RouteFilterConfig> = route.filters.iter().collect();
+    chain.sort_by_key(|entry| entry.order);
+    chain
+}
+
+fn header_pairs(headers: &HeaderMap) -> HeaderPairs {
+    headers
+        .iter()
+        .map(|(name, value)| (name.to_string(), String::from_utf8_lossy(value.as_bytes()).into_owned()))
+        .collect()
+}
+
+pub(crate) fn header_map(pairs: &[(String, String)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in pairs.iter().filter(|(name, _)| !name.starts_with(':')) {
+        match (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+            (Ok(name), Ok(value)) => {
+                headers.append(name, value);
+            }
+            _ => warn!("Ignoring invalid header {} set by a filter", name),
+        }
+    }
+    headers
+}
+
+fn pseudo_header<'a>(pairs: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    pairs.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+}
+
+/// The request's headers as proxies like Envoy hand them to extensions, led by the
+/// `:method`, `:path`, `:authority` and `:scheme` pseudo-headers.
+pub(crate) fn request_pairs(request: &Request) -> HeaderPairs {
+    let authority = request
+        .headers()
+        .get(axum::http::header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| request.uri().authority().map(|authority| authority.as_str()))
+        .unwrap_or_default();
+    let mut pairs = vec![
+        (":method".to_string(), request.method().to_string()),
+        (":path".to_string(), request.uri().path_and_query().map_or("/", |pq| pq.as_str()).to_string()),
+        (":authority".to_string(), authority.to_string()),
+        (":scheme".to_string(), request.uri().scheme_str().unwrap_or("http").to_string()),
+    ];
+    pairs.extend(header_pairs(request.headers()));
+    pairs
+}
+
+/// Replaces the request's headers, and its method, path or host where the pseudo-headers
+/// changed.
+pub(crate) fn apply_request_pairs(request: &mut Request, pairs: &[(String, String)]) {
+    let mut headers = header_map(pairs);
+    if let Some(authority) = pseudo_header(pairs, ":authority").filter(|authority| !authority.is_empty()) {
+        if let Ok(host) = HeaderValue::from_str(authority) {
+            headers.insert(axum::http::header::HOST, host);
+        }
+    }
+    *request.headers_mut() = headers;
+
+    if let Some(method) = pseudo_header(pairs, ":method") {
+        match method.parse() {
+            Ok(method) => *request.method_mut() = method,
+            Err(_) => warn!("Ignoring invalid :method {} set by a filter", method),
+        }
+    }
+    if let Some(path) = pseudo_header(pairs, ":path") {
+        if request.uri().path_and_query().map(|pq| pq.as_str()) != Some(path) {
+            match path.parse() {
+                Ok(uri) => *request.uri_mut() = uri,
+                Err(_) => warn!("Ignoring invalid :path {} set by a filter", path),
+            }
+        }
+    }
+}
+
+/// The response's headers, led by `:status`.
+pub(crate) fn response_pairs(response: &Response) -> HeaderPairs {
+    let mut pairs = vec![(":status".to_string(), response.status().as_u16().to_string())];
+    pairs.extend(header_pairs(response.headers()));
+    pairs
+}
+
+pub(crate) fn apply_response_pairs(response: &mut Response, pairs: &[(String, String)]) {
+    *response.headers_mut() = header_map(pairs);
+    if let Some(status) = pseudo_header(pairs, ":status") {
+        match status.parse::<u16>().ok().and_then(|status| StatusCode::from_u16(status).ok()) {
+            Some(status) => *response.status_mut() = status,
+            None => warn!("Ignoring invalid :status {} set by a filter", status),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::StatusCode, response::IntoResponse};
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    /// Records the order it's called in, and rejects requests when `reject` is set.
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl RequestFilter for Recorder {
+        fn on_request<'a>(
+            &'a self,
+            context: &'a FilterContext<'a>,
+            _request: &'a mut Request,
+        ) -> FilterFuture<'a, FilterAction> {
+            Box::pin(async move {
+                self.0.lock().unwrap().push(format!("request {}", context.settings["id"]));
+                if context.settings["reject"] == json!(true) {
+                    return FilterAction::Respond(StatusCode::FORBIDDEN.into_response());
+                }
+                FilterAction::Continue
+            })
+        }
+    }
+
+    impl ResponseFilter for Recorder {
+        fn on_response<'a>(
+            &'a self,
+            context: &'a FilterContext<'a>,
+            _response: &'a mut Response,
+        ) -> FilterFuture<'a, ()> {
+            Box::pin(async move {
+                self.0.lock().unwrap().push(format!("response {}", context.settings["id"]));
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chain_order() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let recorder = Arc::new(Recorder(calls.clone()));
+        let mut registry = FilterRegistry::new();
+        registry.register_request("recorder", recorder.clone());
+        registry.register_response("recorder", recorder);
+
+        let mut route = Config::default_config().routes[0].clone();
+        route.filters = serde_json::from_value(json!([
+            { "name": "recorder", "order": 20, "settings": { "id": 2 } },
+            { "name": "recorder", "order": 10, "settings": { "id": 1 } },
+        ]))
+        .unwrap();
+
+        let mut request = Request::new(Body::empty());
+        assert!(registry.run_request(&route, "req-1", &mut request).await.is_none());
+        let mut response = Response::new(Body::empty());
+        registry.run_response(&route, "req-1", &mut response).await;
+        assert_eq!(*calls.lock().unwrap(), vec!["request 1", "request 2", "response 2", "response 1"]);
+
+        route.filters[1].settings["reject"] = json!(true);
+        let rejected = registry.run_request(&route, "req-1", &mut request).await.unwrap();
+        assert_eq!(rejected.status(), StatusCode::FORBIDDEN);
+
+        let mut config = Config::default_config();
+        config.routes[0].filters = serde_json::from_value(json!([{ "name": "missing" }])).unwrap();
+        assert!(registry.validate(&config).is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue};
+use std::net::IpAddr;
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+
+/// The client addresses a request passed through, closest to the client first, from
+/// `X-Forwarded-For` or else the `for` parameters of `Forwarded`. Entries that aren't
+/// IPs, like `unknown` or obfuscated identifiers, are kept so callers can stop at them.
+pub fn chain(headers: &HeaderMap) -> Vec<String> {
+    let x_forwarded_for: Vec<String> = headers
+        .get_all(&X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|hop| hop.trim().to_string())
+        .filter(|hop| !hop.is_empty())
+        .collect();
+    if !x_forwarded_for.is_empty() {
+        return x_forwarded_for;
+    }
+
+    headers
+        .get_all(header::FORWARDED)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.trim().split_once('=')?;
+                name.eq_ignore_ascii_case("for").then(|| node_address(value))
+            })
+        })
+        .collect()
+}
+
+/// Strips quotes, IPv6 brackets and the port from a `Forwarded` node.
+fn node_address(node: &str) -> String {
+    let node = node.trim().trim_matches('"');
+    if let Some(bracketed) = node.strip_prefix('[') {
+        return bracketed.split(']').next().unwrap_or_default().to_string();
+    }
+    match node.split_once(':') {
+        Some((address, port)) if !port.contains(':') => address.to_string(),
+        _ => node.to_string(),
+    }
+}
+
+/// Sets the forwarding headers for the backend. A trusted peer's headers are extended
+/// with its own address; anyone else's are replaced, since they could claim any client
+/// address or scheme.
+pub fn apply(headers: &mut HeaderMap, peer: Option<IpAddr>, peer_trusted: bool, proto: &str) {
+    let trusted = peer_trusted && peer.is_some();
+    if !trusted {
+        headers.remove(&X_FORWARDED_FOR);
+        headers.remove(&X_FORWARDED_PROTO);
+        headers.remove(&X_FORWARDED_HOST);
+        headers.remove(header::FORWARDED);
+    }
+    let peer = match peer {
+        Some(peer) => peer,
+        None => return,
+    };
+
+    let mut x_forwarded_for: Vec<String> = headers
+        .get_all(&X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .map(str::to_string)
+        .collect();
+    x_forwarded_for.push(peer.to_string());
+    if let Ok(value) = HeaderValue::from_str(&x_forwarded_for.join(", ")) {
+        headers.insert(&X_FORWARDED_FOR, value);
+    }
+
+    if !headers.contains_key(&X_FORWARDED_PROTO) {
+        headers.insert(&X_FORWARDED_PROTO, HeaderValue::from_static(if proto == "https" { "https" } else { "http" }));
+    }
+
+    let node = match peer {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("\"[{}]\"", ip),
+    };
+    if let Ok(value) = HeaderValue::from_str(&format!("for={};proto={}", node, proto)) {
+        headers.append(header::FORWARDED, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn test_chain() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::FORWARDED,
+            HeaderValue::from_static(r#"for=192.0.2.60:8080;proto=https, For="[2001:db8::17]:4711", for=_hidden"#),
+        );
+        assert_eq!(chain(&headers), vec!["192.0.2.60", "2001:db8::17", "_hidden"]);
+
+        headers.append(&X_FORWARDED_FOR, HeaderValue::from_static("203.0.113.1, 10.0.0.2"));
+        headers.append(&X_FORWARDED_FOR, HeaderValue::from_static("10.0.0.3"));
+        assert_eq!(chain(&headers), vec!["203.0.113.1", "10.0.0.2", "10.0.0.3"]);
+    }
+
+    #[test]
+    fn test_apply() {
+        let spoofed = || {
+            let mut headers = HeaderMap::new();
+            headers.insert(&X_FORWARDED_FOR, HeaderValue::from_static("1.2.3.4"));
+            headers.insert(&X_FORWARDED_PROTO, HeaderValue::from_static("https"));
+            headers.insert(header::FORWARDED, HeaderValue::from_static("for=1.2.3.4"));
+            headers
+        };
+
+        let mut headers = spoofed();
+        apply(&mut headers, ip("198.51.100.7"), false, "http");
+        assert_eq!(headers[&X_FORWARDED_FOR], "198.51.100.7");
+        assert_eq!(headers[&X_FORWARDED_PROTO], "http");
+        assert_eq!(headers.get_all(header::FORWARDED).iter().collect::<Vec<_>>(), vec!["for=198.51.100.7;proto=http"]);
+
+        let mut headers = spoofed();
+        apply(&mut headers, ip("10.0.0.2"), true, "http");
+        assert_eq!(headers[&X_FORWARDED_FOR], "1.2.3.4, 10.0.0.2");
+        assert_eq!(headers[&X_FORWARDED_PROTO], "https");
+        assert_eq!(
+            headers.get_all(header::FORWARDED).iter().collect::<Vec<_>>(),
+            vec!["for=1.2.3.4", "for=10.0.0.2;proto=http"]
+        );
+
+        let mut headers = spoofed();
+        apply(&mut headers, ip("::1"), false, "https");
+        assert_eq!(headers[header::FORWARDED], r#"for="[::1]";proto=https"#);
+    }
+}
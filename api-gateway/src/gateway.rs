@@ -0,0 +1,804 @@
+use axum::{
+    extract::Request,
+    http::Method,
+    middleware,
+    response::IntoResponse,
+    routing::{any, get, patch, post, Route},
+    Router,
+};
+use std::{collections::HashMap, convert::Infallible, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::sync::watch;
+use tower::{Layer, Service, ServiceBuilder};
+use tower_http::{
+    cors::{Any, CorsLayer},
+    trace::TraceLayer,
+    compression::CompressionLayer,
+};
+use tracing::{info, warn, error};
+
+use crate::access_log::AccessLogger;
+use crate::admin_auth::{self, AdminAuth};
+use crate::api_keys::ApiKeyStore;
+use crate::basic_auth::BasicAuthenticator;
+use crate::bot_detection::BotDetector;
+use crate::client_key::ClientKeyExtractor;
+use crate::concurrency_limiter::ConcurrencyLimiter;
+use crate::config::{BackendConfig, Config, ListenerConfig, RouteConfig, ServerConfig, DEFAULT_LISTENER};
+use crate::credentials::CredentialStore;
+use crate::csrf::CsrfProtection;
+use crate::debug_capture::DebugCapture;
+use crate::ext_authz::ExtAuthzClient;
+use crate::ext_proc::ExtProcFilter;
+use crate::fault_injection::FaultInjector;
+use crate::filters::{FilterRegistry, RequestFilter, ResponseFilter};
+use crate::geoip::GeoIp;
+use crate::handlers::*;
+use crate::health::HealthChecker;
+use crate::ip_filter::IpFilter;
+use crate::jwks::JwtVerifier;
+use crate::log_sampler::LogSampler;
+use crate::maintenance::MaintenanceMode;
+use crate::metrics::{MetricsCollector, OPEN_CONNECTIONS};
+use crate::middleware::{
+    admin_auth_middleware, auth_middleware, bot_detection_middleware, concurrency_limit_middleware, ext_authz_middleware,
+    filter_middleware, logging_middleware, geoip_middleware, ip_filter_middleware, policy_middleware,
+    rate_limit_middleware, security_headers_middleware, spike_arrest_middleware, waf_middleware,
+};
+use crate::oidc::{self, OidcClient};
+use crate::opa::PolicyEngine;
+use crate::proxy::ProxyService;
+use crate::rate_limiter::RateLimiter;
+use crate::request_records::RequestRecords;
+use crate::request_signing::RequestVerifier;
+use crate::request_tail::RequestTail;
+use crate::scripting::{self, ScriptFilter};
+use crate::sessions::{self, SessionStore};
+use crate::sockets::{notify_ready, notify_stopping, shutdown_signal, SocketBinder};
+use crate::statsd::StatsdExporter;
+use crate::tcp_proxy::TcpProxy;
+use crate::token_revocation::TokenDenyList;
+use crate::traffic_sampler::TrafficSampler;
+use crate::udp_proxy::UdpProxy;
+use crate::upstream_resolver::DnsCache;
+use crate::waf::Waf;
+use crate::wasm::{self, WasmPlugin};
+use crate::{batch, server, AppState};
+
+type RouterHook = Box<dyn Fn(Router) -> Router + Send>;
+
+/// A gateway with its services set up and its routers built, ready to serve.
+pub struct Gateway {
+    state: AppState,
+    app: Router,
+    /// Set when the management endpoints have a listener of their own
+    management_app: Option<Router>,
+    listeners: Vec<(ListenerConfig, Router)>,
+    tcp_proxies: Vec<Arc<TcpProxy>>,
+    udp_proxies: Vec<Arc<UdpProxy>>,
+}
+
+/// Assembles a [`Gateway`]. Routes and backends are added to the configuration given
+/// to `config`, so set that first.
+pub struct GatewayBuilder {
+    config: Config,
+    filters: FilterRegistry,
+    hooks: Vec<RouterHook>,
+}
+
+impl Gateway {
+    /// Starts from the built-in defaults, without their example routes and backends.
+    pub fn builder() -> GatewayBuilder {
+        let mut config = Config::default_config();
+        config.routes.clear();
+        config.backends.clear();
+        GatewayBuilder {
+            config,
+            filters: FilterRegistry::new(),
+            hooks: Vec::new(),
+        }
+    }
+
+    pub fn state(&self) -> &AppState {
+        &self.state
+    }
+
+    /// The data plane, with the management endpoints unless they have their own
+    /// listener. Requests can be sent straight to it, e.g. with `tower::ServiceExt::oneshot`.
+    pub fn router(&self) -> Router {
+        self.app.clone()
+    }
+
+    pub fn management_router(&self) -> Option<Router> {
+        self.management_app.clone()
+    }
+
+    /// The data plane of a named listener from `server.listeners`.
+    pub fn listener_router(&self, name: &str) -> Option<Router> {
+        self.listeners
+            .iter()
+            .find(|(listener, _)| listener.name == name)
+            .map(|(_, app)| app.clone())
+    }
+
+    /// Starts health checks and the other background tasks, then serves until the
+    /// listener fails or SIGTERM arrives. On SIGTERM the listeners stop accepting and
+    /// open connections get `shutdown_grace_seconds` to finish.
+    pub async fn serve(self) -> anyhow::Result<()> {
+        let Gateway { state, app, management_app, listeners, tcp_proxies, udp_proxies } = self;
+        let config = state.config.clone();
+        let sockets = SocketBinder::from_env(config.server.reuse_port);
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+        // Start health checking background task
+        let health_checker_clone = state.health_checker.clone();
+        tokio::spawn(async move {
+            health_checker_clone.start_health_checks().await;
+        });
+
+        // Watch upstream credential files for rotation
+        let credentials_clone = state.credentials.clone();
+        tokio::spawn(async move {
+            credentials_clone.watch().await;
+        });
+
+        // Drop cached API keys revoked through other instances
+        let api_keys_clone = state.api_keys.clone();
+        tokio::spawn(async move {
+            api_keys_clone.watch_invalidations().await;
+        });
+
+        // Forget spike arrest state for clients that have gone quiet
+        let rate_limiter_clone = state.rate_limiter.clone();
+        tokio::spawn(async move {
+            rate_limiter_clone.prune_spike_arresters().await;
+        });
+
+        // Keep JWKS signing keys fresh
+        let jwt_verifier_clone = state.jwt_verifier.clone();
+        tokio::spawn(async move {
+            jwt_verifier_clone.watch().await;
+        });
+        for tenant_verifier in state.tenant_verifiers.values().cloned() {
+            tokio::spawn(async move {
+                tenant_verifier.watch().await;
+            });
+        }
+
+        if let (Some(management_app), Some(management)) = (management_app, &config.server.management) {
+            let management_listener = sockets.bind_tcp((management.host.as_str(), management.port))?;
+            info!("Management endpoints listening on {}:{}", management.host, management.port);
+
+            // Plain HTTP, TLS and PROXY protocol apply to the data-plane listener only
+            let management_server = ServerConfig {
+                tls: None,
+                proxy_protocol: None,
+                ..config.server.clone()
+            };
+            let shutdown = shutdown_rx.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server::serve(management_listener, management_app, &management_server, shutdown).await {
+                    error!("Management listener failed: {}", e);
+                }
+            });
+        }
+
+        for (listener, listener_app) in listeners {
+            let tcp_listener = sockets.bind_tcp((listener.host.as_str(), listener.port))?;
+            info!("Listener {} listening on {}:{}", listener.name, listener.host, listener.port);
+
+            let listener_server = ServerConfig {
+                host: listener.host.clone(),
+                port: listener.port,
+                tls: listener.tls.clone(),
+                proxy_protocol: listener.proxy_protocol.clone(),
+                management: None,
+                listeners: Vec::new(),
+                ..config.server.clone()
+            };
+            let shutdown = shutdown_rx.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server::serve(tcp_listener, listener_app, &listener_server, shutdown).await {
+                    error!("Listener {} failed: {}", listener.name, e);
+                }
+            });
+        }
+
+        for tcp_proxy in tcp_proxies {
+            let listener = sockets.bind_tcp(tcp_proxy.listen_addr())?;
+            tokio::spawn(tcp_proxy.serve(listener));
+        }
+        for udp_proxy in udp_proxies {
+            let socket = sockets.bind_udp(udp_proxy.listen_addr())?;
+            tokio::spawn(udp_proxy.serve(socket));
+        }
+
+        let addr = SocketAddr::from(([0, 0, 0, 0], config.server.port));
+        info!("API Gateway listening on {}", addr);
+
+        let listener = sockets.bind_tcp(addr)?;
+        let serving = server::serve(listener, app, &config.server, shutdown_rx);
+        tokio::pin!(serving);
+        notify_ready();
+        tokio::select! {
+            result = &mut serving => return result,
+            _ = shutdown_signal() => {}
+        }
+
+        info!(
+            "Shutting down, giving open connections up to {}s to finish",
+            config.server.shutdown_grace_seconds
+        );
+        notify_stopping();
+        let _ = shutdown_tx.send(true);
+        serving.await?;
+
+        let grace = Duration::from_secs(config.server.shutdown_grace_seconds);
+        let drained = tokio::time::timeout(grace, async {
+            while OPEN_CONNECTIONS.get() > 0 {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await;
+        if drained.is_err() {
+            warn!("Closing {} connections still open after the grace period", OPEN_CONNECTIONS.get());
+        }
+        Ok(())
+    }
+}
+
+impl GatewayBuilder {
+    /// Replaces the configuration, including any routes and backends added so far.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Adds a route after those already configured, so earlier routes take precedence.
+    pub fn route(mut self, route: RouteConfig) -> Self {
+        self.config.routes.push(route);
+        self
+    }
+
+    pub fn backend(mut self, name: impl Into<String>, backend: BackendConfig) -> Self {
+        self.config.backends.insert(name.into(), backend);
+        self
+    }
+
+    /// Makes `filter` available to routes' `filters` chains under `name`.
+    pub fn request_filter(mut self, name: impl Into<String>, filter: Arc<dyn RequestFilter>) -> Self {
+        self.filters.register_request(name, filter);
+        self
+    }
+
+    pub fn response_filter(mut self, name: impl Into<String>, filter: Arc<dyn ResponseFilter>) -> Self {
+        self.filters.register_response(name, filter);
+        self
+    }
+
+    /// Wraps the data plane of every listener in `layer`, outside the gateway's own
+    /// middleware. Layers added later run first.
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<Route> + Clone + Send + 'static,
+        L::Service: Service<Request> + Clone + Send + 'static,
+        <L::Service as Service<Request>>::Response: IntoResponse + 'static,
+        <L::Service as Service<Request>>::Error: Into<Infallible> + 'static,
+        <L::Service as Service<Request>>::Future: Send + 'static,
+    {
+        self.hooks.push(Box::new(move |router: Router| router.layer(layer.clone())));
+        self
+    }
+
+    pub async fn build(mut self) -> anyhow::Result<Gateway> {
+        if !self.config.wasm_plugins.is_empty() {
+            let engine = wasm::engine()?;
+            for plugin_config in &self.config.wasm_plugins {
+                let plugin = Arc::new(WasmPlugin::load(&engine, plugin_config)?);
+                self.filters.register_request(plugin_config.name.clone(), plugin.clone());
+                self.filters.register_response(plugin_config.name.clone(), plugin);
+            }
+        }
+        for ext_proc_config in &self.config.ext_proc {
+            let processor = Arc::new(ExtProcFilter::new(ext_proc_config)?);
+            self.filters.register_request(ext_proc_config.name.clone(), processor.clone());
+            self.filters.register_response(ext_proc_config.name.clone(), processor);
+        }
+        if !self.filters.contains(scripting::FILTER_NAME) {
+            let scripts = Arc::new(ScriptFilter::new(&self.config)?);
+            self.filters.register_request(scripting::FILTER_NAME, scripts.clone());
+            self.filters.register_response(scripting::FILTER_NAME, scripts);
+        }
+        self.filters.validate(&self.config)?;
+        self.config.validate_listeners()?;
+        let config = Arc::new(self.config);
+
+        // Initialize services
+        let traffic_sampler = Arc::new(TrafficSampler::new(&config)?);
+        let fault_injector = Arc::new(FaultInjector::new());
+        let maintenance = Arc::new(MaintenanceMode::new(&config.maintenance)?);
+        let debug_capture = Arc::new(DebugCapture::new(&config));
+        let dns_cache = config.upstream_dns.as_ref().map(DnsCache::new);
+        let credentials = Arc::new(CredentialStore::new(&config, dns_cache.clone())?);
+        let proxy_service = Arc::new(
+            ProxyService::new(
+                config.clone(),
+                traffic_sampler.clone(),
+                debug_capture.clone(),
+                credentials.clone(),
+                fault_injector.clone(),
+                maintenance.clone(),
+                dns_cache,
+            )
+            .await?
+        );
+        let rate_limiter = Arc::new(RateLimiter::new(config.clone()).await?);
+        let concurrency_limiter = Arc::new(ConcurrencyLimiter::new());
+        let jwt_verifier = Arc::new(JwtVerifier::new(&config.auth)?);
+        let tenant_verifiers = config
+            .auth
+            .tenants
+            .iter()
+            .map(|tenant| Ok((tenant.name.clone(), Arc::new(JwtVerifier::new(&tenant.auth_config(&config.auth))?))))
+            .collect::<anyhow::Result<HashMap<_, _>>>()?;
+        let client_keys = Arc::new(ClientKeyExtractor::new(&config, jwt_verifier.clone())?);
+        let ip_filter = Arc::new(IpFilter::new(&config)?);
+        let geoip = GeoIp::load(&config)?.map(Arc::new);
+        let waf = match &config.waf {
+            Some(waf_config) => Some(Arc::new(Waf::new(waf_config)?)),
+            None => None,
+        };
+        let bot_detector = BotDetector::load(&config)?.map(Arc::new);
+        let api_keys = Arc::new(ApiKeyStore::new(&config.database, &config.auth.api_key_cache, &config.redis.url)?);
+        if let Err(e) = api_keys.ensure_schema().await {
+            warn!("Could not prepare API key table, key lookups will fail until Postgres is reachable: {}", e);
+        }
+        let request_verifier = match &config.auth.request_signing {
+            Some(signing_config) => Some(Arc::new(RequestVerifier::new(signing_config, &config.redis.url)?)),
+            None => None,
+        };
+        let basic_auth = config.auth.basic_auth.as_ref().map(|basic| Arc::new(BasicAuthenticator::new(basic)));
+        let admin_auth = Arc::new(AdminAuth::new(&config.auth.admin.clone().unwrap_or_default()));
+        let ext_authz = match &config.auth.ext_authz {
+            Some(ext_authz_config) => Some(Arc::new(ExtAuthzClient::new(ext_authz_config)?)),
+            None => None,
+        };
+        let policy = match &config.auth.opa {
+            Some(opa_config) => Some(Arc::new(PolicyEngine::new(opa_config)?)),
+            None => None,
+        };
+        let revoked_tokens = match &config.auth.token_revocation {
+            Some(revocation_config) => Some(Arc::new(TokenDenyList::new(revocation_config, &config.redis.url)?)),
+            None => None,
+        };
+        let sessions = match &config.auth.sessions {
+            Some(session_config) => Some(Arc::new(SessionStore::new(session_config, &config.redis.url)?)),
+            None => None,
+        };
+        let csrf = config.auth.csrf.as_ref().map(|csrf| Arc::new(CsrfProtection::new(csrf)));
+        let oidc = match &config.auth.oidc {
+            Some(oidc_config) => Some(Arc::new(OidcClient::new(oidc_config, &config.redis.url, jwt_verifier.clone())?)),
+            None => None,
+        };
+        let health_checker = Arc::new(HealthChecker::new(config.clone()));
+        let tcp_proxies = config
+            .tcp_proxies
+            .iter()
+            .map(|tcp_proxy| Ok(Arc::new(TcpProxy::new(tcp_proxy, &config, health_checker.clone())?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let udp_proxies = config
+            .udp_proxies
+            .iter()
+            .map(|udp_proxy| Ok(Arc::new(UdpProxy::new(udp_proxy, &config, health_checker.clone())?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let statsd = match &config.statsd {
+            Some(statsd_config) => {
+                let exporter = Arc::new(StatsdExporter::new(statsd_config)?);
+                tokio::spawn(exporter.clone().run());
+                Some(exporter)
+            }
+            None => None,
+        };
+        let metrics = Arc::new(MetricsCollector::new(statsd));
+        let access_log = match config.logging.as_ref().filter(|logging| !logging.access_log.is_empty()) {
+            Some(logging_config) => Some(Arc::new(AccessLogger::new(logging_config).await?)),
+            None => None,
+        };
+        let log_sampler = Arc::new(LogSampler::new(config.logging.as_ref()));
+
+        // Create application state
+        let state = AppState {
+            config: config.clone(),
+            proxy_service,
+            traffic_sampler,
+            fault_injector,
+            maintenance,
+            ip_filter,
+            geoip,
+            waf,
+            bot_detector,
+            debug_capture,
+            credentials,
+            rate_limiter,
+            concurrency_limiter,
+            client_keys,
+            jwt_verifier,
+            tenant_verifiers,
+            api_keys,
+            request_verifier,
+            basic_auth,
+            admin_auth,
+            ext_authz,
+            policy,
+            filters: Arc::new(self.filters),
+            revoked_tokens,
+            sessions,
+            csrf,
+            oidc,
+            health_checker,
+            metrics,
+            access_log,
+            request_tail: Arc::new(RequestTail::new()),
+            request_records: Arc::new(RequestRecords::new(&config.request_records)),
+            log_sampler,
+        };
+
+        // Health, metrics and admin endpoints: the management plane
+        let management_routes = Router::new()
+            .route("/health", get(health_endpoint))
+            .route("/metrics", get(prometheus_metrics_endpoint))
+            .route("/metrics/summary", get(metrics_endpoint))
+            .route("/admin/config", get(config_endpoint))
+            .route("/admin/routes", get(routes_endpoint))
+            .route("/admin/routes/match", post(match_route))
+            .route(admin_auth::DASHBOARD_PATH, get(dashboard))
+            .route("/admin/config/canary", get(config_canary_status).post(start_config_canary).delete(revert_config_canary))
+            .route("/admin/config/canary/promote", post(promote_config_canary))
+            .route("/admin/rate-limits/exemptions", get(get_rate_limit_exemptions).put(update_rate_limit_exemptions))
+            .route("/admin/rate-limits/:client_id", get(get_rate_limit_status).delete(reset_rate_limit))
+            .route("/admin/api-keys", get(list_api_keys).post(create_api_key))
+            .route("/admin/api-keys/:key_id", patch(update_api_key).delete(revoke_api_key))
+            .route("/admin/tokens/revoke", post(revoke_token))
+            .route("/admin/events", get(lifecycle_events))
+            .route("/admin/tail", get(tail_requests))
+            .route("/admin/credentials", get(credentials_status))
+            .route("/admin/credentials/:backend/reload", post(reload_credentials))
+            .route("/admin/sampling", get(list_sampling_sessions).post(start_sampling_session).delete(stop_sampling_session))
+            .route("/admin/faults", get(list_faults).put(set_faults).delete(clear_faults))
+            .route("/admin/maintenance", get(list_maintenance).post(start_maintenance).delete(stop_maintenance))
+            .route("/admin/ip-filter", get(get_ip_filter).put(update_ip_filter))
+            .route("/admin/debug/requests", get(captured_requests))
+            .route("/admin/requests/:request_id", get(request_record));
+
+        // With named listeners, the default one only serves the routes left to it
+        let default_state = match config.server.listeners.is_empty() {
+            true => state.clone(),
+            false => AppState {
+                config: Arc::new(config.for_listener(DEFAULT_LISTENER, None)),
+                ..state.clone()
+            },
+        };
+
+        // With a management listener, the data-plane port never serves the management plane
+        let (app, management_app) = match &config.server.management {
+            Some(_) => (data_plane_routes(), Some(management_routes)),
+            None => (data_plane_routes().merge(management_routes), None),
+        };
+
+        let management_app = management_app.map(|management_app| {
+            management_app
+                .layer(
+                    ServiceBuilder::new()
+                        .layer(TraceLayer::new_for_http())
+                        .layer(CompressionLayer::new())
+                        .layer(middleware::from_fn_with_state(state.clone(), security_headers_middleware))
+                        .layer(middleware::from_fn_with_state(state.clone(), logging_middleware))
+                        .layer(middleware::from_fn_with_state(state.clone(), ip_filter_middleware))
+                        .layer(middleware::from_fn_with_state(state.clone(), admin_auth_middleware))
+                        .layer(middleware::from_fn_with_state(state.clone(), auth_middleware))
+                )
+                .with_state(state.clone())
+        });
+
+        let app = with_data_plane_middleware(app, &default_state);
+        let app = self.hooks.iter().fold(app, |app, hook| hook(app));
+        let app = with_batching(app, &config);
+
+        // Named listeners share the services above but match against their own routes
+        let listeners = config
+            .server
+            .listeners
+            .iter()
+            .map(|listener| {
+                let listener_state = AppState {
+                    config: Arc::new(config.for_listener(&listener.name, listener.auth_required)),
+                    proxy_service: Arc::new(state.proxy_service.for_listener(&listener.name)),
+                    ..state.clone()
+                };
+                let app = with_data_plane_middleware(data_plane_routes(), &listener_state);
+                let app = self.hooks.iter().fold(app, |app, hook| hook(app));
+                (listener.clone(), with_batching(app, &config))
+            })
+            .collect();
+
+        Ok(Gateway { state, app, management_app, listeners, tcp_proxies, udp_proxies })
+    }
+}
+
+fn data_plane_routes() -> Router<AppState> {
+    Router::new()
+        .route(oidc::CALLBACK_PATH, get(oidc_callback))
+        .route(sessions::SESSION_PATH, get(current_session).post(create_session).delete(destroy_session))
+        .route(oidc::LOGOUT_PATH, get(oidc_logout))
+
+        // Proxy all other requests
+        .route("/*path", any(proxy_handler))
+        .fallback(proxy_handler)
+}
+
+fn with_data_plane_middleware(app: Router<AppState>, state: &AppState) -> Router {
+    app.layer(
+        ServiceBuilder::new()
+            .layer(TraceLayer::new_for_http())
+            .layer(CompressionLayer::new())
+            .layer(CorsLayer::new()
+                .allow_origin(Any)
+                .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+                .allow_headers(Any))
+            .layer(middleware::from_fn_with_state(state.clone(), security_headers_middleware))
+            .layer(middleware::from_fn_with_state(state.clone(), logging_middleware))
+            .layer(middleware::from_fn_with_state(state.clone(), ip_filter_middleware))
+            .layer(middleware::from_fn_with_state(state.clone(), geoip_middleware))
+            .layer(middleware::from_fn_with_state(state.clone(), waf_middleware))
+            .layer(middleware::from_fn_with_state(state.clone(), bot_detection_middleware))
+            .layer(middleware::from_fn_with_state(state.clone(), rate_limit_middleware))
+            .layer(middleware::from_fn_with_state(state.clone(), spike_arrest_middleware))
+            .layer(middleware::from_fn_with_state(state.clone(), concurrency_limit_middleware))
+            .layer(middleware::from_fn_with_state(state.clone(), admin_auth_middleware))
+            .layer(middleware::from_fn_with_state(state.clone(), auth_middleware))
+            .layer(middleware::from_fn_with_state(state.clone(), ext_authz_middleware))
+            .layer(middleware::from_fn_with_state(state.clone(), policy_middleware))
+            .layer(middleware::from_fn_with_state(state.clone(), filter_middleware))
+    )
+    .with_state(state.clone())
+}
+
+/// Batched sub-requests are dispatched through the router they're mounted on.
+fn with_batching(app: Router, config: &Config) -> Router {
+    match &config.batch {
+        Some(batch_config) => batch::mount(app, batch_config.clone()),
+        None => app,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_builder_serves_routes_in_process() {
+        let mut config = Gateway::builder().config;
+        config.auth.enabled = false;
+        config.rate_limiting.enabled = false;
+        let route: RouteConfig = serde_json::from_value(json!({
+            "path": "/hello",
+            "backend": "none",
+            "load_balancing": "round_robin",
+            "auth_required": false,
+            "mock": { "body": "hi" }
+        }))
+        .unwrap();
+        let gateway = Gateway::builder().config(config).route(route).build().await.unwrap();
+
+        let request = Request::builder().uri("/hello").body(Body::empty()).unwrap();
+        let response = gateway.router().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"hi");
+
+        let request = Request::builder().uri("/health").body(Body::empty()).unwrap();
+        let response = gateway.router().oneshot(request).await.unwrap();
+        assert!(response.status().is_success());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let health: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(health["data"]["rate_limiter"]["status"].is_string());
+        assert!(health["data"].get("backends").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_config_canary_routes_until_reverted_and_after_promotion() {
+        let mut config = Gateway::builder().config;
+        config.auth.enabled = false;
+        config.rate_limiting.enabled = false;
+        let route: RouteConfig = serde_json::from_value(json!({
+            "path": "/hello",
+            "backend": "none",
+            "load_balancing": "round_robin",
+            "auth_required": false,
+            "mock": { "body": "active" }
+        }))
+        .unwrap();
+        let gateway = Gateway::builder().config(config).route(route).build().await.unwrap();
+        let proxy_service = &gateway.state().proxy_service;
+
+        let mut candidate = (*gateway.state().config).clone();
+        for route in candidate.routes.iter_mut().filter(|route| route.path == "/hello") {
+            route.mock = Some(serde_json::from_value(json!({ "body": "candidate" })).unwrap());
+        }
+        let canary = |percentage: f64| {
+            serde_json::from_value(json!({ "config": candidate, "percentage": percentage, "min_requests": 1000 }))
+                .unwrap()
+        };
+        let body = || async {
+            let request = Request::builder().uri("/hello").body(Body::empty()).unwrap();
+            let response = gateway.router().oneshot(request).await.unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+
+        proxy_service.start_config_canary(canary(100.0)).await.unwrap();
+        assert_eq!(body().await, "candidate");
+        assert!(proxy_service.revert_config_canary());
+        assert_eq!(body().await, "active");
+
+        proxy_service.start_config_canary(canary(0.0)).await.unwrap();
+        assert_eq!(body().await, "active");
+        assert!(proxy_service.promote_config_canary());
+        assert_eq!(body().await, "candidate");
+        assert!(proxy_service.config_canary_status().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_named_listeners_serve_their_own_routes() {
+        let mut config = Gateway::builder().config;
+        config.auth.enabled = false;
+        config.rate_limiting.enabled = false;
+        config.server.listeners.push(serde_json::from_value(json!({ "name": "internal", "port": 8081 })).unwrap());
+        let route: RouteConfig = serde_json::from_value(json!({
+            "path": "/internal",
+            "backend": "none",
+            "load_balancing": "round_robin",
+            "auth_required": false,
+            "mock": { "body": "hi" },
+            "listeners": ["internal"]
+        }))
+        .unwrap();
+        // An embedder's layer wraps every listener, not only the default one
+        let tag = middleware::from_fn(|request: Request, next: middleware::Next| async move {
+            let mut response = next.run(request).await;
+            response.headers_mut().insert("x-embedder", axum::http::HeaderValue::from_static("yes"));
+            response
+        });
+        let gateway = Gateway::builder().config(config).route(route).layer(tag).build().await.unwrap();
+
+        let request = Request::builder().uri("/internal").body(Body::empty()).unwrap();
+        let response = gateway.listener_router("internal").unwrap().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        assert_eq!(response.headers()["x-embedder"], "yes");
+
+        let request = Request::builder().uri("/internal").body(Body::empty()).unwrap();
+        let response = gateway.router().oneshot(request).await.unwrap();
+        assert_ne!(response.status(), axum::http::StatusCode::OK);
+        assert_eq!(response.headers()["x-embedder"], "yes");
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limit_rejects_like_the_other_limiters() {
+        let mut config = Gateway::builder().config;
+        config.auth.enabled = false;
+        config.rate_limiting.enabled = false;
+        let route: RouteConfig = serde_json::from_value(json!({
+            "path": "/slow",
+            "backend": "none",
+            "load_balancing": "round_robin",
+            "auth_required": false,
+            "max_concurrent_requests": 1,
+            "mock": { "body": "done", "latency_ms": 500 }
+        }))
+        .unwrap();
+        let gateway = Gateway::builder().config(config).route(route).build().await.unwrap();
+        let request = || Request::builder().uri("/slow").body(Body::empty()).unwrap();
+
+        let in_flight = tokio::spawn(gateway.router().oneshot(request()));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let response = gateway.router().oneshot(request()).await.unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["Retry-After"], "1");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["success"], false);
+
+        assert_eq!(in_flight.await.unwrap().unwrap().status(), axum::http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_route_permissions_reject_valid_tokens_with_403() {
+        use axum::http::StatusCode;
+
+        let mut config = Gateway::builder().config;
+        config.rate_limiting.enabled = false;
+        let secret = config.auth.jwt_secret.clone();
+        let route: RouteConfig = serde_json::from_value(json!({
+            "path": "/orders",
+            "backend": "none",
+            "load_balancing": "round_robin",
+            "auth_required": true,
+            "required_permissions": ["write"],
+            "mock": { "body": "ok" }
+        }))
+        .unwrap();
+        let gateway = Gateway::builder().config(config).route(route).build().await.unwrap();
+
+        let token = |scope: &str| {
+            let claims = json!({
+                "sub": "client",
+                "scope": scope,
+                "exp": (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp(),
+                "iat": chrono::Utc::now().timestamp(),
+            });
+            let key = jsonwebtoken::EncodingKey::from_secret(secret.as_bytes());
+            jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key).unwrap()
+        };
+        let status = |token: String| {
+            let request = Request::builder()
+                .uri("/orders")
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap();
+            let router = gateway.router();
+            async move { router.oneshot(request).await.unwrap().status() }
+        };
+
+        assert_eq!(status(token("read write")).await, StatusCode::OK);
+        assert_eq!(status(token("read")).await, StatusCode::FORBIDDEN);
+        assert_eq!(status("not-a-token".to_string()).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_admin_api_requires_admin_scopes_by_default() {
+        use axum::http::StatusCode;
+
+        let mut config = Gateway::builder().config;
+        config.rate_limiting.enabled = false;
+        let secret = config.auth.jwt_secret.clone();
+        let route: RouteConfig = serde_json::from_value(json!({
+            "path": "/hello",
+            "backend": "none",
+            "load_balancing": "round_robin",
+            "auth_required": false,
+            "mock": { "body": "hi" }
+        }))
+        .unwrap();
+        let gateway = Gateway::builder().config(config).route(route).build().await.unwrap();
+
+        let token = |scope: &str| {
+            let claims = json!({
+                "sub": "operator",
+                "scope": scope,
+                "exp": (chrono::Utc::now() + chrono::Duration::hours(1)).timestamp(),
+                "iat": chrono::Utc::now().timestamp(),
+            });
+            let key = jsonwebtoken::EncodingKey::from_secret(secret.as_bytes());
+            jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key).unwrap()
+        };
+        let status = |method: &str, path: &str, token: String| {
+            let request = Request::builder()
+                .method(method)
+                .uri(path)
+                .header("Authorization", format!("Bearer {}", token))
+                .header("Content-Type", "application/json")
+                .body(Body::from(r#"{ "path": "/hello" }"#))
+                .unwrap();
+            let router = gateway.router();
+            async move { router.oneshot(request).await.unwrap().status() }
+        };
+
+        assert_eq!(status("GET", "/admin/routes", token("read write")).await, StatusCode::FORBIDDEN);
+        assert_eq!(status("GET", "/admin/routes", token("admin:read")).await, StatusCode::OK);
+        assert_eq!(status("POST", "/admin/routes/match", token("admin:read")).await, StatusCode::OK);
+        assert_eq!(status("PUT", "/admin/rate-limits/exemptions", token("admin:read")).await, StatusCode::FORBIDDEN);
+        assert_eq!(status("PUT", "/admin/rate-limits/exemptions", token("admin")).await, StatusCode::OK);
+    }
+}
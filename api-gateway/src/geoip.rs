@@ -0,0 +1,112 @@
+use maxminddb::{geoip2, Reader};
+use std::net::IpAddr;
+use tracing::info;
+
+use crate::config::{Config, GeoIpConfig, RouteConfig, RouteGeoConfig};
+
+pub const COUNTRY_HEADER: &str = "x-client-country";
+
+/// The client's ISO country code, set on requests by the GeoIP middleware.
+#[derive(Debug, Clone)]
+pub struct ClientCountry(pub String);
+
+pub struct GeoIp {
+    reader: Reader<Vec<u8>>,
+    pub add_country_header: bool,
+}
+
+impl GeoIp {
+    /// Opens the configured database. Routes with `geo` rules need one, so a config
+    /// using them without `geoip` is rejected.
+    pub fn load(config: &Config) -> anyhow::Result<Option<Self>> {
+        let geoip_config = match &config.geoip {
+            Some(geoip_config) => geoip_config,
+            None => {
+                if let Some(route) = config.routes.iter().find(|route| route.geo.is_some()) {
+                    return Err(anyhow::anyhow!("Route {} has geo rules but geoip isn't configured", route.path));
+                }
+                return Ok(None);
+            }
+        };
+        Self::open(geoip_config).map(Some)
+    }
+
+    fn open(config: &GeoIpConfig) -> anyhow::Result<Self> {
+        let reader = Reader::open_readfile(&config.database)
+            .map_err(|e| anyhow::anyhow!("Failed to open GeoIP database {}: {}", config.database, e))?;
+        info!("Loaded GeoIP database {} ({})", config.database, reader.metadata.database_type);
+
+        Ok(Self {
+            reader,
+            add_country_header: config.add_country_header,
+        })
+    }
+
+    pub fn country(&self, ip: IpAddr) -> Option<String> {
+        let record: geoip2::Country = self.reader.lookup(ip).ok()?;
+        record
+            .country
+            .or(record.registered_country)
+            .and_then(|country| country.iso_code)
+            .map(str::to_string)
+    }
+}
+
+pub fn permits(geo: &RouteGeoConfig, country: Option<&str>) -> bool {
+    let listed = |countries: &[String]| {
+        country.map_or(false, |country| countries.iter().any(|listed| listed.eq_ignore_ascii_case(country)))
+    };
+    !listed(&geo.deny_countries) && (geo.allow_countries.is_empty() || listed(&geo.allow_countries))
+}
+
+/// The backend serving the client's country in place of the route's own, if any.
+pub fn regional_backend<'a>(route: &'a RouteConfig, country: Option<&str>) -> Option<&'a String> {
+    let country = country?;
+    route
+        .geo
+        .as_ref()?
+        .backends
+        .iter()
+        .find(|(listed, _)| listed.eq_ignore_ascii_case(country))
+        .map(|(_, backend)| backend)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn route(geo: serde_json::Value) -> RouteConfig {
+        serde_json::from_value(json!({
+            "path": "/api/orders",
+            "backend": "orders",
+            "load_balancing": "round_robin",
+            "auth_required": false,
+            "geo": geo
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_country_rules() {
+        let allow = route(json!({ "allow_countries": ["DE", "FR"] }));
+        let allow = allow.geo.as_ref().unwrap();
+        assert!(permits(allow, Some("de")));
+        assert!(!permits(allow, Some("US")));
+        assert!(!permits(allow, None));
+
+        let deny = route(json!({ "deny_countries": ["KP"] }));
+        let deny = deny.geo.as_ref().unwrap();
+        assert!(!permits(deny, Some("KP")));
+        assert!(permits(deny, Some("US")));
+        assert!(permits(deny, None));
+    }
+
+    #[test]
+    fn test_regional_backend() {
+        let route = route(json!({ "backends": { "DE": "orders-eu", "FR": "orders-eu" } }));
+        assert_eq!(regional_backend(&route, Some("DE")).map(String::as_str), Some("orders-eu"));
+        assert_eq!(regional_backend(&route, Some("US")), None);
+        assert_eq!(regional_backend(&route, None), None);
+    }
+}
@@ -0,0 +1,657 @@
+use axum::{
+    body::Bytes,
+    extract::Query,
+    http::{Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::config::GraphqlConfig;
+
+/// Bounds the parser's recursion, independently of `max_depth`, so a maliciously nested
+/// document can't overflow the stack before it's measured.
+const MAX_NESTING: usize = 128;
+
+/// Checks every operation in a GraphQL request, GET or POST (including batches). Returns
+/// the body to forward when persisted queries had to be expanded, or why the request is
+/// rejected.
+pub fn check(config: &GraphqlConfig, method: &Method, uri: &Uri, body: &Bytes) -> Result<Option<Bytes>, String> {
+    if method == Method::GET {
+        let params = Query::<HashMap<String, String>>::try_from_uri(uri)
+            .map(|Query(params)| params)
+            .unwrap_or_default();
+        let mut request = serde_json::Map::new();
+        for (name, value) in params {
+            let value = match name.as_str() {
+                "variables" | "extensions" => serde_json::from_str(&value).unwrap_or(Value::Null),
+                _ => Value::String(value),
+            };
+            request.insert(name, value);
+        }
+        // A GET can't carry an expanded query to the backend, so the hash is forwarded as is
+        check_request(config, &mut Value::Object(request))?;
+        return Ok(None);
+    }
+
+    let mut payload = match serde_json::from_slice::<Value>(body) {
+        Ok(payload) => payload,
+        // `application/graphql` bodies are the bare query
+        Err(_) => {
+            let query = std::str::from_utf8(body).map_err(|_| "Request body is not valid UTF-8".to_string())?;
+            let mut request = serde_json::json!({ "query": query });
+            check_request(config, &mut request)?;
+            return Ok(None);
+        }
+    };
+
+    let mut expanded = false;
+    match &mut payload {
+        Value::Array(batch) => {
+            for request in batch {
+                expanded |= check_request(config, request)?;
+            }
+        }
+        request => expanded = check_request(config, request)?,
+    }
+
+    Ok(expanded.then(|| Bytes::from(payload.to_string())))
+}
+
+pub fn rejection(message: &str) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(serde_json::json!({ "errors": [{ "message": message }] })),
+    )
+        .into_response()
+}
+
+/// Returns whether the query text was filled in from the persisted queries.
+fn check_request(config: &GraphqlConfig, request: &mut Value) -> Result<bool, String> {
+    let object = request.as_object_mut().ok_or("GraphQL request must be an object")?;
+    let hash = object
+        .get("extensions")
+        .and_then(|extensions| extensions.get("persistedQuery"))
+        .and_then(|persisted| persisted.get("sha256Hash"))
+        .and_then(Value::as_str)
+        .map(str::to_ascii_lowercase);
+    let query = object.get("query").and_then(Value::as_str).map(str::to_string);
+
+    let mut expanded = false;
+    let query = match (query, hash) {
+        (Some(query), _) => {
+            if !config.persisted_queries.is_empty() && !config.persisted_queries.contains_key(&sha256_hex(&query)) {
+                return Err("Query is not in the persisted query allowlist".to_string());
+            }
+            query
+        }
+        (None, Some(hash)) if !config.persisted_queries.is_empty() => {
+            let query = config
+                .persisted_queries
+                .get(&hash)
+                .ok_or("PersistedQueryNotFound")?
+                .clone();
+            object.insert("query".to_string(), Value::String(query.clone()));
+            expanded = true;
+            query
+        }
+        // Left to the backend's own automatic persisted queries, which only ever hold
+        // queries that came through here in full
+        (None, Some(_)) => return Ok(false),
+        (None, None) => return Err("Missing query".to_string()),
+    };
+
+    let document = Parser::new(&query)?.document()?;
+    let operation_name = object.get("operationName").and_then(Value::as_str);
+    let variables = object.get("variables").cloned().unwrap_or(Value::Null);
+    validate(config, &document, operation_name, &variables)?;
+
+    Ok(expanded)
+}
+
+fn sha256_hex(query: &str) -> String {
+    openssl::sha::sha256(query.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn validate(
+    config: &GraphqlConfig,
+    document: &Document,
+    operation_name: Option<&str>,
+    variables: &Value,
+) -> Result<(), String> {
+    let operation = match operation_name {
+        Some(name) => document
+            .operations
+            .iter()
+            .find(|operation| operation.name.as_deref() == Some(name))
+            .ok_or_else(|| format!("Unknown operation '{}'", name))?,
+        None if document.operations.len() == 1 => &document.operations[0],
+        None => return Err("operationName is required for documents with several operations".to_string()),
+    };
+
+    let mut walker = Walker {
+        config,
+        document,
+        variables,
+        fragments: Vec::new(),
+    };
+    walker.cost(&operation.selections, 1)?;
+    Ok(())
+}
+
+struct Walker<'a> {
+    config: &'a GraphqlConfig,
+    document: &'a Document,
+    variables: &'a Value,
+    /// Fragments being expanded, to catch cycles
+    fragments: Vec<&'a str>,
+}
+
+impl<'a> Walker<'a> {
+    /// Fails as soon as a limit is exceeded, which also bounds the work a query that
+    /// reuses fragments heavily can cause.
+    fn cost(&mut self, selections: &'a [Selection], depth: usize) -> Result<u64, String> {
+        let mut cost: u64 = 0;
+        for selection in selections {
+            cost = cost.saturating_add(match selection {
+                Selection::Field(field) => {
+                    if !self.config.allow_introspection && matches!(field.name.as_str(), "__schema" | "__type") {
+                        return Err("Introspection is disabled".to_string());
+                    }
+                    if depth > self.config.max_depth {
+                        return Err(format!("Query depth exceeds the maximum of {}", self.config.max_depth));
+                    }
+                    let children = self.cost(&field.selections, depth + 1)?;
+                    children.saturating_mul(self.list_size(field)).saturating_add(1)
+                }
+                Selection::Spread(name) => {
+                    if self.fragments.contains(&name.as_str()) {
+                        return Err(format!("Fragment '{}' spreads itself", name));
+                    }
+                    let fragment = self
+                        .document
+                        .fragments
+                        .get(name)
+                        .ok_or_else(|| format!("Unknown fragment '{}'", name))?;
+                    self.fragments.push(name);
+                    let cost = self.cost(fragment, depth)?;
+                    self.fragments.pop();
+                    cost
+                }
+                Selection::Inline(selections) => self.cost(selections, depth)?,
+            });
+
+            if cost > self.config.max_complexity {
+                return Err(format!("Query complexity exceeds the maximum of {}", self.config.max_complexity));
+            }
+        }
+        Ok(cost)
+    }
+
+    /// How many items a list field asks for, from its pagination argument
+    fn list_size(&self, field: &Field) -> u64 {
+        field
+            .arguments
+            .iter()
+            .filter(|(name, _)| matches!(name.as_str(), "first" | "last" | "limit"))
+            .find_map(|(_, value)| match value {
+                Argument::Int(size) => u64::try_from(*size).ok(),
+                Argument::Variable(name) => self.variables.get(name).and_then(Value::as_u64),
+                Argument::Other => None,
+            })
+            .unwrap_or(1)
+            .max(1)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Document {
+    operations: Vec<Operation>,
+    fragments: HashMap<String, Vec<Selection>>,
+}
+
+#[derive(Debug)]
+struct Operation {
+    name: Option<String>,
+    selections: Vec<Selection>,
+}
+
+#[derive(Debug)]
+enum Selection {
+    Field(Field),
+    Spread(String),
+    Inline(Vec<Selection>),
+}
+
+/// Aliases and directives don't affect the checks, so they aren't kept.
+#[derive(Debug)]
+struct Field {
+    name: String,
+    arguments: Vec<(String, Argument)>,
+    selections: Vec<Selection>,
+}
+
+#[derive(Debug)]
+enum Argument {
+    Int(i64),
+    Variable(String),
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Punctuator(char),
+    Spread,
+    Name(String),
+    Int(i64),
+    Float,
+    String,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            c if c.is_whitespace() || c == ',' || c == '\u{feff}' => i += 1,
+            '#' => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            '!' | '$' | '&' | '(' | ')' | ':' | '=' | '@' | '[' | ']' | '{' | '|' | '}' => {
+                tokens.push(Token::Punctuator(c));
+                i += 1;
+            }
+            '.' => {
+                if chars[i..].starts_with(&['.', '.', '.']) {
+                    tokens.push(Token::Spread);
+                    i += 3;
+                } else {
+                    return Err("Unexpected '.'".to_string());
+                }
+            }
+            '"' => {
+                if chars[i..].starts_with(&['"', '"', '"']) {
+                    i += 3;
+                    loop {
+                        if i >= chars.len() {
+                            return Err("Unterminated block string".to_string());
+                        }
+                        if chars[i] == '\\' && chars[i + 1..].starts_with(&['"', '"', '"']) {
+                            i += 4;
+                        } else if chars[i..].starts_with(&['"', '"', '"']) {
+                            i += 3;
+                            break;
+                        } else {
+                            i += 1;
+                        }
+                    }
+                } else {
+                    i += 1;
+                    loop {
+                        match chars.get(i) {
+                            None | Some('\n') => return Err("Unterminated string".to_string()),
+                            Some('\\') => i += 2,
+                            Some('"') => {
+                                i += 1;
+                                break;
+                            }
+                            Some(_) => i += 1,
+                        }
+                    }
+                }
+                tokens.push(Token::String);
+            }
+            c if c == '-' || c.is_ascii_digit() => {
+                let start = i;
+                i += 1;
+                let mut float = false;
+                while i < chars.len() {
+                    match chars[i] {
+                        c if c.is_ascii_digit() => i += 1,
+                        '.' | 'e' | 'E' => {
+                            float = true;
+                            i += 1;
+                        }
+                        '+' | '-' if matches!(chars[i - 1], 'e' | 'E') => i += 1,
+                        _ => break,
+                    }
+                }
+                let number: String = chars[start..i].iter().collect();
+                tokens.push(if float {
+                    Token::Float
+                } else {
+                    Token::Int(number.parse().map_err(|_| format!("Invalid number '{}'", number))?)
+                });
+            }
+            c if c == '_' || c.is_ascii_alphabetic() => {
+                let start = i;
+                while i < chars.len() && (chars[i] == '_' || chars[i].is_ascii_alphanumeric()) {
+                    i += 1;
+                }
+                tokens.push(Token::Name(chars[start..i].iter().collect()));
+            }
+            c => return Err(format!("Unexpected character '{}'", c)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Parses executable documents: operations and fragments. Type system definitions
+/// aren't accepted in requests.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    nesting: usize,
+}
+
+impl Parser {
+    fn new(source: &str) -> Result<Self, String> {
+        Ok(Self {
+            tokens: tokenize(source)?,
+            pos: 0,
+            nesting: 0,
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_is(&self, c: char) -> bool {
+        self.peek() == Some(&Token::Punctuator(c))
+    }
+
+    fn peek_name(&self, name: &str) -> bool {
+        matches!(self.peek(), Some(Token::Name(n)) if n == name)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self.tokens.get(self.pos).cloned().ok_or("Unexpected end of query")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.next()? {
+            Token::Punctuator(found) if found == c => Ok(()),
+            token => Err(format!("Expected '{}', found {:?}", c, token)),
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        match self.next()? {
+            Token::Name(name) => Ok(name),
+            token => Err(format!("Expected a name, found {:?}", token)),
+        }
+    }
+
+    fn nest(&mut self) -> Result<(), String> {
+        self.nesting += 1;
+        if self.nesting > MAX_NESTING {
+            return Err("Query is nested too deeply".to_string());
+        }
+        Ok(())
+    }
+
+    fn document(mut self) -> Result<Document, String> {
+        let mut document = Document::default();
+
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Punctuator('{') => document.operations.push(Operation {
+                    name: None,
+                    selections: self.selection_set()?,
+                }),
+                Token::Name(keyword) if matches!(keyword.as_str(), "query" | "mutation" | "subscription") => {
+                    self.pos += 1;
+                    let name = match self.peek() {
+                        Some(Token::Name(_)) => Some(self.name()?),
+                        _ => None,
+                    };
+                    if self.peek_is('(') {
+                        self.variable_definitions()?;
+                    }
+                    self.directives()?;
+                    document.operations.push(Operation {
+                        name,
+                        selections: self.selection_set()?,
+                    });
+                }
+                Token::Name(keyword) if keyword == "fragment" => {
+                    self.pos += 1;
+                    let name = self.name()?;
+                    if self.name()? != "on" {
+                        return Err(format!("Expected 'on' in fragment '{}'", name));
+                    }
+                    self.name()?;
+                    self.directives()?;
+                    let selections = self.selection_set()?;
+                    document.fragments.insert(name, selections);
+                }
+                token => return Err(format!("Unexpected {:?}", token)),
+            }
+        }
+
+        if document.operations.is_empty() {
+            return Err("Query has no operations".to_string());
+        }
+        Ok(document)
+    }
+
+    fn selection_set(&mut self) -> Result<Vec<Selection>, String> {
+        self.nest()?;
+        self.expect('{')?;
+        let mut selections = Vec::new();
+        while !self.peek_is('}') {
+            selections.push(self.selection()?);
+        }
+        self.pos += 1;
+        self.nesting -= 1;
+
+        if selections.is_empty() {
+            return Err("Selection sets can't be empty".to_string());
+        }
+        Ok(selections)
+    }
+
+    fn selection(&mut self) -> Result<Selection, String> {
+        if self.peek() == Some(&Token::Spread) {
+            self.pos += 1;
+            if self.peek_name("on") {
+                self.pos += 1;
+                self.name()?;
+            } else if let Some(Token::Name(_)) = self.peek() {
+                let name = self.name()?;
+                self.directives()?;
+                return Ok(Selection::Spread(name));
+            }
+            self.directives()?;
+            return Ok(Selection::Inline(self.selection_set()?));
+        }
+
+        let mut name = self.name()?;
+        if self.peek_is(':') {
+            self.pos += 1;
+            name = self.name()?;
+        }
+        let arguments = if self.peek_is('(') { self.arguments()? } else { Vec::new() };
+        self.directives()?;
+        let selections = if self.peek_is('{') { self.selection_set()? } else { Vec::new() };
+
+        Ok(Selection::Field(Field {
+            name,
+            arguments,
+            selections,
+        }))
+    }
+
+    fn arguments(&mut self) -> Result<Vec<(String, Argument)>, String> {
+        self.expect('(')?;
+        let mut arguments = Vec::new();
+        while !self.peek_is(')') {
+            let name = self.name()?;
+            self.expect(':')?;
+            arguments.push((name, self.value()?));
+        }
+        self.pos += 1;
+        Ok(arguments)
+    }
+
+    fn value(&mut self) -> Result<Argument, String> {
+        Ok(match self.next()? {
+            Token::Punctuator('$') => Argument::Variable(self.name()?),
+            Token::Int(value) => Argument::Int(value),
+            Token::Float | Token::String | Token::Name(_) => Argument::Other,
+            Token::Punctuator('[') => {
+                self.nest()?;
+                while !self.peek_is(']') {
+                    self.value()?;
+                }
+                self.pos += 1;
+                self.nesting -= 1;
+                Argument::Other
+            }
+            Token::Punctuator('{') => {
+                self.nest()?;
+                while !self.peek_is('}') {
+                    self.name()?;
+                    self.expect(':')?;
+                    self.value()?;
+                }
+                self.pos += 1;
+                self.nesting -= 1;
+                Argument::Other
+            }
+            token => return Err(format!("Unexpected {:?} in a value", token)),
+        })
+    }
+
+    fn directives(&mut self) -> Result<(), String> {
+        while self.peek_is('@') {
+            self.pos += 1;
+            self.name()?;
+            if self.peek_is('(') {
+                self.arguments()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn variable_definitions(&mut self) -> Result<(), String> {
+        self.expect('(')?;
+        while !self.peek_is(')') {
+            self.expect('$')?;
+            self.name()?;
+            self.expect(':')?;
+            self.variable_type()?;
+            if self.peek_is('=') {
+                self.pos += 1;
+                self.value()?;
+            }
+            self.directives()?;
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn variable_type(&mut self) -> Result<(), String> {
+        if self.peek_is('[') {
+            self.nest()?;
+            self.pos += 1;
+            self.variable_type()?;
+            self.expect(']')?;
+            self.nesting -= 1;
+        } else {
+            self.name()?;
+        }
+        if self.peek_is('!') {
+            self.pos += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config() -> GraphqlConfig {
+        serde_json::from_value(json!({ "max_depth": 3, "max_complexity": 50 })).unwrap()
+    }
+
+    fn post(config: &GraphqlConfig, request: Value) -> Result<Option<Bytes>, String> {
+        check(config, &Method::POST, &"/graphql".parse().unwrap(), &Bytes::from(request.to_string()))
+    }
+
+    #[test]
+    fn test_depth_complexity_and_introspection() {
+        let config = config();
+
+        let query = r#"
+            query Feed($count: Int = 10) {
+                viewer { name }
+                posts(first: $count) @include(if: true) {
+                    ...PostFields
+                    author { ... on User { name } }
+                }
+            }
+            fragment PostFields on Post { title, body }
+        "#;
+        // posts costs 1 + 10 * (title + body + author + name)
+        assert!(post(&config, json!({ "query": query, "variables": { "count": 10 } })).is_ok());
+        assert_eq!(
+            post(&config, json!({ "query": query, "variables": { "count": 20 } })).unwrap_err(),
+            "Query complexity exceeds the maximum of 50"
+        );
+
+        let deep = "{ a { b { c { d } } } }";
+        assert_eq!(post(&config, json!({ "query": deep })).unwrap_err(), "Query depth exceeds the maximum of 3");
+
+        let introspection = "{ __schema { types { name } } }";
+        assert_eq!(post(&config, json!({ "query": introspection })).unwrap_err(), "Introspection is disabled");
+        assert!(post(&config, json!({ "query": "{ viewer { __typename } }" })).is_ok());
+
+        let cycle = "{ ...A } fragment A on Query { ...B } fragment B on Query { ...A }";
+        assert!(post(&config, json!({ "query": cycle })).unwrap_err().contains("spreads itself"));
+
+        let nested = format!("{}{}", "{ a ".repeat(200), "}".repeat(200));
+        assert_eq!(post(&config, json!({ "query": nested })).unwrap_err(), "Query is nested too deeply");
+
+        let uri = "/graphql?query=%7B%20__type(name%3A%20%22User%22)%20%7B%20name%20%7D%20%7D".parse().unwrap();
+        assert!(check(&config, &Method::GET, &uri, &Bytes::new()).is_err());
+    }
+
+    #[test]
+    fn test_persisted_queries() {
+        let query = "{ viewer { name } }";
+        let hash = sha256_hex(query);
+        let mut config = config();
+        config.persisted_queries.insert(hash.clone(), query.to_string());
+
+        assert_eq!(post(&config, json!({ "query": query })), Ok(None));
+        assert_eq!(
+            post(&config, json!({ "query": "{ viewer { email } }" })).unwrap_err(),
+            "Query is not in the persisted query allowlist"
+        );
+
+        let expanded = post(&config, json!({ "extensions": { "persistedQuery": { "version": 1, "sha256Hash": hash } } }))
+            .unwrap()
+            .unwrap();
+        let expanded: Value = serde_json::from_slice(&expanded).unwrap();
+        assert_eq!(expanded["query"], query);
+
+        let unknown = json!([{ "query": query }, { "extensions": { "persistedQuery": { "sha256Hash": "00" } } }]);
+        assert_eq!(post(&config, unknown).unwrap_err(), "PersistedQueryNotFound");
+    }
+}
@@ -0,0 +1,937 @@
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, Method, StatusCode, Uri, Version},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        AppendHeaders, Html, IntoResponse, Response,
+    },
+    Extension, Json,
+};
+use serde::Deserialize;
+use std::{net::SocketAddr, time::Instant};
+use tracing::{info, warn, error};
+use futures::StreamExt;
+use uuid::Uuid;
+
+use crate::api_keys::{CreateApiKey, UpdateApiKey};
+use crate::auth::Identity;
+use crate::config::{IpFilterConfig, RateLimitExemptions};
+use crate::middleware::RequestStart;
+use crate::request_tail::TailFilter;
+use crate::request_records::RequestTrace;
+use crate::route_match::{self, RouteMatchRequest};
+use crate::proxy::{add_degradation, ProxiedRequestInfo, DEGRADED_HEADER};
+use crate::traffic_sampler::SamplingRequest;
+use crate::fault_injection::FaultRequest;
+use crate::maintenance::{MaintenanceRequest, MaintenanceSelector};
+use crate::filters::BackendOverride;
+use crate::geoip::ClientCountry;
+use crate::canary::CanaryRequest;
+use crate::tls::{self, ClientCertificate};
+use crate::token_revocation::{self, RevokeTokenRequest};
+use crate::{events, forwarded, hop_by_hop, metrics, ApiResponse, AppState};
+
+pub(crate) async fn health_endpoint(State(state): State<AppState>) -> impl IntoResponse {
+    let request_id = Uuid::new_v4().to_string();
+    let health_status = state.health_checker.get_health_status().await;
+
+    // Backends stay at the top level, where existing health checks look for them
+    let mut health = serde_json::json!(health_status);
+    health["rate_limiter"] = serde_json::json!(state.rate_limiter.health());
+    
+    Json(ApiResponse::success(health, request_id))
+}
+
+/// Prometheus text exposition format, for scraping.
+pub(crate) async fn prometheus_metrics_endpoint(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    // Exemplars linking latency buckets to traces only exist in OpenMetrics
+    let openmetrics = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |accept| accept.contains("application/openmetrics-text"));
+
+    if openmetrics {
+        ([(header::CONTENT_TYPE, metrics::OPENMETRICS_FORMAT)], state.metrics.get_openmetrics())
+    } else {
+        ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], state.metrics.get_prometheus_metrics())
+    }
+}
+
+/// JSON summary of the same metrics, for humans and the dashboard.
+pub(crate) async fn metrics_endpoint(State(state): State<AppState>) -> impl IntoResponse {
+    let request_id = Uuid::new_v4().to_string();
+    let metrics = state.metrics.get_metrics().await;
+    
+    Json(ApiResponse::success(metrics, request_id))
+}
+
+/// The status dashboard: one page that polls `/admin/routes`, `/metrics/summary` and
+/// `/health`, sending the admin token entered on the page when there is one.
+pub(crate) async fn dashboard() -> Html<&'static str> {
+    Html(include_str!("dashboard.html"))
+}
+
+pub(crate) async fn config_endpoint(State(state): State<AppState>) -> impl IntoResponse {
+    let request_id = Uuid::new_v4().to_string();
+    
+    // Return sanitized config (without sensitive data)
+    let config_info = serde_json::json!({
+        "version": "1.0.0",
+        "server": {
+            "port": state.config.server.port,
+            "host": state.config.server.host
+        },
+        "routes": state.config.routes.len(),
+        "rate_limiting": {
+            "enabled": state.config.rate_limiting.enabled,
+            "default_limit": state.config.rate_limiting.default_requests_per_minute
+        }
+    });
+    
+    Json(ApiResponse::success(config_info, request_id))
+}
+
+pub(crate) async fn routes_endpoint(State(state): State<AppState>) -> impl IntoResponse {
+    let request_id = Uuid::new_v4().to_string();
+    let routes: Vec<_> = state.config.routes.iter()
+        .map(|route| serde_json::json!({
+            "name": route.name,
+            "path": route.path,
+            "method": route.method,
+            "backend": route.backend,
+            "load_balancing": route.load_balancing,
+            "rate_limit": route.rate_limit,
+            "max_concurrent_requests": route.max_concurrent_requests,
+            "required_scopes": route.required_scopes,
+            "auth_mode": route.auth_mode
+        }))
+        .collect();
+    
+    Json(ApiResponse::success(routes, request_id))
+}
+
+/// Dry run of route matching: which route, backend and policies a request would get.
+pub(crate) async fn match_route(State(state): State<AppState>, Json(match_request): Json<RouteMatchRequest>) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    match route_match::evaluate(&state.config, &state.client_keys, &match_request) {
+        Ok(Some(matched)) => Json(ApiResponse::success(matched, request_id)).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(format!("No route matches {}", match_request.path), request_id)),
+        ).into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(e.to_string(), request_id)),
+        ).into_response(),
+    }
+}
+
+pub(crate) async fn config_canary_status(State(state): State<AppState>) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    match state.proxy_service.config_canary_status() {
+        Some(status) => Json(ApiResponse::success(status, request_id)).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error("No config canary running".to_string(), request_id)),
+        ).into_response(),
+    }
+}
+
+pub(crate) async fn start_config_canary(
+    State(state): State<AppState>,
+    Json(canary_request): Json<CanaryRequest>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    match state.proxy_service.start_config_canary(canary_request).await {
+        Ok(()) => Json(ApiResponse::success(state.proxy_service.config_canary_status(), request_id)).into_response(),
+        Err(e) => (
+            StatusCode::CONFLICT,
+            Json(ApiResponse::<()>::error(e.to_string(), request_id)),
+        ).into_response(),
+    }
+}
+
+pub(crate) async fn promote_config_canary(State(state): State<AppState>) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    if state.proxy_service.promote_config_canary() {
+        Json(ApiResponse::success(serde_json::json!({ "promoted": true }), request_id)).into_response()
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error("No config canary running".to_string(), request_id)),
+        ).into_response()
+    }
+}
+
+pub(crate) async fn revert_config_canary(State(state): State<AppState>) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    if state.proxy_service.revert_config_canary() {
+        Json(ApiResponse::success(serde_json::json!({ "reverted": true }), request_id)).into_response()
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error("No config canary running".to_string(), request_id)),
+        ).into_response()
+    }
+}
+
+pub(crate) async fn get_rate_limit_exemptions(State(state): State<AppState>) -> impl IntoResponse {
+    let request_id = Uuid::new_v4().to_string();
+    let exemptions = state.rate_limiter.get_exemptions().await;
+
+    Json(ApiResponse::success(exemptions, request_id))
+}
+
+pub(crate) async fn update_rate_limit_exemptions(
+    State(state): State<AppState>,
+    Json(exemptions): Json<RateLimitExemptions>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    match state.rate_limiter.set_exemptions(exemptions.clone()).await {
+        Ok(()) => {
+            info!("Rate limit exemptions updated (request_id: {})", request_id);
+            Json(ApiResponse::success(exemptions, request_id)).into_response()
+        }
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(e.to_string(), request_id)),
+        ).into_response(),
+    }
+}
+
+pub(crate) async fn get_ip_filter(State(state): State<AppState>) -> impl IntoResponse {
+    let request_id = Uuid::new_v4().to_string();
+
+    Json(ApiResponse::success(state.ip_filter.rules().await, request_id))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct OptionalRouteQuery {
+    route: Option<String>,
+}
+
+/// Replaces the global lists, or a route's with `?route=`.
+pub(crate) async fn update_ip_filter(
+    State(state): State<AppState>,
+    Query(query): Query<OptionalRouteQuery>,
+    Json(ip_filter): Json<IpFilterConfig>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    if let Some(route) = &query.route {
+        if !state.config.routes.iter().any(|r| &r.path == route) {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::<()>::error(format!("Unknown route: {}", route), request_id)),
+            ).into_response();
+        }
+    }
+
+    match state.ip_filter.set(query.route.as_deref(), ip_filter.clone()).await {
+        Ok(()) => {
+            info!(
+                "IP filter updated for {} (request_id: {})",
+                query.route.as_deref().unwrap_or("all routes"),
+                request_id
+            );
+            Json(ApiResponse::success(ip_filter, request_id)).into_response()
+        }
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(e.to_string(), request_id)),
+        ).into_response(),
+    }
+}
+
+pub(crate) async fn get_rate_limit_status(
+    State(state): State<AppState>,
+    Path(client_id): Path<String>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    match state.rate_limiter.get_rate_limit_status(&client_id).await {
+        Some(status) => Json(ApiResponse::success(status, request_id)).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(format!("No rate limit state for client: {}", client_id), request_id)),
+        ).into_response(),
+    }
+}
+
+pub(crate) async fn reset_rate_limit(
+    State(state): State<AppState>,
+    Path(client_id): Path<String>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    match state.rate_limiter.reset_rate_limit(&client_id).await {
+        Ok(reset) => {
+            info!("Rate limit reset for client: {} (request_id: {})", client_id, request_id);
+            Json(ApiResponse::success(
+                serde_json::json!({ "client_id": client_id, "reset": reset }),
+                request_id,
+            )).into_response()
+        }
+        Err(e) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiResponse::<()>::error(e.to_string(), request_id)),
+        ).into_response(),
+    }
+}
+
+pub(crate) async fn list_api_keys(State(state): State<AppState>) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    match state.api_keys.list().await {
+        Ok(keys) => Json(ApiResponse::success(keys, request_id)).into_response(),
+        Err(e) => api_key_store_error(e, request_id),
+    }
+}
+
+pub(crate) async fn create_api_key(
+    State(state): State<AppState>,
+    Json(request): Json<CreateApiKey>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    match state.api_keys.create(request).await {
+        Ok(created) => {
+            info!("API key {} created (request_id: {})", created.record.info.key_id, request_id);
+            (StatusCode::CREATED, Json(ApiResponse::success(created, request_id))).into_response()
+        }
+        Err(e) => api_key_store_error(e, request_id),
+    }
+}
+
+pub(crate) async fn update_api_key(
+    State(state): State<AppState>,
+    Path(key_id): Path<i32>,
+    Json(update): Json<UpdateApiKey>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    match state.api_keys.update(key_id, update).await {
+        Ok(Some(record)) => {
+            info!("API key {} updated (request_id: {})", key_id, request_id);
+            Json(ApiResponse::success(record, request_id)).into_response()
+        }
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(format!("API key not found: {}", key_id), request_id)),
+        ).into_response(),
+        Err(e) => api_key_store_error(e, request_id),
+    }
+}
+
+pub(crate) async fn revoke_api_key(
+    State(state): State<AppState>,
+    Path(key_id): Path<i32>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    match state.api_keys.revoke(key_id).await {
+        Ok(true) => {
+            info!("API key {} revoked (request_id: {})", key_id, request_id);
+            Json(ApiResponse::success(
+                serde_json::json!({ "key_id": key_id, "revoked": true }),
+                request_id,
+            )).into_response()
+        }
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(format!("API key not found: {}", key_id), request_id)),
+        ).into_response(),
+        Err(e) => api_key_store_error(e, request_id),
+    }
+}
+
+pub(crate) async fn revoke_token(
+    State(state): State<AppState>,
+    Json(request): Json<RevokeTokenRequest>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    let revoked_tokens = match &state.revoked_tokens {
+        Some(revoked_tokens) => revoked_tokens,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::<()>::error("Token revocation is not enabled".to_string(), request_id)),
+            ).into_response();
+        }
+    };
+
+    let (revocation_id, expires_at) = match (&request.token, &request.jti) {
+        (Some(token), _) => match token_revocation::unverified_claims(token) {
+            Some(claims) => (
+                token_revocation::revocation_id(token, &claims),
+                claims.get("exp").and_then(|exp| exp.as_u64()),
+            ),
+            None => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(ApiResponse::<()>::error("Token is not a JWT".to_string(), request_id)),
+                ).into_response();
+            }
+        },
+        (None, Some(jti)) => (format!("jti:{}", jti), request.expires_at),
+        (None, None) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::<()>::error("Either token or jti is required".to_string(), request_id)),
+            ).into_response();
+        }
+    };
+
+    match revoked_tokens.revoke(&revocation_id, expires_at).await {
+        Ok(added) => {
+            info!("Token {} revoked (request_id: {})", revocation_id, request_id);
+            Json(ApiResponse::success(
+                serde_json::json!({ "revocation_id": revocation_id, "expires_at": expires_at, "revoked": added }),
+                request_id,
+            )).into_response()
+        }
+        Err(e) => {
+            error!("Failed to revoke token: {} (request_id: {})", e, request_id);
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(ApiResponse::<()>::error("Token deny list unavailable".to_string(), request_id)),
+            ).into_response()
+        }
+    }
+}
+
+fn api_key_store_error(e: anyhow::Error, request_id: String) -> Response {
+    error!("API key store error: {} (request_id: {})", e, request_id);
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ApiResponse::<()>::error("API key store unavailable".to_string(), request_id)),
+    ).into_response()
+}
+
+/// Server-sent stream of lifecycle events. Reconnecting clients send `Last-Event-ID` to
+/// receive what they missed, as far back as the replay buffer goes.
+pub(crate) async fn lifecycle_events(
+    headers: HeaderMap,
+) -> Sse<impl futures::Stream<Item = Result<Event, std::convert::Infallible>>> {
+    let last_id = headers
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+    let (backlog, receiver) = events::LIFECYCLE_EVENTS.subscribe(last_id);
+
+    let live = futures::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => return Some((event, receiver)),
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Lifecycle event subscriber fell behind, skipped {} events", skipped);
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    let stream = futures::stream::iter(backlog)
+        .chain(live)
+        .map(|event| {
+            Ok(Event::default()
+                .id(event.id.to_string())
+                .event(event.kind.as_str())
+                .json_data(&event)
+                .unwrap_or_default())
+        });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// Server-sent stream of requests as they complete, narrowed by `route`, `status` (`404`
+/// or `5xx`), `min_latency_ms` and `client`. A subscriber that falls behind gets a
+/// `lagged` event with the number of requests it missed.
+pub(crate) async fn tail_requests(
+    State(state): State<AppState>,
+    Query(filter): Query<TailFilter>,
+) -> Sse<impl futures::Stream<Item = Result<Event, std::convert::Infallible>>> {
+    let receiver = state.request_tail.subscribe();
+
+    let stream = futures::stream::unfold((receiver, filter), |(mut receiver, filter)| async move {
+        loop {
+            let event = match receiver.recv().await {
+                Ok(entry) if filter.matches(&entry) => {
+                    Event::default().event("request").json_data(&entry).unwrap_or_default()
+                }
+                Ok(_) => continue,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                    Event::default().event("lagged").data(skipped.to_string())
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => return None,
+            };
+            return Some((Ok(event), (receiver, filter)));
+        }
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+pub(crate) async fn credentials_status(State(state): State<AppState>) -> impl IntoResponse {
+    let request_id = Uuid::new_v4().to_string();
+
+    Json(ApiResponse::success(state.credentials.status(), request_id))
+}
+
+pub(crate) async fn reload_credentials(
+    State(state): State<AppState>,
+    Path(backend): Path<String>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    match state.credentials.reload(&backend) {
+        Ok(rotated) => Json(ApiResponse::success(
+            serde_json::json!({ "backend": backend, "rotated": rotated }),
+            request_id,
+        )).into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(e.to_string(), request_id)),
+        ).into_response(),
+    }
+}
+
+pub(crate) async fn captured_requests(State(state): State<AppState>) -> impl IntoResponse {
+    let request_id = Uuid::new_v4().to_string();
+
+    Json(ApiResponse::success(state.debug_capture.recent(), request_id))
+}
+
+/// What happened to a recent request: its timings per stage, backend server, retries
+/// and auth outcome.
+pub(crate) async fn request_record(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    match state.request_records.get(&id) {
+        Some(record) => Json(ApiResponse::success(record, request_id)).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(format!("No record of request {}", id), request_id)),
+        ).into_response(),
+    }
+}
+
+pub(crate) async fn list_sampling_sessions(State(state): State<AppState>) -> impl IntoResponse {
+    let request_id = Uuid::new_v4().to_string();
+    let sessions = state.traffic_sampler.list().await;
+
+    Json(ApiResponse::success(sessions, request_id))
+}
+
+pub(crate) async fn start_sampling_session(
+    State(state): State<AppState>,
+    Json(sampling_request): Json<SamplingRequest>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    if !state.config.routes.iter().any(|route| route.path == sampling_request.route) {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(format!("Unknown route: {}", sampling_request.route), request_id)),
+        ).into_response();
+    }
+
+    match state.traffic_sampler.start(sampling_request).await {
+        Ok(session) => Json(ApiResponse::success(session, request_id)).into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(e.to_string(), request_id)),
+        ).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RouteQuery {
+    route: String,
+}
+
+pub(crate) async fn stop_sampling_session(
+    State(state): State<AppState>,
+    Query(query): Query<RouteQuery>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    match state.traffic_sampler.stop(&query.route).await {
+        Some(session) => Json(ApiResponse::success(session, request_id)).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(format!("No sampling session for route: {}", query.route), request_id)),
+        ).into_response(),
+    }
+}
+
+pub(crate) async fn list_faults(State(state): State<AppState>) -> impl IntoResponse {
+    let request_id = Uuid::new_v4().to_string();
+    let faults = state.fault_injector.list(&state.config.routes).await;
+
+    Json(ApiResponse::success(faults, request_id))
+}
+
+pub(crate) async fn set_faults(
+    State(state): State<AppState>,
+    Json(fault_request): Json<FaultRequest>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    let route = match state.config.routes.iter().find(|route| route.path == fault_request.route) {
+        Some(route) => route,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(ApiResponse::<()>::error(format!("Unknown route: {}", fault_request.route), request_id)),
+            ).into_response()
+        }
+    };
+
+    match state.fault_injector.set(route, fault_request).await {
+        Ok(fault_override) => Json(ApiResponse::success(fault_override, request_id)).into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(e.to_string(), request_id)),
+        ).into_response(),
+    }
+}
+
+pub(crate) async fn clear_faults(
+    State(state): State<AppState>,
+    Query(query): Query<RouteQuery>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    match state.fault_injector.clear(&query.route).await {
+        Some(fault_override) => Json(ApiResponse::success(fault_override, request_id)).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(format!("No fault override for route: {}", query.route), request_id)),
+        ).into_response(),
+    }
+}
+
+pub(crate) async fn list_maintenance(State(state): State<AppState>) -> impl IntoResponse {
+    let request_id = Uuid::new_v4().to_string();
+
+    Json(ApiResponse::success(state.maintenance.list().await, request_id))
+}
+
+pub(crate) async fn start_maintenance(
+    State(state): State<AppState>,
+    Json(maintenance_request): Json<MaintenanceRequest>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    let selector = &maintenance_request.selector;
+    let unknown = match (&selector.route, &selector.backend) {
+        (Some(route), _) if !state.config.routes.iter().any(|r| &r.path == route) => {
+            Some(format!("Unknown route: {}", route))
+        }
+        (_, Some(backend)) if !state.config.backends.contains_key(backend) => {
+            Some(format!("Unknown backend: {}", backend))
+        }
+        _ => None,
+    };
+    if let Some(message) = unknown {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(message, request_id)),
+        ).into_response();
+    }
+
+    match state.maintenance.start(maintenance_request).await {
+        Ok(window) => Json(ApiResponse::success(window, request_id)).into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(ApiResponse::<()>::error(e.to_string(), request_id)),
+        ).into_response(),
+    }
+}
+
+pub(crate) async fn stop_maintenance(
+    State(state): State<AppState>,
+    Query(selector): Query<MaintenanceSelector>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    let target = match selector.target() {
+        Ok(target) => target,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(ApiResponse::<()>::error(e.to_string(), request_id)),
+            ).into_response()
+        }
+    };
+
+    match state.maintenance.stop(&target).await {
+        Some(window) => Json(ApiResponse::success(window, request_id)).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(ApiResponse::<()>::error(format!("No maintenance for {}", target), request_id)),
+        ).into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct OidcCallbackParams {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+pub(crate) async fn oidc_callback(
+    State(state): State<AppState>,
+    Query(params): Query<OidcCallbackParams>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    let oidc = match &state.oidc {
+        Some(oidc) => oidc,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    let (code, login_state) = match (params.code, params.state, params.error) {
+        (Some(code), Some(login_state), None) => (code, login_state),
+        (_, _, error) => {
+            let message = format!("Login failed: {}", error.unwrap_or_else(|| "missing code or state".to_string()));
+            return (StatusCode::UNAUTHORIZED, Json(ApiResponse::<()>::error(message, request_id))).into_response();
+        }
+    };
+
+    match oidc.complete_login(&code, &login_state).await {
+        Ok(login) => {
+            let mut headers = vec![
+                (header::LOCATION, login.return_to),
+                (header::SET_COOKIE, oidc.session_cookie(&login.session_id)),
+            ];
+            if let (Some(csrf), Some(oidc_config)) = (&state.csrf, &state.config.auth.oidc) {
+                headers.push((
+                    header::SET_COOKIE,
+                    csrf.cookie(&login.csrf_token, oidc.session_ttl(), oidc_config.cookie_secure),
+                ));
+            }
+
+            (StatusCode::FOUND, AppendHeaders(headers)).into_response()
+        }
+        Err(e) => {
+            warn!("OIDC login failed: {} (request_id: {})", e, request_id);
+            (StatusCode::UNAUTHORIZED, Json(ApiResponse::<()>::error("Login failed".to_string(), request_id))).into_response()
+        }
+    }
+}
+
+pub(crate) async fn oidc_logout(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let oidc = match &state.oidc {
+        Some(oidc) => oidc,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    if let Err(e) = oidc.logout(&headers).await {
+        error!("Failed to end OIDC session: {}", e);
+    }
+
+    let mut headers = vec![
+        (header::LOCATION, oidc.post_logout_redirect().to_string()),
+        (header::SET_COOKIE, oidc.clear_cookie()),
+    ];
+    if let Some(csrf) = &state.csrf {
+        headers.push((header::SET_COOKIE, csrf.cookie("", 0, false)));
+    }
+
+    (StatusCode::FOUND, AppendHeaders(headers)).into_response()
+}
+
+/// Trades the credentials `auth_middleware` accepted for a session cookie.
+pub(crate) async fn create_session(
+    State(state): State<AppState>,
+    identity: Option<Extension<Identity>>,
+) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    let sessions = match &state.sessions {
+        Some(sessions) => sessions,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let identity = match identity {
+        Some(Extension(identity)) => identity,
+        None => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(ApiResponse::<()>::error("Credentials required to start a session".to_string(), request_id)),
+            ).into_response();
+        }
+    };
+
+    match sessions.create(identity).await {
+        Ok((session_id, session)) => {
+            let mut cookies = vec![(header::SET_COOKIE, sessions.session_cookie(&session_id))];
+            if let (Some(csrf), Some(session_config)) = (&state.csrf, &state.config.auth.sessions) {
+                cookies.push((
+                    header::SET_COOKIE,
+                    csrf.cookie(&session.csrf_token, session_config.ttl_seconds, session_config.cookie_secure),
+                ));
+            }
+
+            (StatusCode::CREATED, AppendHeaders(cookies), Json(ApiResponse::success(session, request_id))).into_response()
+        }
+        Err(e) => {
+            error!("Failed to create session: {} (request_id: {})", e, request_id);
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(ApiResponse::<()>::error("Session store unavailable".to_string(), request_id)),
+            ).into_response()
+        }
+    }
+}
+
+pub(crate) async fn current_session(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let request_id = Uuid::new_v4().to_string();
+
+    let sessions = match &state.sessions {
+        Some(sessions) => sessions,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    match sessions.validate(&headers).await {
+        Some(session) => Json(ApiResponse::success(session, request_id)).into_response(),
+        None => (
+            StatusCode::UNAUTHORIZED,
+            Json(ApiResponse::<()>::error("No active session".to_string(), request_id)),
+        ).into_response(),
+    }
+}
+
+pub(crate) async fn destroy_session(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let sessions = match &state.sessions {
+        Some(sessions) => sessions,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    if let Err(e) = sessions.destroy(&headers).await {
+        error!("Failed to end session: {}", e);
+    }
+
+    let mut cookies = vec![(header::SET_COOKIE, sessions.clear_cookie())];
+    if let Some(csrf) = &state.csrf {
+        cookies.push((header::SET_COOKIE, csrf.cookie("", 0, false)));
+    }
+
+    (StatusCode::NO_CONTENT, AppendHeaders(cookies)).into_response()
+}
+
+pub(crate) async fn proxy_handler(
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    client_cert: Option<Extension<ClientCertificate>>,
+    request_start: Option<Extension<RequestStart>>,
+    identity: Option<Extension<Identity>>,
+    client_country: Option<Extension<ClientCountry>>,
+    backend_override: Option<Extension<BackendOverride>>,
+    trace: Option<Extension<RequestTrace>>,
+    method: Method,
+    uri: Uri,
+    version: Version,
+    mut headers: HeaderMap,
+    body: axum::body::Body,
+) -> Result<Response, StatusCode> {
+    // Set by `logging_middleware`, so logs and the backend see the ID the caller gets back
+    let request_id = headers
+        .get("X-Request-ID")
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    if let Some(Extension(trace)) = &trace {
+        trace.mark("proxy");
+    }
+
+    let peer_trusted = connect_info
+        .map_or(false, |ConnectInfo(addr)| state.client_keys.is_trusted(&addr.ip()));
+    let xfcc_config = state.config.server.tls.as_ref().and_then(|tls| tls.forward_client_cert.as_ref());
+    tls::set_forwarded_client_cert(
+        &mut headers,
+        client_cert.as_ref().map(|Extension(cert)| cert),
+        xfcc_config,
+        peer_trusted,
+    );
+
+    hop_by_hop::strip(&mut headers);
+    hop_by_hop::append_via(&mut headers, version);
+    // Forwarding headers are only extended for trusted proxies, and replaced otherwise
+    let proto = if state.config.server.tls.is_some() { "https" } else { "http" };
+    forwarded::apply(&mut headers, connect_info.map(|ConnectInfo(addr)| addr.ip()), peer_trusted, proto);
+
+    // Only the gateway gets to claim it is degraded
+    headers.remove(DEGRADED_HEADER);
+    for flag in state.rate_limiter.degradations() {
+        add_degradation(&mut headers, flag);
+    }
+    
+    // Record request metrics
+    state.metrics.record_request(&method.to_string(), uri.path()).await;
+    
+    // Latency is measured from when the gateway first saw the request
+    let start_time = request_start.map_or_else(Instant::now, |Extension(RequestStart(start))| start);
+    let method_label = method.to_string();
+    let matched_route = state.config.find_route(uri.path());
+    let backend_override = backend_override.map(|Extension(BackendOverride(backend))| backend);
+    let (route_label, backend_label) = matched_route
+        .map(|route| (route.path.clone(), backend_override.clone().unwrap_or_else(|| route.backend.clone())))
+        .unwrap_or_else(|| ("unmatched".to_string(), "none".to_string()));
+    
+    // Proxy the request
+    let in_flight = state.metrics.track_in_flight(&backend_label);
+    let result = state
+        .proxy_service
+        .proxy_request(
+            method,
+            uri,
+            headers,
+            body,
+            &request_id,
+            identity.as_ref().map(|Extension(identity)| identity),
+            client_country.as_ref().map(|Extension(ClientCountry(country))| country.as_str()),
+            backend_override.as_deref(),
+        )
+        .await;
+    drop(in_flight);
+
+    match result {
+        Ok(response) => {
+            let duration = start_time.elapsed();
+            state.metrics.record_response_time(duration).await;
+            match response.extensions().get::<ProxiedRequestInfo>() {
+                Some(info) => {
+                    state.metrics.record_body_sizes(&info.route, info.request_bytes, info.response_bytes);
+                    state.metrics.record_response(&info.route, &info.backend, &method_label, response.status().as_u16(), duration);
+                    state.metrics.record_upstream_latency(&info.route, &info.backend, info.upstream_duration);
+                    if info.contract_violations > 0 {
+                        state.metrics.record_contract_mismatch(&info.route);
+                    }
+                }
+                None => {
+                    state.metrics.record_response(&route_label, &backend_label, &method_label, response.status().as_u16(), duration);
+                }
+            }
+            Ok(response)
+        }
+        Err(e) => {
+            let duration = start_time.elapsed();
+            state.metrics.record_response_time(duration).await;
+            state.metrics.record_response(&route_label, &backend_label, &method_label, StatusCode::BAD_GATEWAY.as_u16(), duration);
+            state.metrics.record_error(&route_label, &backend_label, &e.to_string()).await;
+            
+            error!("Proxy error: {} (request_id: {})", e, request_id);
+            Err(StatusCode::BAD_GATEWAY)
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use tracing::warn;
+
+use crate::config::HeaderRule;
+
+/// Applies `rules` in order. A rule with an invalid header name or value is skipped.
+pub fn apply(rules: &[HeaderRule], headers: &mut HeaderMap) {
+    for rule in rules {
+        if let Err(e) = apply_rule(rule, headers) {
+            warn!("Skipping header rule {:?}: {}", rule, e);
+        }
+    }
+}
+
+fn apply_rule(rule: &HeaderRule, headers: &mut HeaderMap) -> anyhow::Result<()> {
+    match rule {
+        HeaderRule::Add { name, value } => {
+            headers.append(HeaderName::try_from(name.as_str())?, HeaderValue::from_str(value)?);
+        }
+        HeaderRule::Set { name, value } => {
+            headers.insert(HeaderName::try_from(name.as_str())?, HeaderValue::from_str(value)?);
+        }
+        HeaderRule::SetIfAbsent { name, value } => {
+            let name = HeaderName::try_from(name.as_str())?;
+            if !headers.contains_key(&name) {
+                headers.insert(name, HeaderValue::from_str(value)?);
+            }
+        }
+        HeaderRule::Remove { name } => {
+            headers.remove(HeaderName::try_from(name.as_str())?);
+        }
+        HeaderRule::Rename { from, to } => {
+            let from = HeaderName::try_from(from.as_str())?;
+            let to = HeaderName::try_from(to.as_str())?;
+            let values: Vec<HeaderValue> = headers.get_all(&from).iter().cloned().collect();
+            if values.is_empty() {
+                return Ok(());
+            }
+            headers.remove(&from);
+            headers.remove(&to);
+            for value in values {
+                headers.append(to.clone(), value);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules_apply_in_order() {
+        let rules: Vec<HeaderRule> = serde_json::from_value(serde_json::json!([
+            { "type": "set", "name": "X-Internal-Gateway", "value": "true" },
+            { "type": "remove", "name": "Cookie" },
+            { "type": "rename", "from": "X-Client-Version", "to": "X-Api-Version" },
+            { "type": "set_if_absent", "name": "Accept", "value": "application/json" },
+            { "type": "set_if_absent", "name": "X-Region", "value": "eu-west-1" },
+            { "type": "add", "name": "Via", "value": "gateway" },
+            { "type": "set", "name": "Bad Name", "value": "ignored" }
+        ]))
+        .unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("cookie", HeaderValue::from_static("session=abc"));
+        headers.insert("x-client-version", HeaderValue::from_static("2"));
+        headers.insert("accept", HeaderValue::from_static("text/html"));
+        headers.insert("via", HeaderValue::from_static("1.1 cdn"));
+        apply(&rules, &mut headers);
+
+        assert_eq!(headers.get("x-internal-gateway").unwrap(), "true");
+        assert!(!headers.contains_key("cookie"));
+        assert!(!headers.contains_key("x-client-version"));
+        assert_eq!(headers.get("x-api-version").unwrap(), "2");
+        assert_eq!(headers.get("accept").unwrap(), "text/html");
+        assert_eq!(headers.get("x-region").unwrap(), "eu-west-1");
+        assert_eq!(headers.get_all("via").iter().count(), 2);
+        assert_eq!(headers.len(), 6);
+    }
+}
@@ -9,6 +9,8 @@ use tokio::{sync::RwLock, time::interval};
 use tracing::{debug, error, info, warn};
 
 use crate::config::Config;
+use crate::events::{self, LifecycleEventKind};
+use crate::tcp_proxy::server_address;
 
 #[derive(Clone)]
 pub struct HealthChecker {
@@ -101,9 +103,13 @@ impl HealthChecker {
         let mut futures = Vec::new();
         
         for (backend_name, backend_config) in &self.config.backends {
-            if !backend_config.health_check.enabled {
+            // No generic probe exists for UDP, so those servers stay in rotation
+            let udp = self.config.udp_proxies.iter().any(|proxy| &proxy.backend == backend_name);
+            if !backend_config.health_check.enabled || udp {
                 continue;
             }
+            // Backends behind TCP proxies are checked by connecting, not over HTTP
+            let tcp = self.config.tcp_proxies.iter().any(|proxy| &proxy.backend == backend_name);
             
             for server_url in &backend_config.servers {
                 let future = self.check_server_health(
@@ -111,6 +117,7 @@ impl HealthChecker {
                     server_url.clone(),
                     backend_config.health_check.path.clone(),
                     backend_config.health_check.timeout_seconds,
+                    tcp,
                 );
                 futures.push(future);
             }
@@ -131,7 +138,12 @@ impl HealthChecker {
         server_url: String,
         health_path: String,
         timeout_seconds: u64,
+        tcp: bool,
     ) -> (String, String, bool, Option<u64>) {
+        if tcp {
+            return self.check_tcp_server_health(backend_name, server_url, timeout_seconds).await;
+        }
+
         let health_url = format!("{}{}", server_url, health_path);
         let start_time = Instant::now();
         
@@ -178,6 +190,31 @@ impl HealthChecker {
         }
     }
 
+    async fn check_tcp_server_health(
+        &self,
+        backend_name: String,
+        server_url: String,
+        timeout_seconds: u64,
+    ) -> (String, String, bool, Option<u64>) {
+        let start_time = Instant::now();
+        let connect = tokio::net::TcpStream::connect(server_address(&server_url));
+        let is_healthy = match tokio::time::timeout(Duration::from_secs(timeout_seconds), connect).await {
+            Ok(Ok(_)) => true,
+            Ok(Err(e)) => {
+                error!("Health check error for {}: {}", server_url, e);
+                false
+            }
+            Err(_) => {
+                error!("Health check for {} timed out connecting", server_url);
+                false
+            }
+        };
+        let response_time = start_time.elapsed().as_millis() as u64;
+
+        self.update_server_health(&backend_name, &server_url, is_healthy, Some(response_time)).await;
+        (backend_name, server_url, is_healthy, Some(response_time))
+    }
+
     async fn update_server_health(
         &self,
         backend_name: &str,
@@ -198,6 +235,19 @@ impl HealthChecker {
                     server_health.last_check = now;
                     server_health.response_time_ms = response_time_ms;
                     
+                    let was_unhealthy = server_health.status == HealthStatus::Unhealthy;
+                    if is_healthy && was_unhealthy {
+                        events::emit(
+                            LifecycleEventKind::ServerReadmitted,
+                            serde_json::json!({ "backend": backend_name, "server": server_url }),
+                        );
+                    } else if !is_healthy && !was_unhealthy {
+                        events::emit(
+                            LifecycleEventKind::ServerEjected,
+                            serde_json::json!({ "backend": backend_name, "server": server_url }),
+                        );
+                    }
+                    
                     if is_healthy {
                         server_health.status = HealthStatus::Healthy;
                         server_health.consecutive_successes += 1;
@@ -279,4 +329,21 @@ impl HealthChecker {
         
         Vec::new()
     }
-} 
\ No newline at end of file
+
+    /// Servers a failed check has ejected. Those not checked yet aren't included.
+    pub async fn get_unhealthy_servers(&self, backend_name: &str) -> Vec<String> {
+        let health_status = self.health_status.read().await;
+
+        health_status
+            .get(backend_name)
+            .map(|service_health| {
+                service_health
+                    .servers
+                    .iter()
+                    .filter(|server| server.status == HealthStatus::Unhealthy)
+                    .map(|server| server.url.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,89 @@
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Version};
+
+/// Identifies the gateway in `Via` headers.
+pub const VIA_PSEUDONYM: &str = "api-gateway";
+
+/// Headers that describe a single connection (RFC 7230 section 6.1, plus the
+/// non-standard `Proxy-Connection`), so they're never passed through the gateway.
+const HOP_BY_HOP: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// The hop-by-hop header names in `headers`: the standard ones and any listed in
+/// `Connection`, lowercased.
+pub fn names(headers: &HeaderMap) -> Vec<String> {
+    let mut names: Vec<String> = HOP_BY_HOP.iter().map(|name| name.to_string()).collect();
+    names.extend(
+        headers
+            .get_all(header::CONNECTION)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty()),
+    );
+    names
+}
+
+/// Removes the hop-by-hop headers from a message about to be forwarded.
+pub fn strip(headers: &mut HeaderMap) {
+    for name in names(headers) {
+        if let Ok(name) = HeaderName::from_bytes(name.as_bytes()) {
+            headers.remove(name);
+        }
+    }
+}
+
+/// Records the gateway as a hop of a message received over `version`, after any hops
+/// already listed.
+pub fn append_via(headers: &mut HeaderMap, version: Version) {
+    let protocol = match version {
+        Version::HTTP_09 => "0.9",
+        Version::HTTP_10 => "1.0",
+        Version::HTTP_2 => "2",
+        Version::HTTP_3 => "3",
+        _ => "1.1",
+    };
+    if let Ok(value) = HeaderValue::from_str(&format!("{} {}", protocol, VIA_PSEUDONYM)) {
+        headers.append(header::VIA, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONNECTION, HeaderValue::from_static("keep-alive, X-Session-Hint"));
+        headers.insert("keep-alive", HeaderValue::from_static("timeout=5"));
+        headers.insert("x-session-hint", HeaderValue::from_static("abc"));
+        headers.insert(header::TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+        headers.insert(header::UPGRADE, HeaderValue::from_static("h2c"));
+        headers.insert(header::TE, HeaderValue::from_static("trailers"));
+        headers.insert(header::ACCEPT, HeaderValue::from_static("application/json"));
+
+        strip(&mut headers);
+        assert_eq!(headers.len(), 1);
+        assert!(headers.contains_key(header::ACCEPT));
+    }
+
+    #[test]
+    fn test_append_via() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::VIA, HeaderValue::from_static("1.1 cdn"));
+        append_via(&mut headers, Version::HTTP_2);
+
+        let via: Vec<_> = headers.get_all(header::VIA).iter().collect();
+        assert_eq!(via, vec!["1.1 cdn", "2 api-gateway"]);
+    }
+}
@@ -0,0 +1,159 @@
+use ipnet::IpNet;
+use serde::Serialize;
+use std::{collections::HashMap, net::IpAddr};
+use tokio::sync::RwLock;
+
+use crate::config::{Config, IpFilterConfig};
+
+#[derive(Debug, Clone, Default)]
+struct IpRules {
+    config: IpFilterConfig,
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl IpRules {
+    fn parse(config: IpFilterConfig) -> anyhow::Result<Self> {
+        let parse = |ranges: &[String]| {
+            ranges
+                .iter()
+                .map(|range| {
+                    range
+                        .parse::<IpNet>()
+                        .or_else(|_| range.parse::<IpAddr>().map(IpNet::from))
+                        .map_err(|_| anyhow::anyhow!("Invalid IP range: {}", range))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+        };
+
+        Ok(Self {
+            allow: parse(&config.allow)?,
+            deny: parse(&config.deny)?,
+            config,
+        })
+    }
+
+    /// Clients whose address is unknown only get through when nothing is allowlisted.
+    fn permits(&self, ip: Option<IpAddr>) -> bool {
+        match ip {
+            Some(ip) => {
+                !self.deny.iter().any(|range| range.contains(&ip))
+                    && (self.allow.is_empty() || self.allow.iter().any(|range| range.contains(&ip)))
+            }
+            None => self.allow.is_empty(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IpFilterRules {
+    pub global: IpFilterConfig,
+    pub routes: HashMap<String, IpFilterConfig>,
+}
+
+/// The global and per-route IP lists, replaceable at runtime through `/admin/ip-filter`.
+pub struct IpFilter {
+    global: RwLock<IpRules>,
+    /// Keyed by route path
+    routes: RwLock<HashMap<String, IpRules>>,
+}
+
+impl IpFilter {
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        let routes = config
+            .routes
+            .iter()
+            .filter_map(|route| Some((route.path.clone(), route.ip_filter.clone()?)))
+            .map(|(path, ip_filter)| Ok((path, IpRules::parse(ip_filter)?)))
+            .collect::<anyhow::Result<HashMap<_, _>>>()?;
+
+        Ok(Self {
+            global: RwLock::new(IpRules::parse(config.ip_filter.clone())?),
+            routes: RwLock::new(routes),
+        })
+    }
+
+    /// Whether the client may call the route; both the global and the route's lists must
+    /// let it through.
+    pub async fn permits(&self, route: Option<&str>, ip: Option<IpAddr>) -> bool {
+        if !self.global.read().await.permits(ip) {
+            return false;
+        }
+        match route {
+            Some(route) => self.routes.read().await.get(route).map_or(true, |rules| rules.permits(ip)),
+            None => true,
+        }
+    }
+
+    pub async fn rules(&self) -> IpFilterRules {
+        IpFilterRules {
+            global: self.global.read().await.config.clone(),
+            routes: self
+                .routes
+                .read()
+                .await
+                .iter()
+                .map(|(path, rules)| (path.clone(), rules.config.clone()))
+                .collect(),
+        }
+    }
+
+    /// Replaces the global lists, or the route's when `route` is given. Empty lists
+    /// remove the route's rules.
+    pub async fn set(&self, route: Option<&str>, config: IpFilterConfig) -> anyhow::Result<()> {
+        let rules = IpRules::parse(config)?;
+        match route {
+            Some(route) if rules.config.allow.is_empty() && rules.config.deny.is_empty() => {
+                self.routes.write().await.remove(route);
+            }
+            Some(route) => {
+                self.routes.write().await.insert(route.to_string(), rules);
+            }
+            None => *self.global.write().await = rules,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(allow: &[&str], deny: &[&str]) -> IpFilterConfig {
+        IpFilterConfig {
+            allow: allow.iter().map(|range| range.to_string()).collect(),
+            deny: deny.iter().map(|range| range.to_string()).collect(),
+        }
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_global_and_route_lists() {
+        let filter = IpFilter {
+            global: RwLock::new(IpRules::parse(rules(&[], &["203.0.113.0/24"])).unwrap()),
+            routes: RwLock::new(HashMap::new()),
+        };
+        assert!(filter.permits(Some("/api/admin"), ip("198.51.100.7")).await);
+        assert!(!filter.permits(None, ip("203.0.113.9")).await);
+        assert!(filter.permits(None, None).await);
+
+        filter
+            .set(Some("/api/admin"), rules(&["10.0.0.0/8", "192.168.1.10"], &["10.0.0.66"]))
+            .await
+            .unwrap();
+        assert!(filter.permits(Some("/api/admin"), ip("10.1.2.3")).await);
+        assert!(filter.permits(Some("/api/admin"), ip("192.168.1.10")).await);
+        assert!(!filter.permits(Some("/api/admin"), ip("10.0.0.66")).await);
+        assert!(!filter.permits(Some("/api/admin"), ip("198.51.100.7")).await);
+        assert!(!filter.permits(Some("/api/admin"), None).await);
+        assert!(filter.permits(Some("/api/users"), ip("198.51.100.7")).await);
+
+        assert!(filter.set(None, rules(&["not-an-ip"], &[])).await.is_err());
+        filter.set(Some("/api/admin"), IpFilterConfig::default()).await.unwrap();
+        assert!(filter.permits(Some("/api/admin"), ip("198.51.100.7")).await);
+        assert!(filter.rules().await.routes.is_empty());
+    }
+}
@@ -0,0 +1,387 @@
+use dashmap::DashMap;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Bounds `$ref` chains and schema nesting, including recursive schemas.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Violation {
+    /// Where the value was found, e.g. `query.page` or `body.items[0].sku`
+    pub location: String,
+    pub message: String,
+}
+
+impl Violation {
+    pub fn new(location: &str, message: impl Into<String>) -> Self {
+        Self {
+            location: location.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Follows `$ref`s to local definitions, e.g. `#/components/schemas/User`.
+pub fn resolve<'a>(root: &'a Value, mut value: &'a Value) -> &'a Value {
+    for _ in 0..MAX_DEPTH {
+        match value.get("$ref").and_then(Value::as_str).and_then(|reference| lookup(root, reference)) {
+            Some(target) => value = target,
+            None => break,
+        }
+    }
+    value
+}
+
+fn lookup<'a>(root: &'a Value, reference: &str) -> Option<&'a Value> {
+    root.pointer(reference.strip_prefix('#')?)
+}
+
+/// Validates instances against the JSON Schema subset OpenAPI documents use, including
+/// OpenAPI 3.0's `nullable` and boolean `exclusiveMinimum`/`exclusiveMaximum`.
+/// Unknown keywords and formats are ignored.
+pub struct SchemaValidator<'a> {
+    /// Document that `$ref`s are resolved against
+    root: &'a Value,
+    /// `None` for patterns that don't compile, which are ignored
+    regexes: &'a DashMap<String, Option<Regex>>,
+}
+
+impl<'a> SchemaValidator<'a> {
+    pub fn new(root: &'a Value, regexes: &'a DashMap<String, Option<Regex>>) -> Self {
+        Self { root, regexes }
+    }
+
+    pub fn validate(&self, schema: &Value, instance: &Value, location: &str) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        self.check(schema, instance, location, 0, &mut violations);
+        violations
+    }
+
+    fn check(&self, schema: &Value, instance: &Value, location: &str, depth: usize, violations: &mut Vec<Violation>) {
+        if depth > MAX_DEPTH {
+            violations.push(Violation::new(location, "Schema is nested too deeply"));
+            return;
+        }
+        let schema = match schema {
+            Value::Object(schema) => schema,
+            Value::Bool(false) => {
+                violations.push(Violation::new(location, "No value is allowed here"));
+                return;
+            }
+            _ => return,
+        };
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            match lookup(self.root, reference) {
+                Some(target) => self.check(target, instance, location, depth + 1, violations),
+                None => violations.push(Violation::new(location, format!("Unresolvable $ref '{}'", reference))),
+            }
+        }
+
+        if instance.is_null() && schema.get("nullable") == Some(&Value::Bool(true)) {
+            return;
+        }
+
+        if let Some(types) = schema.get("type") {
+            let allowed: Vec<&str> = match types {
+                Value::String(name) => vec![name.as_str()],
+                Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+                _ => Vec::new(),
+            };
+            if !allowed.is_empty() && !allowed.iter().any(|name| type_matches(name, instance)) {
+                violations.push(Violation::new(
+                    location,
+                    format!("Expected {}, found {}", allowed.join(" or "), type_name(instance)),
+                ));
+                return;
+            }
+        }
+
+        if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+            if !allowed.contains(instance) {
+                violations.push(Violation::new(location, format!("{} is not one of the allowed values", instance)));
+            }
+        }
+        if let Some(expected) = schema.get("const") {
+            if expected != instance {
+                violations.push(Violation::new(location, format!("Expected {}", expected)));
+            }
+        }
+
+        match instance {
+            Value::String(text) => self.check_string(schema, text, location, violations),
+            Value::Number(_) => check_number(schema, instance.as_f64().unwrap_or_default(), location, violations),
+            Value::Array(items) => {
+                let len = items.len() as u64;
+                if let Some(min) = schema.get("minItems").and_then(Value::as_u64).filter(|min| len < *min) {
+                    violations.push(Violation::new(location, format!("Expected at least {} items", min)));
+                }
+                if let Some(max) = schema.get("maxItems").and_then(Value::as_u64).filter(|max| len > *max) {
+                    violations.push(Violation::new(location, format!("Expected at most {} items", max)));
+                }
+                if schema.get("uniqueItems") == Some(&Value::Bool(true))
+                    && items.iter().enumerate().any(|(i, item)| items[..i].contains(item))
+                {
+                    violations.push(Violation::new(location, "Items must be unique"));
+                }
+                if let Some(item_schema) = schema.get("items") {
+                    for (i, item) in items.iter().enumerate() {
+                        self.check(item_schema, item, &format!("{}[{}]", location, i), depth + 1, violations);
+                    }
+                }
+            }
+            Value::Object(object) => {
+                for name in schema.get("required").and_then(Value::as_array).into_iter().flatten() {
+                    if let Some(name) = name.as_str().filter(|name| !object.contains_key(*name)) {
+                        violations.push(Violation::new(&format!("{}.{}", location, name), "Missing required property"));
+                    }
+                }
+
+                let len = object.len() as u64;
+                if let Some(min) = schema.get("minProperties").and_then(Value::as_u64).filter(|min| len < *min) {
+                    violations.push(Violation::new(location, format!("Expected at least {} properties", min)));
+                }
+                if let Some(max) = schema.get("maxProperties").and_then(Value::as_u64).filter(|max| len > *max) {
+                    violations.push(Violation::new(location, format!("Expected at most {} properties", max)));
+                }
+
+                let properties = schema.get("properties").and_then(Value::as_object);
+                for (name, value) in object {
+                    let property_location = format!("{}.{}", location, name);
+                    match properties.and_then(|properties| properties.get(name)) {
+                        Some(property_schema) => {
+                            self.check(property_schema, value, &property_location, depth + 1, violations)
+                        }
+                        None => match schema.get("additionalProperties") {
+                            Some(Value::Bool(false)) => {
+                                violations.push(Violation::new(&property_location, "Unexpected property"))
+                            }
+                            Some(additional) => self.check(additional, value, &property_location, depth + 1, violations),
+                            None => {}
+                        },
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        for subschema in schema.get("allOf").and_then(Value::as_array).into_iter().flatten() {
+            self.check(subschema, instance, location, depth + 1, violations);
+        }
+        if let Some(subschemas) = schema.get("anyOf").and_then(Value::as_array) {
+            if !subschemas.iter().any(|subschema| self.passes(subschema, instance, location, depth)) {
+                violations.push(Violation::new(location, "Doesn't match any of the allowed schemas"));
+            }
+        }
+        if let Some(subschemas) = schema.get("oneOf").and_then(Value::as_array) {
+            let matches = subschemas
+                .iter()
+                .filter(|subschema| self.passes(subschema, instance, location, depth))
+                .count();
+            if matches != 1 {
+                violations.push(Violation::new(
+                    location,
+                    format!("Expected exactly one matching schema, found {}", matches),
+                ));
+            }
+        }
+        if let Some(subschema) = schema.get("not") {
+            if self.passes(subschema, instance, location, depth) {
+                violations.push(Violation::new(location, "Matches a schema it must not match"));
+            }
+        }
+    }
+
+    fn passes(&self, schema: &Value, instance: &Value, location: &str, depth: usize) -> bool {
+        let mut violations = Vec::new();
+        self.check(schema, instance, location, depth + 1, &mut violations);
+        violations.is_empty()
+    }
+
+    fn check_string(
+        &self,
+        schema: &serde_json::Map<String, Value>,
+        text: &str,
+        location: &str,
+        violations: &mut Vec<Violation>,
+    ) {
+        let len = text.chars().count() as u64;
+        if let Some(min) = schema.get("minLength").and_then(Value::as_u64).filter(|min| len < *min) {
+            violations.push(Violation::new(location, format!("Expected at least {} characters", min)));
+        }
+        if let Some(max) = schema.get("maxLength").and_then(Value::as_u64).filter(|max| len > *max) {
+            violations.push(Violation::new(location, format!("Expected at most {} characters", max)));
+        }
+        if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+            if let Some(regex) = self.regex(pattern) {
+                if !regex.is_match(text) {
+                    violations.push(Violation::new(location, format!("Doesn't match the pattern '{}'", pattern)));
+                }
+            }
+        }
+        if let Some(format) = schema.get("format").and_then(Value::as_str) {
+            if !format_matches(format, text) {
+                violations.push(Violation::new(location, format!("Not a valid {}", format)));
+            }
+        }
+    }
+
+    fn regex(&self, pattern: &str) -> Option<Regex> {
+        if let Some(regex) = self.regexes.get(pattern) {
+            return regex.clone();
+        }
+        let regex = Regex::new(pattern).ok();
+        self.regexes.insert(pattern.to_string(), regex.clone());
+        regex
+    }
+}
+
+fn check_number(schema: &serde_json::Map<String, Value>, number: f64, location: &str, violations: &mut Vec<Violation>) {
+    let bound = |keyword: &str| schema.get(keyword).and_then(Value::as_f64);
+    // OpenAPI 3.0 makes the exclusive keywords flags on `minimum` and `maximum`
+    let exclusive = |keyword: &str| schema.get(keyword) == Some(&Value::Bool(true));
+
+    if let Some(minimum) = bound("minimum") {
+        if number < minimum || (exclusive("exclusiveMinimum") && number == minimum) {
+            violations.push(Violation::new(location, format!("Must be at least {}", minimum)));
+        }
+    }
+    if let Some(maximum) = bound("maximum") {
+        if number > maximum || (exclusive("exclusiveMaximum") && number == maximum) {
+            violations.push(Violation::new(location, format!("Must be at most {}", maximum)));
+        }
+    }
+    if let Some(minimum) = bound("exclusiveMinimum").filter(|minimum| number <= *minimum) {
+        violations.push(Violation::new(location, format!("Must be greater than {}", minimum)));
+    }
+    if let Some(maximum) = bound("exclusiveMaximum").filter(|maximum| number >= *maximum) {
+        violations.push(Violation::new(location, format!("Must be less than {}", maximum)));
+    }
+    if let Some(divisor) = bound("multipleOf").filter(|divisor| *divisor > 0.0) {
+        let quotient = number / divisor;
+        if (quotient - quotient.round()).abs() > 1e-9 {
+            violations.push(Violation::new(location, format!("Must be a multiple of {}", divisor)));
+        }
+    }
+}
+
+fn type_matches(name: &str, instance: &Value) -> bool {
+    match name {
+        "null" => instance.is_null(),
+        "boolean" => instance.is_boolean(),
+        "string" => instance.is_string(),
+        "number" => instance.is_number(),
+        "integer" => instance.is_i64() || instance.is_u64() || instance.as_f64().map_or(false, |n| n.fract() == 0.0),
+        "array" => instance.is_array(),
+        "object" => instance.is_object(),
+        _ => true,
+    }
+}
+
+fn type_name(instance: &Value) -> &'static str {
+    match instance {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn format_matches(format: &str, text: &str) -> bool {
+    match format {
+        "date-time" => chrono::DateTime::parse_from_rfc3339(text).is_ok(),
+        "date" => chrono::NaiveDate::parse_from_str(text, "%Y-%m-%d").is_ok(),
+        "uuid" => uuid::Uuid::parse_str(text).is_ok(),
+        "email" => text
+            .split_once('@')
+            .map_or(false, |(local, domain)| !local.is_empty() && domain.contains('.') && !domain.contains('@')),
+        "ipv4" => text.parse::<std::net::Ipv4Addr>().is_ok(),
+        "ipv6" => text.parse::<std::net::Ipv6Addr>().is_ok(),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn validate(root: &Value, schema: &Value, instance: Value) -> Vec<String> {
+        let regexes = DashMap::new();
+        SchemaValidator::new(root, &regexes)
+            .validate(schema, &instance, "body")
+            .into_iter()
+            .map(|violation| format!("{}: {}", violation.location, violation.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_object_schema() {
+        let root = json!({
+            "components": { "schemas": {
+                "Item": {
+                    "type": "object",
+                    "required": ["sku", "quantity"],
+                    "additionalProperties": false,
+                    "properties": {
+                        "sku": { "type": "string", "pattern": "^[A-Z]{3}-[0-9]+$" },
+                        "quantity": { "type": "integer", "minimum": 1, "maximum": 10 },
+                        "note": { "type": "string", "nullable": true, "maxLength": 5 }
+                    }
+                }
+            }}
+        });
+        let schema = json!({
+            "type": "object",
+            "required": ["items"],
+            "properties": {
+                "items": { "type": "array", "minItems": 1, "items": { "$ref": "#/components/schemas/Item" } },
+                "email": { "type": "string", "format": "email" },
+                "kind": { "enum": ["retail", "wholesale"] }
+            }
+        });
+
+        let valid = json!({ "items": [{ "sku": "ABC-1", "quantity": 2, "note": null }], "kind": "retail" });
+        assert!(validate(&root, &schema, valid).is_empty());
+
+        let invalid = json!({
+            "items": [{ "sku": "abc", "quantity": 0, "note": "too long", "extra": 1 }, { "quantity": 1.5 }],
+            "email": "nobody",
+            "kind": "other"
+        });
+        assert_eq!(
+            validate(&root, &schema, invalid),
+            vec![
+                "body.email: Not a valid email",
+                "body.items[0].extra: Unexpected property",
+                "body.items[0].note: Expected at most 5 characters",
+                "body.items[0].quantity: Must be at least 1",
+                "body.items[0].sku: Doesn't match the pattern '^[A-Z]{3}-[0-9]+$'",
+                "body.items[1].sku: Missing required property",
+                "body.items[1].quantity: Expected integer, found number",
+                "body.kind: \"other\" is not one of the allowed values",
+            ]
+        );
+        assert_eq!(validate(&root, &schema, json!([])), vec!["body: Expected object, found array"]);
+    }
+
+    #[test]
+    fn test_combinators() {
+        let root = json!({});
+        let schema = json!({
+            "oneOf": [{ "type": "integer" }, { "type": "number", "exclusiveMinimum": 10 }],
+            "not": { "const": 3 }
+        });
+
+        assert!(validate(&root, &schema, json!(4)).is_empty());
+        assert!(validate(&root, &schema, json!(10.5)).is_empty());
+        assert_eq!(validate(&root, &schema, json!(12)), vec!["body: Expected exactly one matching schema, found 2"]);
+        assert_eq!(validate(&root, &schema, json!(3)), vec!["body: Matches a schema it must not match"]);
+
+        let recursive = json!({ "$ref": "#" });
+        assert_eq!(validate(&recursive, &recursive, json!(1)), vec!["body: Schema is nested too deeply"]);
+    }
+}
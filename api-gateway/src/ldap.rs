@@ -0,0 +1,161 @@
+use ldap3::{ldap_escape, LdapConnAsync, LdapConnSettings, LdapError, Scope, SearchEntry};
+use std::time::Duration;
+use tracing::{error, warn};
+
+use crate::auth::{AuthError, Identity};
+use crate::config::LdapConfig;
+
+/// LDAP result code for a failed bind.
+const INVALID_CREDENTIALS: u32 = 49;
+
+pub struct LdapAuthenticator {
+    config: LdapConfig,
+}
+
+impl LdapAuthenticator {
+    pub fn new(config: &LdapConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+
+    pub async fn authenticate(&self, username: &str, password: &str) -> Result<Identity, AuthError> {
+        // An empty password makes the bind unauthenticated, which servers accept
+        if username.is_empty() || password.is_empty() {
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        match tokio::time::timeout(
+            Duration::from_millis(self.config.timeout_ms),
+            self.bind_user(username, password),
+        )
+        .await
+        {
+            Ok(Ok(Some(groups))) => Ok(self.identity(username, &groups)),
+            Ok(Ok(None)) => Err(AuthError::InvalidCredentials),
+            Ok(Err(e)) => {
+                error!("LDAP authentication failed: {}", e);
+                Err(AuthError::StoreUnavailable)
+            }
+            Err(_) => {
+                error!("LDAP authentication timed out");
+                Err(AuthError::StoreUnavailable)
+            }
+        }
+    }
+
+    /// The user's groups, or `None` when the user doesn't exist or the password is wrong.
+    async fn bind_user(&self, username: &str, password: &str) -> Result<Option<Vec<String>>, LdapError> {
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(Duration::from_millis(self.config.timeout_ms))
+            .set_starttls(self.config.starttls);
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.config.url).await?;
+        ldap3::drive!(conn);
+
+        if let Some(bind_dn) = &self.config.bind_dn {
+            ldap.simple_bind(bind_dn, self.config.bind_password.as_deref().unwrap_or(""))
+                .await?
+                .success()?;
+        }
+
+        let filter = self.config.user_filter.replace("{username}", &ldap_escape(username));
+        let (entries, _) = ldap
+            .search(
+                &self.config.user_base_dn,
+                Scope::Subtree,
+                &filter,
+                vec![self.config.group_attribute.as_str()],
+            )
+            .await?
+            .success()?;
+
+        if entries.len() != 1 {
+            if entries.len() > 1 {
+                warn!("LDAP filter matched {} entries for user {}", entries.len(), username);
+            }
+            let _ = ldap.unbind().await;
+            return Ok(None);
+        }
+        let entry = SearchEntry::construct(entries.into_iter().next().unwrap());
+
+        let bound = match ldap.simple_bind(&entry.dn, password).await?.success() {
+            Ok(_) => true,
+            Err(LdapError::LdapResult { result }) if result.rc == INVALID_CREDENTIALS => false,
+            Err(e) => return Err(e),
+        };
+        let _ = ldap.unbind().await;
+
+        Ok(bound.then(|| entry.attrs.get(&self.config.group_attribute).cloned().unwrap_or_default()))
+    }
+
+    fn identity(&self, username: &str, groups: &[String]) -> Identity {
+        let mut scopes: Vec<String> = Vec::new();
+        for group in groups {
+            for scope in self.group_scopes(group) {
+                if !scopes.contains(scope) {
+                    scopes.push(scope.clone());
+                }
+            }
+        }
+
+        let mut claims = serde_json::Map::new();
+        claims.insert("sub".to_string(), username.into());
+        claims.insert("groups".to_string(), groups.into());
+        claims.insert("scope".to_string(), scopes.join(" ").into());
+
+        Identity {
+            user_id: Some(username.to_string()),
+            key_id: None,
+            scopes,
+            claims,
+        }
+    }
+
+    /// Scopes mapped to a group, matched by its full DN or its CN.
+    fn group_scopes(&self, group_dn: &str) -> &[String] {
+        let cn = group_dn
+            .split(',')
+            .next()
+            .and_then(|rdn| rdn.trim().split_once('='))
+            .filter(|(attr, _)| attr.eq_ignore_ascii_case("cn"))
+            .map(|(_, value)| value);
+
+        self.config
+            .group_scopes
+            .iter()
+            .find(|(group, _)| group.eq_ignore_ascii_case(group_dn) || cn.map_or(false, |cn| group.eq_ignore_ascii_case(cn)))
+            .map(|(_, scopes)| scopes.as_slice())
+            .unwrap_or(&[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_scopes_by_dn_or_cn() {
+        let config: LdapConfig = serde_json::from_value(serde_json::json!({
+            "url": "ldap://localhost",
+            "user_base_dn": "dc=example,dc=com",
+            "group_scopes": {
+                "Gateway-Admins": ["admin", "read"],
+                "cn=readers,ou=groups,dc=example,dc=com": ["read"]
+            }
+        }))
+        .unwrap();
+        let ldap = LdapAuthenticator::new(&config);
+
+        let identity = ldap.identity(
+            "jdoe",
+            &[
+                "CN=gateway-admins,OU=Groups,DC=example,DC=com".to_string(),
+                "CN=Readers,OU=Groups,DC=example,DC=com".to_string(),
+                "CN=Unmapped,OU=Groups,DC=example,DC=com".to_string(),
+            ],
+        );
+
+        assert_eq!(identity.scopes, vec!["admin".to_string(), "read".to_string()]);
+        assert_eq!(identity.user_id.as_deref(), Some("jdoe"));
+    }
+}
@@ -0,0 +1,178 @@
+//! The gateway as a library, so it can be embedded in other binaries or exercised in
+//! tests without a process. Start from [`Gateway::builder`].
+
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+
+pub mod access_log;
+pub mod api_keys;
+pub mod config;
+pub mod middleware;
+pub mod proxy;
+pub mod proxy_protocol;
+pub mod rate_limiter;
+pub mod request_signing;
+pub mod health;
+pub mod metrics;
+pub mod auth;
+pub mod basic_auth;
+pub mod admin_auth;
+pub mod concurrency_limiter;
+pub mod client_key;
+pub mod traffic_sampler;
+pub mod upstream_resolver;
+pub mod credentials;
+pub mod csrf;
+pub mod debug_capture;
+pub mod response_cache;
+pub mod header_rules;
+pub mod transform;
+pub mod body_rewrite;
+pub mod xml_json;
+pub mod graphql;
+pub mod composite;
+pub mod batch;
+pub mod openapi;
+pub mod json_schema;
+pub mod openapi_validation;
+pub mod mock;
+pub mod fault_injection;
+pub mod maintenance;
+pub mod ip_filter;
+pub mod geoip;
+pub mod waf;
+pub mod bot_detection;
+pub mod hop_by_hop;
+pub mod forwarded;
+pub mod request_tail;
+pub mod request_records;
+pub mod route_match;
+pub mod scripting;
+pub mod security_headers;
+pub mod events;
+pub mod ext_authz;
+pub mod ext_proc;
+pub mod filters;
+pub mod canary;
+pub mod jwks;
+pub mod ldap;
+pub mod log_sampler;
+pub mod oidc;
+pub mod opa;
+pub mod rewind;
+pub mod route_assertions;
+pub mod server;
+pub mod sessions;
+pub mod smoke;
+pub mod sockets;
+pub mod statsd;
+pub mod streaming;
+pub mod tcp_proxy;
+pub mod udp_proxy;
+pub mod telemetry;
+pub mod tls;
+pub mod token_revocation;
+pub mod wasm;
+pub mod gateway;
+mod handlers;
+
+pub use gateway::{Gateway, GatewayBuilder};
+
+use access_log::AccessLogger;
+use admin_auth::AdminAuth;
+use api_keys::ApiKeyStore;
+use basic_auth::BasicAuthenticator;
+use bot_detection::BotDetector;
+use client_key::ClientKeyExtractor;
+use concurrency_limiter::ConcurrencyLimiter;
+use config::Config;
+use credentials::CredentialStore;
+use csrf::CsrfProtection;
+use debug_capture::DebugCapture;
+use ext_authz::ExtAuthzClient;
+use fault_injection::FaultInjector;
+use filters::FilterRegistry;
+use geoip::GeoIp;
+use health::HealthChecker;
+use ip_filter::IpFilter;
+use jwks::JwtVerifier;
+use log_sampler::LogSampler;
+use maintenance::MaintenanceMode;
+use metrics::MetricsCollector;
+use oidc::OidcClient;
+use opa::PolicyEngine;
+use proxy::ProxyService;
+use rate_limiter::RateLimiter;
+use request_records::RequestRecords;
+use request_signing::RequestVerifier;
+use request_tail::RequestTail;
+use sessions::SessionStore;
+use token_revocation::TokenDenyList;
+use traffic_sampler::TrafficSampler;
+use waf::Waf;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<Config>,
+    pub proxy_service: Arc<ProxyService>,
+    pub traffic_sampler: Arc<TrafficSampler>,
+    pub fault_injector: Arc<FaultInjector>,
+    pub maintenance: Arc<MaintenanceMode>,
+    pub ip_filter: Arc<IpFilter>,
+    pub geoip: Option<Arc<GeoIp>>,
+    pub waf: Option<Arc<Waf>>,
+    pub bot_detector: Option<Arc<BotDetector>>,
+    pub debug_capture: Arc<DebugCapture>,
+    pub credentials: Arc<CredentialStore>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub concurrency_limiter: Arc<ConcurrencyLimiter>,
+    pub client_keys: Arc<ClientKeyExtractor>,
+    pub jwt_verifier: Arc<JwtVerifier>,
+    /// Per-tenant verifiers, keyed by tenant name
+    pub tenant_verifiers: HashMap<String, Arc<JwtVerifier>>,
+    pub api_keys: Arc<ApiKeyStore>,
+    pub request_verifier: Option<Arc<RequestVerifier>>,
+    pub basic_auth: Option<Arc<BasicAuthenticator>>,
+    pub admin_auth: Arc<AdminAuth>,
+    pub ext_authz: Option<Arc<ExtAuthzClient>>,
+    pub policy: Option<Arc<PolicyEngine>>,
+    pub filters: Arc<FilterRegistry>,
+    pub revoked_tokens: Option<Arc<TokenDenyList>>,
+    pub sessions: Option<Arc<SessionStore>>,
+    pub csrf: Option<Arc<CsrfProtection>>,
+    pub oidc: Option<Arc<OidcClient>>,
+    pub health_checker: Arc<HealthChecker>,
+    pub metrics: Arc<MetricsCollector>,
+    pub access_log: Option<Arc<AccessLogger>>,
+    pub request_tail: Arc<RequestTail>,
+    pub request_records: Arc<RequestRecords>,
+    pub log_sampler: Arc<LogSampler>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ApiResponse<T> {
+    pub success: bool,
+    pub data: Option<T>,
+    pub error: Option<String>,
+    pub request_id: String,
+}
+
+impl<T> ApiResponse<T> {
+    pub fn success(data: T, request_id: String) -> Self {
+        Self {
+            success: true,
+            data: Some(data),
+            error: None,
+            request_id,
+        }
+    }
+
+    pub fn error(error: String, request_id: String) -> Self {
+        Self {
+            success: false,
+            data: None,
+            error: Some(error),
+            request_id,
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use dashmap::DashMap;
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use tracing::{debug, error, info, trace, warn};
+
+use crate::config::{LogLevel, LoggingConfig, RouteConfig};
+
+/// Decides which requests get logged, so high-traffic routes don't drown out the rest.
+/// Successful requests are logged 1 in N per route; failed and slow ones always are.
+pub struct LogSampler {
+    sample_rate: u32,
+    slow_request_ms: Option<u64>,
+    counters: DashMap<String, AtomicU64>,
+}
+
+impl LogSampler {
+    pub fn new(config: Option<&LoggingConfig>) -> Self {
+        Self {
+            sample_rate: config.map_or(1, |config| config.sample_rate),
+            slow_request_ms: config.and_then(|config| config.slow_request_ms),
+            counters: DashMap::new(),
+        }
+    }
+
+    /// Whether this request is one of the 1 in N logged whatever its outcome. Called once
+    /// per request, before it's handled.
+    pub fn sample(&self, route: Option<&RouteConfig>) -> bool {
+        let sample_rate = route
+            .and_then(|route| route.logging.as_ref())
+            .and_then(|logging| logging.sample_rate)
+            .unwrap_or(self.sample_rate);
+        if sample_rate <= 1 {
+            return true;
+        }
+
+        let key = route.map(|route| route.path.as_str()).unwrap_or("");
+        let count = match self.counters.get(key) {
+            Some(counter) => counter.fetch_add(1, Ordering::Relaxed),
+            None => self
+                .counters
+                .entry(key.to_string())
+                .or_insert_with(|| AtomicU64::new(0))
+                .fetch_add(1, Ordering::Relaxed),
+        };
+        count % sample_rate as u64 == 0
+    }
+
+    pub fn is_slow(&self, route: Option<&RouteConfig>, duration: Duration) -> bool {
+        route
+            .and_then(|route| route.logging.as_ref())
+            .and_then(|logging| logging.slow_request_ms)
+            .or(self.slow_request_ms)
+            .map_or(false, |slow_request_ms| duration >= Duration::from_millis(slow_request_ms))
+    }
+
+    pub fn level(route: Option<&RouteConfig>) -> LogLevel {
+        route
+            .and_then(|route| route.logging.as_ref())
+            .and_then(|logging| logging.level)
+            .unwrap_or_default()
+    }
+}
+
+/// Emits `message` at a level chosen at runtime, which tracing's macros can't do directly.
+pub fn log_at(level: LogLevel, message: fmt::Arguments) {
+    match level {
+        LogLevel::Off => {}
+        LogLevel::Error => error!("{}", message),
+        LogLevel::Warn => warn!("{}", message),
+        LogLevel::Info => info!("{}", message),
+        LogLevel::Debug => debug!("{}", message),
+        LogLevel::Trace => trace!("{}", message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(sample_rate: Option<u32>) -> RouteConfig {
+        serde_json::from_value(serde_json::json!({
+            "path": "/api/v1/events",
+            "backend": "events",
+            "load_balancing": "round_robin",
+            "rate_limit": null,
+            "auth_required": false,
+            "timeout_ms": null,
+            "max_concurrent_requests": null,
+            "streaming": null,
+            "rate_limit_key": null,
+            "spike_arrest": null,
+            "logging": { "sample_rate": sample_rate, "slow_request_ms": 500 }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_samples_one_in_n_per_route() {
+        let sampler = LogSampler::new(None);
+        let sampled = route(Some(4));
+        let unsampled = route(None);
+
+        let logged = (0..12).filter(|_| sampler.sample(Some(&sampled))).count();
+        assert_eq!(logged, 3);
+        assert!((0..5).all(|_| sampler.sample(Some(&unsampled))));
+
+        assert!(sampler.is_slow(Some(&sampled), Duration::from_millis(750)));
+        assert!(!sampler.is_slow(Some(&sampled), Duration::from_millis(100)));
+        assert!(!sampler.is_slow(None, Duration::from_secs(60)));
+    }
+}
@@ -0,0 +1,227 @@
+use axum::{
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::RwLock;
+use tracing::info;
+
+use crate::config::{MaintenanceConfig, RouteConfig};
+
+/// A route, by path, or every route using a backend.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceTarget {
+    Route(String),
+    Backend(String),
+}
+
+impl std::fmt::Display for MaintenanceTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MaintenanceTarget::Route(path) => write!(f, "route {}", path),
+            MaintenanceTarget::Backend(name) => write!(f, "backend {}", name),
+        }
+    }
+}
+
+/// Names a route or a backend, e.g. `?route=/api/users` or `?backend=users`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MaintenanceSelector {
+    pub route: Option<String>,
+    pub backend: Option<String>,
+}
+
+impl MaintenanceSelector {
+    pub fn target(&self) -> anyhow::Result<MaintenanceTarget> {
+        match (&self.route, &self.backend) {
+            (Some(route), None) => Ok(MaintenanceTarget::Route(route.clone())),
+            (None, Some(backend)) => Ok(MaintenanceTarget::Backend(backend.clone())),
+            _ => Err(anyhow::anyhow!("Exactly one of route or backend is required")),
+        }
+    }
+}
+
+/// Overrides the configured message and Retry-After for one target.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MaintenanceRequest {
+    #[serde(flatten)]
+    pub selector: MaintenanceSelector,
+    pub message: Option<String>,
+    pub retry_after_seconds: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MaintenanceWindow {
+    #[serde(flatten)]
+    pub target: MaintenanceTarget,
+    pub message: String,
+    pub retry_after_seconds: u64,
+    pub since: u64,
+}
+
+/// Routes and backends currently in maintenance. Their routes stay configured but answer
+/// every request with a 503 until maintenance ends.
+pub struct MaintenanceMode {
+    config: MaintenanceConfig,
+    page: Option<String>,
+    windows: RwLock<HashMap<MaintenanceTarget, MaintenanceWindow>>,
+}
+
+impl MaintenanceMode {
+    pub fn new(config: &MaintenanceConfig) -> anyhow::Result<Self> {
+        let page = match &config.page {
+            Some(path) => Some(
+                std::fs::read_to_string(path)
+                    .map_err(|e| anyhow::anyhow!("Failed to read maintenance page {}: {}", path, e))?,
+            ),
+            None => None,
+        };
+
+        let since = now();
+        let targets = config
+            .routes
+            .iter()
+            .cloned()
+            .map(MaintenanceTarget::Route)
+            .chain(config.backends.iter().cloned().map(MaintenanceTarget::Backend));
+        let windows = targets
+            .map(|target| {
+                let window = MaintenanceWindow {
+                    target: target.clone(),
+                    message: config.message.clone(),
+                    retry_after_seconds: config.retry_after_seconds,
+                    since,
+                };
+                (target, window)
+            })
+            .collect();
+
+        Ok(Self {
+            config: config.clone(),
+            page,
+            windows: RwLock::new(windows),
+        })
+    }
+
+    pub async fn start(&self, request: MaintenanceRequest) -> anyhow::Result<MaintenanceWindow> {
+        let target = request.selector.target()?;
+        let window = MaintenanceWindow {
+            target: target.clone(),
+            message: request.message.unwrap_or_else(|| self.config.message.clone()),
+            retry_after_seconds: request.retry_after_seconds.unwrap_or(self.config.retry_after_seconds),
+            since: now(),
+        };
+
+        info!("Maintenance started for {}", target);
+        self.windows.write().await.insert(target, window.clone());
+        Ok(window)
+    }
+
+    pub async fn stop(&self, target: &MaintenanceTarget) -> Option<MaintenanceWindow> {
+        let window = self.windows.write().await.remove(target);
+        if window.is_some() {
+            info!("Maintenance ended for {}", target);
+        }
+        window
+    }
+
+    pub async fn list(&self) -> Vec<MaintenanceWindow> {
+        self.windows.read().await.values().cloned().collect()
+    }
+
+    /// The 503 to answer with if the route, or a backend it uses, is in maintenance.
+    pub async fn check(&self, route: &RouteConfig, headers: &HeaderMap) -> Option<Response> {
+        let windows = self.windows.read().await;
+        if windows.is_empty() {
+            return None;
+        }
+
+        let part_backends = route.composite.iter().flat_map(|composite| &composite.parts).map(|part| &part.backend);
+        let window = std::iter::once(MaintenanceTarget::Route(route.path.clone()))
+            .chain(std::iter::once(&route.backend).chain(part_backends).cloned().map(MaintenanceTarget::Backend))
+            .find_map(|target| windows.get(&target))?;
+        Some(self.respond(window, headers))
+    }
+
+    fn respond(&self, window: &MaintenanceWindow, headers: &HeaderMap) -> Response {
+        let accepts_html = headers
+            .get(header::ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .map_or(false, |accept| accept.contains("text/html"));
+
+        let mut response = match &self.page {
+            Some(page) if accepts_html => (StatusCode::SERVICE_UNAVAILABLE, Html(page.clone())).into_response(),
+            _ => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::json!({
+                    "error": window.message,
+                    "retry_after_seconds": window.retry_after_seconds,
+                })),
+            )
+                .into_response(),
+        };
+        response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(window.retry_after_seconds));
+        response
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn route(path: &str, backend: &str) -> RouteConfig {
+        serde_json::from_value(json!({
+            "path": path,
+            "backend": backend,
+            "load_balancing": "round_robin",
+            "auth_required": false
+        }))
+        .unwrap()
+    }
+
+    fn request(request: serde_json::Value) -> MaintenanceRequest {
+        serde_json::from_value(request).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_route_and_backend_maintenance() {
+        let config: MaintenanceConfig = serde_json::from_value(json!({ "backends": ["billing"] })).unwrap();
+        let maintenance = MaintenanceMode::new(&config).unwrap();
+        let users = route("/api/users", "users");
+        let invoices = route("/api/invoices", "billing");
+
+        assert!(maintenance.check(&users, &HeaderMap::new()).await.is_none());
+        let response = maintenance.check(&invoices, &HeaderMap::new()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "300");
+
+        let window = maintenance
+            .start(request(json!({ "route": "/api/users", "retry_after_seconds": 60 })))
+            .await
+            .unwrap();
+        assert_eq!(serde_json::to_value(&window).unwrap()["route"], "/api/users");
+        let response = maintenance.check(&users, &HeaderMap::new()).await.unwrap();
+        assert_eq!(response.headers()[header::RETRY_AFTER], "60");
+        assert_eq!(maintenance.list().await.len(), 2);
+
+        assert!(maintenance.stop(&MaintenanceTarget::Route("/api/users".to_string())).await.is_some());
+        assert!(maintenance.check(&users, &HeaderMap::new()).await.is_none());
+
+        assert!(maintenance.start(request(json!({ "route": "/a", "backend": "b" }))).await.is_err());
+        assert!(maintenance.start(request(json!({}))).await.is_err());
+    }
+}
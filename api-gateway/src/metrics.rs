@@ -1,24 +1,141 @@
-use prometheus::{Counter, Histogram, Registry, Encoder, TextEncoder};
+use dashmap::DashMap;
+use hdrhistogram::Histogram;
+use prometheus::core::Collector;
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, Encoder, TextEncoder};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
-    sync::Arc,
+    sync::{Arc, Mutex, Once},
     time::Duration,
 };
 use tokio::sync::RwLock;
 use lazy_static::lazy_static;
 
+use crate::statsd::StatsdExporter;
+use crate::telemetry;
+
+static REGISTER_METRICS: Once = Once::new();
+
 lazy_static! {
     static ref REGISTRY: Registry = Registry::new();
-    static ref REQUEST_COUNTER: Counter = Counter::new("gateway_requests_total", "Total number of requests").unwrap();
-    static ref REQUEST_DURATION: Histogram = Histogram::new("gateway_request_duration_seconds", "Request duration in seconds").unwrap();
-    static ref ERROR_COUNTER: Counter = Counter::new("gateway_errors_total", "Total number of errors").unwrap();
-    static ref BACKEND_REQUEST_COUNTER: Counter = Counter::new("gateway_backend_requests_total", "Total number of backend requests").unwrap();
+    static ref REQUEST_COUNTER: IntCounterVec = IntCounterVec::new(
+        Opts::new("gateway_requests_total", "Proxied requests"),
+        &["route", "backend", "method", "status_class"]
+    ).unwrap();
+    static ref REQUEST_DURATION: HistogramVec = HistogramVec::new(
+        HistogramOpts::new("gateway_request_duration_seconds", "Total request duration in seconds, including gateway middleware")
+            .buckets(LATENCY_BUCKETS.to_vec()),
+        &["route", "backend", "method", "status_class"]
+    ).unwrap();
+    static ref UPSTREAM_DURATION: HistogramVec = HistogramVec::new(
+        HistogramOpts::new("gateway_upstream_duration_seconds", "Time spent waiting on the backend in seconds")
+            .buckets(LATENCY_BUCKETS.to_vec()),
+        &["route", "backend"]
+    ).unwrap();
+    static ref RESPONSE_STATUS_COUNTER: IntCounterVec = IntCounterVec::new(
+        Opts::new("gateway_responses_total", "Responses by exact status code, including those the gateway produced itself"),
+        &["route", "status_code", "status_class"]
+    ).unwrap();
+    static ref ERROR_COUNTER: IntCounterVec = IntCounterVec::new(
+        Opts::new("gateway_errors_total", "Requests the gateway failed to proxy"),
+        &["route", "backend"]
+    ).unwrap();
+    static ref BACKEND_REQUEST_COUNTER: IntCounterVec = IntCounterVec::new(
+        Opts::new("gateway_backend_requests_total", "Requests sent to backends"),
+        &["backend", "success"]
+    ).unwrap();
+    static ref IN_FLIGHT_REQUESTS: IntGauge = IntGauge::new(
+        "gateway_in_flight_requests",
+        "Proxied requests waiting on a backend response (streamed bodies not included)"
+    ).unwrap();
+    static ref BACKEND_IN_FLIGHT_REQUESTS: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("gateway_backend_in_flight_requests", "Proxied requests waiting on each backend"),
+        &["backend"]
+    ).unwrap();
+    pub static ref OPEN_CONNECTIONS: IntGauge = IntGauge::new("gateway_open_connections", "Open client connections").unwrap();
+    pub static ref RATE_LIMITER_FALLBACK_ACTIVE: IntGauge = IntGauge::new("gateway_rate_limiter_fallback_active", "Whether the rate limiter has fallen back from Redis to in-memory storage").unwrap();
+    static ref RATE_LIMIT_DECISIONS: IntCounterVec = IntCounterVec::new(
+        Opts::new("gateway_rate_limit_decisions_total", "Rate limiter decisions"),
+        &["limiter", "decision", "key_type", "route", "plan"]
+    ).unwrap();
+    static ref AUTH_REJECTIONS: IntCounterVec = IntCounterVec::new(
+        Opts::new("gateway_auth_rejections_total", "Requests failing authentication or authorization"),
+        &["route", "reason", "mode"]
+    ).unwrap();
+    static ref CONTRACT_MISMATCHES: IntCounterVec = IntCounterVec::new(
+        Opts::new("gateway_contract_mismatches_total", "Backend responses that didn't match the route's OpenAPI operation"),
+        &["route"]
+    ).unwrap();
+    static ref WAF_RULE_HITS: IntCounterVec = IntCounterVec::new(
+        Opts::new("gateway_waf_rule_hits_total", "Requests matching a WAF rule"),
+        &["route", "rule", "action"]
+    ).unwrap();
+    static ref BOT_REQUESTS: IntCounterVec = IntCounterVec::new(
+        Opts::new("gateway_bot_requests_total", "Requests classified as bots, by the action taken"),
+        &["route", "action"]
+    ).unwrap();
+    pub static ref TCP_PROXY_CONNECTIONS: IntCounterVec = IntCounterVec::new(
+        Opts::new("gateway_tcp_proxy_connections_total", "Connections accepted by TCP proxy listeners"),
+        &["listener", "backend", "result"]
+    ).unwrap();
+    pub static ref TCP_PROXY_ACTIVE_CONNECTIONS: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("gateway_tcp_proxy_active_connections", "Open connections on each TCP proxy listener"),
+        &["listener"]
+    ).unwrap();
+    pub static ref UDP_PROXY_SESSIONS: IntGaugeVec = IntGaugeVec::new(
+        Opts::new("gateway_udp_proxy_sessions", "Open sessions on each UDP proxy listener"),
+        &["listener"]
+    ).unwrap();
+    pub static ref UDP_PROXY_DATAGRAMS: IntCounterVec = IntCounterVec::new(
+        Opts::new("gateway_udp_proxy_datagrams_total", "Datagrams relayed by UDP proxy listeners"),
+        &["listener", "direction"]
+    ).unwrap();
+    pub static ref DNS_RESOLUTION_DURATION: HistogramVec = HistogramVec::new(
+        HistogramOpts::new("gateway_dns_resolution_duration_seconds", "Upstream DNS lookups not answered from the cache")
+            .buckets(vec![0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0]),
+        &["result"]
+    ).unwrap();
+    static ref REQUEST_SIZE: HistogramVec = HistogramVec::new(
+        HistogramOpts::new("gateway_request_size_bytes", "Request body size in bytes")
+            .buckets(prometheus::exponential_buckets(64.0, 4.0, 10).unwrap()),
+        &["route"]
+    ).unwrap();
+    static ref RESPONSE_SIZE: HistogramVec = HistogramVec::new(
+        HistogramOpts::new("gateway_response_size_bytes", "Response body size in bytes")
+            .buckets(prometheus::exponential_buckets(64.0, 4.0, 10).unwrap()),
+        &["route"]
+    ).unwrap();
 }
 
+pub const OPENMETRICS_FORMAT: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+const LATENCY_BUCKETS: [f64; 15] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// Slowest latency the percentile sketches track; slower requests are recorded as this.
+const MAX_TRACKED_LATENCY_MICROS: u64 = 5 * 60 * 1_000_000;
+
 #[derive(Clone)]
 pub struct MetricsCollector {
     custom_metrics: Arc<RwLock<HashMap<String, CustomMetric>>>,
+    /// Latency sketches (in microseconds) since startup or the last reset
+    latency: Arc<Mutex<Histogram<u64>>>,
+    route_latency: Arc<DashMap<String, Histogram<u64>>>,
+    upstream_latency: Arc<Mutex<Histogram<u64>>>,
+    backend_latency: Arc<DashMap<String, Histogram<u64>>>,
+    request_rate: Arc<Mutex<RequestRate>>,
+    /// Latest traced observation per latency histogram series and bucket, keyed by
+    /// `series_key`; one slot per bucket plus `+Inf`
+    exemplars: Arc<DashMap<String, Vec<Option<Exemplar>>>>,
+    /// Receives the same request metrics as Prometheus, when configured
+    statsd: Option<Arc<StatsdExporter>>,
+}
+
+/// A trace that produced an observation, shown alongside the bucket it fell into.
+#[derive(Debug, Clone)]
+struct Exemplar {
+    trace_id: String,
+    value: f64,
+    timestamp: f64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -34,12 +151,110 @@ pub struct MetricsSummary {
     pub total_requests: u64,
     pub total_errors: u64,
     pub average_response_time_ms: f64,
+    /// Over the last minute; same as `request_rates.one_minute`
     pub requests_per_second: f64,
+    pub request_rates: RequestRates,
     pub error_rate: f64,
+    pub rate_limiter_fallback_active: bool,
+    pub in_flight_requests: i64,
+    pub open_connections: i64,
+    /// Total latency, including time spent in the gateway
+    pub latency: LatencyPercentiles,
+    pub route_latency: HashMap<String, LatencyPercentiles>,
+    /// Response counts by route, then status code
+    pub route_status_codes: HashMap<String, HashMap<u16, u64>>,
+    /// Rate limiter decisions by route, then decision
+    pub route_rate_limit_decisions: HashMap<String, HashMap<String, u64>>,
+    /// Latency of the backends alone
+    pub upstream_latency: LatencyPercentiles,
+    pub backend_latency: HashMap<String, LatencyPercentiles>,
     pub backend_status: HashMap<String, BackendMetrics>,
     pub custom_metrics: Vec<CustomMetric>,
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LatencyPercentiles {
+    pub count: u64,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub p999_ms: f64,
+    pub max_ms: f64,
+}
+
+impl LatencyPercentiles {
+    fn from_histogram(histogram: &Histogram<u64>) -> Self {
+        let ms = |micros: u64| micros as f64 / 1000.0;
+
+        Self {
+            count: histogram.len(),
+            mean_ms: histogram.mean() / 1000.0,
+            p50_ms: ms(histogram.value_at_quantile(0.5)),
+            p90_ms: ms(histogram.value_at_quantile(0.9)),
+            p99_ms: ms(histogram.value_at_quantile(0.99)),
+            p999_ms: ms(histogram.value_at_quantile(0.999)),
+            max_ms: ms(histogram.max()),
+        }
+    }
+}
+
+/// Requests per second averaged over trailing windows, like load averages.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RequestRates {
+    pub one_minute: f64,
+    pub five_minutes: f64,
+    pub fifteen_minutes: f64,
+}
+
+/// Requests counted in one-second buckets over the longest window. Each bucket remembers
+/// which second it counts, so buckets left over from a previous lap of the ring are
+/// recognised as stale instead of being cleared on a timer.
+struct RequestRate {
+    buckets: Vec<(u64, u64)>,
+    started_at: u64,
+}
+
+const RATE_WINDOW_SECONDS: u64 = 15 * 60;
+
+impl RequestRate {
+    fn new(now: u64) -> Self {
+        Self {
+            buckets: vec![(0, 0); RATE_WINDOW_SECONDS as usize],
+            started_at: now,
+        }
+    }
+
+    fn record(&mut self, now: u64) {
+        let bucket = &mut self.buckets[(now % RATE_WINDOW_SECONDS) as usize];
+        if bucket.0 != now {
+            *bucket = (now, 0);
+        }
+        bucket.1 += 1;
+    }
+
+    /// Average over the last `window` complete seconds, or over the uptime when that's
+    /// shorter, so the rate isn't understated right after startup.
+    fn rate(&self, now: u64, window: u64) -> f64 {
+        let elapsed = now.saturating_sub(self.started_at).clamp(1, window);
+        let count: u64 = self
+            .buckets
+            .iter()
+            .filter(|(second, _)| *second < now && *second >= now - elapsed)
+            .map(|(_, count)| count)
+            .sum();
+        count as f64 / elapsed as f64
+    }
+
+    fn rates(&self, now: u64) -> RequestRates {
+        RequestRates {
+            one_minute: self.rate(now, 60),
+            five_minutes: self.rate(now, 5 * 60),
+            fifteen_minutes: self.rate(now, RATE_WINDOW_SECONDS),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BackendMetrics {
     pub total_requests: u64,
@@ -49,29 +264,150 @@ pub struct BackendMetrics {
 }
 
 impl MetricsCollector {
-    pub fn new() -> Self {
-        // Register metrics with Prometheus
-        REGISTRY.register(Box::new(REQUEST_COUNTER.clone())).unwrap();
-        REGISTRY.register(Box::new(REQUEST_DURATION.clone())).unwrap();
-        REGISTRY.register(Box::new(ERROR_COUNTER.clone())).unwrap();
-        REGISTRY.register(Box::new(BACKEND_REQUEST_COUNTER.clone())).unwrap();
+    pub fn new(statsd: Option<Arc<StatsdExporter>>) -> Self {
+        // The registry is process-wide, while a process may build several gateways
+        REGISTER_METRICS.call_once(|| {
+            // Register metrics with Prometheus
+            REGISTRY.register(Box::new(REQUEST_COUNTER.clone())).unwrap();
+            REGISTRY.register(Box::new(REQUEST_DURATION.clone())).unwrap();
+            REGISTRY.register(Box::new(UPSTREAM_DURATION.clone())).unwrap();
+            REGISTRY.register(Box::new(RESPONSE_STATUS_COUNTER.clone())).unwrap();
+            REGISTRY.register(Box::new(ERROR_COUNTER.clone())).unwrap();
+            REGISTRY.register(Box::new(BACKEND_REQUEST_COUNTER.clone())).unwrap();
+            REGISTRY.register(Box::new(REQUEST_SIZE.clone())).unwrap();
+            REGISTRY.register(Box::new(RATE_LIMITER_FALLBACK_ACTIVE.clone())).unwrap();
+            REGISTRY.register(Box::new(RESPONSE_SIZE.clone())).unwrap();
+            REGISTRY.register(Box::new(RATE_LIMIT_DECISIONS.clone())).unwrap();
+            REGISTRY.register(Box::new(AUTH_REJECTIONS.clone())).unwrap();
+            REGISTRY.register(Box::new(CONTRACT_MISMATCHES.clone())).unwrap();
+            REGISTRY.register(Box::new(WAF_RULE_HITS.clone())).unwrap();
+            REGISTRY.register(Box::new(BOT_REQUESTS.clone())).unwrap();
+            REGISTRY.register(Box::new(IN_FLIGHT_REQUESTS.clone())).unwrap();
+            REGISTRY.register(Box::new(BACKEND_IN_FLIGHT_REQUESTS.clone())).unwrap();
+            REGISTRY.register(Box::new(OPEN_CONNECTIONS.clone())).unwrap();
+            REGISTRY.register(Box::new(DNS_RESOLUTION_DURATION.clone())).unwrap();
+            REGISTRY.register(Box::new(TCP_PROXY_CONNECTIONS.clone())).unwrap();
+            REGISTRY.register(Box::new(TCP_PROXY_ACTIVE_CONNECTIONS.clone())).unwrap();
+            REGISTRY.register(Box::new(UDP_PROXY_SESSIONS.clone())).unwrap();
+            REGISTRY.register(Box::new(UDP_PROXY_DATAGRAMS.clone())).unwrap();
+        });
 
         Self {
             custom_metrics: Arc::new(RwLock::new(HashMap::new())),
+            latency: Arc::new(Mutex::new(new_latency_histogram())),
+            route_latency: Arc::new(DashMap::new()),
+            upstream_latency: Arc::new(Mutex::new(new_latency_histogram())),
+            backend_latency: Arc::new(DashMap::new()),
+            request_rate: Arc::new(Mutex::new(RequestRate::new(unix_seconds()))),
+            exemplars: Arc::new(DashMap::new()),
+            statsd,
         }
     }
 
     pub async fn record_request(&self, method: &str, path: &str) {
-        REQUEST_COUNTER.inc();
-        
         // Record custom metric for method/path combination
         let metric_name = format!("requests_{}_{}", method.to_lowercase(), sanitize_path(path));
         self.increment_custom_metric(&metric_name, 1.0, HashMap::new()).await;
     }
 
+    /// Counts a finished request. `route` is the matched route's path pattern, not the
+    /// request path, so label cardinality stays bounded.
+    pub fn record_response(&self, route: &str, backend: &str, method: &str, status: u16, duration: Duration) {
+        let labels = [route, backend, method_label(method), status_class(status)];
+        REQUEST_COUNTER.with_label_values(&labels).inc();
+        REQUEST_DURATION.with_label_values(&labels).observe(duration.as_secs_f64());
+        self.record_exemplar(
+            "gateway_request_duration_seconds",
+            &[("route", route), ("backend", backend), ("method", labels[2]), ("status_class", labels[3])],
+            duration,
+        );
+        if let Some(statsd) = &self.statsd {
+            let tags = [
+                ("route", labels[0]),
+                ("backend", labels[1]),
+                ("method", labels[2]),
+                ("status_class", labels[3]),
+            ];
+            statsd.count("requests", 1, &tags);
+            statsd.timing("request_duration", duration, &tags);
+        }
+
+        self.request_rate.lock().unwrap().record(unix_seconds());
+
+        let micros = latency_micros(duration);
+        self.latency.lock().unwrap().saturating_record(micros);
+        self.route_latency
+            .entry(route.to_string())
+            .or_insert_with(new_latency_histogram)
+            .saturating_record(micros);
+    }
+
+    /// Counts every response by its exact status code, whether it came from a backend or
+    /// from the gateway rejecting the request (401, 403, 429, ...).
+    pub fn record_status(&self, route: &str, status: u16) {
+        let status_code = status.to_string();
+        RESPONSE_STATUS_COUNTER
+            .with_label_values(&[route, &status_code, status_class(status)])
+            .inc();
+        if let Some(statsd) = &self.statsd {
+            statsd.count(
+                "responses",
+                1,
+                &[("route", route), ("status_code", &status_code), ("status_class", status_class(status))],
+            );
+        }
+    }
+
+    /// The backend's share of a request's latency; the rest was spent in the gateway.
+    pub fn record_upstream_latency(&self, route: &str, backend: &str, duration: Duration) {
+        UPSTREAM_DURATION.with_label_values(&[route, backend]).observe(duration.as_secs_f64());
+        self.record_exemplar("gateway_upstream_duration_seconds", &[("route", route), ("backend", backend)], duration);
+        if let Some(statsd) = &self.statsd {
+            statsd.timing("upstream_duration", duration, &[("route", route), ("backend", backend)]);
+        }
+
+        let micros = latency_micros(duration);
+        self.upstream_latency.lock().unwrap().saturating_record(micros);
+        self.backend_latency
+            .entry(backend.to_string())
+            .or_insert_with(new_latency_histogram)
+            .saturating_record(micros);
+    }
+
+    /// Counts a request as in flight, globally and for `backend`, until the guard drops.
+    pub fn track_in_flight(&self, backend: &str) -> InFlightGuard {
+        InFlightGuard {
+            _total: GaugeGuard::new(IN_FLIGHT_REQUESTS.clone()),
+            _backend: GaugeGuard::new(BACKEND_IN_FLIGHT_REQUESTS.with_label_values(&[backend])),
+        }
+    }
+
+    fn record_exemplar(&self, histogram: &str, labels: &[(&str, &str)], duration: Duration) {
+        let trace_id = match telemetry::current_trace_id() {
+            Some(trace_id) => trace_id,
+            None => return,
+        };
+
+        let value = duration.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|le| value <= *le)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64();
+
+        self.exemplars
+            .entry(series_key(histogram, labels.iter().map(|(name, value)| (*name, *value))))
+            .or_insert_with(|| vec![None; LATENCY_BUCKETS.len() + 1])[bucket] = Some(Exemplar {
+            trace_id,
+            value,
+            timestamp,
+        });
+    }
+
     pub async fn record_response_time(&self, duration: Duration) {
-        REQUEST_DURATION.observe(duration.as_secs_f64());
-        
         // Record custom metric for response time
         let mut labels = HashMap::new();
         labels.insert("unit".to_string(), "milliseconds".to_string());
@@ -83,8 +419,62 @@ impl MetricsCollector {
         ).await;
     }
 
-    pub async fn record_error(&self, error_type: &str) {
-        ERROR_COUNTER.inc();
+    pub fn record_body_sizes(&self, route: &str, request_bytes: usize, response_bytes: Option<usize>) {
+        REQUEST_SIZE.with_label_values(&[route]).observe(request_bytes as f64);
+        if let Some(response_bytes) = response_bytes {
+            RESPONSE_SIZE.with_label_values(&[route]).observe(response_bytes as f64);
+        }
+    }
+
+    /// `decision` is one of `allowed`, `denied`, `shadow_denied` or `exempt`.
+    pub fn record_rate_limit_decision(&self, limiter: &str, decision: &str, key_type: &str, route: &str, plan: &str) {
+        RATE_LIMIT_DECISIONS
+            .with_label_values(&[limiter, decision, key_type, route, plan])
+            .inc();
+        if let Some(statsd) = &self.statsd {
+            statsd.count(
+                "rate_limit_decisions",
+                1,
+                &[("limiter", limiter), ("decision", decision), ("key_type", key_type), ("route", route), ("plan", plan)],
+            );
+        }
+    }
+
+    /// Counts a failed auth check; in `monitor` mode the request was let through anyway.
+    pub fn record_auth_rejection(&self, route: &str, reason: &str, mode: &str) {
+        AUTH_REJECTIONS.with_label_values(&[route, reason, mode]).inc();
+        if let Some(statsd) = &self.statsd {
+            statsd.count("auth_rejections", 1, &[("route", route), ("reason", reason), ("mode", mode)]);
+        }
+    }
+
+    pub fn record_contract_mismatch(&self, route: &str) {
+        CONTRACT_MISMATCHES.with_label_values(&[route]).inc();
+        if let Some(statsd) = &self.statsd {
+            statsd.count("contract_mismatches", 1, &[("route", route)]);
+        }
+    }
+
+    /// `action` is `detected` or `blocked`, depending on the WAF mode.
+    pub fn record_waf_hit(&self, route: &str, rule: &str, action: &str) {
+        WAF_RULE_HITS.with_label_values(&[route, rule, action]).inc();
+        if let Some(statsd) = &self.statsd {
+            statsd.count("waf_rule_hits", 1, &[("route", route), ("rule", rule), ("action", action)]);
+        }
+    }
+
+    pub fn record_bot_request(&self, route: &str, action: &str) {
+        BOT_REQUESTS.with_label_values(&[route, action]).inc();
+        if let Some(statsd) = &self.statsd {
+            statsd.count("bot_requests", 1, &[("route", route), ("action", action)]);
+        }
+    }
+
+    pub async fn record_error(&self, route: &str, backend: &str, error_type: &str) {
+        ERROR_COUNTER.with_label_values(&[route, backend]).inc();
+        if let Some(statsd) = &self.statsd {
+            statsd.count("errors", 1, &[("route", route), ("backend", backend)]);
+        }
         
         // Record custom metric for error type
         let mut labels = HashMap::new();
@@ -94,7 +484,14 @@ impl MetricsCollector {
     }
 
     pub async fn record_backend_request(&self, backend_name: &str, success: bool, response_time: Duration) {
-        BACKEND_REQUEST_COUNTER.inc();
+        BACKEND_REQUEST_COUNTER
+            .with_label_values(&[backend_name, if success { "true" } else { "false" }])
+            .inc();
+        if let Some(statsd) = &self.statsd {
+            let tags = [("backend", backend_name), ("success", if success { "true" } else { "false" })];
+            statsd.count("backend_requests", 1, &tags);
+            statsd.timing("backend_response_time", response_time, &tags);
+        }
         
         let mut labels = HashMap::new();
         labels.insert("backend".to_string(), backend_name.to_string());
@@ -152,22 +549,32 @@ impl MetricsCollector {
         let custom_metrics = self.custom_metrics.read().await;
         
         // Calculate summary statistics
-        let total_requests = REQUEST_COUNTER.get() as u64;
-        let total_errors = ERROR_COUNTER.get() as u64;
+        let total_requests = counter_total(&REQUEST_COUNTER);
+        let total_errors = counter_total(&ERROR_COUNTER);
         let error_rate = if total_requests > 0 {
             (total_errors as f64 / total_requests as f64) * 100.0
         } else {
             0.0
         };
 
-        // Get average response time from custom metrics
-        let average_response_time_ms = custom_metrics
-            .get("response_time_ms")
-            .map(|m| m.value)
-            .unwrap_or(0.0);
+        let latency = LatencyPercentiles::from_histogram(&self.latency.lock().unwrap());
+        let average_response_time_ms = latency.mean_ms;
+        let route_latency = self
+            .route_latency
+            .iter()
+            .map(|entry| (entry.key().clone(), LatencyPercentiles::from_histogram(entry.value())))
+            .collect();
+        let route_status_codes = route_status_codes();
+        let route_rate_limit_decisions = route_rate_limit_decisions();
+        let upstream_latency = LatencyPercentiles::from_histogram(&self.upstream_latency.lock().unwrap());
+        let backend_latency = self
+            .backend_latency
+            .iter()
+            .map(|entry| (entry.key().clone(), LatencyPercentiles::from_histogram(entry.value())))
+            .collect();
 
-        // Calculate requests per second (simplified - would need time window in production)
-        let requests_per_second = total_requests as f64 / 60.0; // Rough estimate
+        let request_rates = self.request_rate.lock().unwrap().rates(unix_seconds());
+        let requests_per_second = request_rates.one_minute;
 
         // Collect backend metrics
         let mut backend_status = HashMap::new();
@@ -191,7 +598,17 @@ impl MetricsCollector {
             total_errors,
             average_response_time_ms,
             requests_per_second,
+            request_rates,
             error_rate,
+            rate_limiter_fallback_active: RATE_LIMITER_FALLBACK_ACTIVE.get() == 1,
+            in_flight_requests: IN_FLIGHT_REQUESTS.get(),
+            open_connections: OPEN_CONNECTIONS.get(),
+            latency,
+            route_latency,
+            route_status_codes,
+            route_rate_limit_decisions,
+            upstream_latency,
+            backend_latency,
             backend_status,
             custom_metrics: custom_metrics.values().cloned().collect(),
         }
@@ -210,9 +627,21 @@ impl MetricsCollector {
         }
     }
 
+    /// The same metrics in OpenMetrics format, with trace exemplars on the latency
+    /// histogram buckets. Prometheus only reads exemplars in this format.
+    pub fn get_openmetrics(&self) -> String {
+        render_openmetrics(&self.get_prometheus_metrics(), &self.exemplars)
+    }
+
     pub async fn reset_metrics(&self) {
         let mut custom_metrics = self.custom_metrics.write().await;
         custom_metrics.clear();
+        self.latency.lock().unwrap().reset();
+        self.route_latency.clear();
+        self.upstream_latency.lock().unwrap().reset();
+        self.backend_latency.clear();
+        *self.request_rate.lock().unwrap() = RequestRate::new(unix_seconds());
+        self.exemplars.clear();
         
         // Note: Prometheus metrics cannot be reset easily
         // In production, you might want to use a different approach
@@ -236,6 +665,209 @@ impl MetricsCollector {
     }
 }
 
+/// Holds a gauge one higher for as long as it's alive, however the holder exits.
+pub struct GaugeGuard(IntGauge);
+
+impl GaugeGuard {
+    pub fn new(gauge: IntGauge) -> Self {
+        gauge.inc();
+        Self(gauge)
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+pub struct InFlightGuard {
+    _total: GaugeGuard,
+    _backend: GaugeGuard,
+}
+
+fn new_latency_histogram() -> Histogram<u64> {
+    Histogram::new_with_bounds(1, MAX_TRACKED_LATENCY_MICROS, 3).expect("valid histogram bounds")
+}
+
+fn unix_seconds() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn latency_micros(duration: Duration) -> u64 {
+    (duration.as_micros() as u64).clamp(1, MAX_TRACKED_LATENCY_MICROS)
+}
+
+/// Sum of a counter over all its label values.
+fn counter_total(counter: &IntCounterVec) -> u64 {
+    counter
+        .collect()
+        .iter()
+        .flat_map(|family| family.get_metric())
+        .map(|metric| metric.get_counter().get_value() as u64)
+        .sum()
+}
+
+/// Identifies a histogram series regardless of label order.
+fn series_key<'a>(histogram: &str, labels: impl Iterator<Item = (&'a str, &'a str)>) -> String {
+    let mut labels: Vec<String> = labels.map(|(name, value)| format!("{}={:?}", name, value)).collect();
+    labels.sort();
+    format!("{}{{{}}}", histogram, labels.join(","))
+}
+
+/// Converts Prometheus text format to OpenMetrics: counter families lose their `_total`
+/// suffix, latency buckets get their exemplars, and the output ends with `# EOF`.
+fn render_openmetrics(text: &str, exemplars: &DashMap<String, Vec<Option<Exemplar>>>) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut output = String::with_capacity(text.len() + 16);
+
+    for (index, line) in lines.iter().enumerate() {
+        if let Some(rest) = line.strip_prefix("# HELP ") {
+            let (name, help) = rest.split_once(' ').unwrap_or((rest, ""));
+            // HELP comes just before its family's TYPE line
+            let counter = lines.get(index + 1) == Some(&format!("# TYPE {} counter", name).as_str());
+            match name.strip_suffix("_total").filter(|_| counter) {
+                Some(family) => output.push_str(&format!("# HELP {} {}", family, help)),
+                None => output.push_str(line),
+            }
+        } else if let Some(family) = line
+            .strip_prefix("# TYPE ")
+            .and_then(|rest| rest.strip_suffix(" counter"))
+            .and_then(|name| name.strip_suffix("_total"))
+        {
+            output.push_str(&format!("# TYPE {} counter", family));
+        } else {
+            output.push_str(line);
+            if let Some(exemplar) = bucket_exemplar(line, exemplars) {
+                output.push_str(&format!(
+                    " # {{trace_id=\"{}\"}} {} {:.3}",
+                    exemplar.trace_id, exemplar.value, exemplar.timestamp
+                ));
+            }
+        }
+        output.push('\n');
+    }
+
+    output.push_str("# EOF\n");
+    output
+}
+
+/// The exemplar for a `<histogram>_bucket{...,le="..."} <count>` sample line, if any.
+fn bucket_exemplar(line: &str, exemplars: &DashMap<String, Vec<Option<Exemplar>>>) -> Option<Exemplar> {
+    let open = line.find("_bucket{")?;
+    let close = line.rfind('}')?;
+    let labels = parse_labels(&line[open + "_bucket{".len()..close])?;
+
+    let le = labels.iter().find(|(name, _)| name == "le").map(|(_, value)| value.as_str())?;
+    let bucket = match le {
+        "+Inf" => LATENCY_BUCKETS.len(),
+        le => {
+            let le: f64 = le.parse().ok()?;
+            LATENCY_BUCKETS.iter().position(|bound| (bound - le).abs() < 1e-9)?
+        }
+    };
+
+    let key = series_key(
+        &line[..open],
+        labels
+            .iter()
+            .filter(|(name, _)| name != "le")
+            .map(|(name, value)| (name.as_str(), value.as_str())),
+    );
+    exemplars.get(&key)?.get(bucket)?.clone()
+}
+
+/// `name="value",...` with the text format's escaping, or `None` if malformed.
+fn parse_labels(text: &str) -> Option<Vec<(String, String)>> {
+    let mut labels = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while chars.peek().is_some() {
+        let name: String = chars.by_ref().take_while(|c| *c != '=').collect();
+        if chars.next() != Some('"') {
+            return None;
+        }
+
+        let mut value = String::new();
+        loop {
+            match chars.next()? {
+                '"' => break,
+                '\\' => match chars.next()? {
+                    'n' => value.push('\n'),
+                    escaped => value.push(escaped),
+                },
+                c => value.push(c),
+            }
+        }
+
+        labels.push((name.trim().to_string(), value));
+        if chars.peek() == Some(&',') {
+            chars.next();
+        }
+    }
+
+    Some(labels)
+}
+
+fn route_status_codes() -> HashMap<String, HashMap<u16, u64>> {
+    let mut counts: HashMap<String, HashMap<u16, u64>> = HashMap::new();
+
+    for metric in RESPONSE_STATUS_COUNTER.collect().iter().flat_map(|family| family.get_metric()) {
+        let label = |name: &str| {
+            metric
+                .get_label()
+                .iter()
+                .find(|pair| pair.get_name() == name)
+                .map(|pair| pair.get_value().to_string())
+        };
+        if let (Some(route), Some(status)) = (label("route"), label("status_code").and_then(|s| s.parse().ok())) {
+            *counts.entry(route).or_default().entry(status).or_default() += metric.get_counter().get_value() as u64;
+        }
+    }
+
+    counts
+}
+
+fn route_rate_limit_decisions() -> HashMap<String, HashMap<String, u64>> {
+    let mut counts: HashMap<String, HashMap<String, u64>> = HashMap::new();
+
+    for metric in RATE_LIMIT_DECISIONS.collect().iter().flat_map(|family| family.get_metric()) {
+        let label = |name: &str| {
+            metric
+                .get_label()
+                .iter()
+                .find(|pair| pair.get_name() == name)
+                .map(|pair| pair.get_value().to_string())
+        };
+        if let (Some(route), Some(decision)) = (label("route"), label("decision")) {
+            *counts.entry(route).or_default().entry(decision).or_default() += metric.get_counter().get_value() as u64;
+        }
+    }
+
+    counts
+}
+
+/// Unusual methods share one label value, so clients can't mint new series.
+fn method_label(method: &str) -> &str {
+    match method {
+        "GET" | "HEAD" | "POST" | "PUT" | "PATCH" | "DELETE" | "OPTIONS" => method,
+        _ => "OTHER",
+    }
+}
+
+fn status_class(status: u16) -> &'static str {
+    match status {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    }
+}
+
 fn sanitize_path(path: &str) -> String {
     path.replace('/', "_")
         .replace('-', "_")
@@ -243,4 +875,86 @@ fn sanitize_path(path: &str) -> String {
         .chars()
         .filter(|c| c.is_alphanumeric() || *c == '_')
         .collect()
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_percentiles() {
+        let mut histogram = new_latency_histogram();
+        for ms in 1..=1000u64 {
+            histogram.record(ms * 1000).unwrap();
+        }
+
+        let latency = LatencyPercentiles::from_histogram(&histogram);
+        assert_eq!(latency.count, 1000);
+        assert!((latency.p50_ms - 500.0).abs() < 1.0);
+        assert!((latency.p99_ms - 990.0).abs() < 1.0);
+        assert!((latency.max_ms - 1000.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_openmetrics_exemplars() {
+        let exemplars = DashMap::new();
+        let mut slots = vec![None; LATENCY_BUCKETS.len() + 1];
+        slots[6] = Some(Exemplar {
+            trace_id: "4bf92f3577b34da6a3ce929d0e0e4736".to_string(),
+            value: 0.087,
+            timestamp: 1_700_000_000.5,
+        });
+        exemplars.insert(
+            series_key("gateway_upstream_duration_seconds", [("route", "/api/v1/users"), ("backend", "users")].into_iter()),
+            slots,
+        );
+
+        let text = "# HELP gateway_errors_total Requests the gateway failed to proxy\n\
+            # TYPE gateway_errors_total counter\n\
+            gateway_errors_total{backend=\"users\",route=\"/api/v1/users\"} 3\n\
+            gateway_upstream_duration_seconds_bucket{backend=\"users\",route=\"/api/v1/users\",le=\"0.05\"} 4\n\
+            gateway_upstream_duration_seconds_bucket{backend=\"users\",route=\"/api/v1/users\",le=\"0.1\"} 9\n";
+
+        let rendered = render_openmetrics(text, &exemplars);
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[0], "# HELP gateway_errors Requests the gateway failed to proxy");
+        assert_eq!(lines[1], "# TYPE gateway_errors counter");
+        assert_eq!(lines[2], "gateway_errors_total{backend=\"users\",route=\"/api/v1/users\"} 3");
+        assert!(!lines[3].contains('#'));
+        assert_eq!(
+            lines[4],
+            "gateway_upstream_duration_seconds_bucket{backend=\"users\",route=\"/api/v1/users\",le=\"0.1\"} 9 \
+             # {trace_id=\"4bf92f3577b34da6a3ce929d0e0e4736\"} 0.087 1700000000.500"
+        );
+        assert_eq!(lines[5], "# EOF");
+    }
+
+    #[test]
+    fn test_request_rates_over_windows() {
+        let start = 1_700_000_000;
+        let mut rate = RequestRate::new(start);
+
+        // 10 requests a second for the first two minutes, then nothing
+        for second in start..start + 120 {
+            for _ in 0..10 {
+                rate.record(second);
+            }
+        }
+
+        // Half a minute in, the rate is averaged over the uptime rather than a full minute
+        assert!((rate.rates(start + 30).one_minute - 10.0).abs() < 0.01);
+
+        let rates = rate.rates(start + 180);
+        assert_eq!(rates.one_minute, 0.0);
+        assert!((rates.five_minutes - 1200.0 / 180.0).abs() < 0.01);
+
+        let rates = rate.rates(start + 600);
+        assert!((rates.five_minutes - 0.0).abs() < 0.01);
+        assert!((rates.fifteen_minutes - 2.0).abs() < 0.01);
+
+        // A lap of the ring later, the first seconds' buckets are reused; seconds 6 to 119
+        // of the burst are still in the window, plus the new request
+        rate.record(start + RATE_WINDOW_SECONDS + 5);
+        let rates = rate.rates(start + RATE_WINDOW_SECONDS + 6);
+        assert!((rates.fifteen_minutes - 1141.0 / 900.0).abs() < 0.001);
+    }
+}
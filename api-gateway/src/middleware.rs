@@ -1,143 +1,1120 @@
 use axum::{
     extract::{Request, State},
-    http::{HeaderMap, StatusCode},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Redirect, Response},
+    Json,
 };
-use tracing::{info, warn};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 use uuid::Uuid;
 
-use crate::{auth::AuthService, AppState};
+use crate::{access_log::AccessLogEntry, admin_auth::{AdminAuth, DASHBOARD_PATH}, auth::{AuthError, AuthService, Identity}, basic_auth::BasicAuthenticator, bot_detection, ext_authz::ExtAuthzDecision, geoip::{self, ClientCountry}, log_sampler::{log_at, LogSampler}, security_headers, config::{path_matches, AdminRole, AuthEnforcementMode, IdentityHeadersConfig, BotAction, LogLevel, RouteConfig, TenantConfig, WafMode}, oidc::{self, OidcSession}, proxy::ProxiedRequestInfo, rate_limiter::EffectiveLimits, request_records::{self, RequestRecord, RequestTrace}, request_signing::{RequestVerifier, MAX_SIGNED_BODY_BYTES}, sessions, telemetry, ApiResponse, AppState};
+
+/// When the gateway first saw the request, so handlers can report latency including
+/// the time spent in middleware.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestStart(pub std::time::Instant);
 
 pub async fn logging_middleware(
     State(state): State<AppState>,
     request: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
+    let start_time = std::time::Instant::now();
     let method = request.method().clone();
     let uri = request.uri().clone();
     let request_id = Uuid::new_v4().to_string();
+    let client_ip = state.client_keys.client_ip(&request);
+    let user_agent = request
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from);
     
     // Add request ID to headers
     let (mut parts, body) = request.into_parts();
     parts.headers.insert("X-Request-ID", request_id.parse().unwrap());
+    parts.extensions.insert(RequestStart(start_time));
+    let trace = state.request_records.enabled().then(|| RequestTrace::new(start_time));
+    if let Some(trace) = &trace {
+        parts.extensions.insert(trace.clone());
+    }
     let request = Request::from_parts(parts, body);
 
-    info!(
-        "Request started: {} {} (request_id: {})",
-        method,
-        uri,
-        request_id
-    );
+    let route = find_route(&state, uri.path());
+    let level = LogSampler::level(route);
+    let sampled = state.log_sampler.sample(route);
 
-    let start_time = std::time::Instant::now();
-    let response = next.run(request).await;
+    if sampled {
+        log_at(level, format_args!("Request started: {} {} (request_id: {})", method, uri, request_id));
+    }
+
+    // Root span of the request's trace; route matching, auth, rate limiting and the
+    // upstream call show up as its children
+    let span = info_span!("request", method = %method, path = %uri.path(), request_id = %request_id);
+    telemetry::continue_trace(&span, request.headers());
+
+    let mut response = next.run(request).instrument(span).await;
     let duration = start_time.elapsed();
+    // So callers can quote it when asking what happened to their request
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert("X-Request-ID", value);
+    }
 
-    info!(
-        "Request completed: {} {} {} (duration: {:?}, request_id: {})",
-        method,
-        uri,
-        response.status(),
-        duration,
-        request_id
-    );
+    state
+        .metrics
+        .record_status(route.map(|route| route.path.as_str()).unwrap_or("unmatched"), response.status().as_u16());
+
+    let failed = response.status().is_client_error() || response.status().is_server_error();
+    let slow = state.log_sampler.is_slow(route, duration);
+    let logged = sampled || failed || slow;
+
+    if logged {
+        // Routes turned off still report their failures and slow requests
+        let level = if level == LogLevel::Off { LogLevel::Warn } else { level };
+        log_at(
+            level,
+            format_args!(
+                "Request completed: {} {} {} (duration: {:?}, request_id: {}{})",
+                method,
+                uri,
+                response.status(),
+                duration,
+                request_id,
+                if slow { ", slow" } else { "" }
+            ),
+        );
+    }
+
+    let proxied = response.extensions().get::<ProxiedRequestInfo>();
+    if let Some(trace) = &trace {
+        let record = RequestRecord {
+            request_id: request_id.clone(),
+            timestamp: chrono::Utc::now(),
+            client_ip,
+            method: method.to_string(),
+            path: uri.path().to_string(),
+            status: response.status().as_u16(),
+            duration_ms: duration.as_secs_f64() * 1000.0,
+            route: proxied.map(|info| info.route.clone()).or_else(|| route.map(|route| route.path.clone())),
+            backend: proxied.map(|info| info.backend.clone()),
+            server: proxied.and_then(|info| info.server.clone()),
+            attempts: proxied.map_or(0, |info| info.attempts),
+            upstream_ms: proxied.map(|info| info.upstream_duration.as_secs_f64() * 1000.0),
+            auth: None,
+            stages: Vec::new(),
+        };
+        state.request_records.record(record, trace);
+    }
+
+    // `/admin/tail` sees every request, not just the sampled ones
+    let access_log = state.access_log.as_ref().filter(|_| logged);
+    if access_log.is_none() && !state.request_tail.is_active() {
+        return Ok(response);
+    }
+
+    let entry = AccessLogEntry {
+        timestamp: chrono::Utc::now(),
+        request_id,
+        client_ip,
+        method: method.to_string(),
+        path: uri.path().to_string(),
+        status: response.status().as_u16(),
+        duration_ms: duration.as_secs_f64() * 1000.0,
+        user_agent,
+        route: proxied.map(|info| info.route.clone()),
+        backend: proxied.map(|info| info.backend.clone()),
+        request_bytes: proxied.map(|info| info.request_bytes),
+        response_bytes: proxied.and_then(|info| info.response_bytes),
+    };
+    state.request_tail.publish(&entry);
+    if let Some(access_log) = access_log {
+        access_log.log(entry);
+    }
 
     Ok(response)
 }
 
+/// Adds the configured security headers to every response, including the gateway's own errors.
+pub async fn security_headers_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let config = match &state.config.security_headers {
+        Some(config) => config,
+        None => return Ok(next.run(request).await),
+    };
+    let route = find_route(&state, request.uri().path());
+
+    let mut response = next.run(request).await;
+    security_headers::apply(config, route.and_then(|r| r.security_headers.as_ref()), response.headers_mut());
+    Ok(response)
+}
+
+/// Rejects clients outside the global or route's IP allowlist, or inside a denylist.
+pub async fn ip_filter_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let route = find_route(&state, request.uri().path());
+    let client_ip = state.client_keys.client_ip(&request);
+
+    if state.ip_filter.permits(route.map(|r| r.path.as_str()), client_ip).await {
+        return Ok(next.run(request).await);
+    }
+
+    warn!(
+        "Blocked request from {} to {}",
+        client_ip.map(|ip| ip.to_string()).unwrap_or_else(|| "unknown address".to_string()),
+        request.uri().path()
+    );
+    let request_id = request
+        .headers()
+        .get("X-Request-ID")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+
+    Ok((
+        StatusCode::FORBIDDEN,
+        Json(ApiResponse::<()>::error("Access denied for client address".to_string(), request_id)),
+    ).into_response())
+}
+
+/// Looks up the client's country, refusing it when the route's country rules don't
+/// allow it. The country is kept on the request for regional routing.
+pub async fn geoip_middleware(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let geoip = match &state.geoip {
+        Some(geoip) => geoip,
+        None => return Ok(next.run(request).await),
+    };
+
+    // Only the gateway gets to say where a client is
+    request.headers_mut().remove(geoip::COUNTRY_HEADER);
+    let country = state.client_keys.client_ip(&request).and_then(|ip| geoip.country(ip));
+
+    if let Some(route) = find_route(&state, request.uri().path()) {
+        if let Some(geo) = &route.geo {
+            if !geoip::permits(geo, country.as_deref()) {
+                warn!(
+                    "Blocked request from {} to {}",
+                    country.as_deref().unwrap_or("unknown country"),
+                    request.uri().path()
+                );
+                let request_id = request
+                    .headers()
+                    .get("X-Request-ID")
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string();
+                return Ok((
+                    StatusCode::FORBIDDEN,
+                    Json(ApiResponse::<()>::error("Access denied from this country".to_string(), request_id)),
+                ).into_response());
+            }
+        }
+    }
+
+    if let Some(country) = country {
+        if geoip.add_country_header {
+            if let Ok(value) = HeaderValue::from_str(&country) {
+                request.headers_mut().insert(geoip::COUNTRY_HEADER, value);
+            }
+        }
+        request.extensions_mut().insert(ClientCountry(country));
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Matches the request against the WAF rules. Bodies are only inspected when their
+/// declared length is within `waf.max_body_bytes`.
+pub async fn waf_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    request_records::mark(request.extensions(), "waf");
+
+    let waf = match &state.waf {
+        Some(waf) => waf,
+        None => return Ok(next.run(request).await),
+    };
+    let route = find_route(&state, request.uri().path());
+    let mode = match waf.mode(route) {
+        Some(mode) => mode,
+        None => return Ok(next.run(request).await),
+    };
+
+    let inspect_body = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok())
+        .map_or(false, |length| length > 0 && length <= waf.max_body_bytes());
+    let (parts, body) = request.into_parts();
+    let (body, hits) = if inspect_body {
+        let bytes = axum::body::to_bytes(body, waf.max_body_bytes())
+            .await
+            .map_err(|_| StatusCode::BAD_REQUEST)?;
+        let hits = waf.inspect(route, &parts.uri, &parts.headers, &bytes);
+        (axum::body::Body::from(bytes), hits)
+    } else {
+        (body, waf.inspect(route, &parts.uri, &parts.headers, &[]))
+    };
+    let request = Request::from_parts(parts, body);
+
+    if hits.is_empty() {
+        return Ok(next.run(request).await);
+    }
+
+    let route_label = route.map(|r| r.path.as_str()).unwrap_or("unmatched");
+    let action = match mode {
+        WafMode::Detect => "detected",
+        WafMode::Block => "blocked",
+    };
+    for hit in &hits {
+        warn!(
+            "WAF rule {} matched the {} of {} {} ({})",
+            hit.rule,
+            hit.target.as_str(),
+            request.method(),
+            request.uri().path(),
+            action
+        );
+        state.metrics.record_waf_hit(route_label, hit.rule, action);
+    }
+
+    if mode == WafMode::Detect {
+        return Ok(next.run(request).await);
+    }
+
+    let request_id = request
+        .headers()
+        .get("X-Request-ID")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    Ok((
+        StatusCode::FORBIDDEN,
+        Json(ApiResponse::<()>::error("Request blocked".to_string(), request_id)),
+    ).into_response())
+}
+
+/// Applies the route's bot action to requests classified as bots. Clients without a
+/// known address are still scored on their headers.
+pub async fn bot_detection_middleware(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    request_records::mark(request.extensions(), "bot_detection");
+
+    let detector = match &state.bot_detector {
+        Some(detector) => detector,
+        None => return Ok(next.run(request).await),
+    };
+    let route = find_route(&state, request.uri().path());
+    let action = detector.action(route);
+    if action == BotAction::Allow {
+        return Ok(next.run(request).await);
+    }
+
+    let client_ip = state.client_keys.client_ip(&request);
+    let verdict = detector.classify(request.headers(), client_ip);
+    let solved = action == BotAction::Challenge && detector.solved(request.headers());
+    request.headers_mut().remove(bot_detection::CHALLENGE_RESPONSE_HEADER);
+    if !detector.is_bot(&verdict) || solved {
+        return Ok(next.run(request).await);
+    }
+
+    let route_label = route.map(|r| r.path.as_str()).unwrap_or("unmatched");
+    debug!(
+        "Bot detected on {} (score {}: {})",
+        request.uri().path(),
+        verdict.score,
+        verdict.reasons.join(", ")
+    );
+    let request_id = request
+        .headers()
+        .get("X-Request-ID")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+
+    match action {
+        BotAction::Allow => Ok(next.run(request).await),
+        BotAction::Tarpit => {
+            state.metrics.record_bot_request(route_label, "tarpit");
+            tokio::time::sleep(std::time::Duration::from_millis(detector.tarpit_ms())).await;
+            Ok(next.run(request).await)
+        }
+        BotAction::Block => {
+            state.metrics.record_bot_request(route_label, "block");
+            Ok((
+                StatusCode::FORBIDDEN,
+                Json(ApiResponse::<()>::error("Automated requests are not allowed".to_string(), request_id)),
+            ).into_response())
+        }
+        BotAction::Challenge => {
+            state.metrics.record_bot_request(route_label, "challenge");
+            Ok(detector.challenge_response(&request_id))
+        }
+    }
+}
+
 pub async fn rate_limit_middleware(
     State(state): State<AppState>,
     request: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
+    request_records::mark(request.extensions(), "rate_limit");
+
     if !state.config.rate_limiting.enabled {
         return Ok(next.run(request).await);
     }
 
-    // Extract client identifier (IP address or API key)
-    let client_id = extract_client_id(&request);
+    // Extract client identifier using the route's key strategy
+    let route = find_route(&state, request.uri().path());
+    let key_strategy = route.and_then(|r| r.rate_limit_key.as_ref());
+    let client_id = state.client_keys.rate_limit_key(&request, key_strategy);
+    let key_type = state.client_keys.rate_limit_key_type(&request, key_strategy);
+    let route_label = route.map(|r| r.path.as_str()).unwrap_or("unmatched");
+    let span = info_span!("rate_limit", client_id = %client_id, route = route_label);
+
+    // Resolve the client's plan so inner layers see the same limits
+    let limits = match state.client_keys.api_key(&request) {
+        Some(api_key) => match AuthService::validate_api_key(&state.api_keys, api_key).instrument(span.clone()).await {
+            Ok(key_info) => AuthService::resolve_limits(&key_info, &state.config.rate_limiting),
+            Err(_) => EffectiveLimits::defaults(&state.config.rate_limiting),
+        },
+        None => EffectiveLimits::defaults(&state.config.rate_limiting),
+    };
+    let plan_label = limits.plan.as_deref().unwrap_or("default");
+
+    if state.rate_limiter.is_exempt(&state.client_keys.identity(&request)).instrument(span.clone()).await {
+        debug!("Rate limit exemption applied for client: {}", client_id);
+        state.metrics.record_rate_limit_decision("rate_limit", "exempt", key_type, route_label, plan_label);
+        return Ok(next.run(request).await);
+    }
     
     // Check rate limit
-    if let Err(_) = state.rate_limiter.check_rate_limit(&client_id).await {
-        warn!("Rate limit exceeded for client: {}", client_id);
-        return Err(StatusCode::TOO_MANY_REQUESTS);
+    if let Err(_) = state.rate_limiter.check_rate_limit(&client_id, &limits).instrument(span).await {
+        if state.config.rate_limiting.shadow_mode {
+            info!("Rate limit would be exceeded for client: {} (plan: {:?}, shadow mode)", client_id, limits.plan);
+            state.metrics.record_rate_limit_decision("rate_limit", "shadow_denied", key_type, route_label, plan_label);
+        } else {
+            warn!("Rate limit exceeded for client: {} (plan: {:?})", client_id, limits.plan);
+            state.metrics.record_rate_limit_decision("rate_limit", "denied", key_type, route_label, plan_label);
+            return Ok(rate_limited_response(&state, &request, &limits));
+        }
+    } else {
+        state.metrics.record_rate_limit_decision("rate_limit", "allowed", key_type, route_label, plan_label);
     }
 
+    let mut request = request;
+    request.extensions_mut().insert(limits);
+
     Ok(next.run(request).await)
 }
 
-pub async fn auth_middleware(
+pub async fn spike_arrest_middleware(
     State(state): State<AppState>,
     request: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
-    if !state.config.auth.enabled {
+    request_records::mark(request.extensions(), "spike_arrest");
+
+    let route = match find_route(&state, request.uri().path()) {
+        Some(route) if route.spike_arrest.is_some() => route,
+        _ => return Ok(next.run(request).await),
+    };
+
+    let client_id = state.client_keys.rate_limit_key(&request, route.rate_limit_key.as_ref());
+    let key_type = state.client_keys.rate_limit_key_type(&request, route.rate_limit_key.as_ref());
+    let plan_label = request
+        .extensions()
+        .get::<EffectiveLimits>()
+        .and_then(|limits| limits.plan.clone())
+        .unwrap_or_else(|| "default".to_string());
+
+    if state.rate_limiter.check_spike_arrest(route, &client_id).await.is_err() {
+        if state.config.rate_limiting.shadow_mode {
+            info!("Spike arrest would trigger for route: {} (client: {}, shadow mode)", route.path, client_id);
+            state.metrics.record_rate_limit_decision("spike_arrest", "shadow_denied", key_type, &route.path, &plan_label);
+            return Ok(next.run(request).await);
+        }
+
+        warn!("Spike arrest triggered for route: {} (client: {})", route.path, client_id);
+        state.metrics.record_rate_limit_decision("spike_arrest", "denied", key_type, &route.path, &plan_label);
+        return Ok(too_many_requests(&request, "Request rate too high, slow down"));
+    }
+
+    state.metrics.record_rate_limit_decision("spike_arrest", "allowed", key_type, &route.path, &plan_label);
+    Ok(next.run(request).await)
+}
+
+/// A 429 in the gateway's JSON error format, asking the client to retry in a second.
+fn too_many_requests(request: &Request, message: &str) -> Response {
+    let request_id = request
+        .headers()
+        .get("X-Request-ID")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+
+    let mut response = (
+        StatusCode::TOO_MANY_REQUESTS,
+        Json(ApiResponse::<()>::error(message.to_string(), request_id)),
+    ).into_response();
+    response.headers_mut().insert("Retry-After", HeaderValue::from(1));
+    response
+}
+
+pub async fn concurrency_limit_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    request_records::mark(request.extensions(), "concurrency_limit");
+
+    let route = find_route(&state, request.uri().path());
+    let route_limit = route.and_then(|r| r.max_concurrent_requests.map(|max| (r.path.clone(), max)));
+    let plan_limit = request
+        .extensions()
+        .get::<EffectiveLimits>()
+        .and_then(|limits| limits.max_concurrent_requests);
+
+    if route_limit.is_none() && plan_limit.is_none() {
         return Ok(next.run(request).await);
     }
 
-    let path = request.uri().path();
-    
-    // Check if path is in bypass list
-    for bypass_path in &state.config.auth.bypass_paths {
-        if path_matches(bypass_path, path) {
-            return Ok(next.run(request).await);
+    let key_strategy = route.and_then(|r| r.rate_limit_key.as_ref());
+    let client_id = state.client_keys.rate_limit_key(&request, key_strategy);
+    let key_type = state.client_keys.rate_limit_key_type(&request, key_strategy);
+    let route_label = route.map(|r| r.path.as_str()).unwrap_or("unmatched");
+    let plan_label = request
+        .extensions()
+        .get::<EffectiveLimits>()
+        .and_then(|limits| limits.plan.clone())
+        .unwrap_or_else(|| "default".to_string());
+
+    // Plan limits apply across all routes. Hold the permits until the response has been produced
+    let mut permits = Vec::new();
+    let mut decision = "allowed";
+    for (scope, max_concurrent) in route_limit.into_iter().chain(plan_limit.map(|max| ("plan".to_string(), max))) {
+        if let Ok(permit) = state.concurrency_limiter.try_acquire(&scope, &client_id, max_concurrent) {
+            permits.push(permit);
+            continue;
         }
+
+        if state.config.rate_limiting.shadow_mode {
+            info!("Concurrent request limit would be exceeded for client: {} ({}, shadow mode)", client_id, scope);
+            decision = "shadow_denied";
+            continue;
+        }
+
+        warn!("Concurrent request limit exceeded for client: {} ({})", client_id, scope);
+        state.metrics.record_rate_limit_decision("concurrency", "denied", key_type, route_label, &plan_label);
+        return Ok(too_many_requests(&request, "Too many concurrent requests"));
+    }
+
+    state.metrics.record_rate_limit_decision("concurrency", decision, key_type, route_label, &plan_label);
+    Ok(next.run(request).await)
+}
+
+/// Guards the `/admin` API in place of the regular route auth: a dedicated admin token
+/// or the admin scopes on the caller's credentials decide the role, and read-only
+/// callers may only read.
+pub async fn admin_auth_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let path = request.uri().path();
+    if !AdminAuth::is_admin_path(path) || path == DASHBOARD_PATH {
+        return Ok(next.run(request).await);
     }
+    let admin_auth = &state.admin_auth;
 
-    // Extract and validate authentication
     let headers = request.headers();
-    
-    if let Some(auth_header) = headers.get("Authorization") {
-        if let Ok(auth_str) = auth_header.to_str() {
-            if auth_str.starts_with("Bearer ") {
-                let token = &auth_str[7..];
-                if AuthService::validate_jwt_token(token, &state.config.auth.jwt_secret).is_ok() {
-                    return Ok(next.run(request).await);
+    let bearer_token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(AuthService::extract_bearer_token);
+
+    let (caller, role) = match bearer_token.and_then(|token| admin_auth.token_role(token)) {
+        Some((name, role)) => (format!("admin token {}", name), role),
+        None => {
+            let api_key = headers
+                .get(&state.config.auth.api_key_header)
+                .and_then(|value| value.to_str().ok());
+            let result = AuthService::authorize(&state.jwt_verifier, &state.api_keys, None, bearer_token, api_key).await;
+            let result = match (result, bearer_token, &state.revoked_tokens) {
+                (Ok(identity), Some(token), Some(revoked_tokens)) if identity.key_id.is_none() => {
+                    revoked_tokens.check(token, &identity.claims).await.map(|()| identity)
+                }
+                (result, _, _) => result,
+            };
+
+            let identity = match result {
+                Ok(identity) => identity,
+                Err(AuthError::StoreUnavailable) => return Err(StatusCode::SERVICE_UNAVAILABLE),
+                Err(e) => {
+                    warn!("Admin authentication failed for path: {} ({})", request.uri().path(), e);
+                    return Err(StatusCode::UNAUTHORIZED);
+                }
+            };
+            let caller = identity
+                .user_id
+                .clone()
+                .or_else(|| identity.key_id.clone())
+                .unwrap_or_else(|| "anonymous".to_string());
+            match admin_auth.scope_role(&identity.scopes) {
+                Some(role) => (caller, role),
+                None => {
+                    warn!("{} has no admin role for path: {}", caller, request.uri().path());
+                    return Err(StatusCode::FORBIDDEN);
                 }
             }
         }
+    };
+
+    let required = AdminAuth::required_role(request.method(), request.uri().path());
+    if role < required {
+        warn!("{} is read-only, rejected {} {}", caller, request.method(), request.uri().path());
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if required == AdminRole::ReadWrite {
+        info!("Admin {} {} by {}", request.method(), request.uri().path(), caller);
     }
 
-    // Check for API key
-    if let Some(api_key_header) = headers.get(&state.config.auth.api_key_header) {
-        if let Ok(api_key) = api_key_header.to_str() {
-            if AuthService::validate_api_key(api_key).await.is_ok() {
+    Ok(next.run(request).await)
+}
+
+pub async fn auth_middleware(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    request_records::mark(request.extensions(), "auth");
+    let trace = request.extensions().get::<RequestTrace>().cloned();
+    let set_auth = |outcome: String| {
+        if let Some(trace) = &trace {
+            trace.set_auth(outcome);
+        }
+    };
+
+    let path = request.uri().path().to_string();
+
+    // Identity headers are only ever set by the gateway
+    for name in IDENTITY_HEADERS {
+        request.headers_mut().remove(name);
+    }
+
+    let host = request
+        .uri()
+        .host()
+        .or_else(|| request.headers().get(header::HOST).and_then(|value| value.to_str().ok()));
+    let tenant = state.config.find_tenant(host, &path);
+
+    if !state.config.requires_auth_for(tenant, &path) {
+        return Ok(next.run(request).await);
+    }
+
+    // `admin_auth_middleware` already decided on these
+    if AdminAuth::is_admin_path(&path) {
+        return Ok(next.run(request).await);
+    }
+
+    let route = find_route(&state, &path);
+    let jwt_verifier = tenant
+        .and_then(|tenant| state.tenant_verifiers.get(&tenant.name))
+        .unwrap_or(&state.jwt_verifier);
+    let span = info_span!("auth", tenant = tenant.map(|tenant| tenant.name.as_str()));
+
+    // A browser session from OIDC login stands in for a token
+    let oidc_session = match &state.oidc {
+        Some(oidc) => {
+            if path == oidc::CALLBACK_PATH || path == oidc::LOGOUT_PATH {
                 return Ok(next.run(request).await);
             }
+
+            oidc.session(request.headers()).instrument(span.clone()).await
+        }
+        None => None,
+    };
+
+    let gateway_session = match &state.sessions {
+        Some(sessions) => {
+            // Session endpoints handle a missing or expired session themselves; only
+            // creating one needs credentials
+            if path == sessions::SESSION_PATH && request.method() != Method::POST {
+                return Ok(next.run(request).await);
+            }
+
+            sessions.validate(request.headers()).instrument(span.clone()).await
+        }
+        None => None,
+    };
+
+    let signed_verifier = state
+        .request_verifier
+        .as_ref()
+        .filter(|_| RequestVerifier::is_signed(request.headers()));
+
+    let basic_auth = state.basic_auth.as_ref().filter(|basic| basic.applies_to(&path));
+    let basic_credentials = basic_auth.and_then(|_| BasicAuthenticator::credentials(request.headers()));
+
+    let result = if let Some(session) = &oidc_session {
+        check_csrf(&state, &request, &session.csrf_token)
+            .and_then(|()| AuthService::check_scopes(route, &session.scopes))
+            .map(|()| session_identity(session))
+    } else if let Some(session) = gateway_session {
+        check_csrf(&state, &request, &session.csrf_token)
+            .and_then(|()| AuthService::check_scopes(route, &session.identity.scopes))
+            .map(|()| session.identity)
+    } else if let Some(verifier) = signed_verifier {
+        // The body is part of the signature, so it has to be read before proxying
+        let (parts, body) = request.into_parts();
+        let body = match axum::body::to_bytes(body, MAX_SIGNED_BODY_BYTES).await {
+            Ok(body) => body,
+            Err(_) => return Err(StatusCode::PAYLOAD_TOO_LARGE),
+        };
+        let result = verifier
+            .verify(&parts.method, &parts.uri, &parts.headers, &body)
+            .instrument(span.clone())
+            .await
+            .and_then(|identity| AuthService::check_scopes(route, &identity.scopes).map(|()| identity));
+        request = Request::from_parts(parts, axum::body::Body::from(body));
+        result
+    } else if let (Some(basic), Some((username, password))) = (basic_auth, &basic_credentials) {
+        basic
+            .authenticate(&state.api_keys, username, password)
+            .instrument(span.clone())
+            .await
+            .and_then(|identity| AuthService::check_scopes(route, &identity.scopes).map(|()| identity))
+    } else {
+        let headers = request.headers();
+        let bearer_token = headers
+            .get("Authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(AuthService::extract_bearer_token);
+        let api_key = headers
+            .get(&state.config.auth.api_key_header)
+            .and_then(|value| value.to_str().ok());
+
+        let result = AuthService::authorize(jwt_verifier, &state.api_keys, route, bearer_token, api_key)
+            .instrument(span.clone())
+            .await
+            .and_then(|identity| check_key_tenant(&identity, tenant).map(|()| identity));
+
+        // An identity without a key id came from the bearer token
+        match (result, bearer_token, &state.revoked_tokens) {
+            (Ok(identity), Some(token), Some(revoked_tokens)) if identity.key_id.is_none() => {
+                revoked_tokens.check(token, &identity.claims).instrument(span.clone()).await.map(|()| identity)
+            }
+            (result, _, _) => result,
+        }
+    };
+
+    let error = match result {
+        Ok(identity) => {
+            if let Some(session) = oidc_session {
+                forward_session_identity(&mut request, &session);
+            }
+            forward_identity(&mut request, &identity, &state.config.auth.identity_headers);
+            set_auth("authenticated".to_string());
+            // Later checks (policy evaluation) decide based on who the caller is
+            request.extensions_mut().insert(identity);
+            return Ok(next.run(request).await);
         }
+        Err(error) => error,
+    };
+
+    // Anonymous callers are welcome on optional routes; bad credentials are still rejected
+    if let (AuthError::MissingCredentials, Some(AuthEnforcementMode::Optional)) = (&error, route.map(|r| r.auth_mode)) {
+        debug!("No credentials for path: {} (optional auth)", path);
+        set_auth("anonymous".to_string());
+        return Ok(next.run(request).await);
     }
 
-    warn!("Authentication failed for path: {}", path);
-    Err(StatusCode::UNAUTHORIZED)
-}
+    let route_label = route.map(|r| r.path.as_str()).unwrap_or("unmatched");
+
+    if route.map_or(false, |r| r.auth_mode == AuthEnforcementMode::Monitor) {
+        info!("Auth would reject request for path: {} ({}, monitor mode)", path, error);
+        state.metrics.record_auth_rejection(route_label, error.reason(), "monitor");
+        set_auth(format!("monitor: {}", error.reason()));
+        return Ok(next.run(request).await);
+    }
+
+    state.metrics.record_auth_rejection(route_label, error.reason(), "enforce");
+    set_auth(format!("rejected: {}", error.reason()));
+
+    if let (Some(oidc), AuthError::MissingCredentials, None) = (&state.oidc, &error, basic_auth) {
+        if oidc::is_browser_navigation(request.method(), request.headers()) {
+            let return_to = request
+                .uri()
+                .path_and_query()
+                .map(|pq| pq.as_str())
+                .unwrap_or("/");
+
+            return match oidc.login_url(return_to).await {
+                Ok(login_url) => Ok(Redirect::to(&login_url).into_response()),
+                Err(e) => {
+                    error!("Failed to start OIDC login: {}", e);
+                    Err(StatusCode::BAD_GATEWAY)
+                }
+            };
+        }
+    }
 
-fn extract_client_id(request: &Request) -> String {
-    // Try to get API key first
-    if let Some(api_key) = request.headers().get("X-API-Key") {
-        if let Ok(key_str) = api_key.to_str() {
-            return format!("api_key:{}", key_str);
+    match error {
+        AuthError::InsufficientScope => {
+            warn!("Missing required scopes for path: {}", path);
+            Err(StatusCode::FORBIDDEN)
+        }
+        AuthError::CsrfTokenMismatch => {
+            warn!("CSRF token missing or invalid for path: {}", path);
+            Err(StatusCode::FORBIDDEN)
+        }
+        AuthError::StoreUnavailable => Err(StatusCode::SERVICE_UNAVAILABLE),
+        _ => {
+            warn!("Authentication failed for path: {}", path);
+            match basic_auth {
+                Some(basic) => Ok((
+                    StatusCode::UNAUTHORIZED,
+                    [(header::WWW_AUTHENTICATE, basic.challenge())],
+                ).into_response()),
+                None => Err(StatusCode::UNAUTHORIZED),
+            }
         }
     }
+}
+
+/// Runs after `auth_middleware`, so the service sees the identity headers it added.
+pub async fn ext_authz_middleware(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    request_records::mark(request.extensions(), "ext_authz");
+
+    let ext_authz = match &state.ext_authz {
+        Some(ext_authz) if ext_authz.applies_to(request.uri().path()) => ext_authz,
+        _ => return Ok(next.run(request).await),
+    };
 
-    // Fall back to IP address
-    if let Some(forwarded) = request.headers().get("X-Forwarded-For") {
-        if let Ok(forwarded_str) = forwarded.to_str() {
-            if let Some(ip) = forwarded_str.split(',').next() {
-                return format!("ip:{}", ip.trim());
+    let client_ip = state.client_keys.client_ip(&request);
+    let decision = ext_authz
+        .check(request.method(), request.uri(), request.headers(), client_ip)
+        .await;
+
+    let status = match decision {
+        ExtAuthzDecision::Allow(headers) => {
+            for (name, value) in headers {
+                request.headers_mut().insert(name, value);
             }
+            return Ok(next.run(request).await);
         }
+        ExtAuthzDecision::Deny(status) => status,
+    };
+
+    let route = find_route(&state, request.uri().path());
+    let route_label = route.map(|r| r.path.as_str()).unwrap_or("unmatched");
+
+    if route.map_or(false, |r| r.auth_mode == AuthEnforcementMode::Monitor) {
+        info!("External authorization would reject request for path: {} (monitor mode)", request.uri().path());
+        state.metrics.record_auth_rejection(route_label, "ext_authz_denied", "monitor");
+        return Ok(next.run(request).await);
+    }
+
+    state.metrics.record_auth_rejection(route_label, "ext_authz_denied", "enforce");
+    Err(status)
+}
+
+/// Runs after `auth_middleware`, so policies see the authenticated identity.
+pub async fn policy_middleware(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    request_records::mark(request.extensions(), "policy");
+
+    let policy = match &state.policy {
+        Some(policy) => policy,
+        None => return Ok(next.run(request).await),
+    };
+
+    let path = request.uri().path().to_string();
+    if state.config.auth.bypass_paths.iter().any(|bypass_path| path_matches(bypass_path, &path)) {
+        return Ok(next.run(request).await);
+    }
+
+    let route = find_route(&state, &path);
+    let client_ip = state.client_keys.client_ip(&request);
+    let result = policy
+        .evaluate(
+            request.method(),
+            request.uri(),
+            request.headers(),
+            route,
+            request.extensions().get::<Identity>(),
+            client_ip,
+        )
+        .await;
+
+    let status = match result {
+        Ok(()) => return Ok(next.run(request).await),
+        Err(status) => status,
+    };
+
+    let route_label = route.map(|r| r.path.as_str()).unwrap_or("unmatched");
+
+    if route.map_or(false, |r| r.auth_mode == AuthEnforcementMode::Monitor) {
+        info!("Policy would reject request for path: {} (monitor mode)", path);
+        state.metrics.record_auth_rejection(route_label, "policy_denied", "monitor");
+        return Ok(next.run(request).await);
     }
 
-    // Default to connection info
-    "unknown".to_string()
+    state.metrics.record_auth_rejection(route_label, "policy_denied", "enforce");
+    Err(status)
 }
 
-fn path_matches(pattern: &str, path: &str) -> bool {
-    if pattern.ends_with("*") {
-        let prefix = &pattern[..pattern.len() - 1];
-        path.starts_with(prefix)
+/// Runs the route's custom filter chain around the rest of the request.
+pub async fn filter_middleware(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let route = match find_route(&state, request.uri().path()) {
+        Some(route) if !route.filters.is_empty() => route,
+        _ => return Ok(next.run(request).await),
+    };
+    request_records::mark(request.extensions(), "filters");
+
+    let request_id = request
+        .headers()
+        .get("X-Request-ID")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let mut response = match state.filters.run_request(route, &request_id, &mut request).await {
+        Some(response) => response,
+        None => next.run(request).await,
+    };
+    state.filters.run_response(route, &request_id, &mut response).await;
+
+    Ok(response)
+}
+
+const OIDC_USER_HEADER: &str = "X-Forwarded-User";
+const OIDC_EMAIL_HEADER: &str = "X-Forwarded-Email";
+const USER_ID_HEADER: &str = "X-User-Id";
+const KEY_ID_HEADER: &str = "X-Key-Id";
+const SCOPES_HEADER: &str = "X-Scopes";
+const CLAIMS_HEADER: &str = "X-Auth-Claims";
+
+const IDENTITY_HEADERS: [&str; 6] = [
+    OIDC_USER_HEADER,
+    OIDC_EMAIL_HEADER,
+    USER_ID_HEADER,
+    KEY_ID_HEADER,
+    SCOPES_HEADER,
+    CLAIMS_HEADER,
+];
+
+/// How long the signed claims header is valid for; it only has to survive the hop to the backend.
+const SIGNED_CLAIMS_TTL_SECONDS: i64 = 60;
+
+/// API keys only work in the realm they were issued for: a tenant's keys within that
+/// tenant, and keys without a tenant outside every tenant.
+fn check_key_tenant(identity: &Identity, tenant: Option<&TenantConfig>) -> Result<(), AuthError> {
+    if identity.key_id.is_none() {
+        return Ok(());
+    }
+
+    let key_tenant = identity.claims.get("tenant").and_then(|tenant| tenant.as_str());
+    if key_tenant == tenant.map(|tenant| tenant.name.as_str()) {
+        Ok(())
     } else {
-        pattern == path
+        Err(AuthError::InvalidApiKey)
+    }
+}
+
+/// Cookies are sent by the browser on cross-site requests too, so cookie-authenticated
+/// requests have to prove they came from the app by echoing the session's CSRF token.
+fn check_csrf(state: &AppState, request: &Request, session_token: &str) -> Result<(), AuthError> {
+    match &state.csrf {
+        Some(csrf) if csrf.applies_to(request.method(), request.uri().path()) => {
+            if csrf.verify(request.headers(), session_token) {
+                Ok(())
+            } else {
+                Err(AuthError::CsrfTokenMismatch)
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+fn session_identity(session: &OidcSession) -> Identity {
+    let mut claims = serde_json::Map::new();
+    claims.insert("sub".to_string(), session.subject.clone().into());
+    if let Some(email) = &session.email {
+        claims.insert("email".to_string(), email.clone().into());
+    }
+    claims.insert("scope".to_string(), session.scopes.join(" ").into());
+
+    Identity {
+        user_id: Some(session.subject.clone()),
+        key_id: None,
+        scopes: session.scopes.clone(),
+        claims,
+    }
+}
+
+fn forward_identity(request: &mut Request, identity: &Identity, config: &IdentityHeadersConfig) {
+    if !config.enabled {
+        return;
+    }
+    let headers = request.headers_mut();
+
+    if let Some(Ok(value)) = identity.user_id.as_deref().map(HeaderValue::from_str) {
+        headers.insert(USER_ID_HEADER, value);
+    }
+    if let Some(Ok(value)) = identity.key_id.as_deref().map(HeaderValue::from_str) {
+        headers.insert(KEY_ID_HEADER, value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&identity.scopes.join(" ")) {
+        headers.insert(SCOPES_HEADER, value);
     }
-} 
\ No newline at end of file
+
+    if let Some(secret) = &config.claims_signing_secret {
+        let mut claims = identity.claims.clone();
+        let now = chrono::Utc::now().timestamp();
+        claims.insert("iat".to_string(), now.into());
+        claims.insert("exp".to_string(), (now + SIGNED_CLAIMS_TTL_SECONDS).into());
+
+        match jsonwebtoken::encode(
+            &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::HS256),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_secret(secret.as_bytes()),
+        ) {
+            Ok(token) => {
+                if let Ok(value) = HeaderValue::from_str(&token) {
+                    headers.insert(CLAIMS_HEADER, value);
+                }
+            }
+            Err(e) => error!("Failed to sign forwarded claims: {}", e),
+        }
+    }
+}
+
+fn forward_session_identity(request: &mut Request, session: &OidcSession) {
+    let headers = request.headers_mut();
+
+    if let Ok(value) = HeaderValue::from_str(&session.subject) {
+        headers.insert(OIDC_USER_HEADER, value);
+    }
+    if let Some(Ok(value)) = session.email.as_deref().map(HeaderValue::from_str) {
+        headers.insert(OIDC_EMAIL_HEADER, value);
+    }
+}
+
+fn rate_limited_response(state: &AppState, request: &Request, limits: &EffectiveLimits) -> Response {
+    let request_id = request
+        .headers()
+        .get("X-Request-ID")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+
+    let limit = limits.requests_per_minute;
+    let reset_time = state.rate_limiter.current_window_reset();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let retry_after = reset_time.saturating_sub(now);
+
+    let rejection = state.config.rate_limiting.rejection.as_ref();
+
+    let status = rejection
+        .and_then(|r| r.status_code)
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .unwrap_or(StatusCode::TOO_MANY_REQUESTS);
+
+    let body = match rejection.and_then(|r| r.body_template.as_ref()) {
+        Some(template) => {
+            let vars = [
+                ("request_id", serde_json::Value::from(request_id.clone())),
+                ("limit", serde_json::Value::from(limit)),
+                ("reset_time", serde_json::Value::from(reset_time)),
+                ("retry_after", serde_json::Value::from(retry_after)),
+            ];
+            render_template(template, &vars)
+        }
+        None => serde_json::to_value(ApiResponse::<()>::error("Rate limit exceeded".to_string(), request_id))
+            .unwrap_or_default(),
+    };
+
+    let mut response = (status, Json(body)).into_response();
+    let headers = response.headers_mut();
+    headers.insert("Retry-After", HeaderValue::from(retry_after));
+    headers.insert("X-RateLimit-Limit", HeaderValue::from(limit));
+    headers.insert("X-RateLimit-Reset", HeaderValue::from(reset_time));
+
+    for (name, value) in rejection.map(|r| &r.headers).into_iter().flatten() {
+        match (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+            (Ok(name), Ok(value)) => {
+                headers.insert(name, value);
+            }
+            _ => warn!("Skipping invalid rate limit rejection header: {}", name),
+        }
+    }
+
+    response
+}
+
+/// Substitutes `{{name}}` placeholders in string values. A string that is exactly one
+/// placeholder takes the variable's JSON type, so `"{{limit}}"` renders as a number.
+pub(crate) fn render_template(template: &serde_json::Value, vars: &[(&str, serde_json::Value)]) -> serde_json::Value {
+    match template {
+        serde_json::Value::String(s) => {
+            for (name, value) in vars {
+                if s == &format!("{{{{{}}}}}", name) {
+                    return value.clone();
+                }
+            }
+
+            let mut rendered = s.clone();
+            for (name, value) in vars {
+                let replacement = match value {
+                    serde_json::Value::String(v) => v.clone(),
+                    other => other.to_string(),
+                };
+                rendered = rendered.replace(&format!("{{{{{}}}}}", name), &replacement);
+            }
+            serde_json::Value::String(rendered)
+        }
+        serde_json::Value::Array(items) => {
+            serde_json::Value::Array(items.iter().map(|item| render_template(item, vars)).collect())
+        }
+        serde_json::Value::Object(map) => serde_json::Value::Object(
+            map.iter()
+                .map(|(key, value)| (key.clone(), render_template(value, vars)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+fn find_route<'a>(state: &'a AppState, path: &str) -> Option<&'a RouteConfig> {
+    state.config.find_route(path)
+}
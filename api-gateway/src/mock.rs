@@ -0,0 +1,168 @@
+use axum::{
+    body::Body,
+    extract::Query,
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri},
+    response::Response,
+};
+use rand::Rng;
+use serde_json::Value;
+use std::{collections::HashMap, time::Duration};
+use tracing::warn;
+
+use crate::config::{path_params, MockConfig, RouteConfig};
+use crate::middleware::render_template;
+
+/// Builds the route's mock response, after its configured latency.
+pub async fn respond(
+    mock: &MockConfig,
+    route: &RouteConfig,
+    method: &Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+    request_id: &str,
+) -> Response {
+    let jitter = if mock.latency_jitter_ms > 0 {
+        rand::thread_rng().gen_range(0..=mock.latency_jitter_ms)
+    } else {
+        0
+    };
+    let latency = Duration::from_millis(mock.latency_ms + jitter);
+    if !latency.is_zero() {
+        tokio::time::sleep(latency).await;
+    }
+
+    render(mock, route, method, uri, headers, request_id)
+}
+
+fn render(
+    mock: &MockConfig,
+    route: &RouteConfig,
+    method: &Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+    request_id: &str,
+) -> Response {
+    let vars = variables(route, method, uri, headers, request_id);
+    let vars: Vec<(&str, Value)> = vars.iter().map(|(name, value)| (name.as_str(), value.clone())).collect();
+
+    let (body, content_type) = match mock.body.as_ref().map(|body| render_template(body, &vars)) {
+        None => (Body::empty(), None),
+        Some(Value::String(text)) => (Body::from(text), Some("text/plain; charset=utf-8")),
+        Some(json) => (Body::from(json.to_string()), Some("application/json")),
+    };
+
+    let mut response = Response::new(body);
+    *response.status_mut() = StatusCode::from_u16(mock.status).unwrap_or(StatusCode::OK);
+    if let Some(content_type) = content_type {
+        response.headers_mut().insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    }
+    for (name, value) in &mock.headers {
+        match (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+            (Ok(name), Ok(value)) => {
+                response.headers_mut().insert(name, value);
+            }
+            _ => warn!("Skipping invalid mock header for route {}: {}", route.path, name),
+        }
+    }
+    response
+}
+
+fn variables(
+    route: &RouteConfig,
+    method: &Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+    request_id: &str,
+) -> Vec<(String, Value)> {
+    let mut vars = vec![
+        ("request_id".to_string(), Value::from(request_id)),
+        ("method".to_string(), Value::from(method.as_str())),
+        ("path".to_string(), Value::from(uri.path())),
+        ("timestamp".to_string(), Value::from(chrono::Utc::now().to_rfc3339())),
+    ];
+
+    if let Ok(Query(query)) = Query::<HashMap<String, String>>::try_from_uri(uri) {
+        vars.extend(query.into_iter().map(|(name, value)| (format!("query.{}", name), Value::from(value))));
+    }
+    for (name, value) in headers {
+        if let Ok(value) = value.to_str() {
+            vars.push((format!("header.{}", name.as_str()), Value::from(value)));
+        }
+    }
+    vars.extend(
+        path_params(&route.path, uri.path())
+            .into_iter()
+            .map(|(name, value)| (format!("param.{}", name), Value::from(value))),
+    );
+    vars
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn route(mock: Value) -> RouteConfig {
+        serde_json::from_value(json!({
+            "path": "/users/{id}",
+            "backend": "users",
+            "load_balancing": "round_robin",
+            "auth_required": false,
+            "mock": mock
+        }))
+        .unwrap()
+    }
+
+    async fn body(response: Response) -> String {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_templated_json_body() {
+        let route = route(json!({
+            "status": 201,
+            "headers": { "X-Mock": "true" },
+            "body": { "id": "{{param.id}}", "expand": "{{query.expand}}", "by": "{{header.x-user}} via {{method}}" }
+        }));
+        let mut headers = HeaderMap::new();
+        headers.insert("x-user", HeaderValue::from_static("jdoe"));
+
+        let response = respond(
+            route.mock.as_ref().unwrap(),
+            &route,
+            &Method::POST,
+            &"/users/42?expand=orders".parse().unwrap(),
+            &headers,
+            "req-1",
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()["x-mock"], "true");
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(
+            serde_json::from_str::<Value>(&body(response).await).unwrap(),
+            json!({ "id": "42", "expand": "orders", "by": "jdoe via POST" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_text_body_and_latency() {
+        let route = route(json!({ "body": "Hello from {{path}}", "latency_ms": 20 }));
+
+        let start = std::time::Instant::now();
+        let response = respond(
+            route.mock.as_ref().unwrap(),
+            &route,
+            &Method::GET,
+            &"/users/7".parse().unwrap(),
+            &HeaderMap::new(),
+            "req-2",
+        )
+        .await;
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/plain; charset=utf-8");
+        assert_eq!(body(response).await, "Hello from /users/7");
+    }
+}
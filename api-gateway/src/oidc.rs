@@ -0,0 +1,482 @@
+use axum::http::HeaderMap;
+use rand::Rng;
+use redis::AsyncCommands;
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::RwLock;
+use tracing::info;
+
+use crate::auth::scopes_from_claims;
+use crate::config::OidcConfig;
+use crate::jwks::JwtVerifier;
+use crate::sessions::cookie_value;
+
+pub const CALLBACK_PATH: &str = "/auth/oidc/callback";
+pub const LOGOUT_PATH: &str = "/auth/oidc/logout";
+
+const DEFAULT_SESSION_TTL_SECONDS: u64 = 8 * 60 * 60;
+const DEFAULT_COOKIE_NAME: &str = "gateway_session";
+/// How long a user has to finish logging in at the IdP.
+const LOGIN_STATE_TTL_SECONDS: u64 = 10 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcSession {
+    pub subject: String,
+    pub email: Option<String>,
+    pub scopes: Vec<String>,
+    pub expires_at: u64,
+    #[serde(default)]
+    pub csrf_token: String,
+}
+
+/// A completed login: the new session and where to send the browser.
+pub struct OidcLogin {
+    pub session_id: String,
+    pub csrf_token: String,
+    pub return_to: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+    authorization_endpoint: String,
+    token_endpoint: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LoginState {
+    nonce: String,
+    return_to: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// OpenID Connect relying party. Sessions and in-flight login state live in Redis so
+/// any gateway instance can serve the callback and later requests.
+pub struct OidcClient {
+    config: OidcConfig,
+    http: Client,
+    redis: redis::Client,
+    verifier: Arc<JwtVerifier>,
+    metadata: RwLock<Option<ProviderMetadata>>,
+}
+
+impl OidcClient {
+    pub fn new(config: &OidcConfig, redis_url: &str, verifier: Arc<JwtVerifier>) -> anyhow::Result<Self> {
+        let http = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+
+        Ok(Self {
+            config: config.clone(),
+            http,
+            redis: redis::Client::open(redis_url)?,
+            verifier,
+            metadata: RwLock::new(None),
+        })
+    }
+
+    /// Builds the IdP authorization URL for a browser that asked for `return_to`.
+    pub async fn login_url(&self, return_to: &str) -> anyhow::Result<String> {
+        let metadata = self.metadata().await?;
+        let state = random_token();
+        let login_state = LoginState {
+            nonce: random_token(),
+            return_to: local_path(return_to).to_string(),
+        };
+
+        let mut conn = self.redis.get_async_connection().await?;
+        conn.set_ex::<_, _, ()>(
+            format!("oidc_state:{}", state),
+            serde_json::to_string(&login_state)?,
+            LOGIN_STATE_TTL_SECONDS,
+        )
+        .await?;
+
+        let mut url = Url::parse(&metadata.authorization_endpoint)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", &self.config.redirect_uri)
+            .append_pair("scope", &self.config.scopes.join(" "))
+            .append_pair("state", &state)
+            .append_pair("nonce", &login_state.nonce);
+
+        Ok(url.to_string())
+    }
+
+    /// Exchanges the authorization code, verifies the ID token and starts a session.
+    pub async fn complete_login(&self, code: &str, state: &str) -> anyhow::Result<OidcLogin> {
+        let mut conn = self.redis.get_async_connection().await?;
+        let state_key = format!("oidc_state:{}", state);
+        let (login_state,): (Option<String>,) = redis::pipe()
+            .get(&state_key)
+            .del(&state_key)
+            .ignore()
+            .query_async(&mut conn)
+            .await?;
+        let login_state: LoginState = serde_json::from_str(
+            &login_state.ok_or_else(|| anyhow::anyhow!("Unknown or expired login state"))?,
+        )?;
+
+        let metadata = self.metadata().await?;
+        let tokens: TokenResponse = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", self.config.redirect_uri.as_str()),
+                ("client_id", self.config.client_id.as_str()),
+                ("client_secret", self.config.client_secret.as_str()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let claims = self
+            .verifier
+            .verify_with(
+                &tokens.id_token,
+                Some(&[self.config.issuer.clone()]),
+                Some(&[self.config.client_id.clone()]),
+            )
+            .await
+            .map_err(|e| anyhow::anyhow!("ID token rejected: {}", e))?;
+
+        if claims.get("nonce").and_then(|n| n.as_str()) != Some(login_state.nonce.as_str()) {
+            return Err(anyhow::anyhow!("ID token nonce mismatch"));
+        }
+
+        let subject = claims
+            .get("sub")
+            .and_then(|sub| sub.as_str())
+            .ok_or_else(|| anyhow::anyhow!("ID token has no subject"))?
+            .to_string();
+
+        let session = OidcSession {
+            subject,
+            email: claims.get("email").and_then(|email| email.as_str()).map(String::from),
+            scopes: scopes_from_claims(&claims),
+            expires_at: now() + self.session_ttl(),
+            csrf_token: random_token(),
+        };
+
+        let session_id = random_token();
+        conn.set_ex::<_, _, ()>(
+            format!("oidc_session:{}", session_id),
+            serde_json::to_string(&session)?,
+            self.session_ttl(),
+        )
+        .await?;
+
+        info!("OIDC login completed for subject {}", session.subject);
+
+        Ok(OidcLogin {
+            session_id,
+            csrf_token: session.csrf_token,
+            return_to: local_path(&login_state.return_to).to_string(),
+        })
+    }
+
+    /// Looks up the session named by the request's cookie, if any.
+    pub async fn session(&self, headers: &HeaderMap) -> Option<OidcSession> {
+        let session_id = self.session_id(headers)?;
+        let mut conn = self.redis.get_async_connection().await.ok()?;
+        let session: Option<String> = conn.get(format!("oidc_session:{}", session_id)).await.ok()?;
+
+        session
+            .and_then(|session| serde_json::from_str::<OidcSession>(&session).ok())
+            .filter(|session| session.expires_at > now())
+    }
+
+    pub async fn logout(&self, headers: &HeaderMap) -> anyhow::Result<()> {
+        if let Some(session_id) = self.session_id(headers) {
+            let mut conn = self.redis.get_async_connection().await?;
+            conn.del::<_, ()>(format!("oidc_session:{}", session_id)).await?;
+        }
+        Ok(())
+    }
+
+    pub fn session_cookie(&self, session_id: &str) -> String {
+        self.cookie(session_id, self.session_ttl())
+    }
+
+    pub fn clear_cookie(&self) -> String {
+        self.cookie("", 0)
+    }
+
+    pub fn post_logout_redirect(&self) -> &str {
+        self.config.post_logout_redirect.as_deref().unwrap_or("/")
+    }
+
+    fn cookie(&self, value: &str, max_age: u64) -> String {
+        let mut cookie = format!(
+            "{}={}; Path=/; HttpOnly; SameSite=Lax; Max-Age={}",
+            self.cookie_name(),
+            value,
+            max_age
+        );
+        if self.config.cookie_secure {
+            cookie.push_str("; Secure");
+        }
+        cookie
+    }
+
+    fn cookie_name(&self) -> &str {
+        self.config.cookie_name.as_deref().unwrap_or(DEFAULT_COOKIE_NAME)
+    }
+
+    pub fn session_ttl(&self) -> u64 {
+        self.config.session_ttl_seconds.unwrap_or(DEFAULT_SESSION_TTL_SECONDS)
+    }
+
+    fn session_id(&self, headers: &HeaderMap) -> Option<String> {
+        cookie_value(headers, self.cookie_name())
+    }
+
+    async fn metadata(&self) -> anyhow::Result<ProviderMetadata> {
+        if let Some(metadata) = self.metadata.read().await.as_ref() {
+            return Ok(metadata.clone());
+        }
+
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.config.issuer.trim_end_matches('/')
+        );
+        let metadata: ProviderMetadata = self
+            .http
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        *self.metadata.write().await = Some(metadata.clone());
+        Ok(metadata)
+    }
+}
+
+/// Whether a request looks like a browser page load that can follow a login redirect.
+pub fn is_browser_navigation(method: &axum::http::Method, headers: &HeaderMap) -> bool {
+    method == axum::http::Method::GET
+        && headers
+            .get("Accept")
+            .and_then(|value| value.to_str().ok())
+            .map_or(false, |accept| accept.contains("text/html"))
+}
+
+/// `return_to` if it's a path on this gateway, otherwise `/`, so the callback can't be
+/// turned into an open redirect. Browsers treat `\` like `/` and drop tabs and newlines,
+/// so `/\host` and `/\t/host` would leave the gateway too.
+fn local_path(return_to: &str) -> &str {
+    let mut chars = return_to.chars();
+    let local = chars.next() == Some('/')
+        && !matches!(chars.next(), Some('/' | '\\'))
+        && !return_to.chars().any(|c| c.is_ascii_control());
+
+    if local {
+        return_to
+    } else {
+        "/"
+    }
+}
+
+fn random_token() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use axum::{routing::{get, post}, Json, Router};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use std::{collections::HashMap, sync::Mutex};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+    };
+
+    const JWT_SECRET: &str = "oidc-test-secret";
+    const CLIENT_ID: &str = "gateway";
+
+    /// Just enough of Redis for login state: SETEX, GET and DEL.
+    async fn fake_redis() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        let data: Arc<Mutex<HashMap<String, String>>> = Arc::default();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let data = data.clone();
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    while let Some(command) = read_command(&mut stream).await {
+                        let reply = {
+                            let mut data = data.lock().unwrap();
+                            match command[0].to_ascii_uppercase().as_str() {
+                                "SETEX" => {
+                                    data.insert(command[1].clone(), command[3].clone());
+                                    "+OK\r\n".to_string()
+                                }
+                                "GET" => match data.get(&command[1]) {
+                                    Some(value) => format!("${}\r\n{}\r\n", value.len(), value),
+                                    None => "$-1\r\n".to_string(),
+                                },
+                                "DEL" => format!(":{}\r\n", data.remove(&command[1]).map_or(0, |_| 1)),
+                                _ => "+OK\r\n".to_string(),
+                            }
+                        };
+                        if stream.get_mut().write_all(reply.as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        url
+    }
+
+    async fn read_command(stream: &mut BufReader<TcpStream>) -> Option<Vec<String>> {
+        let mut line = String::new();
+        stream.read_line(&mut line).await.ok()?;
+        let count: usize = line.trim_end().strip_prefix('*')?.parse().ok()?;
+
+        let mut args = Vec::with_capacity(count);
+        for _ in 0..count {
+            line.clear();
+            stream.read_line(&mut line).await.ok()?;
+            let len: usize = line.trim_end().strip_prefix('$')?.parse().ok()?;
+            let mut arg = vec![0; len + 2];
+            stream.read_exact(&mut arg).await.ok()?;
+            arg.truncate(len);
+            args.push(String::from_utf8(arg).ok()?);
+        }
+        Some(args)
+    }
+
+    /// An IdP whose token endpoint hands out whatever ID token the test last set.
+    async fn client() -> (OidcClient, Arc<Mutex<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let id_token = Arc::new(Mutex::new(String::new()));
+
+        let metadata = serde_json::json!({
+            "authorization_endpoint": format!("{}/authorize", issuer),
+            "token_endpoint": format!("{}/token", issuer),
+        });
+        let issued = id_token.clone();
+        let idp = Router::new()
+            .route("/.well-known/openid-configuration", get(move || async move { Json(metadata) }))
+            .route(
+                "/token",
+                post(move || {
+                    let id_token = issued.lock().unwrap().clone();
+                    async move { Json(serde_json::json!({ "id_token": id_token })) }
+                }),
+            );
+        tokio::spawn(async move { axum::serve(listener, idp).await });
+
+        let mut auth = Config::default_config().auth;
+        auth.jwt_secret = JWT_SECRET.to_string();
+        let config = OidcConfig {
+            issuer,
+            client_id: CLIENT_ID.to_string(),
+            client_secret: "client-secret".to_string(),
+            redirect_uri: "https://gateway.example/auth/oidc/callback".to_string(),
+            scopes: vec!["openid".to_string()],
+            session_ttl_seconds: None,
+            cookie_name: None,
+            cookie_secure: true,
+            post_logout_redirect: None,
+        };
+        let verifier = Arc::new(JwtVerifier::new(&auth).unwrap());
+        let client = OidcClient::new(&config, &fake_redis().await, verifier).unwrap();
+
+        (client, id_token)
+    }
+
+    fn signed_id_token(client: &OidcClient, nonce: &str) -> String {
+        let claims = serde_json::json!({
+            "sub": "user-1",
+            "iss": client.config.issuer,
+            "aud": CLIENT_ID,
+            "nonce": nonce,
+            "exp": now() + 300,
+        });
+        encode(&Header::default(), &claims, &EncodingKey::from_secret(JWT_SECRET.as_bytes())).unwrap()
+    }
+
+    /// The `state` and `nonce` the login URL sends to the IdP.
+    fn state_and_nonce(login_url: &str) -> (String, String) {
+        let url = Url::parse(login_url).unwrap();
+        let param = |name: &str| url.query_pairs().find(|(key, _)| key == name).unwrap().1.into_owned();
+        (param("state"), param("nonce"))
+    }
+
+    #[test]
+    fn test_return_to_must_be_a_local_path() {
+        assert_eq!(local_path("/orders?page=2"), "/orders?page=2");
+        assert_eq!(local_path("/"), "/");
+        let external = ["https://evil.example/", "//evil.example", "/\\evil.example", "/\t/evil.example", "", "orders"];
+        for external in external {
+            assert_eq!(local_path(external), "/", "{:?}", external);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_login_completes_once_per_state() {
+        let (client, id_token) = client().await;
+
+        let (state, nonce) = state_and_nonce(&client.login_url("/orders?page=2").await.unwrap());
+        *id_token.lock().unwrap() = signed_id_token(&client, &nonce);
+        let login = client.complete_login("code", &state).await.unwrap();
+        assert_eq!(login.return_to, "/orders?page=2");
+        assert!(client.session_cookie(&login.session_id).starts_with("gateway_session="));
+
+        // A state is consumed by its callback, so replaying the callback fails
+        assert!(client.complete_login("code", &state).await.is_err());
+        assert!(client.complete_login("code", "unknown-state").await.is_err());
+
+        let (state, nonce) = state_and_nonce(&client.login_url("//evil.example").await.unwrap());
+        *id_token.lock().unwrap() = signed_id_token(&client, &nonce);
+        assert_eq!(client.complete_login("code", &state).await.unwrap().return_to, "/");
+    }
+
+    #[tokio::test]
+    async fn test_id_token_must_carry_the_logins_nonce() {
+        let (client, id_token) = client().await;
+
+        let (first_state, first_nonce) = state_and_nonce(&client.login_url("/").await.unwrap());
+        let (second_state, second_nonce) = state_and_nonce(&client.login_url("/").await.unwrap());
+        assert_ne!(first_state, second_state);
+        assert_ne!(first_nonce, second_nonce);
+
+        // An ID token issued for another login doesn't complete this one
+        *id_token.lock().unwrap() = signed_id_token(&client, &second_nonce);
+        let error = client.complete_login("code", &first_state).await.err().unwrap();
+        assert!(error.to_string().contains("nonce"), "{}", error);
+
+        assert!(client.complete_login("code", &second_state).await.is_ok());
+    }
+}
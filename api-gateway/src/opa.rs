@@ -0,0 +1,187 @@
+use axum::http::{HeaderMap, Method, StatusCode, Uri};
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{net::IpAddr, time::Duration};
+use tracing::{error, warn};
+
+use crate::auth::Identity;
+use crate::config::{OpaConfig, RouteConfig};
+
+/// OPA answers either with a bare boolean or with an object carrying the decision.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum PolicyResult {
+    Allow(bool),
+    Decision {
+        allow: bool,
+        /// Status to deny with instead of 403
+        status: Option<u16>,
+        reason: Option<String>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct QueryResponse {
+    /// Absent when the decision is undefined, which counts as a denial
+    result: Option<PolicyResult>,
+}
+
+pub struct PolicyEngine {
+    config: OpaConfig,
+    decision_url: String,
+    http: Client,
+}
+
+impl PolicyEngine {
+    pub fn new(config: &OpaConfig) -> anyhow::Result<Self> {
+        let http = Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()?;
+
+        Ok(Self {
+            config: config.clone(),
+            decision_url: format!(
+                "{}/v1/data/{}",
+                config.url.trim_end_matches('/'),
+                config.decision.trim_matches('/')
+            ),
+            http,
+        })
+    }
+
+    /// `Ok(())` lets the request through, `Err` carries the status to reject it with.
+    pub async fn evaluate(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        route: Option<&RouteConfig>,
+        identity: Option<&Identity>,
+        client_ip: Option<IpAddr>,
+    ) -> Result<(), StatusCode> {
+        let input = policy_input(method, uri, headers, route, identity, client_ip);
+
+        let response = match self.http.post(&self.decision_url).json(&json!({ "input": input })).send().await {
+            Ok(response) if response.status().is_success() => response,
+            Ok(response) => return self.unavailable(&format!("OPA returned {}", response.status())),
+            Err(e) => return self.unavailable(&e.to_string()),
+        };
+
+        let result = match response.json::<QueryResponse>().await {
+            Ok(body) => body.result,
+            Err(e) => return self.unavailable(&format!("unreadable OPA response: {}", e)),
+        };
+
+        match result {
+            Some(PolicyResult::Allow(true)) | Some(PolicyResult::Decision { allow: true, .. }) => Ok(()),
+            Some(PolicyResult::Decision { status, reason, .. }) => {
+                warn!(
+                    "Policy denied {} {}: {}",
+                    method,
+                    uri.path(),
+                    reason.as_deref().unwrap_or("no reason given")
+                );
+                Err(status
+                    .and_then(|status| StatusCode::from_u16(status).ok())
+                    .filter(|status| status.is_client_error())
+                    .unwrap_or(StatusCode::FORBIDDEN))
+            }
+            _ => {
+                warn!("Policy denied {} {}", method, uri.path());
+                Err(StatusCode::FORBIDDEN)
+            }
+        }
+    }
+
+    fn unavailable(&self, reason: &str) -> Result<(), StatusCode> {
+        error!("Policy evaluation failed: {}", reason);
+        if self.config.failure_mode_allow {
+            Ok(())
+        } else {
+            Err(StatusCode::SERVICE_UNAVAILABLE)
+        }
+    }
+}
+
+fn policy_input(
+    method: &Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+    route: Option<&RouteConfig>,
+    identity: Option<&Identity>,
+    client_ip: Option<IpAddr>,
+) -> Value {
+    let headers: serde_json::Map<String, Value> = headers
+        .iter()
+        // Credentials have already been checked; policies don't need to see them
+        .filter(|(name, _)| *name != "authorization" && *name != "cookie")
+        .filter_map(|(name, value)| Some((name.as_str().to_string(), value.to_str().ok()?.into())))
+        .collect();
+
+    json!({
+        "method": method.as_str(),
+        "path": uri.path(),
+        "path_segments": uri.path().split('/').filter(|segment| !segment.is_empty()).collect::<Vec<_>>(),
+        "query": uri.query(),
+        "headers": headers,
+        "client_ip": client_ip,
+        "route": route.map(|route| json!({
+            "name": route.name,
+            "path": route.path,
+            "backend": route.backend,
+        })),
+        "identity": identity.map(|identity| json!({
+            "user_id": identity.user_id,
+            "key_id": identity.key_id,
+            "scopes": identity.scopes,
+            "claims": identity.claims,
+        })),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_result_shapes() {
+        let bare: QueryResponse = serde_json::from_str(r#"{"result": true}"#).unwrap();
+        assert!(matches!(bare.result, Some(PolicyResult::Allow(true))));
+
+        let decision: QueryResponse =
+            serde_json::from_str(r#"{"result": {"allow": false, "status": 404, "reason": "other tenant"}}"#).unwrap();
+        assert!(matches!(
+            decision.result,
+            Some(PolicyResult::Decision { allow: false, status: Some(404), .. })
+        ));
+
+        let undefined: QueryResponse = serde_json::from_str("{}").unwrap();
+        assert!(undefined.result.is_none());
+    }
+
+    #[test]
+    fn test_input_omits_credentials() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", "Bearer secret".parse().unwrap());
+        headers.insert("X-Tenant-Id", "acme".parse().unwrap());
+        let identity = Identity {
+            user_id: Some("u1".to_string()),
+            ..Default::default()
+        };
+
+        let input = policy_input(
+            &Method::GET,
+            &"/api/v1/tenants/acme/orders".parse().unwrap(),
+            &headers,
+            None,
+            Some(&identity),
+            None,
+        );
+
+        assert!(input["headers"].get("authorization").is_none());
+        assert_eq!(input["headers"]["x-tenant-id"], "acme");
+        assert_eq!(input["path_segments"][3], "acme");
+        assert_eq!(input["identity"]["user_id"], "u1");
+    }
+}
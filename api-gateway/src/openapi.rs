@@ -0,0 +1,265 @@
+use serde_json::Value;
+use std::collections::BTreeSet;
+use tracing::info;
+
+use crate::config::{
+    AuthEnforcementMode, BackendConfig, CircuitBreakerConfig, Config, HealthCheckConfig, LoadBalancingStrategy,
+    OpenApiSource, RouteConfig, RouteOpenApiConfig,
+};
+
+const METHODS: [&str; 8] = ["get", "put", "post", "delete", "options", "head", "patch", "trace"];
+
+/// Appends the routes generated from `config.openapi`, and adds their backends when
+/// they aren't configured.
+pub fn expand(config: &mut Config) -> anyhow::Result<()> {
+    for source in config.openapi.clone() {
+        let document = load(&source.spec)?;
+
+        if !config.backends.contains_key(&source.backend) {
+            let servers = servers(&document);
+            if servers.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "Backend '{}' isn't configured and {} has no absolute server URLs",
+                    source.backend,
+                    source.spec
+                ));
+            }
+            config.backends.insert(source.backend.clone(), backend(&source.backend, servers));
+        }
+
+        let routes = routes(&source, &document)?;
+        info!("Generated {} routes from {}", routes.len(), source.spec);
+        config.routes.extend(routes);
+    }
+    Ok(())
+}
+
+/// Reads a JSON or YAML document.
+pub fn load(spec: &str) -> anyhow::Result<Value> {
+    let text = std::fs::read_to_string(spec)
+        .map_err(|e| anyhow::anyhow!("Failed to read OpenAPI document {}: {}", spec, e))?;
+    let document = if text.trim_start().starts_with('{') {
+        serde_json::from_str(&text)?
+    } else {
+        serde_yaml::from_str(&text)?
+    };
+    Ok(document)
+}
+
+/// One route per path item. Paths without templates come first, so `/users/me` isn't
+/// shadowed by `/users/{id}`.
+fn routes(source: &OpenApiSource, document: &Value) -> anyhow::Result<Vec<RouteConfig>> {
+    let paths = document
+        .get("paths")
+        .and_then(Value::as_object)
+        .ok_or_else(|| anyhow::anyhow!("OpenAPI document {} has no paths", source.spec))?;
+    let default_security = document.get("security");
+
+    let mut routes = Vec::new();
+    for (path, item) in paths {
+        let operations: Vec<(&str, &Value)> = METHODS
+            .iter()
+            .filter_map(|method| item.get(*method).map(|operation| (*method, operation)))
+            .collect();
+        if operations.is_empty() {
+            continue;
+        }
+
+        let secured: Vec<BTreeSet<String>> = operations
+            .iter()
+            .filter_map(|(_, operation)| required_scopes(operation.get("security").or(default_security)))
+            .collect();
+        // Only scopes every secured operation needs can be required for the whole path
+        let required_scopes = secured
+            .iter()
+            .skip(1)
+            .fold(secured.first().cloned().unwrap_or_default(), |common, scopes| {
+                common.intersection(scopes).cloned().collect()
+            });
+
+        let name = match operations.as_slice() {
+            [(_, operation)] => operation.get("operationId").and_then(Value::as_str).map(str::to_string),
+            _ => None,
+        };
+        let methods: Vec<String> = operations.iter().map(|(method, _)| method.to_uppercase()).collect();
+
+        routes.push(RouteConfig {
+            name,
+            path: path.clone(),
+            method: Some(methods.join(",")),
+            backend: source.backend.clone(),
+            load_balancing: source.load_balancing.clone().unwrap_or(LoadBalancingStrategy::RoundRobin),
+            rate_limit: source.rate_limit,
+            auth_required: !secured.is_empty(),
+            timeout_ms: source.timeout_ms,
+            max_concurrent_requests: None,
+            streaming: None,
+            rate_limit_key: None,
+            spike_arrest: None,
+            required_scopes: required_scopes.into_iter().collect(),
+            expected_issuers: None,
+            expected_audiences: None,
+            auth_mode: AuthEnforcementMode::Enforce,
+            logging: None,
+            debug_capture: false,
+            cache: None,
+            request_headers: Vec::new(),
+            response_headers: Vec::new(),
+            transform: None,
+            rewrite: None,
+            xml: None,
+            graphql: None,
+            composite: None,
+            openapi: Some(RouteOpenApiConfig {
+                spec: source.spec.clone(),
+                path: path.clone(),
+                validate_requests: source.validate_requests,
+                validate_responses: source.validate_responses,
+            }),
+            mock: None,
+            fault_injection: None,
+            ip_filter: None,
+            geo: None,
+            waf: None,
+            bot_protection: None,
+            security_headers: None,
+            filters: Vec::new(),
+            listeners: Vec::new(),
+        });
+    }
+
+    routes.sort_by_key(|route| route.path.contains('{'));
+    Ok(routes)
+}
+
+/// The scopes an operation's security requirement asks for, or `None` when it can be
+/// called anonymously. With several alternative requirements no scopes are required,
+/// since any one of them may be satisfied.
+fn required_scopes(security: Option<&Value>) -> Option<BTreeSet<String>> {
+    let requirements = security?.as_array()?;
+    // `{}` is the anonymous alternative
+    let anonymous = requirements
+        .iter()
+        .any(|requirement| requirement.as_object().map_or(false, |schemes| schemes.is_empty()));
+    if requirements.is_empty() || anonymous {
+        return None;
+    }
+
+    match requirements.as_slice() {
+        [requirement] => Some(
+            requirement
+                .as_object()
+                .into_iter()
+                .flat_map(|schemes| schemes.values())
+                .filter_map(Value::as_array)
+                .flatten()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect(),
+        ),
+        _ => Some(BTreeSet::new()),
+    }
+}
+
+/// Absolute server URLs, with variables replaced by their defaults and without a
+/// trailing slash. Relative URLs can't be proxied to and are skipped.
+fn servers(document: &Value) -> Vec<String> {
+    document
+        .get("servers")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|server| {
+            let mut url = server.get("url")?.as_str()?.to_string();
+            if let Some(variables) = server.get("variables").and_then(Value::as_object) {
+                for (name, variable) in variables {
+                    let default = variable.get("default").and_then(Value::as_str).unwrap_or("");
+                    url = url.replace(&format!("{{{}}}", name), default);
+                }
+            }
+            let url = url.trim_end_matches('/').to_string();
+            (url.starts_with("http://") || url.starts_with("https://")).then_some(url)
+        })
+        .collect()
+}
+
+/// Health checks are off since the document doesn't say where they'd go.
+fn backend(name: &str, servers: Vec<String>) -> BackendConfig {
+    BackendConfig {
+        name: name.to_string(),
+        servers,
+        health_check: HealthCheckConfig {
+            enabled: false,
+            path: "/health".to_string(),
+            interval_seconds: 30,
+            timeout_seconds: 5,
+            healthy_threshold: 2,
+            unhealthy_threshold: 3,
+        },
+        circuit_breaker: CircuitBreakerConfig {
+            enabled: true,
+            failure_threshold: 5,
+            recovery_timeout_seconds: 60,
+        },
+        network: None,
+        credentials: None,
+        pool: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::path_matches;
+    use serde_json::json;
+
+    #[test]
+    fn test_routes_from_document() {
+        let document = json!({
+            "openapi": "3.0.3",
+            "servers": [
+                { "url": "https://{region}.example.com/v1/", "variables": { "region": { "default": "eu" } } },
+                { "url": "/relative" }
+            ],
+            "security": [{ "oauth2": ["users:read"] }],
+            "paths": {
+                "/users/{id}": {
+                    "get": { "operationId": "getUser" },
+                    "delete": { "security": [{ "oauth2": ["users:read", "users:write"] }] }
+                },
+                "/users/me": { "get": { "operationId": "getMe" } },
+                "/status": { "get": { "security": [] }, "parameters": [] },
+                "/search": { "get": { "security": [{ "apiKey": [] }, {}] } }
+            }
+        });
+        let source: OpenApiSource = serde_json::from_value(json!({ "spec": "users.yaml", "backend": "users" })).unwrap();
+
+        assert_eq!(servers(&document), vec!["https://eu.example.com/v1"]);
+
+        let routes = routes(&source, &document).unwrap();
+        let summary: Vec<_> = routes
+            .iter()
+            .map(|route| (route.path.as_str(), route.method.as_deref(), route.auth_required, route.required_scopes.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("/search", Some("GET"), false, vec![]),
+                ("/status", Some("GET"), false, vec![]),
+                ("/users/me", Some("GET"), true, vec!["users:read".to_string()]),
+                ("/users/{id}", Some("GET,DELETE"), true, vec!["users:read".to_string()]),
+            ]
+        );
+        assert_eq!(routes[2].name.as_deref(), Some("getMe"));
+        assert_eq!(routes[3].openapi.as_ref().unwrap().path, "/users/{id}");
+    }
+
+    #[test]
+    fn test_templated_paths() {
+        assert!(path_matches("/users/{id}", "/users/42"));
+        assert!(path_matches("/users/{id}/orders/{order}", "/users/42/orders/7"));
+        assert!(!path_matches("/users/{id}", "/users/"));
+        assert!(!path_matches("/users/{id}", "/users/42/orders"));
+        assert!(!path_matches("/users/{id}", "/accounts/42"));
+    }
+}
@@ -0,0 +1,483 @@
+use axum::{
+    body::Bytes,
+    extract::Query,
+    http::{header, HeaderMap, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    Json,
+};
+use dashmap::DashMap;
+use regex::Regex;
+use serde_json::Value;
+use std::{collections::HashMap, sync::Arc};
+use tracing::{debug, warn};
+
+use crate::config::{path_params, RouteConfig};
+use crate::json_schema::{resolve, SchemaValidator, Violation};
+use crate::openapi;
+use crate::transform;
+
+/// Checks requests and backend responses against the OpenAPI operation their route
+/// was generated from.
+pub struct OpenApiValidator {
+    /// `None` for documents that failed to load, so the error is only logged once
+    documents: DashMap<String, Option<Arc<Value>>>,
+    regexes: DashMap<String, Option<Regex>>,
+}
+
+impl OpenApiValidator {
+    pub fn new() -> Self {
+        Self {
+            documents: DashMap::new(),
+            regexes: DashMap::new(),
+        }
+    }
+
+    /// The rejection to send instead of proxying, if the route validates requests and
+    /// this one doesn't match its operation.
+    pub fn check_request(
+        &self,
+        route: &RouteConfig,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        body: &Bytes,
+    ) -> Option<Response> {
+        let link = route.openapi.as_ref().filter(|link| link.validate_requests)?;
+        let document = self.document(&link.spec)?;
+        let path_item = resolve(&document, document.get("paths")?.get(&link.path)?);
+
+        let operation = match path_item.get(method.as_str().to_ascii_lowercase()) {
+            Some(operation) => resolve(&document, operation),
+            None => {
+                let message = format!("{} isn't defined for {}", method, link.path);
+                return Some((StatusCode::METHOD_NOT_ALLOWED, Json(serde_json::json!({ "error": message }))).into_response());
+            }
+        };
+
+        let validator = SchemaValidator::new(&document, &self.regexes);
+        let mut violations = check_parameters(&validator, &document, &link.path, path_item, operation, uri, headers);
+        violations.extend(check_body(&validator, &document, operation, headers, body));
+        if violations.is_empty() {
+            return None;
+        }
+
+        debug!("Rejecting request to {} with {} violations", uri.path(), violations.len());
+        Some(
+            (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({
+                    "error": "Request doesn't match the API specification",
+                    "violations": violations,
+                })),
+            )
+                .into_response(),
+        )
+    }
+
+    /// How the backend's response differs from what the operation documents for its
+    /// status. Empty unless the route validates responses.
+    pub fn check_response(
+        &self,
+        route: &RouteConfig,
+        method: &Method,
+        status: StatusCode,
+        headers: &HeaderMap,
+        body: &Bytes,
+    ) -> Vec<Violation> {
+        let link = match route.openapi.as_ref().filter(|link| link.validate_responses.is_some()) {
+            Some(link) => link,
+            None => return Vec::new(),
+        };
+        let document = match self.document(&link.spec) {
+            Some(document) => document,
+            None => return Vec::new(),
+        };
+        let operation = document
+            .get("paths")
+            .and_then(|paths| paths.get(&link.path))
+            .map(|path_item| resolve(&document, path_item))
+            .and_then(|path_item| path_item.get(method.as_str().to_ascii_lowercase()))
+            .map(|operation| resolve(&document, operation));
+        let responses = match operation.and_then(|operation| operation.get("responses")) {
+            Some(responses) => responses,
+            None => return Vec::new(),
+        };
+
+        // An exact code wins over a range like `2XX`, which wins over `default`
+        let range = format!("{}XX", status.as_u16() / 100);
+        let response = match responses
+            .get(status.as_str())
+            .or_else(|| responses.get(&range))
+            .or_else(|| responses.get(range.to_ascii_lowercase()))
+            .or_else(|| responses.get("default"))
+        {
+            Some(response) => resolve(&document, response),
+            None => return vec![Violation::new("status", format!("{} isn't a documented status", status.as_u16()))],
+        };
+
+        let validator = SchemaValidator::new(&document, &self.regexes);
+        let mut violations = Vec::new();
+        for (name, header) in response.get("headers").and_then(Value::as_object).into_iter().flatten() {
+            let header = resolve(&document, header);
+            let location = format!("header.{}", name);
+            match headers.get(name.as_str()).and_then(|value| value.to_str().ok()) {
+                Some(value) => {
+                    if let Some(schema) = header.get("schema") {
+                        let value = coerce(resolve(&document, schema), &document, &[value.to_string()]);
+                        violations.extend(validator.validate(schema, &value, &location));
+                    }
+                }
+                None if header.get("required") == Some(&Value::Bool(true)) => {
+                    violations.push(Violation::new(&location, "Missing required header"));
+                }
+                None => {}
+            }
+        }
+
+        match response.get("content").and_then(Value::as_object) {
+            Some(content) if !body.is_empty() => violations.extend(check_content(&validator, content, headers, body)),
+            Some(_) => {}
+            None if !body.is_empty() => violations.push(Violation::new("body", "No body is documented")),
+            None => {}
+        }
+        violations
+    }
+
+    fn document(&self, spec: &str) -> Option<Arc<Value>> {
+        if let Some(document) = self.documents.get(spec) {
+            return document.clone();
+        }
+
+        let document = match openapi::load(spec) {
+            Ok(document) => Some(Arc::new(document)),
+            Err(e) => {
+                warn!("Not validating against {}: {}", spec, e);
+                None
+            }
+        };
+        self.documents.insert(spec.to_string(), document.clone());
+        document
+    }
+}
+
+impl Default for OpenApiValidator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Path, query and header parameters. Cookies aren't checked.
+fn check_parameters(
+    validator: &SchemaValidator,
+    document: &Value,
+    template: &str,
+    path_item: &Value,
+    operation: &Value,
+    uri: &Uri,
+    headers: &HeaderMap,
+) -> Vec<Violation> {
+    let path_values = path_params(template, uri.path());
+    let mut query_values: HashMap<String, Vec<String>> = HashMap::new();
+    if let Ok(Query(pairs)) = Query::<Vec<(String, String)>>::try_from_uri(uri) {
+        for (name, value) in pairs {
+            query_values.entry(name).or_default().push(value);
+        }
+    }
+
+    let mut violations = Vec::new();
+    for parameter in parameters(document, path_item, operation) {
+        let (name, location) = match (
+            parameter.get("name").and_then(Value::as_str),
+            parameter.get("in").and_then(Value::as_str),
+        ) {
+            (Some(name), Some(location)) => (name, location),
+            _ => continue,
+        };
+
+        let raw: Vec<String> = match location {
+            "path" => path_values.get(name).cloned().into_iter().collect(),
+            "query" => query_values.get(name).cloned().unwrap_or_default(),
+            "header" => headers
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .map(str::to_string)
+                .collect(),
+            _ => continue,
+        };
+        let location = format!("{}.{}", location, name);
+
+        if raw.is_empty() {
+            if parameter.get("required") == Some(&Value::Bool(true)) {
+                violations.push(Violation::new(&location, "Missing required parameter"));
+            }
+            continue;
+        }
+        if let Some(schema) = parameter.get("schema") {
+            let value = coerce(resolve(document, schema), document, &raw);
+            violations.extend(validator.validate(schema, &value, &location));
+        }
+    }
+    violations
+}
+
+/// The path item's parameters, overridden by the operation's by name and location.
+fn parameters<'a>(document: &'a Value, path_item: &'a Value, operation: &'a Value) -> Vec<&'a Value> {
+    let declared = |item: &'a Value| {
+        item.get("parameters")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .map(|parameter| resolve(document, parameter))
+    };
+    let key = |parameter: &Value| (parameter.get("name").cloned(), parameter.get("in").cloned());
+
+    let mut parameters: Vec<&Value> = declared(path_item).collect();
+    for parameter in declared(operation) {
+        parameters.retain(|existing| key(existing) != key(parameter));
+        parameters.push(parameter);
+    }
+    parameters
+}
+
+/// Parameters arrive as strings, so they're converted to the type their schema expects
+/// before validation. Values that don't convert stay strings and fail the type check.
+fn coerce(schema: &Value, document: &Value, raw: &[String]) -> Value {
+    match schema.get("type").and_then(Value::as_str) {
+        Some("array") => {
+            let items = schema.get("items").map_or(&Value::Null, |items| resolve(document, items));
+            // Repeated query parameters, or one comma-separated value
+            let values: Vec<String> = match raw {
+                [single] => single.split(',').map(str::to_string).collect(),
+                _ => raw.to_vec(),
+            };
+            Value::Array(values.iter().map(|value| coerce(items, document, std::slice::from_ref(value))).collect())
+        }
+        Some("integer") => raw[0].parse::<i64>().map(Value::from).unwrap_or_else(|_| Value::String(raw[0].clone())),
+        Some("number") => raw[0]
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map(Value::Number)
+            .unwrap_or_else(|| Value::String(raw[0].clone())),
+        Some("boolean") => match raw[0].as_str() {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            other => Value::String(other.to_string()),
+        },
+        _ => Value::String(raw[0].clone()),
+    }
+}
+
+fn check_body(
+    validator: &SchemaValidator,
+    document: &Value,
+    operation: &Value,
+    headers: &HeaderMap,
+    body: &Bytes,
+) -> Vec<Violation> {
+    let request_body = match operation.get("requestBody") {
+        Some(request_body) => resolve(document, request_body),
+        None => return Vec::new(),
+    };
+    if body.is_empty() {
+        return if request_body.get("required") == Some(&Value::Bool(true)) {
+            vec![Violation::new("body", "Request body is required")]
+        } else {
+            Vec::new()
+        };
+    }
+
+    match request_body.get("content").and_then(Value::as_object) {
+        Some(content) => check_content(validator, content, headers, body),
+        None => Vec::new(),
+    }
+}
+
+/// Checks a body against the media type matching its `Content-Type`. Only JSON bodies
+/// are checked against schemas.
+fn check_content(
+    validator: &SchemaValidator,
+    content: &serde_json::Map<String, Value>,
+    headers: &HeaderMap,
+    body: &Bytes,
+) -> Vec<Violation> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.split(';').next().unwrap_or("").trim().to_ascii_lowercase())
+        .unwrap_or_default();
+    let range = format!("{}/*", content_type.split('/').next().unwrap_or(""));
+    let media_type = content
+        .get(&content_type)
+        .or_else(|| content.get(&range))
+        .or_else(|| content.get("*/*"));
+    let media_type = match media_type {
+        Some(media_type) => media_type,
+        None => {
+            return vec![Violation::new(
+                "header.content-type",
+                format!("Content type '{}' isn't accepted", content_type),
+            )]
+        }
+    };
+
+    let schema = match media_type.get("schema") {
+        Some(schema) if transform::is_json(headers) => schema,
+        _ => return Vec::new(),
+    };
+    match serde_json::from_slice::<Value>(body) {
+        Ok(instance) => validator.validate(schema, &instance, "body"),
+        Err(e) => vec![Violation::new("body", format!("Invalid JSON: {}", e))],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn document() -> Value {
+        json!({
+            "paths": {
+                "/users/{id}/orders": {
+                    "parameters": [{ "$ref": "#/components/parameters/UserId" }],
+                    "post": {
+                        "parameters": [
+                            { "name": "tags", "in": "query", "schema": { "type": "array", "items": { "type": "integer" } } },
+                            { "name": "X-Tenant", "in": "header", "required": true, "schema": { "type": "string" } }
+                        ],
+                        "requestBody": {
+                            "required": true,
+                            "content": {
+                                "application/json": {
+                                    "schema": {
+                                        "type": "object",
+                                        "required": ["sku"],
+                                        "properties": { "sku": { "type": "string" } }
+                                    }
+                                }
+                            }
+                        },
+                        "responses": {
+                            "201": {
+                                "headers": { "Location": { "required": true, "schema": { "type": "string" } } },
+                                "content": { "application/json": { "schema": { "$ref": "#/components/schemas/Order" } } }
+                            },
+                            "4XX": { "content": { "application/problem+json": { "schema": { "type": "object" } } } },
+                            "204": { "description": "Nothing" }
+                        }
+                    }
+                }
+            },
+            "components": {
+                "parameters": {
+                    "UserId": { "name": "id", "in": "path", "required": true, "schema": { "type": "integer", "minimum": 1 } }
+                },
+                "schemas": {
+                    "Order": { "type": "object", "required": ["id"], "properties": { "id": { "type": "integer" } } }
+                }
+            }
+        })
+    }
+
+    fn violations(uri: &str, headers: &[(&'static str, &'static str)], body: &'static str) -> Vec<String> {
+        let document = document();
+        let regexes = DashMap::new();
+        let validator = SchemaValidator::new(&document, &regexes);
+        let path_item = &document["paths"]["/users/{id}/orders"];
+        let operation = &path_item["post"];
+
+        let uri: Uri = uri.parse().unwrap();
+        let mut header_map = HeaderMap::new();
+        for (name, value) in headers {
+            header_map.insert(*name, value.parse().unwrap());
+        }
+        let body = Bytes::from_static(body.as_bytes());
+
+        let mut violations = check_parameters(&validator, &document, "/users/{id}/orders", path_item, operation, &uri, &header_map);
+        violations.extend(check_body(&validator, &document, operation, &header_map, &body));
+        violations
+            .into_iter()
+            .map(|violation| format!("{}: {}", violation.location, violation.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_request_validation() {
+        let json = ("content-type", "application/json");
+        let tenant = ("x-tenant", "acme");
+
+        assert!(violations("/users/7/orders?tags=1,2", &[json, tenant], r#"{"sku":"A1"}"#).is_empty());
+        assert!(violations("/users/7/orders?tags=1&tags=2", &[json, tenant], r#"{"sku":"A1"}"#).is_empty());
+
+        assert_eq!(
+            violations("/users/0/orders?tags=1,x", &[json], r#"{"sku":1}"#),
+            vec![
+                "path.id: Must be at least 1",
+                "query.tags[1]: Expected integer, found string",
+                "header.X-Tenant: Missing required parameter",
+                "body.sku: Expected string, found number",
+            ]
+        );
+        assert_eq!(violations("/users/7/orders", &[json, tenant], ""), vec!["body: Request body is required"]);
+        assert_eq!(
+            violations("/users/7/orders", &[("content-type", "text/plain"), tenant], "sku"),
+            vec!["header.content-type: Content type 'text/plain' isn't accepted"]
+        );
+        assert_eq!(
+            violations("/users/7/orders", &[json, tenant], "{"),
+            vec!["body: Invalid JSON: EOF while parsing an object at line 1 column 1"]
+        );
+    }
+
+    #[test]
+    fn test_response_validation() {
+        let dir = std::env::temp_dir().join(format!("openapi-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let spec = dir.join("orders.json");
+        std::fs::write(&spec, document().to_string()).unwrap();
+
+        let route: RouteConfig = serde_json::from_value(json!({
+            "path": "/users/*",
+            "backend": "orders",
+            "load_balancing": "round_robin",
+            "rate_limit": null,
+            "auth_required": false,
+            "timeout_ms": null,
+            "max_concurrent_requests": null,
+            "streaming": null,
+            "rate_limit_key": null,
+            "spike_arrest": null,
+            "logging": null,
+            "cache": null,
+            "openapi": { "spec": spec, "path": "/users/{id}/orders", "validate_responses": "report" }
+        }))
+        .unwrap();
+        let validator = OpenApiValidator::new();
+        let check = |status: u16, headers: &[(&'static str, &'static str)], body: &'static str| -> Vec<String> {
+            let mut header_map = HeaderMap::new();
+            for (name, value) in headers {
+                header_map.insert(*name, value.parse().unwrap());
+            }
+            let status = StatusCode::from_u16(status).unwrap();
+            validator
+                .check_response(&route, &Method::POST, status, &header_map, &Bytes::from_static(body.as_bytes()))
+                .into_iter()
+                .map(|violation| format!("{}: {}", violation.location, violation.message))
+                .collect()
+        };
+
+        let json = ("content-type", "application/json");
+        assert!(check(201, &[json, ("location", "/orders/1")], r#"{"id":1}"#).is_empty());
+        assert_eq!(
+            check(201, &[json], r#"{"id":"1"}"#),
+            vec!["header.Location: Missing required header", "body.id: Expected integer, found string"]
+        );
+        assert!(check(404, &[("content-type", "application/problem+json")], "{}").is_empty());
+        assert_eq!(check(204, &[], "unexpected"), vec!["body: No body is documented"]);
+        assert_eq!(check(500, &[json], "{}"), vec!["status: 500 isn't a documented status"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,27 +1,187 @@
 use axum::{
-    body::Body,
+    body::{Body, Bytes},
     http::{HeaderMap, Method, StatusCode, Uri},
-    response::Response,
+    response::{IntoResponse, Response},
 };
-use reqwest::Client;
+use futures::StreamExt;
+use reqwest::{Client, Identity};
 use std::{
     collections::HashMap,
     sync::{
         atomic::{AtomicUsize, Ordering},
-        Arc,
+        Arc, RwLock as StdRwLock,
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
-use tokio::sync::RwLock;
-use tracing::{debug, error, info, warn};
+use tokio::sync::{OwnedSemaphorePermit, RwLock, Semaphore};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 
-use crate::config::{BackendConfig, Config, LoadBalancingStrategy, RouteConfig};
+use crate::config::{
+    BackendConfig, CompositeConfig, CompositePart, Config, ContractValidationMode, LoadBalancingStrategy, RouteConfig,
+    DEFAULT_LISTENER,
+};
+use crate::streaming::limit_stream;
+use crate::traffic_sampler::TrafficSampler;
+use crate::fault_injection::FaultInjector;
+use crate::maintenance::MaintenanceMode;
+use crate::debug_capture::{DebugCapture, DEBUG_CAPTURE_HEADER};
+use crate::response_cache::{self, CacheLookup, CacheStatus, CachedResponse, ResponseCache};
+use crate::upstream_resolver::{AddressFamilyResolver, DnsCache};
+use crate::credentials::{CredentialStore, UpstreamCredential};
+use crate::auth;
+use crate::canary::{CanaryDecision, CanaryRequest, CanaryStatus, ConfigCanary};
+use crate::events::{self, LifecycleEventKind};
+use crate::telemetry;
+use crate::header_rules;
+use crate::hop_by_hop;
+use crate::transform;
+use crate::body_rewrite::BodyRewriter;
+use crate::xml_json;
+use crate::graphql;
+use crate::composite;
+use crate::mock;
+use crate::geoip;
+use crate::openapi_validation::OpenApiValidator;
+
+/// How long a request waits for a free slot on a backend at its `max_connections`.
+const POOL_WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Upper bound for streaming routes that don't set `max_duration_seconds`.
+const STREAMING_FALLBACK_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Tells backends which degraded modes the gateway was in while handling the request.
+pub const DEGRADED_HEADER: &str = "X-Gateway-Degraded";
+
+/// Appends a flag to the comma-separated `X-Gateway-Degraded` value.
+pub fn add_degradation(headers: &mut HeaderMap, flag: &str) {
+    let value = match headers.get(DEGRADED_HEADER).and_then(|value| value.to_str().ok()) {
+        Some(existing) if !existing.is_empty() => format!("{}, {}", existing, flag),
+        _ => flag.to_string(),
+    };
+    if let Ok(value) = value.parse() {
+        headers.insert(DEGRADED_HEADER, value);
+    }
+}
+
+/// Attached to proxied responses so handlers can record per-route metrics.
+#[derive(Debug, Clone)]
+pub struct ProxiedRequestInfo {
+    pub route: String,
+    pub backend: String,
+    /// The backend server chosen by load balancing; composite routes call several
+    pub server: Option<String>,
+    /// Calls made to the server, counting the retry with previous credentials
+    pub attempts: u32,
+    pub request_bytes: usize,
+    /// Unknown for streamed responses.
+    pub response_bytes: Option<usize>,
+    /// Time spent on the backend: sending the request and receiving the response, up to
+    /// its headers for streamed responses.
+    pub upstream_duration: Duration,
+    /// Ways the backend response didn't match the route's OpenAPI operation
+    pub contract_violations: usize,
+}
+
+/// Builds the HTTP client for a backend, applying its network and pool settings and an
+/// optional mTLS client identity. Hostnames go through `dns_cache` when there is one.
+pub fn build_backend_client(
+    backend: &BackendConfig,
+    identity: Option<Identity>,
+    dns_cache: Option<&DnsCache>,
+) -> reqwest::Result<Client> {
+    let mut builder = Client::builder().timeout(Duration::from_secs(30));
+
+    if let Some(dns_cache) = dns_cache {
+        builder = builder.dns_resolver(Arc::new(dns_cache.clone()));
+    }
+
+    if let Some(network) = &backend.network {
+        builder = builder.dns_resolver(Arc::new(AddressFamilyResolver::new(network, dns_cache)));
+
+        if let Some(connect_timeout_ms) = network.connect_timeout_ms {
+            builder = builder.connect_timeout(Duration::from_millis(connect_timeout_ms));
+        }
+    }
+
+    if let Some(pool) = &backend.pool {
+        if let Some(max_idle_per_host) = pool.max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle_per_host);
+        }
+        if let Some(idle_timeout_seconds) = pool.idle_timeout_seconds {
+            builder = builder.pool_idle_timeout(Duration::from_secs(idle_timeout_seconds));
+        }
+        if let Some(tcp_keepalive_seconds) = pool.tcp_keepalive_seconds {
+            builder = builder.tcp_keepalive(Duration::from_secs(tcp_keepalive_seconds));
+        }
+    }
+
+    if let Some(identity) = identity {
+        builder = builder.identity(identity);
+    }
+
+    builder.build()
+}
+
+/// The backend's response headers without its hop-by-hop ones, and with the gateway
+/// added to `Via`.
+fn copy_response_headers(response: &reqwest::Response) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in response.headers().iter() {
+        if let Ok(header_name) = axum::http::HeaderName::from_bytes(name.as_str().as_bytes()) {
+            if let Ok(header_value) = axum::http::HeaderValue::from_bytes(value.as_bytes()) {
+                headers.append(header_name, header_value);
+            }
+        }
+    }
+    hop_by_hop::strip(&mut headers);
+    hop_by_hop::append_via(&mut headers, response.version());
+    headers
+}
+
+/// Answers with the stale cached response if it may stand in for a failed backend
+/// request, and otherwise fails with `error`.
+fn serve_stale_on_error(
+    stale: Option<(CachedResponse, HeaderMap)>,
+    error: anyhow::Error,
+    request_id: &str,
+) -> anyhow::Result<Response> {
+    match stale {
+        Some((cached, client_headers)) if cached.usable_on_error() => {
+            warn!("Serving stale cached response after backend error: {} (request_id: {})", error, request_id);
+            response_cache::respond(cached, &client_headers, CacheStatus::Stale)
+        }
+        _ => Err(error),
+    }
+}
+
+/// Whether repeating `method` has the same effect as sending it once (RFC 9110 9.2.2).
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
 
 #[derive(Clone)]
 pub struct ProxyService {
-    config: Arc<Config>,
+    /// Active routing config; replaced when a canaried config is promoted
+    config: Arc<StdRwLock<Arc<Config>>>,
+    canary: Arc<ConfigCanary>,
     client: Client,
+    backend_clients: HashMap<String, Client>,
+    /// For backends with a `max_connections`
+    connection_slots: HashMap<String, Arc<Semaphore>>,
     backend_states: Arc<RwLock<HashMap<String, BackendState>>>,
+    traffic_sampler: Arc<TrafficSampler>,
+    debug_capture: Arc<DebugCapture>,
+    credentials: Arc<CredentialStore>,
+    fault_injector: Arc<FaultInjector>,
+    maintenance: Arc<MaintenanceMode>,
+    response_cache: Arc<ResponseCache>,
+    body_rewriter: Arc<BodyRewriter>,
+    openapi_validator: Arc<OpenApiValidator>,
+    /// Whose routes requests are matched against
+    listener: String,
 }
 
 #[derive(Debug, Clone)]
@@ -38,14 +198,33 @@ struct ServerState {
 }
 
 impl ProxyService {
-    pub async fn new(config: Arc<Config>) -> anyhow::Result<Self> {
-        let client = Client::builder()
-            .timeout(Duration::from_secs(30))
-            .build()?;
+    pub async fn new(
+        config: Arc<Config>,
+        traffic_sampler: Arc<TrafficSampler>,
+        debug_capture: Arc<DebugCapture>,
+        credentials: Arc<CredentialStore>,
+        fault_injector: Arc<FaultInjector>,
+        maintenance: Arc<MaintenanceMode>,
+        dns_cache: Option<DnsCache>,
+    ) -> anyhow::Result<Self> {
+        let mut client = Client::builder().timeout(Duration::from_secs(30));
+        if let Some(dns_cache) = &dns_cache {
+            client = client.dns_resolver(Arc::new(dns_cache.clone()));
+        }
+        let client = client.build()?;
 
+        let mut backend_clients = HashMap::new();
+        let mut connection_slots = HashMap::new();
         let mut backend_states = HashMap::new();
         
         for (name, backend) in &config.backends {
+            if backend.network.is_some() || backend.pool.is_some() {
+                backend_clients.insert(name.clone(), build_backend_client(backend, None, dns_cache.as_ref())?);
+            }
+            if let Some(max_connections) = backend.pool.as_ref().and_then(|pool| pool.max_connections) {
+                connection_slots.insert(name.clone(), Arc::new(Semaphore::new(max_connections)));
+            }
+
             let servers = backend
                 .servers
                 .iter()
@@ -66,12 +245,34 @@ impl ProxyService {
         }
 
         Ok(Self {
-            config,
+            config: Arc::new(StdRwLock::new(config)),
+            canary: Arc::new(ConfigCanary::new()),
             client,
+            backend_clients,
+            connection_slots,
             backend_states: Arc::new(RwLock::new(backend_states)),
+            traffic_sampler,
+            debug_capture,
+            credentials,
+            fault_injector,
+            maintenance,
+            response_cache: Arc::new(ResponseCache::new()),
+            body_rewriter: Arc::new(BodyRewriter::new()),
+            openapi_validator: Arc::new(OpenApiValidator::new()),
+            listener: DEFAULT_LISTENER.to_string(),
         })
     }
 
+    /// The same service, with backends, caches and canaries shared, matching requests
+    /// against another listener's routes.
+    pub fn for_listener(&self, listener: &str) -> Self {
+        Self {
+            listener: listener.to_string(),
+            ..self.clone()
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub async fn proxy_request(
         &self,
         method: Method,
@@ -79,16 +280,132 @@ impl ProxyService {
         headers: HeaderMap,
         body: Body,
         request_id: &str,
+        identity: Option<&auth::Identity>,
+        client_country: Option<&str>,
+        backend_override: Option<&str>,
+    ) -> anyhow::Result<Response> {
+        let (config, is_candidate) = match self.canary.select() {
+            Some(candidate) => (candidate, true),
+            None => (self.active_config(), false),
+        };
+
+        let route = self.find_matching_route(&config, uri.path()).ok();
+        let mut result = self
+            .proxy_request_with_config(
+                &config,
+                method,
+                uri,
+                headers,
+                body,
+                request_id,
+                identity,
+                client_country,
+                backend_override,
+            )
+            .await;
+
+        // Covers responses served from the cache as well as fresh ones
+        if let Ok(response) = &mut result {
+            header_rules::apply(&config.response_headers, response.headers_mut());
+            if let Some(route) = route {
+                header_rules::apply(&route.response_headers, response.headers_mut());
+            }
+        }
+
+        let failed = match &result {
+            Ok(response) => response.status().is_server_error(),
+            Err(_) => true,
+        };
+        self.evaluate_canary(is_candidate, failed);
+
+        result
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn proxy_request_with_config(
+        &self,
+        config: &Arc<Config>,
+        method: Method,
+        uri: Uri,
+        mut headers: HeaderMap,
+        body: Body,
+        request_id: &str,
+        identity: Option<&auth::Identity>,
+        client_country: Option<&str>,
+        backend_override: Option<&str>,
     ) -> anyhow::Result<Response> {
         // Find matching route
-        let route = self.find_matching_route(&uri.path())?;
+        let route = info_span!("route_match", path = %uri.path())
+            .in_scope(|| self.find_matching_route(config, uri.path()))?;
+
+        // A filter's choice of backend wins over the route's regional ones
+        let rerouted_route;
+        let backend = backend_override.or_else(|| geoip::regional_backend(route, client_country).map(String::as_str));
+        let route = match backend {
+            Some(backend) => {
+                rerouted_route = RouteConfig {
+                    backend: backend.to_string(),
+                    ..route.clone()
+                };
+                &rerouted_route
+            }
+            None => route,
+        };
+
+        if let Some(unavailable) = self.maintenance.check(route, &headers).await {
+            debug!("Route {} is in maintenance (request_id: {})", route.path, request_id);
+            return Ok(unavailable);
+        }
+        if let Some(fault) = self.fault_injector.inject(route, request_id).await {
+            return Ok(fault);
+        }
+        if let Some(mock) = &route.mock {
+            debug!("Serving mock response for {} (request_id: {})", uri.path(), request_id);
+            return Ok(mock::respond(mock, route, &method, &uri, &headers, request_id).await);
+        }
+        if let Some(composite) = &route.composite {
+            return self.proxy_composite(config, route, composite, method, uri, headers, body, request_id).await;
+        }
+
+        // Fresh cached responses are served without contacting the backend. Stale ones
+        // are served while being refreshed in the background if the route allows it, and
+        // otherwise revalidated with a conditional request.
+        let cache_key = ResponseCache::key(route, &method, &uri, &headers, identity, &config.auth.api_key_header);
+        // The stale response, with the client's own headers from before the validators
+        // replaced its conditional ones
+        let mut stale: Option<(CachedResponse, HeaderMap)> = None;
+        match cache_key.as_ref().and_then(|key| self.response_cache.lookup(route, key, &headers)) {
+            Some(CacheLookup::Fresh(cached)) => {
+                debug!("Serving {} from cache (request_id: {})", uri.path(), request_id);
+                return response_cache::respond(cached, &headers, CacheStatus::Hit);
+            }
+            Some(CacheLookup::Stale(cached)) if cached.serve_while_revalidating() => {
+                debug!("Serving stale {} while revalidating (request_id: {})", uri.path(), request_id);
+                if let Some(key) = &cache_key {
+                    self.revalidate_in_background(config, route, key, &method, &uri, &headers, &cached, request_id);
+                }
+                return response_cache::respond(cached, &headers, CacheStatus::Stale);
+            }
+            Some(CacheLookup::Stale(cached)) => {
+                let client_headers = headers.clone();
+                cached.add_validators(&mut headers);
+                stale = Some((cached, client_headers));
+            }
+            None => {}
+        }
         
         // Get backend configuration
-        let backend = self.config.backends.get(&route.backend)
+        let backend = config.backends.get(&route.backend)
             .ok_or_else(|| anyhow::anyhow!("Backend '{}' not found", route.backend))?;
 
         // Select server based on load balancing strategy
-        let server_url = self.select_server(backend, &route.load_balancing).await?;
+        let server_url = match self.select_server(backend, &route.load_balancing).await {
+            Ok(server_url) => server_url,
+            Err(e) => return serve_stale_on_error(stale, e, request_id),
+        };
+        if self.has_unhealthy_servers(&backend.name).await {
+            add_degradation(&mut headers, "backend-reduced-capacity");
+        }
         
         debug!(
             "Proxying request to {} (backend: {}, server: {}, request_id: {})",
@@ -98,54 +415,189 @@ impl ProxyService {
             request_id
         );
 
+        // The capture header is meant for the gateway, not the backend
+        let capture = self.debug_capture.enabled_for(route, &headers);
+        headers.remove(DEBUG_CAPTURE_HEADER);
+        header_rules::apply(&route.request_headers, &mut headers);
+
         // Build target URL
         let target_url = format!("{}{}", server_url, uri.path_and_query().map(|pq| pq.as_str()).unwrap_or(""));
 
         // Convert axum body to reqwest body
         let body_bytes = axum::body::to_bytes(body, usize::MAX).await?;
+        let request_bytes = body_bytes.len();
+        if let Some(rejection) = self.openapi_validator.check_request(route, &method, &uri, &headers, &body_bytes) {
+            return Ok(rejection);
+        }
+        let body_bytes = match &route.graphql {
+            Some(graphql_config) => match graphql::check(graphql_config, &method, &uri, &body_bytes) {
+                Ok(expanded) => expanded.unwrap_or(body_bytes),
+                Err(message) => {
+                    warn!("Rejecting GraphQL request to {}: {} (request_id: {})", uri.path(), message, request_id);
+                    return Ok(graphql::rejection(&message));
+                }
+            },
+            None => body_bytes,
+        };
+        let body_bytes = transform::request_body(route, &headers, body_bytes);
+        let body_bytes = self.body_rewriter.request_body(route, &headers, body_bytes);
+        let body_bytes = xml_json::request_body(route, &mut headers, body_bytes);
 
-        // Build request
-        let mut request_builder = self.client.request(method.clone(), &target_url);
+        // Tee to a debug backend if sampling is active for this route
+        self.traffic_sampler
+            .maybe_sample(&route.path, &method, &uri, &headers, &body_bytes, request_id)
+            .await;
 
-        // Copy headers (excluding host and connection headers)
-        for (name, value) in headers.iter() {
-            let name_str = name.as_str().to_lowercase();
-            if !["host", "connection", "content-length"].contains(&name_str.as_str()) {
-                request_builder = request_builder.header(name, value);
+        let upstream_start = Instant::now();
+        let span = info_span!("upstream", backend = %route.backend, url = %target_url);
+        let credential = self.credentials.current(&route.backend);
+        // Held until the body has been read, which for streamed responses is when the
+        // stream ends
+        let connection_slot = match self.connection_slot(&route.backend).await {
+            Ok(slot) => slot,
+            Err(e) => return serve_stale_on_error(stale, e, request_id),
+        };
+        let response = match self
+            .send_upstream(route, &method, &target_url, &headers, &body_bytes, request_id, credential.as_ref())
+            .instrument(span.clone())
+            .await
+        {
+            Ok(response) => response,
+            Err(e) => return serve_stale_on_error(stale, e, request_id),
+        };
+
+        // During a credential rotation the backend may not accept the new secret yet. Only
+        // idempotent requests are retried, since the backend may have acted on the first
+        let mut attempts = 1;
+        let response = if is_idempotent(&method) && matches!(
+            response.status(),
+            reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN
+        ) {
+            match self.credentials.previous(&route.backend) {
+                Some(previous) => {
+                    warn!(
+                        "Backend {} rejected current credentials, retrying with previous (request_id: {})",
+                        route.backend,
+                        request_id
+                    );
+                    attempts += 1;
+                    self.send_upstream(route, &method, &target_url, &headers, &body_bytes, request_id, Some(&previous))
+                        .instrument(span)
+                        .await?
+                }
+                None => response,
             }
-        }
+        } else {
+            response
+        };
 
-        // Add request ID header
-        request_builder = request_builder.header("X-Request-ID", request_id);
+        // Convert reqwest response to axum response
+        let status = StatusCode::from_u16(response.status().as_u16())?;
+        let mut response_headers = copy_response_headers(&response);
 
-        // Add body if present
-        if !body_bytes.is_empty() {
-            request_builder = request_builder.body(body_bytes);
+        if status.is_server_error() && stale.as_ref().map_or(false, |(cached, _)| cached.usable_on_error()) {
+            return serve_stale_on_error(stale, anyhow::anyhow!("backend returned {}", status), request_id);
         }
 
-        // Set timeout
-        if let Some(timeout_ms) = route.timeout_ms {
-            request_builder = request_builder.timeout(Duration::from_millis(timeout_ms));
+        if let (Some((_, client_headers)), StatusCode::NOT_MODIFIED) = (&stale, status) {
+            if let Some(cached) = cache_key
+                .as_ref()
+                .and_then(|key| self.response_cache.refresh(route, key, client_headers, &response_headers))
+            {
+                debug!("Revalidated cached {} (request_id: {})", uri.path(), request_id);
+                let mut response = response_cache::respond(cached, client_headers, CacheStatus::Revalidated)?;
+                response.extensions_mut().insert(ProxiedRequestInfo {
+                    route: route.path.clone(),
+                    backend: route.backend.clone(),
+                    server: Some(server_url.clone()),
+                    attempts,
+                    request_bytes,
+                    response_bytes: Some(0),
+                    upstream_duration: upstream_start.elapsed(),
+                    contract_violations: 0,
+                });
+                return Ok(response);
+            }
         }
 
-        // Execute request
-        let response = request_builder.send().await?;
+        let mut contract_violations = 0;
+        let (body, response_bytes) = if let Some(streaming) = &route.streaming {
+            let event_stream = response.headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(|content_type| content_type.starts_with("text/event-stream"))
+                .unwrap_or(false);
 
-        // Convert reqwest response to axum response
-        let status = StatusCode::from_u16(response.status().as_u16())?;
-        let mut response_headers = HeaderMap::new();
+            let stream = limit_stream(
+                response.bytes_stream(),
+                streaming,
+                event_stream,
+                route.path.clone(),
+                request_id.to_string(),
+            )
+            .inspect(move |_| {
+                let _ = &connection_slot;
+            });
+            // Streamed bodies are never buffered, so only the request body is captured
+            if capture {
+                self.debug_capture
+                    .record(request_id, route, &method, &uri, &headers, &body_bytes, status, &response_headers, None);
+            }
+            (Body::from_stream(stream), None)
+        } else {
+            let upstream_body = response.bytes().await?;
+            let violations = self.openapi_validator.check_response(route, &method, status, &response_headers, &upstream_body);
+            if !violations.is_empty() {
+                let details: Vec<String> = violations
+                    .iter()
+                    .map(|violation| format!("{}: {}", violation.location, violation.message))
+                    .collect();
+                warn!(
+                    "Response from {} for {} {} doesn't match the API specification: {} (request_id: {})",
+                    route.backend,
+                    method,
+                    uri.path(),
+                    details.join("; "),
+                    request_id
+                );
+                contract_violations = violations.len();
 
-        // Copy response headers
-        for (name, value) in response.headers().iter() {
-            if let Ok(header_name) = axum::http::HeaderName::from_bytes(name.as_str().as_bytes()) {
-                if let Ok(header_value) = axum::http::HeaderValue::from_bytes(value.as_bytes()) {
-                    response_headers.insert(header_name, header_value);
+                let mode = route.openapi.as_ref().and_then(|link| link.validate_responses);
+                if mode == Some(ContractValidationMode::Block) {
+                    let mut response = (
+                        StatusCode::BAD_GATEWAY,
+                        axum::Json(serde_json::json!({ "error": "Backend response doesn't match the API specification" })),
+                    )
+                        .into_response();
+                    response.extensions_mut().insert(ProxiedRequestInfo {
+                        route: route.path.clone(),
+                        backend: route.backend.clone(),
+                        server: Some(server_url.clone()),
+                        attempts,
+                        request_bytes,
+                        response_bytes: None,
+                        upstream_duration: upstream_start.elapsed(),
+                        contract_violations,
+                    });
+                    return Ok(response);
                 }
             }
-        }
 
-        let body_bytes = response.bytes().await?;
-        let body = Body::from(body_bytes);
+            let response_body = xml_json::response_body(route, &mut response_headers, upstream_body);
+            let response_body = transform::response_body(route, &mut response_headers, response_body);
+            let response_body = self.body_rewriter.response_body(route, &mut response_headers, response_body);
+            let response_bytes = response_body.len();
+            if let Some(key) = cache_key {
+                self.response_cache.store(route, key, &headers, status, &response_headers, &response_body);
+                response_cache::set_cache_status(&mut response_headers, CacheStatus::Miss);
+            }
+            if capture {
+                self.debug_capture
+                    .record(request_id, route, &method, &uri, &headers, &body_bytes, status, &response_headers, Some(&response_body));
+            }
+            (Body::from(response_body), Some(response_bytes))
+        };
+        let upstream_duration = upstream_start.elapsed();
 
         let mut response_builder = Response::builder().status(status);
         
@@ -154,7 +606,17 @@ impl ProxyService {
             response_builder = response_builder.header(name, value);
         }
 
-        let response = response_builder.body(body)?;
+        let mut response = response_builder.body(body)?;
+        response.extensions_mut().insert(ProxiedRequestInfo {
+            route: route.path.clone(),
+            backend: route.backend.clone(),
+            server: Some(server_url),
+            attempts,
+            request_bytes,
+            response_bytes,
+            upstream_duration,
+            contract_violations,
+        });
 
         info!(
             "Request proxied successfully (status: {}, request_id: {})",
@@ -165,25 +627,354 @@ impl ProxyService {
         Ok(response)
     }
 
-    fn find_matching_route(&self, path: &str) -> anyhow::Result<&RouteConfig> {
-        for route in &self.config.routes {
-            if self.path_matches(&route.path, path) {
-                return Ok(route);
+    /// Calls every part of a composite route concurrently and merges their responses.
+    #[allow(clippy::too_many_arguments)]
+    async fn proxy_composite(
+        &self,
+        config: &Config,
+        route: &RouteConfig,
+        composite: &CompositeConfig,
+        method: Method,
+        uri: Uri,
+        mut headers: HeaderMap,
+        body: Body,
+        request_id: &str,
+    ) -> anyhow::Result<Response> {
+        headers.remove(DEBUG_CAPTURE_HEADER);
+        header_rules::apply(&route.request_headers, &mut headers);
+        let body_bytes = axum::body::to_bytes(body, usize::MAX).await?;
+
+        let upstream_start = Instant::now();
+        let results = futures::future::join_all(composite.parts.iter().map(|part| {
+            self.fetch_composite_part(config, route, part, &method, &uri, &headers, &body_bytes, request_id)
+        }))
+        .await;
+        let upstream_duration = upstream_start.elapsed();
+
+        let mut response = composite::merge(&composite.parts, results, request_id)?;
+        let response_bytes = axum::body::HttpBody::size_hint(response.body()).exact().map(|bytes| bytes as usize);
+        response.extensions_mut().insert(ProxiedRequestInfo {
+            route: route.path.clone(),
+            backend: route.backend.clone(),
+            server: None,
+            attempts: 1,
+            request_bytes: body_bytes.len(),
+            response_bytes,
+            upstream_duration,
+            contract_violations: 0,
+        });
+        Ok(response)
+    }
+
+    /// Sends the request to one part's backend the way the composite route would send
+    /// it to its own, and returns the body of a successful response.
+    #[allow(clippy::too_many_arguments)]
+    async fn fetch_composite_part(
+        &self,
+        config: &Config,
+        route: &RouteConfig,
+        part: &CompositePart,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        body_bytes: &Bytes,
+        request_id: &str,
+    ) -> anyhow::Result<Bytes> {
+        let backend = config.backends.get(&part.backend)
+            .ok_or_else(|| anyhow::anyhow!("Backend '{}' not found", part.backend))?;
+        let server_url = self.select_server(backend, &route.load_balancing).await?;
+        let target_url = match uri.query() {
+            Some(query) => format!("{}{}?{}", server_url, part.path, query),
+            None => format!("{}{}", server_url, part.path),
+        };
+        let method = match &part.method {
+            Some(method) => Method::from_bytes(method.to_uppercase().as_bytes())?,
+            None => method.clone(),
+        };
+        let part_route = RouteConfig {
+            backend: part.backend.clone(),
+            timeout_ms: part.timeout_ms.or(route.timeout_ms),
+            streaming: None,
+            ..route.clone()
+        };
+
+        let span = info_span!("upstream", backend = %part.backend, url = %target_url);
+        let credential = self.credentials.current(&part.backend);
+        let _connection_slot = self.connection_slot(&part.backend).await?;
+        let response = self
+            .send_upstream(&part_route, &method, &target_url, headers, body_bytes, request_id, credential.as_ref())
+            .instrument(span)
+            .await?;
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("backend returned {}", response.status()));
+        }
+        Ok(response.bytes().await?)
+    }
+
+    /// Refreshes a stale cached response off the request path, so the client that found
+    /// it stale doesn't wait. Only one refresh of an entry runs at a time.
+    #[allow(clippy::too_many_arguments)]
+    fn revalidate_in_background(
+        &self,
+        config: &Arc<Config>,
+        route: &RouteConfig,
+        key: &str,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        cached: &CachedResponse,
+        request_id: &str,
+    ) {
+        if !self.response_cache.begin_refresh(key) {
+            return;
+        }
+
+        let mut headers = headers.clone();
+        headers.remove(DEBUG_CAPTURE_HEADER);
+        header_rules::apply(&route.request_headers, &mut headers);
+        cached.add_validators(&mut headers);
+
+        let proxy = self.clone();
+        let config = Arc::clone(config);
+        let route = route.clone();
+        let key = key.to_string();
+        let method = method.clone();
+        let uri = uri.clone();
+        let request_id = request_id.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = proxy.revalidate(&config, &route, &key, &method, &uri, &headers, &request_id).await {
+                warn!(
+                    "Background revalidation of {} failed: {} (request_id: {})",
+                    uri.path(),
+                    e,
+                    request_id
+                );
             }
+            proxy.response_cache.end_refresh(&key);
+        });
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn revalidate(
+        &self,
+        config: &Config,
+        route: &RouteConfig,
+        key: &str,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        request_id: &str,
+    ) -> anyhow::Result<()> {
+        let backend = config.backends.get(&route.backend)
+            .ok_or_else(|| anyhow::anyhow!("Backend '{}' not found", route.backend))?;
+        let server_url = self.select_server(backend, &route.load_balancing).await?;
+        let target_url = format!("{}{}", server_url, uri.path_and_query().map(|pq| pq.as_str()).unwrap_or(""));
+
+        let credential = self.credentials.current(&route.backend);
+        let _connection_slot = self.connection_slot(&route.backend).await?;
+        let response = self
+            .send_upstream(route, method, &target_url, headers, &Bytes::new(), request_id, credential.as_ref())
+            .instrument(info_span!("revalidate", backend = %route.backend, url = %target_url))
+            .await?;
+
+        let status = StatusCode::from_u16(response.status().as_u16())?;
+        let mut response_headers = copy_response_headers(&response);
+        if status == StatusCode::NOT_MODIFIED {
+            self.response_cache.refresh(route, key, headers, &response_headers);
+        } else {
+            let body = xml_json::response_body(route, &mut response_headers, response.bytes().await?);
+            let body = transform::response_body(route, &mut response_headers, body);
+            let body = self.body_rewriter.response_body(route, &mut response_headers, body);
+            self.response_cache.store(route, key.to_string(), headers, status, &response_headers, &body);
         }
-        
-        Err(anyhow::anyhow!("No matching route found for path: {}", path))
+        debug!("Revalidated cached {} in the background (status: {}, request_id: {})", uri.path(), status, request_id);
+        Ok(())
     }
 
-    fn path_matches(&self, pattern: &str, path: &str) -> bool {
-        if pattern.ends_with("*") {
-            let prefix = &pattern[..pattern.len() - 1];
-            path.starts_with(prefix)
+    /// Waits for a free slot on backends with a `max_connections`.
+    async fn connection_slot(&self, backend: &str) -> anyhow::Result<Option<OwnedSemaphorePermit>> {
+        let slots = match self.connection_slots.get(backend) {
+            Some(slots) => slots.clone(),
+            None => return Ok(None),
+        };
+        match tokio::time::timeout(POOL_WAIT_TIMEOUT, slots.acquire_owned()).await {
+            Ok(Ok(permit)) => Ok(Some(permit)),
+            _ => Err(anyhow::anyhow!("Backend '{}' has no free connections", backend)),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn send_upstream(
+        &self,
+        route: &RouteConfig,
+        method: &Method,
+        target_url: &str,
+        headers: &HeaderMap,
+        body_bytes: &Bytes,
+        request_id: &str,
+        credential: Option<&UpstreamCredential>,
+    ) -> anyhow::Result<reqwest::Response> {
+        // Build request
+        let client = credential
+            .and_then(|c| c.client.as_ref())
+            .or_else(|| self.backend_clients.get(&route.backend))
+            .unwrap_or(&self.client);
+        let mut request_builder = client.request(method.clone(), target_url);
+
+        // Copy headers (excluding host, length, request ID and hop-by-hop headers, and the
+        // caller's trace context when the gateway continues the trace itself)
+        let trace_headers = telemetry::upstream_trace_headers();
+        let hop_by_hop = hop_by_hop::names(headers);
+        let credential_header = credential.and_then(|c| c.header.as_ref());
+        for (name, value) in headers.iter() {
+            let name_str = name.as_str().to_lowercase();
+            if ["host", "content-length", "x-request-id"].contains(&name_str.as_str()) || hop_by_hop.contains(&name_str) {
+                continue;
+            }
+            // The gateway's credential replaces the caller's, rather than being sent alongside it
+            if credential_header.map_or(false, |(credential_name, _)| credential_name.eq_ignore_ascii_case(&name_str)) {
+                continue;
+            }
+            if trace_headers.is_some() && telemetry::TRACE_HEADERS.contains(&name_str.as_str()) {
+                continue;
+            }
+            request_builder = request_builder.header(name, value);
+        }
+        if let Some(trace_headers) = trace_headers {
+            request_builder = request_builder.headers(trace_headers);
+        }
+
+        // Add request ID header
+        request_builder = request_builder.header("X-Request-ID", request_id);
+
+        // Add upstream credentials
+        if let Some((name, value)) = credential_header {
+            request_builder = request_builder.header(name.as_str(), value.as_str());
+        }
+
+        // Add body if present
+        if !body_bytes.is_empty() {
+            request_builder = request_builder.body(body_bytes.clone());
+        }
+
+        // Set timeout. Streaming routes apply it to the response headers only, since
+        // reqwest's request timeout also covers reading the body.
+        let response = if let Some(streaming) = &route.streaming {
+            let stream_timeout = streaming.max_duration_seconds
+                .map(Duration::from_secs)
+                .unwrap_or(STREAMING_FALLBACK_TIMEOUT);
+            request_builder = request_builder.timeout(stream_timeout);
+
+            match route.timeout_ms {
+                Some(timeout_ms) => tokio::time::timeout(Duration::from_millis(timeout_ms), request_builder.send())
+                    .await
+                    .map_err(|_| anyhow::anyhow!("Timed out waiting for streaming response headers"))??,
+                None => request_builder.send().await?,
+            }
         } else {
-            pattern == path
+            if let Some(timeout_ms) = route.timeout_ms {
+                request_builder = request_builder.timeout(Duration::from_millis(timeout_ms));
+            }
+
+            // Execute request
+            request_builder.send().await?
+        };
+
+        Ok(response)
+    }
+
+    fn active_config(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
+
+    pub async fn start_config_canary(&self, request: CanaryRequest) -> anyhow::Result<()> {
+        // Candidate backends need load balancing state before traffic reaches them
+        self.ensure_backend_states(&request.config).await;
+        self.canary.start(request, &self.active_config())?;
+        info!("Config canary started");
+        events::emit(LifecycleEventKind::ConfigCanaryStarted, serde_json::json!({}));
+        Ok(())
+    }
+
+    pub fn config_canary_status(&self) -> Option<CanaryStatus> {
+        self.canary.status()
+    }
+
+    /// Makes the canaried config the active routing config. Middleware keeps reading the
+    /// startup config, so only routes and backends change (see `ConfigCanary`).
+    pub fn promote_config_canary(&self) -> bool {
+        match self.canary.finish() {
+            Some(candidate) => {
+                *self.config.write().unwrap() = candidate;
+                info!("Config canary promoted");
+                events::emit(LifecycleEventKind::ConfigApplied, serde_json::json!({ "source": "canary" }));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn revert_config_canary(&self) -> bool {
+        let reverted = self.canary.finish().is_some();
+        if reverted {
+            warn!("Config canary reverted");
+            events::emit(LifecycleEventKind::ConfigCanaryReverted, serde_json::json!({}));
+        }
+        reverted
+    }
+
+    fn evaluate_canary(&self, is_candidate: bool, failed: bool) {
+        match self.canary.record(is_candidate, failed) {
+            CanaryDecision::Continue => {}
+            CanaryDecision::Promote => {
+                self.promote_config_canary();
+            }
+            CanaryDecision::Revert => {
+                if let Some(status) = self.canary.status() {
+                    warn!(
+                        "Config canary error rate {:.2}% exceeds active {:.2}%",
+                        status.candidate.error_rate,
+                        status.active.error_rate
+                    );
+                }
+                self.revert_config_canary();
+            }
+        }
+    }
+
+    async fn ensure_backend_states(&self, config: &Config) {
+        let mut backend_states = self.backend_states.write().await;
+
+        for (name, backend) in &config.backends {
+            if backend_states.contains_key(name) {
+                continue;
+            }
+
+            backend_states.insert(name.clone(), BackendState {
+                servers: backend
+                    .servers
+                    .iter()
+                    .map(|url| ServerState {
+                        url: url.clone(),
+                        healthy: true,
+                        connections: Arc::new(AtomicUsize::new(0)),
+                    })
+                    .collect(),
+                current_index: Arc::new(AtomicUsize::new(0)),
+            });
+            events::emit(
+                LifecycleEventKind::BackendAdded,
+                serde_json::json!({ "backend": name, "servers": backend.servers }),
+            );
         }
     }
 
+    fn find_matching_route<'a>(&self, config: &'a Config, path: &str) -> anyhow::Result<&'a RouteConfig> {
+        config
+            .find_listener_route(&self.listener, path)
+            .ok_or_else(|| anyhow::anyhow!("No matching route found for path: {}", path))
+    }
+
     async fn select_server(
         &self,
         backend: &BackendConfig,
@@ -232,6 +1023,15 @@ impl ProxyService {
         Ok(selected_server.url.clone())
     }
 
+    /// Whether some, but not necessarily all, of a backend's servers are marked unhealthy.
+    async fn has_unhealthy_servers(&self, backend_name: &str) -> bool {
+        self.backend_states
+            .read()
+            .await
+            .get(backend_name)
+            .map_or(false, |state| state.servers.iter().any(|server| !server.healthy))
+    }
+
     pub async fn update_server_health(&self, backend_name: &str, server_url: &str, healthy: bool) {
         let mut backend_states = self.backend_states.write().await;
         if let Some(backend_state) = backend_states.get_mut(backend_name) {
@@ -0,0 +1,262 @@
+use ipnet::IpNet;
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::config::ProxyProtocolConfig;
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+/// Longest v1 header the spec allows, CRLF included
+const V1_MAX_LEN: usize = 107;
+const V2_HEADER_LEN: usize = 16;
+
+/// Reads PROXY protocol v1/v2 headers off accepted connections, so the address of the
+/// client behind an L4 load balancer replaces the balancer's as the peer address.
+pub struct ProxyProtocol {
+    trusted_networks: Vec<IpNet>,
+    optional: bool,
+    timeout: Duration,
+}
+
+/// What a header said about the connection. LOCAL (v2) and UNKNOWN (v1) headers, sent
+/// for the balancer's own health checks, carry no source.
+#[derive(Debug, PartialEq)]
+pub struct ProxyHeader {
+    pub source: Option<SocketAddr>,
+}
+
+impl ProxyProtocol {
+    pub fn new(config: &ProxyProtocolConfig) -> anyhow::Result<Self> {
+        let trusted_networks = config
+            .trusted_networks
+            .iter()
+            .map(|range| {
+                range
+                    .parse::<IpNet>()
+                    .or_else(|_| range.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| anyhow::anyhow!("Invalid IP range: {}", range))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if trusted_networks.is_empty() {
+            return Err(anyhow::anyhow!("PROXY protocol needs trusted_networks listing the load balancers"));
+        }
+
+        Ok(Self {
+            trusted_networks,
+            optional: config.optional,
+            timeout: Duration::from_millis(config.header_timeout_ms),
+        })
+    }
+
+    /// The address to treat as the client's, along with any bytes read past the header
+    /// that the connection's first request starts with. Peers outside the trusted
+    /// networks are served as they are, with any header they send left for HTTP parsing
+    /// to reject.
+    pub async fn client_addr<S: AsyncRead + Unpin>(
+        &self,
+        stream: &mut S,
+        peer: SocketAddr,
+    ) -> anyhow::Result<(SocketAddr, Vec<u8>)> {
+        if !self.trusted_networks.iter().any(|range| range.contains(&peer.ip())) {
+            return Ok((peer, Vec::new()));
+        }
+
+        let (header, rest) = tokio::time::timeout(self.timeout, read_header(stream))
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out waiting for a PROXY protocol header"))??;
+
+        match header {
+            Some(header) => Ok((header.source.unwrap_or(peer), rest)),
+            None if self.optional => Ok((peer, rest)),
+            None => Err(anyhow::anyhow!("Connection didn't start with a PROXY protocol header")),
+        }
+    }
+}
+
+/// Consumes a v1 or v2 header from the start of `stream`, returning it with the bytes
+/// read past its end. Returns `None`, with everything read so far, when the connection
+/// starts with something else.
+pub async fn read_header<S: AsyncRead + Unpin>(stream: &mut S) -> anyhow::Result<(Option<ProxyHeader>, Vec<u8>)> {
+    let mut read = Vec::with_capacity(V1_MAX_LEN);
+    let mut buf = [0u8; 512];
+
+    loop {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Ok((None, read));
+        }
+        read.extend_from_slice(&buf[..n]);
+
+        let v1 = shares_prefix(&read, V1_PREFIX);
+        let v2 = shares_prefix(&read, V2_SIGNATURE);
+        if !(v1 || v2) {
+            return Ok((None, read));
+        }
+
+        if v1 && read.len() >= V1_PREFIX.len() {
+            let line = &read[..read.len().min(V1_MAX_LEN)];
+            if let Some(end) = line.windows(2).position(|pair| pair == b"\r\n") {
+                let rest = read.split_off(end + 2);
+                return parse_v1(&read).map(|header| (Some(header), rest));
+            }
+            if read.len() >= V1_MAX_LEN {
+                return Err(anyhow::anyhow!("PROXY protocol v1 header is too long"));
+            }
+        }
+
+        if v2 && read.len() >= V2_HEADER_LEN {
+            let len = V2_HEADER_LEN + u16::from_be_bytes([read[14], read[15]]) as usize;
+            if read.len() >= len {
+                let rest = read.split_off(len);
+                return parse_v2(&read).map(|header| (Some(header), rest));
+            }
+        }
+    }
+}
+
+/// Whether `read` and `expected` agree on the bytes both have.
+fn shares_prefix(read: &[u8], expected: &[u8]) -> bool {
+    let len = read.len().min(expected.len());
+    read[..len] == expected[..len]
+}
+
+/// Parses `PROXY TCP4 <src> <dst> <src port> <dst port>\r\n` and its TCP6/UNKNOWN forms.
+fn parse_v1(line: &[u8]) -> anyhow::Result<ProxyHeader> {
+    let line = std::str::from_utf8(line)
+        .map_err(|_| anyhow::anyhow!("PROXY protocol v1 header isn't ASCII"))?
+        .trim_end_matches("\r\n");
+    let fields: Vec<&str> = line.split(' ').collect();
+
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(ProxyHeader { source: None }),
+        ["PROXY", "TCP4" | "TCP6", source, _, source_port, _] => {
+            let ip = source
+                .parse::<IpAddr>()
+                .map_err(|_| anyhow::anyhow!("Invalid PROXY protocol source address: {}", source))?;
+            let port = source_port
+                .parse::<u16>()
+                .map_err(|_| anyhow::anyhow!("Invalid PROXY protocol source port: {}", source_port))?;
+            Ok(ProxyHeader { source: Some(SocketAddr::new(ip, port)) })
+        }
+        _ => Err(anyhow::anyhow!("Malformed PROXY protocol v1 header: {}", line)),
+    }
+}
+
+/// Parses a binary v2 header, signature included. TLVs after the addresses are ignored.
+fn parse_v2(header: &[u8]) -> anyhow::Result<ProxyHeader> {
+    if header.len() < V2_HEADER_LEN || !header.starts_with(V2_SIGNATURE) {
+        return Err(anyhow::anyhow!("Malformed PROXY protocol v2 header"));
+    }
+
+    let version_command = header[12];
+    if version_command >> 4 != 2 {
+        return Err(anyhow::anyhow!("Unsupported PROXY protocol version {}", version_command >> 4));
+    }
+
+    let addresses = &header[V2_HEADER_LEN..];
+    let source = match (version_command & 0x0f, header[13] >> 4) {
+        // LOCAL: the balancer's own connection
+        (0x0, _) => None,
+        (0x1, 0x1) if addresses.len() >= 12 => {
+            let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Some(SocketAddr::new(IpAddr::V4(ip), port))
+        }
+        (0x1, 0x2) if addresses.len() >= 36 => {
+            let octets: [u8; 16] = addresses[..16].try_into().unwrap();
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port))
+        }
+        // UNSPEC and unix sockets have no IP address to report
+        (0x1, 0x0 | 0x3) => None,
+        (0x1, family) => return Err(anyhow::anyhow!("Unsupported PROXY protocol address family {}", family)),
+        (command, _) => return Err(anyhow::anyhow!("Unsupported PROXY protocol command {}", command)),
+    };
+
+    Ok(ProxyHeader { source })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rewind::Rewind;
+
+    #[test]
+    fn test_parse_v1() {
+        let header = parse_v1(b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 443\r\n").unwrap();
+        assert_eq!(header.source, Some("203.0.113.7:51234".parse().unwrap()));
+
+        let header = parse_v1(b"PROXY TCP6 2001:db8::7 2001:db8::1 51234 443\r\n").unwrap();
+        assert_eq!(header.source, Some("[2001:db8::7]:51234".parse().unwrap()));
+
+        assert_eq!(parse_v1(b"PROXY UNKNOWN\r\n").unwrap().source, None);
+        assert!(parse_v1(b"PROXY TCP4 not-an-ip 10.0.0.1 1 2\r\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_v2() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x11, 0, 12]);
+        header.extend_from_slice(&[203, 0, 113, 7, 10, 0, 0, 1]);
+        header.extend_from_slice(&51234u16.to_be_bytes());
+        header.extend_from_slice(&443u16.to_be_bytes());
+        assert_eq!(parse_v2(&header).unwrap().source, Some("203.0.113.7:51234".parse().unwrap()));
+
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend_from_slice(&[0x20, 0x00, 0, 0]);
+        assert_eq!(parse_v2(&local).unwrap().source, None);
+    }
+
+    fn proxy_protocol(trusted_networks: &[&str]) -> anyhow::Result<ProxyProtocol> {
+        ProxyProtocol::new(&ProxyProtocolConfig {
+            trusted_networks: trusted_networks.iter().map(|range| range.to_string()).collect(),
+            optional: false,
+            header_timeout_ms: 1000,
+        })
+    }
+
+    #[test]
+    fn test_trusted_networks_are_required() {
+        assert!(proxy_protocol(&[]).is_err());
+        assert!(proxy_protocol(&["10.0.0.0/8", "192.0.2.1"]).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_read_header_returns_other_traffic() {
+        let mut stream: &[u8] = b"GET / HTTP/1.1\r\n\r\n";
+        let (header, read) = read_header(&mut stream).await.unwrap();
+        assert_eq!(header, None);
+
+        let mut request = [0u8; 3];
+        Rewind::new(read, stream).read_exact(&mut request).await.unwrap();
+        assert_eq!(&request, b"GET");
+    }
+
+    #[tokio::test]
+    async fn test_client_addr_from_a_header_split_across_reads() {
+        let proxy_protocol = proxy_protocol(&["10.0.0.0/8"]).unwrap();
+        let balancer: SocketAddr = "10.0.0.1:40000".parse().unwrap();
+        let (mut stream, mut balancer_side) = tokio::io::duplex(1024);
+        tokio::spawn(async move {
+            use tokio::io::AsyncWriteExt;
+            for part in [&b"PROXY TCP4 203.0.113.7 "[..], b"10.0.0.1 51234 443\r\nGET"] {
+                balancer_side.write_all(part).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+
+        let (client, rest) = proxy_protocol.client_addr(&mut stream, balancer).await.unwrap();
+        assert_eq!(client, "203.0.113.7:51234".parse().unwrap());
+        assert_eq!(rest, b"GET");
+
+        // Headers from peers outside the trusted networks are left unread
+        let header: &[u8] = b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 443\r\n";
+        let mut stream = header;
+        let peer: SocketAddr = "198.51.100.9:40000".parse().unwrap();
+        let (client, rest) = proxy_protocol.client_addr(&mut stream, peer).await.unwrap();
+        assert_eq!((client, rest.len(), stream), (peer, 0, header));
+    }
+}
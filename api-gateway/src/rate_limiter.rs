@@ -1,22 +1,205 @@
 use dashmap::DashMap;
-use governor::{Quota, RateLimiter as GovernorRateLimiter};
+use ipnet::IpNet;
+use governor::{
+    clock::{Clock, DefaultClock},
+    DefaultKeyedRateLimiter, Quota, RateLimiter as GovernorRateLimiter,
+};
 use nonzero_ext::*;
 use redis::AsyncCommands;
+use serde::Serialize;
 use std::{
+    net::IpAddr,
     num::NonZeroU32,
-    sync::Arc,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio::sync::RwLock;
-use tracing::{debug, error};
+use tracing::{debug, error, info, warn};
+
+use crate::config::{Config, RateLimitExemptions, RateLimitingConfig, RouteConfig};
+use crate::health::HealthStatus;
+use crate::events::{self, LifecycleEventKind};
+use crate::metrics::RATE_LIMITER_FALLBACK_ACTIVE;
 
-use crate::config::Config;
+/// Bounds each Redis round trip so an unreachable Redis can't stall requests.
+const REDIS_OPERATION_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How often idle clients are dropped from the per-client spike arrest limiters.
+const SPIKE_ARREST_PRUNE_INTERVAL: Duration = Duration::from_secs(60);
 
 #[derive(Clone)]
 pub struct RateLimiter {
     config: Arc<Config>,
     memory_limiters: Arc<DashMap<String, GovernorRateLimiter<String, dashmap::DashMap<String, governor::state::InMemoryState>, governor::clock::DefaultClock>>>,
     redis_client: Option<redis::Client>,
+    exemptions: Arc<RwLock<ExemptionSet>>,
+    redis_circuit: Arc<RedisCircuit>,
+    spike_arresters: Arc<DashMap<String, Arc<DefaultKeyedRateLimiter<String>>>>,
+    daily_usage: Arc<DashMap<String, (u64, u64)>>, // client -> (day, count)
+}
+
+/// Tracks Redis failures so the limiter can fall back to in-memory counting while Redis
+/// is down and probe it again once the recovery timeout has elapsed.
+struct RedisCircuit {
+    failure_threshold: u32,
+    recovery_timeout: Duration,
+    state: Mutex<RedisCircuitState>,
+}
+
+#[derive(Default)]
+struct RedisCircuitState {
+    consecutive_failures: u32,
+    open_since: Option<Instant>,
+    /// A probe has been let through and its result isn't in yet.
+    probing: bool,
+    last_error: Option<String>,
+}
+
+impl RedisCircuit {
+    fn new(failure_threshold: u32, recovery_timeout: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            recovery_timeout,
+            state: Mutex::new(RedisCircuitState::default()),
+        }
+    }
+
+    /// Whether Redis should be tried. While open, one probe is let through per recovery timeout.
+    fn allow_request(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state.open_since {
+            None => true,
+            Some(opened) if opened.elapsed() >= self.recovery_timeout => {
+                state.open_since = Some(Instant::now());
+                state.probing = true;
+                true
+            }
+            Some(_) => false,
+        }
+    }
+
+    fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.open_since.is_some() {
+            info!("Redis rate limit storage recovered, leaving in-memory fallback");
+            RATE_LIMITER_FALLBACK_ACTIVE.set(0);
+            events::emit(LifecycleEventKind::CircuitClosed, serde_json::json!({ "circuit": "redis_rate_limit" }));
+        }
+        *state = RedisCircuitState::default();
+    }
+
+    fn record_failure(&self, error: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.consecutive_failures += 1;
+        state.probing = false;
+        state.last_error = Some(error.to_string());
+
+        if state.open_since.is_none() && state.consecutive_failures >= self.failure_threshold {
+            warn!(
+                "Redis rate limit storage unavailable after {} failures, falling back to in-memory limiting: {}",
+                state.consecutive_failures, error
+            );
+            state.open_since = Some(Instant::now());
+            RATE_LIMITER_FALLBACK_ACTIVE.set(1);
+            events::emit(
+                LifecycleEventKind::CircuitOpened,
+                serde_json::json!({ "circuit": "redis_rate_limit", "error": error }),
+            );
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.state.lock().unwrap().open_since.is_some()
+    }
+}
+
+/// Limits that apply to a particular client, resolved from its API key plan.
+#[derive(Debug, Clone)]
+pub struct EffectiveLimits {
+    pub plan: Option<String>,
+    pub requests_per_minute: u32,
+    pub burst_size: u32,
+    pub daily_quota: Option<u64>,
+    pub max_concurrent_requests: Option<u32>,
+}
+
+impl EffectiveLimits {
+    pub fn defaults(config: &RateLimitingConfig) -> Self {
+        Self {
+            plan: None,
+            requests_per_minute: config.default_requests_per_minute,
+            burst_size: config.burst_size,
+            daily_quota: None,
+            max_concurrent_requests: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RateLimiterHealth {
+    pub storage: String,
+    pub active_storage: String,
+    pub status: HealthStatus,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+}
+
+/// Identifying attributes of a caller, as far as they are known before authentication.
+#[derive(Debug, Default)]
+pub struct ClientIdentity {
+    pub api_key: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub jwt_subject: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ExemptionSet {
+    exemptions: RateLimitExemptions,
+    ip_ranges: Vec<IpNet>,
+}
+
+impl ExemptionSet {
+    fn parse(exemptions: RateLimitExemptions) -> Result<Self, RateLimitError> {
+        let ip_ranges = exemptions
+            .ip_ranges
+            .iter()
+            .map(|range| parse_ip_range(range))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            exemptions,
+            ip_ranges,
+        })
+    }
+
+    fn matches(&self, client: &ClientIdentity) -> bool {
+        if let Some(api_key) = &client.api_key {
+            if self.exemptions.api_keys.iter().any(|key| key == api_key) {
+                return true;
+            }
+        }
+
+        if let Some(ip) = &client.ip {
+            if self.ip_ranges.iter().any(|range| range.contains(ip)) {
+                return true;
+            }
+        }
+
+        if let Some(subject) = &client.jwt_subject {
+            if self.exemptions.jwt_subjects.iter().any(|sub| sub == subject) {
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+fn parse_ip_range(range: &str) -> Result<IpNet, RateLimitError> {
+    range
+        .parse::<IpNet>()
+        .or_else(|_| range.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| RateLimitError::InternalError(format!("Invalid IP range: {}", range)))
 }
 
 #[derive(Debug)]
@@ -44,28 +227,184 @@ impl RateLimiter {
             None
         };
 
+        let exemptions = ExemptionSet::parse(config.rate_limiting.exemptions.clone())?;
+
+        let redis_circuit = match &config.rate_limiting.redis_circuit_breaker {
+            Some(breaker) if breaker.enabled => RedisCircuit::new(
+                breaker.failure_threshold,
+                Duration::from_secs(breaker.recovery_timeout_seconds),
+            ),
+            Some(_) => RedisCircuit::new(u32::MAX, Duration::ZERO),
+            None => RedisCircuit::new(3, Duration::from_secs(30)),
+        };
+
         Ok(Self {
             config,
             memory_limiters: Arc::new(DashMap::new()),
             redis_client,
+            exemptions: Arc::new(RwLock::new(exemptions)),
+            redis_circuit: Arc::new(redis_circuit),
+            spike_arresters: Arc::new(DashMap::new()),
+            daily_usage: Arc::new(DashMap::new()),
         })
     }
 
-    pub async fn check_rate_limit(&self, client_id: &str) -> Result<(), RateLimitError> {
-        if self.config.rate_limiting.storage == "redis" {
-            self.check_rate_limit_redis(client_id).await
+    /// Spaces requests on a route evenly at the configured per-second rate, with no burst
+    /// allowance. Requests arriving early wait up to `max_delay_ms` for their slot and are
+    /// rejected if it is further away than that.
+    pub async fn check_spike_arrest(&self, route: &RouteConfig, client_id: &str) -> Result<(), RateLimitError> {
+        let spike_arrest = match &route.spike_arrest {
+            Some(spike_arrest) => spike_arrest,
+            None => return Ok(()),
+        };
+
+        let limiter = self
+            .spike_arresters
+            .entry(route.path.clone())
+            .or_insert_with(|| {
+                let quota = Quota::per_second(
+                    NonZeroU32::new(spike_arrest.requests_per_second).unwrap_or(nonzero!(1u32))
+                ).allow_burst(nonzero!(1u32));
+
+                Arc::new(GovernorRateLimiter::keyed(quota))
+            })
+            .clone();
+
+        let key = if spike_arrest.per_client {
+            client_id.to_string()
+        } else {
+            String::new()
+        };
+
+        let max_delay = Duration::from_millis(spike_arrest.max_delay_ms.unwrap_or(0));
+        let clock = DefaultClock::default();
+        let started = Instant::now();
+
+        loop {
+            match limiter.check_key(&key) {
+                Ok(_) => return Ok(()),
+                Err(not_until) => {
+                    let wait = not_until.wait_time_from(clock.now());
+
+                    if started.elapsed() + wait > max_delay {
+                        debug!("Spike arrest rejected request on route: {} (client: {})", route.path, client_id);
+                        return Err(RateLimitError::Exceeded);
+                    }
+
+                    tokio::time::sleep(wait).await;
+                }
+            }
+        }
+    }
+
+    /// Periodically forgets per-client spike arrest state for clients whose slot has
+    /// already passed, so the keyed limiters don't grow with every client ever seen.
+    pub async fn prune_spike_arresters(&self) {
+        let mut interval = tokio::time::interval(SPIKE_ARREST_PRUNE_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.retain_recent_spike_arrests();
+        }
+    }
+
+    fn retain_recent_spike_arrests(&self) {
+        for limiter in self.spike_arresters.iter() {
+            limiter.retain_recent();
+            limiter.shrink_to_fit();
+        }
+    }
+
+    pub fn health(&self) -> RateLimiterHealth {
+        let storage = self.config.rate_limiting.storage.clone();
+        let state = self.redis_circuit.state.lock().unwrap();
+
+        let (active_storage, status) = if storage != "redis" {
+            (storage.clone(), HealthStatus::Healthy)
+        } else if state.open_since.is_some() {
+            ("memory".to_string(), HealthStatus::Unhealthy)
         } else {
-            self.check_rate_limit_memory(client_id).await
+            (storage.clone(), HealthStatus::Healthy)
+        };
+
+        RateLimiterHealth {
+            storage,
+            active_storage,
+            status,
+            consecutive_failures: state.consecutive_failures,
+            last_error: state.last_error.clone(),
+        }
+    }
+
+    /// Degraded modes the limiter is currently running in, as `X-Gateway-Degraded` flags.
+    pub fn degradations(&self) -> Vec<&'static str> {
+        if self.config.rate_limiting.storage != "redis" {
+            return Vec::new();
+        }
+
+        let state = self.redis_circuit.state.lock().unwrap();
+        let mut flags = Vec::new();
+        if state.open_since.is_some() {
+            flags.push("rate-limit-local");
+        }
+        if state.probing {
+            flags.push("redis-circuit-half-open");
+        }
+        flags
+    }
+
+    pub async fn is_exempt(&self, client: &ClientIdentity) -> bool {
+        self.exemptions.read().await.matches(client)
+    }
+
+    pub async fn get_exemptions(&self) -> RateLimitExemptions {
+        self.exemptions.read().await.exemptions.clone()
+    }
+
+    pub async fn set_exemptions(&self, exemptions: RateLimitExemptions) -> Result<(), RateLimitError> {
+        let parsed = ExemptionSet::parse(exemptions)?;
+        *self.exemptions.write().await = parsed;
+        Ok(())
+    }
+
+    pub async fn check_rate_limit(&self, client_id: &str, limits: &EffectiveLimits) -> Result<(), RateLimitError> {
+        self.check_rate_limit_window(client_id, limits).await?;
+
+        match limits.daily_quota {
+            Some(quota) => self.check_daily_quota(client_id, quota).await,
+            None => Ok(()),
         }
     }
 
-    async fn check_rate_limit_memory(&self, client_id: &str) -> Result<(), RateLimitError> {
+    async fn check_rate_limit_window(&self, client_id: &str, limits: &EffectiveLimits) -> Result<(), RateLimitError> {
+        if self.config.rate_limiting.storage != "redis" || !self.redis_circuit.allow_request() {
+            return self.check_rate_limit_memory(client_id, limits).await;
+        }
+
+        let result = match tokio::time::timeout(REDIS_OPERATION_TIMEOUT, self.check_rate_limit_redis(client_id, limits)).await {
+            Ok(result) => result,
+            Err(_) => Err(RateLimitError::InternalError("Redis operation timed out".to_string())),
+        };
+
+        match result {
+            Err(RateLimitError::InternalError(e)) => {
+                error!("Redis rate limit check failed, using in-memory fallback: {}", e);
+                self.redis_circuit.record_failure(&e);
+                self.check_rate_limit_memory(client_id, limits).await
+            }
+            result => {
+                self.redis_circuit.record_success();
+                result
+            }
+        }
+    }
+
+    async fn check_rate_limit_memory(&self, client_id: &str, limits: &EffectiveLimits) -> Result<(), RateLimitError> {
         let limiter = self.memory_limiters.entry(client_id.to_string()).or_insert_with(|| {
             let quota = Quota::per_minute(
-                NonZeroU32::new(self.config.rate_limiting.default_requests_per_minute)
+                NonZeroU32::new(limits.requests_per_minute)
                     .unwrap_or(nonzero!(60u32))
             ).allow_burst(
-                NonZeroU32::new(self.config.rate_limiting.burst_size)
+                NonZeroU32::new(limits.burst_size)
                     .unwrap_or(nonzero!(10u32))
             );
             
@@ -84,7 +423,7 @@ impl RateLimiter {
         }
     }
 
-    async fn check_rate_limit_redis(&self, client_id: &str) -> Result<(), RateLimitError> {
+    async fn check_rate_limit_redis(&self, client_id: &str, limits: &EffectiveLimits) -> Result<(), RateLimitError> {
         let redis_client = self.redis_client.as_ref()
             .ok_or_else(|| RateLimitError::InternalError("Redis client not configured".to_string()))?;
 
@@ -105,7 +444,7 @@ impl RateLimiter {
             .await
             .map_err(|e| RateLimitError::InternalError(format!("Redis query error: {}", e)))?;
 
-        if current_count > self.config.rate_limiting.default_requests_per_minute as i32 {
+        if current_count > limits.requests_per_minute as i32 {
             debug!("Rate limit exceeded for client: {} (count: {})", client_id, current_count);
             Err(RateLimitError::Exceeded)
         } else {
@@ -114,6 +453,71 @@ impl RateLimiter {
         }
     }
 
+    async fn check_daily_quota(&self, client_id: &str, quota: u64) -> Result<(), RateLimitError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let day = now / 86_400;
+
+        let used = if self.config.rate_limiting.storage == "redis" && !self.redis_circuit.is_open() {
+            match tokio::time::timeout(REDIS_OPERATION_TIMEOUT, self.increment_daily_usage_redis(client_id, day)).await {
+                Ok(Ok(used)) => used,
+                Ok(Err(RateLimitError::InternalError(e))) => {
+                    self.redis_circuit.record_failure(&e);
+                    self.increment_daily_usage_memory(client_id, day)
+                }
+                Ok(Err(e)) => return Err(e),
+                Err(_) => {
+                    self.redis_circuit.record_failure("Redis operation timed out");
+                    self.increment_daily_usage_memory(client_id, day)
+                }
+            }
+        } else {
+            self.increment_daily_usage_memory(client_id, day)
+        };
+
+        if used > quota {
+            debug!("Daily quota exhausted for client: {} ({}/{})", client_id, used, quota);
+            Err(RateLimitError::Exceeded)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn increment_daily_usage_memory(&self, client_id: &str, day: u64) -> u64 {
+        let mut usage = self.daily_usage.entry(client_id.to_string()).or_insert((day, 0));
+        if usage.0 != day {
+            *usage = (day, 0);
+        }
+        usage.1 += 1;
+        usage.1
+    }
+
+    async fn increment_daily_usage_redis(&self, client_id: &str, day: u64) -> Result<u64, RateLimitError> {
+        let redis_client = self.redis_client.as_ref()
+            .ok_or_else(|| RateLimitError::InternalError("Redis client not configured".to_string()))?;
+
+        let mut conn = redis_client.get_async_connection().await
+            .map_err(|e| RateLimitError::InternalError(format!("Redis connection error: {}", e)))?;
+
+        let key = format!("quota:{}:{}", client_id, day);
+        let (used,): (u64,) = redis::pipe()
+            .incr(&key, 1)
+            .expire(&key, 86_400)
+            .ignore()
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| RateLimitError::InternalError(format!("Redis query error: {}", e)))?;
+
+        Ok(used)
+    }
+
+    /// Unix time at which the current rate limit window resets.
+    pub fn current_window_reset(&self) -> u64 {
+        self.get_current_window_start() + 60
+    }
+
     fn get_current_window_start(&self) -> u64 {
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -125,7 +529,7 @@ impl RateLimiter {
     }
 
     pub async fn get_rate_limit_status(&self, client_id: &str) -> Option<RateLimitStatus> {
-        if self.config.rate_limiting.storage == "redis" {
+        if self.config.rate_limiting.storage == "redis" && !self.redis_circuit.is_open() {
             self.get_rate_limit_status_redis(client_id).await
         } else {
             self.get_rate_limit_status_memory(client_id).await
@@ -133,12 +537,17 @@ impl RateLimiter {
     }
 
     async fn get_rate_limit_status_memory(&self, client_id: &str) -> Option<RateLimitStatus> {
+        if !self.memory_limiters.contains_key(client_id) {
+            return None;
+        }
+
         // For in-memory rate limiting, we can't easily get the current count
         // This is a limitation of the governor crate
         Some(RateLimitStatus {
+            client_id: client_id.to_string(),
             limit: self.config.rate_limiting.default_requests_per_minute,
-            remaining: 0, // Unknown for memory-based limiting
-            reset_time: 0, // Unknown for memory-based limiting
+            remaining: None,
+            reset_time: None,
         })
     }
 
@@ -159,16 +568,176 @@ impl RateLimiter {
         };
 
         Some(RateLimitStatus {
+            client_id: client_id.to_string(),
             limit,
-            remaining,
-            reset_time: window_start + 60, // Next minute
+            remaining: Some(remaining),
+            reset_time: Some(window_start + 60), // Next minute
         })
     }
+
+    /// Clears a client's counters. Returns whether any state existed.
+    pub async fn reset_rate_limit(&self, client_id: &str) -> Result<bool, RateLimitError> {
+        // Fallback counters may hold state from a Redis outage, so always clear them
+        let memory_reset = self.memory_limiters.remove(client_id).is_some();
+
+        if self.config.rate_limiting.storage == "redis" {
+            Ok(self.reset_rate_limit_redis(client_id).await? || memory_reset)
+        } else {
+            Ok(memory_reset)
+        }
+    }
+
+    async fn reset_rate_limit_redis(&self, client_id: &str) -> Result<bool, RateLimitError> {
+        let redis_client = self.redis_client.as_ref()
+            .ok_or_else(|| RateLimitError::InternalError("Redis client not configured".to_string()))?;
+
+        let mut conn = redis_client.get_async_connection().await
+            .map_err(|e| RateLimitError::InternalError(format!("Redis connection error: {}", e)))?;
+
+        let window_key = format!("rate_limit:{}:{}", client_id, self.get_current_window_start());
+        let deleted: i32 = conn.del(&window_key).await
+            .map_err(|e| RateLimitError::InternalError(format!("Redis query error: {}", e)))?;
+
+        Ok(deleted > 0)
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct RateLimitStatus {
+    pub client_id: String,
     pub limit: u32,
-    pub remaining: u32,
-    pub reset_time: u64,
-} 
\ No newline at end of file
+    pub remaining: Option<u32>, // Unknown for memory-based limiting
+    pub reset_time: Option<u64>, // Unknown for memory-based limiting
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exemption_set() -> ExemptionSet {
+        ExemptionSet::parse(RateLimitExemptions {
+            api_keys: vec!["ak_monitoring".to_string()],
+            ip_ranges: vec!["10.0.0.0/8".to_string(), "192.168.1.5".to_string()],
+            jwt_subjects: vec!["prometheus".to_string()],
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_exempt_api_key_and_subject() {
+        let set = exemption_set();
+
+        assert!(set.matches(&ClientIdentity {
+            api_key: Some("ak_monitoring".to_string()),
+            ..Default::default()
+        }));
+        assert!(set.matches(&ClientIdentity {
+            jwt_subject: Some("prometheus".to_string()),
+            ..Default::default()
+        }));
+        assert!(!set.matches(&ClientIdentity {
+            api_key: Some("ak_other".to_string()),
+            jwt_subject: Some("someone".to_string()),
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn test_exempt_ip_ranges() {
+        let set = exemption_set();
+
+        let ip = |s: &str| ClientIdentity {
+            ip: Some(s.parse().unwrap()),
+            ..Default::default()
+        };
+
+        assert!(set.matches(&ip("10.1.2.3")));
+        assert!(set.matches(&ip("192.168.1.5")));
+        assert!(!set.matches(&ip("192.168.1.6")));
+    }
+
+    #[test]
+    fn test_invalid_ip_range_rejected() {
+        let result = ExemptionSet::parse(RateLimitExemptions {
+            ip_ranges: vec!["not-an-ip".to_string()],
+            ..Default::default()
+        });
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_redis_circuit_half_open_probe() {
+        let circuit = RedisCircuit::new(1, Duration::ZERO);
+        circuit.record_failure("connection refused");
+        assert!(circuit.is_open());
+
+        assert!(circuit.allow_request());
+        assert!(circuit.state.lock().unwrap().probing);
+
+        circuit.record_success();
+        assert!(!circuit.is_open());
+        assert!(!circuit.state.lock().unwrap().probing);
+    }
+
+    #[test]
+    fn test_redis_circuit_opens_at_threshold_and_blocks_until_recovery() {
+        let circuit = RedisCircuit::new(3, Duration::from_secs(60));
+
+        circuit.record_failure("timeout");
+        circuit.record_failure("timeout");
+        assert!(!circuit.is_open());
+        assert!(circuit.allow_request());
+
+        // A success in between starts the count over
+        circuit.record_success();
+        circuit.record_failure("timeout");
+        circuit.record_failure("timeout");
+        assert!(!circuit.is_open());
+
+        circuit.record_failure("timeout");
+        assert!(circuit.is_open());
+        assert!(!circuit.allow_request());
+        assert_eq!(circuit.state.lock().unwrap().last_error.as_deref(), Some("timeout"));
+    }
+
+    #[test]
+    fn test_redis_circuit_failed_probe_stays_open() {
+        let circuit = RedisCircuit::new(1, Duration::ZERO);
+        circuit.record_failure("connection refused");
+
+        assert!(circuit.allow_request());
+        circuit.record_failure("connection refused");
+        assert!(circuit.is_open());
+        assert!(!circuit.state.lock().unwrap().probing);
+        assert_eq!(circuit.state.lock().unwrap().consecutive_failures, 2);
+
+        assert!(circuit.allow_request());
+        circuit.record_success();
+        assert!(!circuit.is_open());
+        assert_eq!(circuit.state.lock().unwrap().consecutive_failures, 0);
+    }
+
+    #[tokio::test]
+    async fn test_idle_per_client_spike_arrests_are_pruned() {
+        let mut config = Config::default_config();
+        config.rate_limiting.storage = "memory".to_string();
+        let limiter = RateLimiter::new(Arc::new(config.clone())).await.unwrap();
+        let mut route = config.routes[0].clone();
+        route.spike_arrest = Some(crate::config::SpikeArrestConfig {
+            requests_per_second: 100,
+            max_delay_ms: None,
+            per_client: true,
+        });
+
+        for client in ["a", "b", "c"] {
+            assert!(limiter.check_spike_arrest(&route, client).await.is_ok());
+        }
+        assert_eq!(limiter.spike_arresters.get(&route.path).unwrap().len(), 3);
+
+        // Each client's next slot is 10ms out, after which its state can go
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        limiter.retain_recent_spike_arrests();
+        assert!(limiter.spike_arresters.get(&route.path).unwrap().is_empty());
+    }
+}
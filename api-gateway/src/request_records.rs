@@ -0,0 +1,203 @@
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use crate::config::RequestRecordsConfig;
+
+/// What happened to one request, kept so support can look it up by request ID.
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestRecord {
+    pub request_id: String,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub client_ip: Option<IpAddr>,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub duration_ms: f64,
+    pub route: Option<String>,
+    pub backend: Option<String>,
+    pub server: Option<String>,
+    /// Calls made to the backend server, including retries
+    pub attempts: u32,
+    /// Time spent waiting on the backend
+    pub upstream_ms: Option<f64>,
+    /// e.g. `authenticated`, `anonymous` or `rejected: expired_token`; absent when the
+    /// request didn't need auth or never reached it
+    pub auth: Option<String>,
+    /// The stages the request went through, in order
+    pub stages: Vec<StageTiming>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StageTiming {
+    pub stage: &'static str,
+    /// When the stage began, from when the gateway received the request
+    pub started_ms: f64,
+    /// Until the next stage began, or the response for the last one
+    pub duration_ms: f64,
+}
+
+/// Collects stage timings and the auth outcome while a request is handled. Middleware
+/// find it in the request extensions; it's absent when recording is off.
+#[derive(Debug, Clone)]
+pub struct RequestTrace(Arc<Mutex<TraceState>>);
+
+#[derive(Debug)]
+struct TraceState {
+    start: Instant,
+    stages: Vec<(&'static str, f64)>,
+    auth: Option<String>,
+}
+
+impl RequestTrace {
+    pub fn new(start: Instant) -> Self {
+        Self(Arc::new(Mutex::new(TraceState {
+            start,
+            stages: Vec::new(),
+            auth: None,
+        })))
+    }
+
+    /// Notes that `stage` begins now.
+    pub fn mark(&self, stage: &'static str) {
+        if let Ok(mut state) = self.0.lock() {
+            let elapsed = state.start.elapsed().as_secs_f64() * 1000.0;
+            state.stages.push((stage, elapsed));
+        }
+    }
+
+    pub fn set_auth(&self, outcome: impl Into<String>) {
+        if let Ok(mut state) = self.0.lock() {
+            state.auth = Some(outcome.into());
+        }
+    }
+
+    /// The stages with their durations, given the request's total duration, and the
+    /// auth outcome.
+    fn finish(&self, duration_ms: f64) -> (Vec<StageTiming>, Option<String>) {
+        let state = match self.0.lock() {
+            Ok(state) => state,
+            Err(_) => return (Vec::new(), None),
+        };
+        let stages = state
+            .stages
+            .iter()
+            .enumerate()
+            .map(|(i, (stage, started_ms))| {
+                let ended_ms = state.stages.get(i + 1).map_or(duration_ms, |(_, next)| *next);
+                StageTiming {
+                    stage,
+                    started_ms: *started_ms,
+                    duration_ms: (ended_ms - started_ms).max(0.0),
+                }
+            })
+            .collect();
+        (stages, state.auth.clone())
+    }
+}
+
+/// Marks the start of `stage` on the request's trace, if it has one.
+pub fn mark(extensions: &axum::http::Extensions, stage: &'static str) {
+    if let Some(trace) = extensions.get::<RequestTrace>() {
+        trace.mark(stage);
+    }
+}
+
+/// Ring buffer of the most recent request records.
+pub struct RequestRecords {
+    buffer_size: usize,
+    records: Mutex<VecDeque<RequestRecord>>,
+}
+
+impl RequestRecords {
+    pub fn new(config: &RequestRecordsConfig) -> Self {
+        Self {
+            buffer_size: config.buffer_size,
+            records: Mutex::new(VecDeque::with_capacity(config.buffer_size)),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.buffer_size > 0
+    }
+
+    /// Stores the record, filling in the stages and auth outcome from its trace.
+    pub fn record(&self, mut record: RequestRecord, trace: &RequestTrace) {
+        if !self.enabled() {
+            return;
+        }
+        let (stages, auth) = trace.finish(record.duration_ms);
+        record.stages = stages;
+        record.auth = auth;
+
+        let mut records = self.records.lock().unwrap();
+        if records.len() >= self.buffer_size {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    pub fn get(&self, request_id: &str) -> Option<RequestRecord> {
+        self.records
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|record| record.request_id == request_id)
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(request_id: &str) -> RequestRecord {
+        RequestRecord {
+            request_id: request_id.to_string(),
+            timestamp: chrono::Utc::now(),
+            client_ip: None,
+            method: "GET".to_string(),
+            path: "/api/orders".to_string(),
+            status: 200,
+            duration_ms: 50.0,
+            route: None,
+            backend: None,
+            server: None,
+            attempts: 0,
+            upstream_ms: None,
+            auth: None,
+            stages: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_ring_buffer() {
+        let records = RequestRecords::new(&RequestRecordsConfig { buffer_size: 2 });
+        let trace = RequestTrace::new(Instant::now());
+        for request_id in ["a", "b", "c"] {
+            records.record(record(request_id), &trace);
+        }
+
+        assert!(records.get("a").is_none());
+        assert_eq!(records.get("c").unwrap().request_id, "c");
+    }
+
+    #[test]
+    fn test_trace_stages() {
+        let trace = RequestTrace::new(Instant::now());
+        trace.mark("auth");
+        trace.mark("proxy");
+        trace.set_auth("authenticated");
+
+        let (stages, auth) = trace.finish(1000.0);
+        assert_eq!(stages.iter().map(|stage| stage.stage).collect::<Vec<_>>(), vec!["auth", "proxy"]);
+        assert_eq!(stages[0].duration_ms, stages[1].started_ms - stages[0].started_ms);
+        assert_eq!(stages[1].duration_ms, 1000.0 - stages[1].started_ms);
+        assert_eq!(auth.as_deref(), Some("authenticated"));
+    }
+}
@@ -0,0 +1,129 @@
+use serde::Deserialize;
+use tokio::sync::broadcast;
+
+use crate::access_log::AccessLogEntry;
+use crate::config::path_matches;
+
+/// Entries buffered per subscriber; a subscriber further behind than this skips ahead.
+const TAIL_CAPACITY: usize = 1024;
+
+/// Fans completed requests out to `/admin/tail` subscribers. Nothing is kept or built
+/// while no one is watching.
+pub struct RequestTail {
+    sender: broadcast::Sender<AccessLogEntry>,
+}
+
+impl RequestTail {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(TAIL_CAPACITY);
+        Self { sender }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    pub fn publish(&self, entry: &AccessLogEntry) {
+        if self.is_active() {
+            let _ = self.sender.send(entry.clone());
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<AccessLogEntry> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for RequestTail {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Narrows a tail to the requests of interest; every field left out matches anything.
+#[derive(Debug, Default, Deserialize)]
+pub struct TailFilter {
+    /// A route path, or any path pattern in route syntax (e.g. `/api/orders/*`)
+    pub route: Option<String>,
+    /// An exact status like `404`, or a class like `5xx`
+    pub status: Option<String>,
+    pub min_latency_ms: Option<f64>,
+    /// Client IP
+    pub client: Option<String>,
+}
+
+impl TailFilter {
+    pub fn matches(&self, entry: &AccessLogEntry) -> bool {
+        let route = self.route.as_deref().map_or(true, |route| {
+            entry.route.as_deref() == Some(route) || path_matches(route, &entry.path)
+        });
+        let status = self.status.as_deref().map_or(true, |status| {
+            let status = status.to_ascii_lowercase();
+            match status.strip_suffix("xx") {
+                Some(class) => class == (entry.status / 100).to_string(),
+                None => status == entry.status.to_string(),
+            }
+        });
+        let latency = self.min_latency_ms.map_or(true, |min| entry.duration_ms >= min);
+        let client = self
+            .client
+            .as_deref()
+            .map_or(true, |client| entry.client_ip.map_or(false, |ip| ip.to_string() == client));
+
+        route && status && latency && client
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn entry(path: &str, status: u16, duration_ms: f64) -> AccessLogEntry {
+        AccessLogEntry {
+            timestamp: chrono::Utc::now(),
+            request_id: "req-1".to_string(),
+            client_ip: "198.51.100.7".parse().ok(),
+            method: "GET".to_string(),
+            path: path.to_string(),
+            status,
+            duration_ms,
+            user_agent: None,
+            route: Some("/api/orders/*".to_string()),
+            backend: Some("orders".to_string()),
+            request_bytes: None,
+            response_bytes: None,
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = |filter: serde_json::Value| -> TailFilter { serde_json::from_value(filter).unwrap() };
+
+        assert!(TailFilter::default().matches(&entry("/api/orders/1", 200, 3.0)));
+        assert!(filter(json!({ "route": "/api/orders/*" })).matches(&entry("/api/orders/1", 200, 3.0)));
+        assert!(filter(json!({ "route": "/api/orders/1" })).matches(&entry("/api/orders/1", 200, 3.0)));
+        assert!(!filter(json!({ "route": "/api/users/*" })).matches(&entry("/api/orders/1", 200, 3.0)));
+
+        assert!(filter(json!({ "status": "5XX" })).matches(&entry("/", 503, 3.0)));
+        assert!(!filter(json!({ "status": "5xx" })).matches(&entry("/", 404, 3.0)));
+        assert!(filter(json!({ "status": "404" })).matches(&entry("/", 404, 3.0)));
+
+        let slow_from_client = filter(json!({ "min_latency_ms": 250.0, "client": "198.51.100.7" }));
+        assert!(slow_from_client.matches(&entry("/", 200, 300.0)));
+        assert!(!slow_from_client.matches(&entry("/", 200, 20.0)));
+        assert!(!filter(json!({ "client": "203.0.113.1" })).matches(&entry("/", 200, 300.0)));
+    }
+
+    #[tokio::test]
+    async fn test_publish_only_reaches_subscribers() {
+        let tail = RequestTail::new();
+        assert!(!tail.is_active());
+        tail.publish(&entry("/dropped", 200, 1.0));
+
+        let mut receiver = tail.subscribe();
+        assert!(tail.is_active());
+        tail.publish(&entry("/seen", 200, 1.0));
+        assert_eq!(receiver.recv().await.unwrap().path, "/seen");
+    }
+}
@@ -0,0 +1,737 @@
+use axum::{
+    body::{Body, Bytes},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri},
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use lru::LruCache;
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    num::NonZeroUsize,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::auth::Identity;
+use crate::config::{RouteCacheConfig, RouteConfig};
+
+pub const CACHE_STATUS_HEADER: &str = "X-Cache";
+
+/// Marks a configured `vary` entry as a claim of the authenticated caller
+const CLAIM_PREFIX: &str = "claim:";
+
+/// Headers a 304 carries: copied from the cached response into the ones the gateway
+/// sends, and from the backend's into the cached response.
+const NOT_MODIFIED_HEADERS: [HeaderName; 7] = [
+    header::CACHE_CONTROL,
+    header::CONTENT_LOCATION,
+    header::DATE,
+    header::ETAG,
+    header::EXPIRES,
+    header::LAST_MODIFIED,
+    header::VARY,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    Hit,
+    Miss,
+    /// Served from the cache after the backend confirmed it with a 304
+    Revalidated,
+    /// Served past its freshness lifetime, while refreshing or because the backend failed
+    Stale,
+}
+
+impl CacheStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            CacheStatus::Hit => "HIT",
+            CacheStatus::Miss => "MISS",
+            CacheStatus::Revalidated => "REVALIDATED",
+            CacheStatus::Stale => "STALE",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    stored_at: Instant,
+    expires_at: Instant,
+    stale_while_revalidate: Duration,
+    stale_if_error: Duration,
+}
+
+impl CachedResponse {
+    /// Seconds since the response was stored or last revalidated, for the `Age` header
+    pub fn age(&self) -> u64 {
+        self.stored_at.elapsed().as_secs()
+    }
+
+    /// Whether the response can be served as is while it's refreshed in the background
+    pub fn serve_while_revalidating(&self) -> bool {
+        Instant::now() < self.expires_at + self.stale_while_revalidate
+    }
+
+    /// Whether the response can stand in for a failed backend request
+    pub fn usable_on_error(&self) -> bool {
+        Instant::now() < self.expires_at + self.stale_if_error
+    }
+
+    fn has_validators(&self) -> bool {
+        self.headers.contains_key(header::ETAG) || self.headers.contains_key(header::LAST_MODIFIED)
+    }
+
+    /// Whether there's any use for the response once it's expired
+    fn usable_when_stale(&self) -> bool {
+        self.has_validators() || self.serve_while_revalidating() || self.usable_on_error()
+    }
+
+    /// Makes a request to the backend conditional on the cached copy having changed.
+    pub fn add_validators(&self, headers: &mut HeaderMap) {
+        headers.remove(header::IF_NONE_MATCH);
+        headers.remove(header::IF_MODIFIED_SINCE);
+        if let Some(etag) = self.headers.get(header::ETAG) {
+            headers.insert(header::IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(last_modified) = self.headers.get(header::LAST_MODIFIED) {
+            headers.insert(header::IF_MODIFIED_SINCE, last_modified.clone());
+        }
+    }
+
+    /// Whether the client's conditional headers show it already has this response.
+    /// `If-Modified-Since` only counts when there's no `If-None-Match`.
+    fn not_modified_for(&self, request_headers: &HeaderMap) -> bool {
+        if self.status != StatusCode::OK {
+            return false;
+        }
+
+        if let Some(if_none_match) = request_headers.get(header::IF_NONE_MATCH).and_then(|value| value.to_str().ok()) {
+            let etag = match self.headers.get(header::ETAG).and_then(|value| value.to_str().ok()) {
+                Some(etag) => etag,
+                None => return false,
+            };
+            return if_none_match.trim() == "*"
+                || if_none_match.split(',').any(|tag| weak_etag(tag.trim()) == weak_etag(etag));
+        }
+
+        match (
+            request_headers.get(header::IF_MODIFIED_SINCE).and_then(http_date),
+            self.headers.get(header::LAST_MODIFIED).and_then(http_date),
+        ) {
+            (Some(if_modified_since), Some(last_modified)) => last_modified <= if_modified_since,
+            _ => false,
+        }
+    }
+}
+
+pub enum CacheLookup {
+    Fresh(CachedResponse),
+    /// Past its freshness lifetime, but still within a stale window or has validators
+    /// to revalidate it with
+    Stale(CachedResponse),
+}
+
+/// Upper bound on the variants kept under one key, so a backend varying on a header
+/// with unbounded values can't grow an entry without limit.
+const MAX_VARIANTS: usize = 32;
+
+/// The responses stored under one cache key, one per combination of values of the
+/// request headers the backend lists in `Vary`.
+struct Variants {
+    vary: Vec<HeaderName>,
+    responses: HashMap<String, CachedResponse>,
+}
+
+/// Per-route LRU caches of backend responses. Each route gets its own cache so one busy
+/// route can't evict everything else.
+pub struct ResponseCache {
+    routes: Mutex<HashMap<String, LruCache<String, Variants>>>,
+    /// Keys with a background refresh in progress
+    refreshing: Mutex<HashSet<String>>,
+}
+
+impl ResponseCache {
+    pub fn new() -> Self {
+        Self {
+            routes: Mutex::new(HashMap::new()),
+            refreshing: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// The cache key for a request, or `None` if it can't be served from the cache.
+    /// Includes the values of the route's configured `vary` headers and claims.
+    pub fn key(
+        route: &RouteConfig,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        identity: Option<&Identity>,
+        api_key_header: &str,
+    ) -> Option<String> {
+        let config = route.cache.as_ref()?;
+        if route.streaming.is_some() || !(method == Method::GET || method == Method::HEAD) {
+            return None;
+        }
+        // Responses to requests with credentials (a bearer token, an API key, or any
+        // cookie, which includes session cookies) may be specific to the caller, so
+        // they're only shared between requests the key says come from the same caller
+        let presented: Vec<&str> = [header::AUTHORIZATION.as_str(), header::COOKIE.as_str(), api_key_header]
+            .into_iter()
+            .filter(|name| headers.contains_key(*name))
+            .collect();
+        if identity.is_some() || !presented.is_empty() {
+            let identifies_caller = config.vary.iter().any(|vary| {
+                presented.iter().any(|name| vary.eq_ignore_ascii_case(name))
+                    || (vary.starts_with(CLAIM_PREFIX) && identity.is_some())
+            });
+            if !identifies_caller {
+                return None;
+            }
+        }
+
+        let mut key = format!(
+            "{} {}",
+            method,
+            uri.path_and_query().map(|pq| pq.as_str()).unwrap_or(uri.path())
+        );
+        for vary in &config.vary {
+            let value = match vary.strip_prefix(CLAIM_PREFIX) {
+                Some(claim) => match identity.and_then(|identity| identity.claims.get(claim)) {
+                    Some(Value::String(value)) => value.clone(),
+                    Some(value) => value.to_string(),
+                    None => String::new(),
+                },
+                None => header_values(headers, vary),
+            };
+            key.push_str(&format!("\n{}={}", vary.to_ascii_lowercase(), value));
+        }
+        Some(key)
+    }
+
+    pub fn lookup(&self, route: &RouteConfig, key: &str, request_headers: &HeaderMap) -> Option<CacheLookup> {
+        let mut routes = self.routes.lock().unwrap();
+        let cache = routes.get_mut(&route.path)?;
+
+        let variants = cache.get_mut(key)?;
+        let variant = variant_key(&variants.vary, request_headers);
+        let cached = variants.responses.get(&variant)?;
+        let found = if cached.expires_at > Instant::now() {
+            Some(CacheLookup::Fresh(cached.clone()))
+        } else if cached.usable_when_stale() {
+            Some(CacheLookup::Stale(cached.clone()))
+        } else {
+            None
+        };
+
+        if found.is_none() {
+            variants.responses.remove(&variant);
+            if variants.responses.is_empty() {
+                cache.pop(key);
+            }
+        }
+        found
+    }
+
+    /// Stores the response if it's cacheable; returns whether it was stored.
+    pub fn store(
+        &self,
+        route: &RouteConfig,
+        key: String,
+        request_headers: &HeaderMap,
+        status: StatusCode,
+        headers: &HeaderMap,
+        body: &Bytes,
+    ) -> bool {
+        let config = match &route.cache {
+            Some(config) => config,
+            None => return false,
+        };
+        if !is_cacheable(config, status, headers, body) {
+            return false;
+        }
+        let lifetime = match freshness_lifetime(config, headers) {
+            Some(lifetime) => lifetime,
+            None => return false,
+        };
+        let vary = match vary_headers(headers) {
+            Some(vary) => vary,
+            None => return false,
+        };
+
+        let (stale_while_revalidate, stale_if_error) = stale_windows(config, headers);
+        let now = Instant::now();
+        let cached = CachedResponse {
+            status,
+            headers: headers.clone(),
+            body: body.clone(),
+            stored_at: now,
+            expires_at: now + lifetime,
+            stale_while_revalidate,
+            stale_if_error,
+        };
+        if lifetime.is_zero() && !cached.usable_when_stale() {
+            return false;
+        }
+
+        let capacity = NonZeroUsize::new(config.max_entries).unwrap_or(NonZeroUsize::MIN);
+        let mut routes = self.routes.lock().unwrap();
+        let cache = routes
+            .entry(route.path.clone())
+            .or_insert_with(|| LruCache::new(capacity));
+        // Picks up a changed `max_entries` after a config reload
+        if cache.cap() != capacity {
+            cache.resize(capacity);
+        }
+
+        let variants = cache.get_or_insert_mut(key, || Variants {
+            vary: vary.clone(),
+            responses: HashMap::new(),
+        });
+        // Variants stored under different `Vary` headers can't be matched any more
+        if variants.vary != vary {
+            variants.vary = vary;
+            variants.responses.clear();
+        }
+        let variant = variant_key(&variants.vary, request_headers);
+        if variants.responses.len() >= MAX_VARIANTS && !variants.responses.contains_key(&variant) {
+            let oldest = variants
+                .responses
+                .iter()
+                .min_by_key(|(_, cached)| cached.stored_at)
+                .map(|(variant, _)| variant.clone());
+            if let Some(oldest) = oldest {
+                variants.responses.remove(&oldest);
+            }
+        }
+        variants.responses.insert(variant, cached);
+        true
+    }
+
+    /// Freshens a stale entry the backend answered with a 304, taking the updated headers
+    /// from it.
+    pub fn refresh(
+        &self,
+        route: &RouteConfig,
+        key: &str,
+        request_headers: &HeaderMap,
+        not_modified_headers: &HeaderMap,
+    ) -> Option<CachedResponse> {
+        let config = route.cache.as_ref()?;
+        let mut routes = self.routes.lock().unwrap();
+        let variants = routes.get_mut(&route.path)?.get_mut(key)?;
+        let cached = variants
+            .responses
+            .get_mut(&variant_key(&variants.vary, request_headers))?;
+
+        for name in NOT_MODIFIED_HEADERS.iter() {
+            if let Some(value) = not_modified_headers.get(name) {
+                cached.headers.insert(name.clone(), value.clone());
+            }
+        }
+        let now = Instant::now();
+        cached.stored_at = now;
+        cached.expires_at = now + freshness_lifetime(config, &cached.headers).unwrap_or_default();
+        (cached.stale_while_revalidate, cached.stale_if_error) = stale_windows(config, &cached.headers);
+        Some(cached.clone())
+    }
+
+    /// Claims the background refresh of `key`; false if one is already running.
+    pub fn begin_refresh(&self, key: &str) -> bool {
+        self.refreshing.lock().unwrap().insert(key.to_string())
+    }
+
+    pub fn end_refresh(&self, key: &str) {
+        self.refreshing.lock().unwrap().remove(key);
+    }
+}
+
+impl Default for ResponseCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn set_cache_status(headers: &mut HeaderMap, status: CacheStatus) {
+    headers.insert(CACHE_STATUS_HEADER, HeaderValue::from_static(status.as_str()));
+}
+
+/// Builds the response for a request answered from the cache: a 304 when the client's
+/// conditional headers match, otherwise the cached response itself.
+pub fn respond(cached: CachedResponse, request_headers: &HeaderMap, status: CacheStatus) -> anyhow::Result<Response> {
+    let age = cached.age();
+
+    let mut response = if cached.not_modified_for(request_headers) {
+        let mut builder = Response::builder().status(StatusCode::NOT_MODIFIED);
+        for name in NOT_MODIFIED_HEADERS.iter() {
+            if let Some(value) = cached.headers.get(name) {
+                builder = builder.header(name, value);
+            }
+        }
+        builder.body(Body::empty())?
+    } else {
+        let mut builder = Response::builder().status(cached.status);
+        for (name, value) in cached.headers.iter() {
+            builder = builder.header(name, value);
+        }
+        builder.body(Body::from(cached.body))?
+    };
+
+    set_cache_status(response.headers_mut(), status);
+    response.headers_mut().insert(header::AGE, age.into());
+    Ok(response)
+}
+
+/// Only final, shareable responses small enough for the route's limit are kept.
+fn is_cacheable(config: &RouteCacheConfig, status: StatusCode, headers: &HeaderMap, body: &Bytes) -> bool {
+    matches!(status.as_u16(), 200 | 203 | 204 | 300 | 301 | 404 | 410)
+        && !headers.contains_key(header::SET_COOKIE)
+        && body.len() <= config.max_object_bytes
+}
+
+/// How long a response stays fresh, from its `Cache-Control` or `Expires` headers and
+/// falling back to the route's TTL. `None` means it mustn't be stored at all.
+fn freshness_lifetime(config: &RouteCacheConfig, headers: &HeaderMap) -> Option<Duration> {
+    let directives = cache_control(headers);
+    let directive = |name: &str| directives.iter().find(|(directive, _)| directive == name);
+
+    if directive("no-store").is_some() || directive("private").is_some() {
+        return None;
+    }
+    if directive("no-cache").is_some() {
+        return Some(Duration::ZERO);
+    }
+
+    // s-maxage applies to shared caches like this one and takes precedence
+    for name in ["s-maxage", "max-age"] {
+        if let Some((_, Some(value))) = directive(name) {
+            return Some(Duration::from_secs(value.parse().unwrap_or(0)));
+        }
+    }
+
+    if let Some(expires) = headers.get(header::EXPIRES) {
+        // An invalid Expires means already expired
+        let expires = match http_date(expires) {
+            Some(expires) => expires,
+            None => return Some(Duration::ZERO),
+        };
+        let date = headers.get(header::DATE).and_then(http_date).unwrap_or_else(Utc::now);
+        return Some((expires - date).to_std().unwrap_or_default());
+    }
+
+    Some(Duration::from_secs(config.ttl_seconds))
+}
+
+/// How long past expiry the response may be served while revalidating and on errors.
+/// Responses that must be revalidated before every use get neither.
+fn stale_windows(config: &RouteCacheConfig, headers: &HeaderMap) -> (Duration, Duration) {
+    let directives = cache_control(headers);
+    if directives
+        .iter()
+        .any(|(name, _)| matches!(name.as_str(), "no-cache" | "must-revalidate" | "proxy-revalidate"))
+    {
+        return (Duration::ZERO, Duration::ZERO);
+    }
+
+    let window = |name: &str, configured: Option<u64>| {
+        let seconds = directives
+            .iter()
+            .find(|(directive, _)| directive == name)
+            .and_then(|(_, value)| value.as_ref()?.parse().ok())
+            .or(configured)
+            .unwrap_or(0);
+        Duration::from_secs(seconds)
+    };
+    (
+        window("stale-while-revalidate", config.stale_while_revalidate_seconds),
+        window("stale-if-error", config.stale_if_error_seconds),
+    )
+}
+
+/// The request headers the response varies on, sorted, or `None` for `Vary: *`, which
+/// no later request can be known to match.
+fn vary_headers(headers: &HeaderMap) -> Option<Vec<HeaderName>> {
+    let mut vary = Vec::new();
+    for name in headers
+        .get_all(header::VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        if name == "*" {
+            return None;
+        }
+        if let Ok(name) = HeaderName::from_bytes(name.as_bytes()) {
+            vary.push(name);
+        }
+    }
+    vary.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    vary.dedup();
+    Some(vary)
+}
+
+/// Identifies the variant a request gets: its values of the `Vary` headers
+fn variant_key(vary: &[HeaderName], request_headers: &HeaderMap) -> String {
+    vary.iter()
+        .map(|name| header_values(request_headers, name.as_str()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// All of a header's values, comma-joined, with whitespace around them dropped
+fn header_values(headers: &HeaderMap, name: &str) -> String {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Lower-cased directive names with their unquoted values
+fn cache_control(headers: &HeaderMap) -> Vec<(String, Option<String>)> {
+    headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|directive| {
+            let directive = directive.trim();
+            if directive.is_empty() {
+                return None;
+            }
+            Some(match directive.split_once('=') {
+                Some((name, value)) => (
+                    name.trim().to_ascii_lowercase(),
+                    Some(value.trim().trim_matches('"').to_string()),
+                ),
+                None => (directive.to_ascii_lowercase(), None),
+            })
+        })
+        .collect()
+}
+
+fn http_date(value: &HeaderValue) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc2822(value.to_str().ok()?)
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+/// `If-None-Match` uses weak comparison, so `W/"v1"` matches `"v1"`
+fn weak_etag(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(max_entries: usize) -> RouteConfig {
+        serde_json::from_value(serde_json::json!({
+            "path": "/api/v1/products",
+            "backend": "products",
+            "load_balancing": "round_robin",
+            "rate_limit": null,
+            "auth_required": false,
+            "timeout_ms": null,
+            "max_concurrent_requests": null,
+            "streaming": null,
+            "rate_limit_key": null,
+            "spike_arrest": null,
+            "cache": { "ttl_seconds": 60, "max_entries": max_entries, "max_object_bytes": 16 }
+        }))
+        .unwrap()
+    }
+
+    /// The cache key for a GET with the default API key header.
+    fn key(route: &RouteConfig, uri: &str, headers: &HeaderMap, identity: Option<&Identity>) -> Option<String> {
+        ResponseCache::key(route, &Method::GET, &uri.parse().unwrap(), headers, identity, "X-API-Key")
+    }
+
+    fn headers(pairs: &[(HeaderName, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.clone(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    #[test]
+    fn test_cache_keys_and_eviction() {
+        let route = route(2);
+        let cache = ResponseCache::new();
+
+        let page = key(&route, "/api/v1/products?page=2", &HeaderMap::new(), None);
+        assert_eq!(page.unwrap(), "GET /api/v1/products?page=2");
+        let uri = "/api/v1/products".parse::<Uri>().unwrap();
+        assert!(ResponseCache::key(&route, &Method::POST, &uri, &HeaderMap::new(), None, "X-API-Key").is_none());
+
+        let mut authorized = HeaderMap::new();
+        authorized.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer token"));
+        assert!(key(&route, "/api/v1/products", &authorized, None).is_none());
+
+        let body = Bytes::from("[]");
+        assert!(cache.store(&route, "a".to_string(), &HeaderMap::new(), StatusCode::OK, &HeaderMap::new(), &body));
+        assert!(!cache.store(&route, "b".to_string(), &HeaderMap::new(), StatusCode::INTERNAL_SERVER_ERROR, &HeaderMap::new(), &body));
+        assert!(!cache.store(&route, "b".to_string(), &HeaderMap::new(), StatusCode::OK, &HeaderMap::new(), &Bytes::from(vec![0; 17])));
+
+        cache.store(&route, "b".to_string(), &HeaderMap::new(), StatusCode::OK, &HeaderMap::new(), &body);
+        cache.store(&route, "c".to_string(), &HeaderMap::new(), StatusCode::OK, &HeaderMap::new(), &body);
+        assert!(cache.lookup(&route, "a", &HeaderMap::new()).is_none());
+        assert!(matches!(cache.lookup(&route, "c", &HeaderMap::new()), Some(CacheLookup::Fresh(cached)) if cached.body == body));
+    }
+
+    #[test]
+    fn test_vary() {
+        let mut route = route(10);
+        let cache = ResponseCache::new();
+        let uri = "/api/v1/products";
+        let english = headers(&[(header::ACCEPT_LANGUAGE, "en"), (header::ACCEPT_ENCODING, "gzip, br")]);
+        let french = headers(&[(header::ACCEPT_LANGUAGE, "fr"), (header::ACCEPT_ENCODING, "gzip,br")]);
+
+        let varies = headers(&[(header::VARY, "Accept-Language")]);
+        assert!(cache.store(&route, "a".to_string(), &english, StatusCode::OK, &varies, &Bytes::from("hello")));
+        assert!(cache.lookup(&route, "a", &french).is_none());
+        assert!(cache.store(&route, "a".to_string(), &french, StatusCode::OK, &varies, &Bytes::from("bonjour")));
+        assert!(matches!(cache.lookup(&route, "a", &english), Some(CacheLookup::Fresh(cached)) if cached.body == "hello"));
+        assert!(matches!(cache.lookup(&route, "a", &french), Some(CacheLookup::Fresh(cached)) if cached.body == "bonjour"));
+        assert!(!cache.store(&route, "b".to_string(), &english, StatusCode::OK, &headers(&[(header::VARY, "*")]), &Bytes::new()));
+
+        // Authenticated requests are cached per tenant once the key includes the tenant
+        let mut authorized = english.clone();
+        authorized.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer token"));
+        let identity = Identity {
+            user_id: Some("user-1".to_string()),
+            key_id: None,
+            scopes: Vec::new(),
+            claims: serde_json::json!({ "tenant": "acme" }).as_object().unwrap().clone(),
+        };
+        assert!(key(&route, uri, &authorized, Some(&identity)).is_none());
+
+        route.cache.as_mut().unwrap().vary = vec!["claim:tenant".to_string(), "Accept-Encoding".to_string()];
+        assert!(key(&route, uri, &authorized, None).is_none());
+        assert_eq!(
+            key(&route, uri, &authorized, Some(&identity)).unwrap(),
+            "GET /api/v1/products\nclaim:tenant=acme\naccept-encoding=gzip,br"
+        );
+        assert_eq!(
+            key(&route, uri, &french, None).unwrap(),
+            "GET /api/v1/products\nclaim:tenant=\naccept-encoding=gzip,br"
+        );
+    }
+
+    #[test]
+    fn test_requests_with_credentials_bypass_the_cache() {
+        let mut route = route(10);
+        let uri = "/api/v1/products";
+        let api_key = headers(&[(HeaderName::from_static("x-api-key"), "ak_live_1")]);
+        let session = headers(&[(header::COOKIE, "gateway_session=abc")]);
+        let identity = Identity {
+            user_id: None,
+            key_id: Some("1".to_string()),
+            scopes: Vec::new(),
+            claims: serde_json::Map::new(),
+        };
+
+        assert!(key(&route, uri, &api_key, None).is_none());
+        assert!(key(&route, uri, &session, None).is_none());
+        // e.g. a client certificate, with no credential header at all
+        assert!(key(&route, uri, &HeaderMap::new(), Some(&identity)).is_none());
+
+        // A key that includes the API key separates callers, so their responses can be cached
+        route.cache.as_mut().unwrap().vary = vec!["X-API-Key".to_string()];
+        assert_eq!(key(&route, uri, &api_key, None).unwrap(), "GET /api/v1/products\nx-api-key=ak_live_1");
+        assert!(key(&route, uri, &session, None).is_none());
+    }
+
+    #[test]
+    fn test_freshness_from_upstream_headers() {
+        let config = route(1).cache.unwrap();
+
+        let lifetime = |pairs: &[(HeaderName, &'static str)]| freshness_lifetime(&config, &headers(pairs));
+        assert_eq!(lifetime(&[]), Some(Duration::from_secs(60)));
+        assert_eq!(lifetime(&[(header::CACHE_CONTROL, "public, max-age=30, s-maxage=120")]), Some(Duration::from_secs(120)));
+        assert_eq!(lifetime(&[(header::CACHE_CONTROL, "no-cache")]), Some(Duration::ZERO));
+        assert_eq!(lifetime(&[(header::CACHE_CONTROL, "private, max-age=30")]), None);
+        assert_eq!(
+            lifetime(&[
+                (header::DATE, "Sun, 06 Nov 1994 08:49:37 GMT"),
+                (header::EXPIRES, "Sun, 06 Nov 1994 08:59:37 GMT"),
+            ]),
+            Some(Duration::from_secs(600))
+        );
+        assert_eq!(lifetime(&[(header::EXPIRES, "0")]), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_stale_windows() {
+        let mut config = route(1).cache.unwrap();
+        config.stale_if_error_seconds = Some(300);
+
+        let windows = |pairs: &[(HeaderName, &'static str)]| stale_windows(&config, &headers(pairs));
+        assert_eq!(windows(&[]), (Duration::ZERO, Duration::from_secs(300)));
+        assert_eq!(
+            windows(&[(header::CACHE_CONTROL, "max-age=60, stale-while-revalidate=30, stale-if-error=600")]),
+            (Duration::from_secs(30), Duration::from_secs(600))
+        );
+        assert_eq!(
+            windows(&[(header::CACHE_CONTROL, "max-age=60, must-revalidate")]),
+            (Duration::ZERO, Duration::ZERO)
+        );
+
+        // Expired on arrival, but can still be served on errors
+        let route = route(1);
+        let cache = ResponseCache::new();
+        let stale = headers(&[(header::CACHE_CONTROL, "max-age=0, stale-if-error=600")]);
+        assert!(cache.store(&route, "a".to_string(), &HeaderMap::new(), StatusCode::OK, &stale, &Bytes::from("[]")));
+        match cache.lookup(&route, "a", &HeaderMap::new()) {
+            Some(CacheLookup::Stale(cached)) => {
+                assert!(cached.usable_on_error());
+                assert!(!cached.serve_while_revalidating());
+            }
+            _ => panic!("expected a stale entry"),
+        }
+
+        assert!(cache.begin_refresh("a"));
+        assert!(!cache.begin_refresh("a"));
+        cache.end_refresh("a");
+        assert!(cache.begin_refresh("a"));
+    }
+
+    #[test]
+    fn test_conditional_requests() {
+        let route = route(10);
+        let cache = ResponseCache::new();
+        let stored = headers(&[
+            (header::CACHE_CONTROL, "no-cache"),
+            (header::ETAG, "\"v1\""),
+            (header::LAST_MODIFIED, "Sun, 06 Nov 1994 08:49:37 GMT"),
+        ]);
+        assert!(cache.store(&route, "a".to_string(), &HeaderMap::new(), StatusCode::OK, &stored, &Bytes::from("[]")));
+
+        let cached = match cache.lookup(&route, "a", &HeaderMap::new()) {
+            Some(CacheLookup::Stale(cached)) => cached,
+            _ => panic!("no-cache responses must be revalidated"),
+        };
+        let mut upstream = HeaderMap::new();
+        cached.add_validators(&mut upstream);
+        assert_eq!(upstream.get(header::IF_NONE_MATCH).unwrap(), "\"v1\"");
+
+        assert!(cached.not_modified_for(&headers(&[(header::IF_NONE_MATCH, "\"v0\", W/\"v1\"")])));
+        assert!(!cached.not_modified_for(&headers(&[(header::IF_NONE_MATCH, "\"v2\"")])));
+        assert!(cached.not_modified_for(&headers(&[(header::IF_MODIFIED_SINCE, "Mon, 07 Nov 1994 08:49:37 GMT")])));
+        assert!(!cached.not_modified_for(&headers(&[(header::IF_MODIFIED_SINCE, "Sat, 05 Nov 1994 08:49:37 GMT")])));
+
+        let refreshed = cache
+            .refresh(&route, "a", &HeaderMap::new(), &headers(&[(header::CACHE_CONTROL, "max-age=300"), (header::ETAG, "\"v1\"")]))
+            .unwrap();
+        assert_eq!(refreshed.headers.get(header::CACHE_CONTROL).unwrap(), "max-age=300");
+        assert!(matches!(cache.lookup(&route, "a", &HeaderMap::new()), Some(CacheLookup::Fresh(_))));
+    }
+}
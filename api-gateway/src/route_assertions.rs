@@ -0,0 +1,210 @@
+use crate::api_keys::ApiKeyStore;
+use crate::auth::{AuthError, AuthService};
+use crate::config::{AuthEnforcementMode, Config, RouteAssertion, RouteConfig};
+use crate::jwks::JwtVerifier;
+
+#[derive(Debug, Clone)]
+pub struct AssertionFailure {
+    pub assertion: String,
+    pub reasons: Vec<String>,
+}
+
+/// Runs the config's route assertions against its routing table, returning the ones
+/// that failed.
+pub async fn run(config: &Config, verifier: &JwtVerifier, api_keys: &ApiKeyStore) -> Vec<AssertionFailure> {
+    let mut failures = Vec::new();
+
+    for assertion in &config.assertions {
+        let reasons = check(config, verifier, api_keys, assertion).await;
+        if !reasons.is_empty() {
+            failures.push(AssertionFailure {
+                assertion: label(assertion),
+                reasons,
+            });
+        }
+    }
+
+    failures
+}
+
+fn label(assertion: &RouteAssertion) -> String {
+    assertion
+        .name
+        .clone()
+        .unwrap_or_else(|| format!("{} {}", assertion.request.method, assertion.request.path))
+}
+
+async fn check(
+    config: &Config,
+    verifier: &JwtVerifier,
+    api_keys: &ApiKeyStore,
+    assertion: &RouteAssertion,
+) -> Vec<String> {
+    let request = &assertion.request;
+    let expect = &assertion.expect;
+    let mut reasons = Vec::new();
+
+    let route = config.find_route(&request.path);
+
+    if expect.unmatched {
+        if let Some(route) = route {
+            reasons.push(format!("expected no route to match, got {}", route_id(route)));
+        }
+        return reasons;
+    }
+
+    let route = match route {
+        Some(route) => route,
+        None => {
+            reasons.push("no route matches".to_string());
+            return reasons;
+        }
+    };
+
+    if let Some(expected) = &expect.route {
+        let matches = route.name.as_ref() == Some(expected) || &route.path == expected;
+        if !matches {
+            reasons.push(format!("expected route {}, got {}", expected, route_id(route)));
+        }
+    }
+
+    if let Some(expected) = &expect.backend {
+        if &route.backend != expected {
+            reasons.push(format!("expected backend {}, got {}", expected, route.backend));
+        }
+    }
+
+    let auth_required = config.requires_auth(&request.path);
+
+    if let Some(expected) = expect.auth_required {
+        if auth_required != expected {
+            reasons.push(format!("expected auth_required {}, got {}", expected, auth_required));
+        }
+    }
+
+    let missing: Vec<&String> = expect
+        .required_scopes
+        .iter()
+        .filter(|scope| !route.required_scopes.contains(scope))
+        .collect();
+    if !missing.is_empty() {
+        reasons.push(format!("route does not require scopes {:?}", missing));
+    }
+
+    if let Some(expected) = expect.authorized {
+        let authorized = !auth_required || {
+            let bearer_token = header(assertion, "Authorization").and_then(AuthService::extract_bearer_token);
+            let api_key = header(assertion, &config.auth.api_key_header);
+            match AuthService::authorize(verifier, api_keys, Some(route), bearer_token, api_key).await {
+                Ok(_) => true,
+                Err(AuthError::MissingCredentials) => route.auth_mode == AuthEnforcementMode::Optional,
+                Err(_) => false,
+            }
+        };
+
+        if authorized != expected {
+            reasons.push(format!("expected authorized {}, got {}", expected, authorized));
+        }
+    }
+
+    reasons
+}
+
+fn route_id(route: &RouteConfig) -> String {
+    route.name.clone().unwrap_or_else(|| route.path.clone())
+}
+
+fn header<'a>(assertion: &'a RouteAssertion, name: &str) -> Option<&'a str> {
+    assertion
+        .request
+        .headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api_keys::test_store;
+    use crate::auth::ApiKeyInfo;
+
+    fn user_keys() -> ApiKeyStore {
+        test_store(vec![(
+            "ak_user_09876543210987654321",
+            ApiKeyInfo {
+                key_id: "2".to_string(),
+                user_id: Some("2".to_string()),
+                permissions: vec!["read".to_string()],
+                rate_limit: 1000,
+                expires_at: None,
+                is_active: true,
+                plan: Some("free".to_string()),
+                tenant: None,
+            },
+        )])
+    }
+
+    fn config_with(assertions: serde_json::Value) -> Config {
+        let mut config = Config::default_config();
+        config.assertions = serde_json::from_value(assertions).unwrap();
+        config
+    }
+
+    #[tokio::test]
+    async fn test_passing_assertions() {
+        let config = config_with(serde_json::json!([
+            {
+                "request": { "path": "/api/v1/users", "headers": { "X-API-Key": "ak_user_09876543210987654321" } },
+                "expect": { "route": "api-v1", "backend": "backend_api", "auth_required": true, "authorized": true }
+            },
+            {
+                "request": { "path": "/public/docs" },
+                "expect": { "route": "/public/*", "auth_required": false }
+            },
+            {
+                "request": { "path": "/unknown" },
+                "expect": { "unmatched": true }
+            }
+        ]));
+
+        let verifier = JwtVerifier::new(&config.auth).unwrap();
+        assert!(run(&config, &verifier, &user_keys()).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_optional_auth_admits_anonymous_requests() {
+        let mut config = config_with(serde_json::json!([
+            {
+                "request": { "path": "/api/v1/users" },
+                "expect": { "auth_required": true, "authorized": true }
+            },
+            {
+                "request": { "path": "/api/v1/users", "headers": { "X-API-Key": "ak_unknown" } },
+                "expect": { "authorized": false }
+            }
+        ]));
+        config.routes[0].auth_mode = AuthEnforcementMode::Optional;
+
+        let verifier = JwtVerifier::new(&config.auth).unwrap();
+        assert!(run(&config, &verifier, &user_keys()).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failing_assertions_report_reasons() {
+        let mut config = config_with(serde_json::json!([
+            {
+                "name": "users need read:users",
+                "request": { "method": "GET", "path": "/api/v1/users", "headers": { "X-API-Key": "ak_user_09876543210987654321" } },
+                "expect": { "route": "auth", "required_scopes": ["read:users"], "authorized": true }
+            }
+        ]));
+        config.routes[0].required_scopes = vec!["admin".to_string()];
+
+        let verifier = JwtVerifier::new(&config.auth).unwrap();
+        let failures = run(&config, &verifier, &user_keys()).await;
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].assertion, "users need read:users");
+        assert_eq!(failures[0].reasons.len(), 3);
+    }
+}
@@ -0,0 +1,269 @@
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{HeaderMap, HeaderName, HeaderValue, Method},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::client_key::ClientKeyExtractor;
+use crate::config::{path_matches, AuthEnforcementMode, Config, LoadBalancingStrategy, RouteConfig, SpikeArrestConfig};
+use crate::header_rules;
+use crate::rate_limiter::EffectiveLimits;
+
+/// A request to run through route matching without sending it anywhere.
+#[derive(Debug, Deserialize)]
+pub struct RouteMatchRequest {
+    #[serde(default = "default_method")]
+    pub method: String,
+    /// May include a query string
+    pub path: String,
+    pub host: Option<String>,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+/// What the gateway would do with a request.
+#[derive(Debug, Serialize)]
+pub struct RouteMatch {
+    pub route: MatchedRoute,
+    /// Later routes whose path also matches, which this one takes precedence over
+    pub shadowed_routes: Vec<String>,
+    pub rewrite: RewriteResult,
+    pub backend: SelectedBackend,
+    pub auth: AuthPolicy,
+    pub rate_limit: RateLimitPolicy,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MatchedRoute {
+    pub name: Option<String>,
+    pub path: String,
+    pub method: Option<String>,
+    /// False when the route declares a different method; it still matches, as the
+    /// gateway routes by path
+    pub method_matches: bool,
+}
+
+/// The request as it would be forwarded.
+#[derive(Debug, Serialize)]
+pub struct RewriteResult {
+    pub path: String,
+    /// After the route's `request_headers` rules
+    pub headers: BTreeMap<String, Vec<String>>,
+    /// Whether `transform`, `rewrite` or `xml` would change the body
+    pub body_rewritten: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SelectedBackend {
+    /// `mock` or `composite` when the route doesn't forward to a single backend
+    pub kind: &'static str,
+    pub name: Option<String>,
+    pub servers: Vec<String>,
+    pub load_balancing: LoadBalancingStrategy,
+    /// Country code to the backend serving it instead
+    pub regional: HashMap<String, String>,
+    /// For composite routes
+    pub parts: Vec<String>,
+    /// The route names a backend that isn't configured
+    pub missing: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuthPolicy {
+    pub required: bool,
+    pub mode: AuthEnforcementMode,
+    pub tenant: Option<String>,
+    pub required_scopes: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RateLimitPolicy {
+    pub enabled: bool,
+    pub shadow_mode: bool,
+    /// The kind of client key the request would be counted under
+    pub key_type: &'static str,
+    pub key: String,
+    /// The defaults; an API key's plan may replace them
+    pub requests_per_minute: u32,
+    pub burst_size: u32,
+    pub max_concurrent_requests: Option<u32>,
+    pub spike_arrest: Option<SpikeArrestConfig>,
+}
+
+/// Runs `request` through the same lookups the middleware and proxy use. Returns
+/// `None` when no route matches.
+pub fn evaluate(
+    config: &Config,
+    client_keys: &ClientKeyExtractor,
+    request: &RouteMatchRequest,
+) -> anyhow::Result<Option<RouteMatch>> {
+    let method = Method::from_bytes(request.method.to_uppercase().as_bytes())
+        .map_err(|_| anyhow::anyhow!("Invalid method: {}", request.method))?;
+    let mut headers = HeaderMap::new();
+    for (name, value) in &request.headers {
+        let name =
+            HeaderName::from_bytes(name.as_bytes()).map_err(|_| anyhow::anyhow!("Invalid header name: {}", name))?;
+        let value = HeaderValue::from_str(value).map_err(|_| anyhow::anyhow!("Invalid value for header {}", name))?;
+        headers.append(name, value);
+    }
+    if let Some(host) = &request.host {
+        headers.insert(
+            axum::http::header::HOST,
+            HeaderValue::from_str(host).map_err(|_| anyhow::anyhow!("Invalid host: {}", host))?,
+        );
+    }
+
+    let path = request.path.split('?').next().unwrap_or_default();
+    let route = match config.find_route(path) {
+        Some(route) => route,
+        None => return Ok(None),
+    };
+    let shadowed_routes = config
+        .routes
+        .iter()
+        .filter(|other| !std::ptr::eq(*other, route) && path_matches(&other.path, path))
+        .map(|other| other.name.clone().unwrap_or_else(|| other.path.clone()))
+        .collect();
+
+    let mut http_request = Request::builder()
+        .method(method.clone())
+        .uri(&request.path)
+        .body(Body::empty())
+        .map_err(|_| anyhow::anyhow!("Invalid path: {}", request.path))?;
+    *http_request.headers_mut() = headers.clone();
+
+    let mut forwarded_headers = headers;
+    header_rules::apply(&route.request_headers, &mut forwarded_headers);
+
+    let tenant = config.find_tenant(request.host.as_deref(), path);
+    let limits = EffectiveLimits::defaults(&config.rate_limiting);
+
+    Ok(Some(RouteMatch {
+        route: MatchedRoute {
+            name: route.name.clone(),
+            path: route.path.clone(),
+            method: route.method.clone(),
+            method_matches: route
+                .method
+                .as_deref()
+                .map_or(true, |declared| declared.eq_ignore_ascii_case(method.as_str())),
+        },
+        shadowed_routes,
+        rewrite: RewriteResult {
+            path: request.path.clone(),
+            headers: header_map(&forwarded_headers),
+            body_rewritten: body_rewritten(route),
+        },
+        backend: selected_backend(config, route),
+        auth: AuthPolicy {
+            required: config.requires_auth_for(tenant, path),
+            mode: route.auth_mode,
+            tenant: tenant.map(|tenant| tenant.name.clone()),
+            required_scopes: route.required_scopes.clone(),
+        },
+        rate_limit: RateLimitPolicy {
+            enabled: config.rate_limiting.enabled,
+            shadow_mode: config.rate_limiting.shadow_mode,
+            key_type: client_keys.rate_limit_key_type(&http_request, route.rate_limit_key.as_ref()),
+            key: client_keys.rate_limit_key(&http_request, route.rate_limit_key.as_ref()),
+            requests_per_minute: limits.requests_per_minute,
+            burst_size: limits.burst_size,
+            max_concurrent_requests: route.max_concurrent_requests,
+            spike_arrest: route.spike_arrest.clone(),
+        },
+    }))
+}
+
+fn selected_backend(config: &Config, route: &RouteConfig) -> SelectedBackend {
+    let mut selected = SelectedBackend {
+        kind: "backend",
+        name: None,
+        servers: Vec::new(),
+        load_balancing: route.load_balancing.clone(),
+        regional: route.geo.as_ref().map(|geo| geo.backends.clone()).unwrap_or_default(),
+        parts: Vec::new(),
+        missing: false,
+    };
+    if route.mock.is_some() {
+        selected.kind = "mock";
+    } else if let Some(composite) = &route.composite {
+        selected.kind = "composite";
+        selected.parts = composite.parts.iter().map(|part| part.backend.clone()).collect();
+    } else {
+        selected.name = Some(route.backend.clone());
+        match config.backends.get(&route.backend) {
+            Some(backend) => selected.servers = backend.servers.clone(),
+            None => selected.missing = true,
+        }
+    }
+    selected
+}
+
+fn body_rewritten(route: &RouteConfig) -> bool {
+    route.transform.as_ref().map_or(false, |transform| transform.request.is_some())
+        || route.rewrite.as_ref().map_or(false, |rewrite| !rewrite.request.is_empty())
+        || route.xml.is_some()
+}
+
+fn header_map(headers: &HeaderMap) -> BTreeMap<String, Vec<String>> {
+    let mut map: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (name, value) in headers {
+        map.entry(name.to_string())
+            .or_default()
+            .push(String::from_utf8_lossy(value.as_bytes()).into_owned());
+    }
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jwks::JwtVerifier;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_evaluate() {
+        let mut config = Config::default_config();
+        let mut broad = config.routes[0].clone();
+        broad.name = Some("catch-all".to_string());
+        broad.path = "/*".to_string();
+        config.routes.push(broad);
+        config.routes[0].request_headers =
+            serde_json::from_value(json!([{ "type": "set", "name": "x-gateway", "value": "1" }])).unwrap();
+
+        let verifier = Arc::new(JwtVerifier::new(&config.auth).unwrap());
+        let client_keys = ClientKeyExtractor::new(&config, verifier).unwrap();
+        let route_path = config.routes[0].path.replace('*', "orders");
+        let request: RouteMatchRequest = serde_json::from_value(json!({
+            "method": "post",
+            "path": format!("{}?page=2", route_path),
+            "headers": { "X-API-Key": "secret" }
+        }))
+        .unwrap();
+
+        let matched = evaluate(&config, &client_keys, &request).unwrap().unwrap();
+        assert_eq!(matched.route.path, config.routes[0].path);
+        assert_eq!(matched.shadowed_routes, vec!["catch-all"]);
+        assert_eq!(matched.rewrite.path, format!("{}?page=2", route_path));
+        assert_eq!(matched.rewrite.headers["x-gateway"], vec!["1"]);
+        assert_eq!(matched.backend.name.as_deref(), Some(config.routes[0].backend.as_str()));
+        assert_eq!(matched.rate_limit.key_type, "api_key");
+        assert!(!matched.rate_limit.key.contains("secret"));
+
+        let unmatched = RouteMatchRequest {
+            method: "GET".to_string(),
+            path: "/nowhere".to_string(),
+            host: None,
+            headers: HashMap::new(),
+        };
+        config.routes.pop();
+        assert!(evaluate(&config, &client_keys, &unmatched).unwrap().is_none());
+    }
+}
@@ -0,0 +1,418 @@
+//! Rhai scripts as the built-in `script` route filter. A script defines `on_request`
+//! and/or `on_response`, which see the request or response as `this`:
+//!
+//! ```text
+//! fn on_request() {
+//!     if !("x-tenant" in this.headers) {
+//!         return #{ status: 400, body: "missing x-tenant" };
+//!     }
+//!     this.headers["x-tenant"] = this.headers["x-tenant"].to_lower();
+//!     if this.path.starts_with("/api/v2") { this.backend = "orders-v2"; }
+//! }
+//! ```
+//!
+//! Changes to `this` are applied to the request or response. Returning a status code or
+//! a `#{ status, headers, body }` map answers with that instead.
+
+use axum::{
+    body::{Body, Bytes},
+    extract::Request,
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Map, Scope, AST};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tracing::{debug, error, info};
+
+use crate::config::Config;
+use crate::filters::{BackendOverride, FilterAction, FilterContext, FilterFuture, RequestFilter, ResponseFilter};
+
+/// What routes call the filter in their `filters`.
+pub const FILTER_NAME: &str = "script";
+
+/// The `settings` of a `script` filter entry.
+#[derive(Debug, Deserialize)]
+struct ScriptSettings {
+    source: Option<String>,
+    /// Read instead of `source`
+    file: Option<String>,
+    /// Steps one hook call may take before it's stopped
+    #[serde(default = "default_max_operations")]
+    max_operations: u64,
+    /// Give the script the body as `this.body`
+    #[serde(default)]
+    read_body: bool,
+    /// Larger bodies fail the request when `read_body` is set
+    #[serde(default = "default_max_body_bytes")]
+    max_body_bytes: usize,
+}
+
+fn default_max_operations() -> u64 {
+    100_000
+}
+
+fn default_max_body_bytes() -> usize {
+    1024 * 1024
+}
+
+struct Script {
+    engine: Engine,
+    ast: AST,
+    settings: ScriptSettings,
+    on_request: bool,
+    on_response: bool,
+}
+
+impl Script {
+    fn compile(settings: &serde_json::Value) -> anyhow::Result<Self> {
+        let settings: ScriptSettings = serde_json::from_value(settings.clone())
+            .map_err(|e| anyhow::anyhow!("Invalid script settings: {}", e))?;
+        let source = match (&settings.source, &settings.file) {
+            (Some(source), _) => source.clone(),
+            (None, Some(file)) => std::fs::read_to_string(file)
+                .map_err(|e| anyhow::anyhow!("Failed to read script {}: {}", file, e))?,
+            (None, None) => return Err(anyhow::anyhow!("Script filter needs a source or a file")),
+        };
+
+        let mut engine = Engine::new();
+        engine.set_max_operations(settings.max_operations);
+        engine.set_max_call_levels(32);
+        engine.set_max_expr_depths(64, 32);
+        engine.set_max_string_size(settings.max_body_bytes.max(64 * 1024));
+        engine.on_print(|text| info!("[script] {}", text));
+        engine.on_debug(|text, _, _| debug!("[script] {}", text));
+
+        let ast = engine.compile(&source).map_err(|e| anyhow::anyhow!("Failed to compile script: {}", e))?;
+        let defines = |hook: &str| ast.iter_functions().any(|function| function.name == hook);
+        let (on_request, on_response) = (defines("on_request"), defines("on_response"));
+        if !on_request && !on_response {
+            return Err(anyhow::anyhow!("Script defines neither on_request nor on_response"));
+        }
+        Ok(Self { engine, ast, settings, on_request, on_response })
+    }
+
+    fn call(&self, hook: &str, this: &mut Dynamic) -> anyhow::Result<Dynamic> {
+        let options = CallFnOptions::new().bind_this_ptr(this);
+        self.engine
+            .call_fn_with_options(options, &mut Scope::new(), &self.ast, hook, ())
+            .map_err(|e| anyhow::anyhow!("Script {} failed: {}", hook, e))
+    }
+
+    async fn read_body(&self, body: &mut Body) -> anyhow::Result<Option<Bytes>> {
+        if !self.settings.read_body {
+            return Ok(None);
+        }
+        let bytes = axum::body::to_bytes(std::mem::take(body), self.settings.max_body_bytes)
+            .await
+            .map_err(|_| anyhow::anyhow!("Body is larger than {} bytes", self.settings.max_body_bytes))?;
+        Ok(Some(bytes))
+    }
+}
+
+/// Compiles each route's scripts once, keyed by their settings.
+#[derive(Default)]
+pub struct ScriptFilter {
+    scripts: Mutex<HashMap<String, Arc<Script>>>,
+}
+
+impl ScriptFilter {
+    /// Compiles the scripts `config`'s routes use, so mistakes surface at startup.
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        let filter = Self::default();
+        for route in &config.routes {
+            for entry in route.filters.iter().filter(|entry| entry.name == FILTER_NAME) {
+                filter
+                    .script(&entry.settings)
+                    .map_err(|e| anyhow::anyhow!("Route {}: {}", route.path, e))?;
+            }
+        }
+        Ok(filter)
+    }
+
+    fn script(&self, settings: &serde_json::Value) -> anyhow::Result<Arc<Script>> {
+        let key = settings.to_string();
+        if let Some(script) = self.scripts.lock().unwrap().get(&key) {
+            return Ok(script.clone());
+        }
+        let script = Arc::new(Script::compile(settings)?);
+        self.scripts.lock().unwrap().insert(key, script.clone());
+        Ok(script)
+    }
+
+    async fn run_request(&self, context: &FilterContext<'_>, request: &mut Request) -> anyhow::Result<FilterAction> {
+        let script = self.script(context.settings)?;
+        if !script.on_request {
+            return Ok(FilterAction::Continue);
+        }
+        let body = script.read_body(request.body_mut()).await?;
+
+        let mut this = Map::new();
+        this.insert("method".into(), request.method().to_string().into());
+        this.insert("path".into(), request.uri().path().into());
+        this.insert("query".into(), request.uri().query().unwrap_or_default().into());
+        this.insert("headers".into(), Dynamic::from_map(headers_to_map(request.headers())));
+        this.insert("backend".into(), context.route.backend.clone().into());
+        this.insert("request_id".into(), context.request_id.into());
+        if let Some(body) = &body {
+            this.insert("body".into(), String::from_utf8_lossy(body).into_owned().into());
+        }
+        let mut this = Dynamic::from_map(this);
+        let result = script.call("on_request", &mut this)?;
+        let this = this
+            .try_cast::<Map>()
+            .ok_or_else(|| anyhow::anyhow!("Script on_request replaced `this`"))?;
+
+        if let Some(response) = local_response(result)? {
+            return Ok(FilterAction::Respond(response));
+        }
+
+        if let Some(method) = this.get("method").map(|method| method.to_string()) {
+            *request.method_mut() = Method::from_bytes(method.as_bytes())
+                .map_err(|_| anyhow::anyhow!("Script set an invalid method: {}", method))?;
+        }
+        let path = this.get("path").map_or_else(|| request.uri().path().to_string(), |path| path.to_string());
+        let query = this.get("query").map(|query| query.to_string()).unwrap_or_default();
+        let uri = if query.is_empty() { path } else { format!("{}?{}", path, query) };
+        if request.uri().path_and_query().map(|pq| pq.as_str()) != Some(uri.as_str()) {
+            *request.uri_mut() = uri.parse().map_err(|_| anyhow::anyhow!("Script set an invalid path: {}", uri))?;
+        }
+        if let Some(headers) = this.get("headers") {
+            *request.headers_mut() = map_to_headers(headers)?;
+        }
+        if let Some(body) = body {
+            *request.body_mut() = changed_body(this.get("body"), body, request.headers_mut());
+        }
+        if let Some(backend) = this.get("backend").map(|backend| backend.to_string()) {
+            if backend != context.route.backend {
+                request.extensions_mut().insert(BackendOverride(backend));
+            }
+        }
+        Ok(FilterAction::Continue)
+    }
+
+    async fn run_response(&self, context: &FilterContext<'_>, response: &mut Response) -> anyhow::Result<()> {
+        let script = self.script(context.settings)?;
+        if !script.on_response {
+            return Ok(());
+        }
+        let body = script.read_body(response.body_mut()).await?;
+
+        let mut this = Map::new();
+        this.insert("status".into(), (response.status().as_u16() as i64).into());
+        this.insert("headers".into(), Dynamic::from_map(headers_to_map(response.headers())));
+        this.insert("request_id".into(), context.request_id.into());
+        if let Some(body) = &body {
+            this.insert("body".into(), String::from_utf8_lossy(body).into_owned().into());
+        }
+        let mut this = Dynamic::from_map(this);
+        let result = script.call("on_response", &mut this)?;
+        let this = this
+            .try_cast::<Map>()
+            .ok_or_else(|| anyhow::anyhow!("Script on_response replaced `this`"))?;
+
+        if let Some(replacement) = local_response(result)? {
+            *response = replacement;
+            return Ok(());
+        }
+
+        if let Some(status) = this.get("status") {
+            *response.status_mut() = status_code(status)?;
+        }
+        if let Some(headers) = this.get("headers") {
+            *response.headers_mut() = map_to_headers(headers)?;
+        }
+        if let Some(body) = body {
+            *response.body_mut() = changed_body(this.get("body"), body, response.headers_mut());
+        }
+        Ok(())
+    }
+}
+
+impl RequestFilter for ScriptFilter {
+    fn on_request<'a>(
+        &'a self,
+        context: &'a FilterContext<'a>,
+        request: &'a mut Request,
+    ) -> FilterFuture<'a, FilterAction> {
+        Box::pin(async move {
+            self.run_request(context, request).await.unwrap_or_else(|e| {
+                error!("{} (request_id: {})", e, context.request_id);
+                FilterAction::Respond(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+            })
+        })
+    }
+}
+
+impl ResponseFilter for ScriptFilter {
+    fn on_response<'a>(
+        &'a self,
+        context: &'a FilterContext<'a>,
+        response: &'a mut Response,
+    ) -> FilterFuture<'a, ()> {
+        Box::pin(async move {
+            if let Err(e) = self.run_response(context, response).await {
+                error!("{} (request_id: {})", e, context.request_id);
+                *response = StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        })
+    }
+}
+
+/// Headers with one value become strings, repeated ones arrays of strings.
+fn headers_to_map(headers: &HeaderMap) -> Map {
+    let mut map = Map::new();
+    for name in headers.keys() {
+        let values: Array = headers
+            .get_all(name)
+            .iter()
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned().into())
+            .collect();
+        let value = if values.len() == 1 { values.into_iter().next().unwrap() } else { Dynamic::from_array(values) };
+        map.insert(name.as_str().into(), value);
+    }
+    map
+}
+
+fn map_to_headers(map: &Dynamic) -> anyhow::Result<HeaderMap> {
+    let map = map
+        .read_lock::<Map>()
+        .ok_or_else(|| anyhow::anyhow!("Script set headers to something other than a map"))?;
+    let mut headers = HeaderMap::new();
+    for (name, value) in map.iter() {
+        let header_name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| anyhow::anyhow!("Script set an invalid header name: {}", name))?;
+        let values = match value.read_lock::<Array>() {
+            Some(values) => values.clone(),
+            None => vec![value.clone()],
+        };
+        for value in values {
+            let value = HeaderValue::from_str(&value.to_string())
+                .map_err(|_| anyhow::anyhow!("Script set an invalid value for header {}", name))?;
+            headers.append(header_name.clone(), value);
+        }
+    }
+    Ok(headers)
+}
+
+/// The script's body if it changed `this.body`, and otherwise the original bytes, so
+/// bodies that aren't UTF-8 survive scripts that don't touch them.
+fn changed_body(script_body: Option<&Dynamic>, original: Bytes, headers: &mut HeaderMap) -> Body {
+    let script_body = script_body.map(|body| body.to_string()).unwrap_or_default();
+    if script_body == String::from_utf8_lossy(&original) {
+        return Body::from(original);
+    }
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(script_body.len()));
+    Body::from(script_body)
+}
+
+fn status_code(status: &Dynamic) -> anyhow::Result<StatusCode> {
+    status
+        .as_int()
+        .ok()
+        .and_then(|status| u16::try_from(status).ok())
+        .and_then(|status| StatusCode::from_u16(status).ok())
+        .ok_or_else(|| anyhow::anyhow!("Script set an invalid status: {}", status))
+}
+
+/// The response a hook answered with: nothing for `()`, an empty one for a status code,
+/// or one built from a `#{ status, headers, body }` map.
+fn local_response(result: Dynamic) -> anyhow::Result<Option<Response>> {
+    if result.is_unit() {
+        return Ok(None);
+    }
+    if result.is_int() {
+        return Ok(Some(status_code(&result)?.into_response()));
+    }
+    let map = result
+        .try_cast::<Map>()
+        .ok_or_else(|| anyhow::anyhow!("Script returned something other than (), a status code or a response map"))?;
+    let status = match map.get("status") {
+        Some(status) => status_code(status)?,
+        None => StatusCode::OK,
+    };
+    let headers = match map.get("headers") {
+        Some(headers) => map_to_headers(headers)?,
+        None => HeaderMap::new(),
+    };
+    let body = map.get("body").map(|body| body.to_string()).unwrap_or_default();
+    Ok(Some((status, headers, body).into_response()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const SCRIPT: &str = r#"
+        fn on_request() {
+            if !("x-tenant" in this.headers) {
+                return #{ status: 400, headers: #{ "content-type": "text/plain" }, body: "missing x-tenant" };
+            }
+            this.headers["x-tenant"] = this.headers["x-tenant"].to_lower();
+            this.body.replace("secret", "[redacted]");
+            if this.path.starts_with("/api/v2") {
+                this.backend = "orders-v2";
+                this.path = "/orders";
+            }
+        }
+
+        fn on_response() {
+            this.headers["x-script"] = "1";
+            if this.status == 502 { this.status = 503; }
+        }
+    "#;
+
+    #[tokio::test]
+    async fn test_script_hooks() {
+        let mut config = Config::default_config();
+        config.routes[0].filters = serde_json::from_value(json!([
+            { "name": "script", "settings": { "source": SCRIPT, "read_body": true } }
+        ]))
+        .unwrap();
+        let filter = ScriptFilter::new(&config).unwrap();
+        let route = &config.routes[0];
+        let context = FilterContext { route, request_id: "req-1", settings: &route.filters[0].settings };
+
+        let mut request = Request::builder()
+            .uri("/api/v2/orders?page=2")
+            .header("x-tenant", "ACME")
+            .body(Body::from("a secret"))
+            .unwrap();
+        assert!(matches!(filter.on_request(&context, &mut request).await, FilterAction::Continue));
+        assert_eq!(request.uri(), "/orders?page=2");
+        assert_eq!(request.headers()["x-tenant"], "acme");
+        assert_eq!(request.extensions().get::<BackendOverride>().unwrap().0, "orders-v2");
+        let body = axum::body::to_bytes(std::mem::take(request.body_mut()), usize::MAX).await.unwrap();
+        assert_eq!(body, "a [redacted]");
+
+        let mut request = Request::builder().uri("/api/v1/orders").body(Body::empty()).unwrap();
+        match filter.on_request(&context, &mut request).await {
+            FilterAction::Respond(response) => assert_eq!(response.status(), StatusCode::BAD_REQUEST),
+            FilterAction::Continue => panic!("request without x-tenant was let through"),
+        }
+
+        let mut response = StatusCode::BAD_GATEWAY.into_response();
+        filter.on_response(&context, &mut response).await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["x-script"], "1");
+    }
+
+    #[test]
+    fn test_rejects_bad_scripts() {
+        let mut config = Config::default_config();
+        config.routes[0].filters =
+            serde_json::from_value(json!([{ "name": "script", "settings": { "source": "fn on_request( {" } }]))
+                .unwrap();
+        assert!(ScriptFilter::new(&config).is_err());
+
+        config.routes[0].filters[0].settings =
+            json!({ "source": "fn on_request() { loop {} }", "max_operations": 1000 });
+        let filter = ScriptFilter::new(&config).unwrap();
+        let mut this = Dynamic::from_map(Map::new());
+        assert!(filter.script(&config.routes[0].filters[0].settings).unwrap().call("on_request", &mut this).is_err());
+    }
+}
@@ -0,0 +1,82 @@
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue};
+use tracing::warn;
+
+use crate::config::{RouteSecurityHeadersConfig, SecurityHeadersConfig};
+
+/// Adds the configured security headers to a response, with the route's values taking
+/// the place of the global ones.
+pub fn apply(config: &SecurityHeadersConfig, route: Option<&RouteSecurityHeadersConfig>, headers: &mut HeaderMap) {
+    if route.map_or(false, |route| !route.enabled) {
+        return;
+    }
+
+    let values = [
+        (header::STRICT_TRANSPORT_SECURITY, &config.hsts, route.and_then(|route| route.hsts.as_ref())),
+        (
+            header::X_CONTENT_TYPE_OPTIONS,
+            &config.content_type_options,
+            route.and_then(|route| route.content_type_options.as_ref()),
+        ),
+        (header::X_FRAME_OPTIONS, &config.frame_options, route.and_then(|route| route.frame_options.as_ref())),
+        (header::REFERRER_POLICY, &config.referrer_policy, route.and_then(|route| route.referrer_policy.as_ref())),
+        (
+            header::CONTENT_SECURITY_POLICY,
+            &config.content_security_policy,
+            route.and_then(|route| route.content_security_policy.as_ref()),
+        ),
+    ];
+
+    for (name, global, route_value) in values {
+        let value = match route_value.or(global.as_ref()) {
+            Some(value) if !value.is_empty() => value,
+            _ => continue,
+        };
+        if headers.contains_key(&name) && !config.override_backend {
+            continue;
+        }
+        set(headers, name, value);
+    }
+}
+
+fn set(headers: &mut HeaderMap, name: HeaderName, value: &str) {
+    match HeaderValue::from_str(value) {
+        Ok(value) => {
+            headers.insert(name, value);
+        }
+        Err(_) => warn!("Skipping invalid {} security header value", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_apply() {
+        let config: SecurityHeadersConfig =
+            serde_json::from_value(json!({ "frame_options": null, "content_security_policy": "default-src 'self'" }))
+                .unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::REFERRER_POLICY, HeaderValue::from_static("no-referrer"));
+        apply(&config, None, &mut headers);
+        assert_eq!(headers[header::STRICT_TRANSPORT_SECURITY], "max-age=31536000; includeSubDomains");
+        assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(headers[header::REFERRER_POLICY], "no-referrer");
+        assert_eq!(headers[header::CONTENT_SECURITY_POLICY], "default-src 'self'");
+        assert!(!headers.contains_key(header::X_FRAME_OPTIONS));
+
+        let route: RouteSecurityHeadersConfig =
+            serde_json::from_value(json!({ "frame_options": "SAMEORIGIN", "content_security_policy": "" })).unwrap();
+        let mut headers = HeaderMap::new();
+        apply(&config, Some(&route), &mut headers);
+        assert_eq!(headers[header::X_FRAME_OPTIONS], "SAMEORIGIN");
+        assert!(!headers.contains_key(header::CONTENT_SECURITY_POLICY));
+
+        let disabled: RouteSecurityHeadersConfig = serde_json::from_value(json!({ "enabled": false })).unwrap();
+        let mut headers = HeaderMap::new();
+        apply(&config, Some(&disabled), &mut headers);
+        assert!(headers.is_empty());
+    }
+}
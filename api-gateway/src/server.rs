@@ -0,0 +1,325 @@
+use axum::{
+    extract::{ConnectInfo, Request},
+    Router,
+};
+use hyper::{body::Incoming, server::conn::http2};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto,
+};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite},
+    net::TcpListener,
+    sync::watch,
+};
+use tower::Service;
+use tracing::{debug, error};
+
+use crate::config::{Http2Config, ServerConfig};
+use crate::metrics::{GaugeGuard, OPEN_CONNECTIONS};
+use crate::proxy_protocol::ProxyProtocol;
+use crate::rewind::Rewind;
+use crate::tls::{build_acceptor, watch_certificates, ClientCertificate};
+
+/// What an HTTP/2 client sends first, without negotiating through HTTP/1.1 or ALPN
+const H2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// How long to wait before accepting again after a failed accept. Failures like
+/// running out of file descriptors persist, so retrying at once would spin.
+pub(crate) const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Serves HTTP/1 through hyper-util's builder and HTTP/2 through hyper's own, since
+/// only the latter exposes every HTTP/2 setting (the HPACK table size among them).
+#[derive(Clone)]
+struct ConnectionBuilder {
+    http1: auto::Builder<TokioExecutor>,
+    http2: http2::Builder<TokioExecutor>,
+}
+
+/// Serves connections until `shutdown` changes. Connections already open are then
+/// closed once their in-flight requests complete.
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    server_config: &ServerConfig,
+    mut shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    let builder = build_connection_builder(server_config);
+    let header_read_timeout = Duration::from_secs(server_config.header_read_timeout_seconds);
+    let acceptor = match &server_config.tls {
+        Some(tls) => {
+            let (acceptor, resolver) = build_acceptor(tls)?;
+            if tls.reload_interval_seconds > 0 {
+                tokio::spawn(watch_certificates(resolver, Duration::from_secs(tls.reload_interval_seconds)));
+            }
+            Some(acceptor)
+        }
+        None => None,
+    };
+    let proxy_protocol = match &server_config.proxy_protocol {
+        Some(config) => Some(Arc::new(ProxyProtocol::new(config)?)),
+        None => None,
+    };
+
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.changed() => return Ok(()),
+        };
+        let (mut stream, peer_addr) = match accepted {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to accept connection: {}", e);
+                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                continue;
+            }
+        };
+
+        let tower_service = app.clone();
+        let builder = builder.clone();
+        let acceptor = acceptor.clone();
+        let proxy_protocol = proxy_protocol.clone();
+        let shutdown = shutdown.clone();
+
+        tokio::spawn(async move {
+            let _connection = GaugeGuard::new(OPEN_CONNECTIONS.clone());
+
+            let (remote_addr, read) = match proxy_protocol {
+                Some(proxy_protocol) => match proxy_protocol.client_addr(&mut stream, peer_addr).await {
+                    Ok(read) => read,
+                    Err(e) => {
+                        debug!("Rejected connection from {}: {}", peer_addr, e);
+                        return;
+                    }
+                },
+                None => (peer_addr, Vec::new()),
+            };
+            // Bytes read past a PROXY protocol header belong to the TLS handshake or first request
+            let stream = Rewind::new(read, stream);
+
+            match acceptor {
+                Some(acceptor) => {
+                    let stream = match tokio::time::timeout(header_read_timeout, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => stream,
+                        Ok(Err(e)) => {
+                            debug!("TLS handshake with {} failed: {}", remote_addr, e);
+                            return;
+                        }
+                        Err(_) => {
+                            debug!("TLS handshake with {} timed out", remote_addr);
+                            return;
+                        }
+                    };
+
+                    let client_cert = stream
+                        .get_ref()
+                        .1
+                        .peer_certificates()
+                        .and_then(ClientCertificate::from_chain);
+
+                    serve_connection(
+                        &builder,
+                        stream,
+                        tower_service,
+                        remote_addr,
+                        client_cert,
+                        header_read_timeout,
+                        shutdown,
+                    )
+                    .await;
+                }
+                None => {
+                    serve_connection(&builder, stream, tower_service, remote_addr, None, header_read_timeout, shutdown)
+                        .await;
+                }
+            }
+        });
+    }
+}
+
+async fn serve_connection<I>(
+    builder: &ConnectionBuilder,
+    mut stream: I,
+    tower_service: Router,
+    remote_addr: SocketAddr,
+    client_cert: Option<ClientCertificate>,
+    header_read_timeout: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let hyper_service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+        request.extensions_mut().insert(ConnectInfo(remote_addr));
+        if let Some(client_cert) = &client_cert {
+            request.extensions_mut().insert(client_cert.clone());
+        }
+        tower_service.clone().call(request)
+    });
+
+    // Otherwise a client that connects and sends nothing holds its connection forever
+    let preface = tokio::select! {
+        preface = tokio::time::timeout(header_read_timeout, read_preface(&mut stream)) => preface,
+        _ = shutdown.changed() => return,
+    };
+    let (is_http2, preface) = match preface {
+        Ok(Ok(read)) => read,
+        Ok(Err(e)) => {
+            debug!("Connection from {} closed before its first request: {}", remote_addr, e);
+            return;
+        }
+        Err(_) => {
+            debug!("Connection from {} sent no request within {:?}", remote_addr, header_read_timeout);
+            return;
+        }
+    };
+    let io = TokioIo::new(Rewind::new(preface, stream));
+
+    let result = if is_http2 {
+        let connection = builder.http2.serve_connection(io, hyper_service);
+        tokio::pin!(connection);
+        tokio::select! {
+            result = connection.as_mut() => result,
+            _ = shutdown.changed() => {
+                connection.as_mut().graceful_shutdown();
+                connection.await
+            }
+        }
+        .map_err(|e| e.to_string())
+    } else {
+        let connection = builder.http1.serve_connection_with_upgrades(io, hyper_service);
+        tokio::pin!(connection);
+        tokio::select! {
+            result = connection.as_mut() => result,
+            _ = shutdown.changed() => {
+                connection.as_mut().graceful_shutdown();
+                connection.await
+            }
+        }
+        .map_err(|e| e.to_string())
+    };
+
+    if let Err(e) = result {
+        debug!("Connection from {} closed with error: {}", remote_addr, e);
+    }
+}
+
+/// Reads until the connection has either sent the HTTP/2 preface or diverged from it,
+/// returning whether it did and the bytes read so far.
+async fn read_preface<I: AsyncRead + Unpin>(stream: &mut I) -> std::io::Result<(bool, Vec<u8>)> {
+    let mut read = Vec::with_capacity(H2_PREFACE.len());
+    let mut buf = [0u8; 24];
+
+    while read.len() < H2_PREFACE.len() && H2_PREFACE.starts_with(&read) {
+        let n = stream.read(&mut buf[..H2_PREFACE.len() - read.len()]).await?;
+        if n == 0 {
+            break;
+        }
+        read.extend_from_slice(&buf[..n]);
+    }
+
+    Ok((read == H2_PREFACE, read))
+}
+
+fn build_connection_builder(server_config: &ServerConfig) -> ConnectionBuilder {
+    let mut http2_builder = http2::Builder::new(TokioExecutor::new());
+
+    if let Some(http2) = &server_config.http2 {
+        apply_http2_settings(&mut http2_builder, http2);
+    }
+
+    ConnectionBuilder {
+        http1: auto::Builder::new(TokioExecutor::new()).http1_only(),
+        http2: http2_builder,
+    }
+}
+
+fn apply_http2_settings(http2_builder: &mut http2::Builder<TokioExecutor>, http2: &Http2Config) {
+
+    if let Some(max_streams) = http2.max_concurrent_streams {
+        http2_builder.max_concurrent_streams(max_streams);
+    }
+
+    if let Some(window_size) = http2.initial_stream_window_size {
+        http2_builder.initial_stream_window_size(window_size);
+    }
+
+    if let Some(window_size) = http2.initial_connection_window_size {
+        http2_builder.initial_connection_window_size(window_size);
+    }
+
+    if let Some(adaptive) = http2.adaptive_window {
+        http2_builder.adaptive_window(adaptive);
+    }
+
+    if let Some(frame_size) = http2.max_frame_size {
+        http2_builder.max_frame_size(frame_size);
+    }
+
+    if let Some(list_size) = http2.max_header_list_size {
+        http2_builder.max_header_list_size(list_size);
+    }
+
+    if let Some(table_size) = http2.header_table_size {
+        http2_builder.header_table_size(table_size);
+    }
+
+    if let Some(interval) = http2.keep_alive_interval_seconds {
+        http2_builder
+            .timer(TokioTimer::new())
+            .keep_alive_interval(Duration::from_secs(interval));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use axum::routing::get;
+
+    #[tokio::test]
+    async fn test_serves_http1_and_http2_prior_knowledge() {
+        let mut server_config = Config::default_config().server;
+        server_config.http2 = Some(Http2Config {
+            max_concurrent_streams: None,
+            initial_stream_window_size: None,
+            initial_connection_window_size: None,
+            adaptive_window: None,
+            max_frame_size: None,
+            max_header_list_size: None,
+            header_table_size: Some(8192),
+            keep_alive_interval_seconds: None,
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/", get(|| async { "ok" }));
+        let (_shutdown_tx, shutdown) = watch::channel(false);
+        tokio::spawn(async move { serve(listener, app, &server_config, shutdown).await });
+
+        let http1 = reqwest::Client::builder().http1_only().build().unwrap();
+        let response = http1.get(format!("http://{}/", addr)).send().await.unwrap();
+        assert_eq!(response.version(), reqwest::Version::HTTP_11);
+        assert_eq!(response.text().await.unwrap(), "ok");
+
+        let http2 = reqwest::Client::builder().http2_prior_knowledge().build().unwrap();
+        let response = http2.get(format!("http://{}/", addr)).send().await.unwrap();
+        assert_eq!(response.version(), reqwest::Version::HTTP_2);
+        assert_eq!(response.text().await.unwrap(), "ok");
+    }
+
+    #[tokio::test]
+    async fn test_closes_connections_that_send_nothing() {
+        let mut server_config = Config::default_config().server;
+        server_config.header_read_timeout_seconds = 1;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/", get(|| async { "ok" }));
+        let (_shutdown_tx, shutdown) = watch::channel(false);
+        tokio::spawn(async move { serve(listener, app, &server_config, shutdown).await });
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut buf = [0u8; 16];
+        let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf)).await;
+        assert_eq!(read.expect("connection wasn't closed").unwrap(), 0);
+    }
+}
@@ -0,0 +1,164 @@
+use axum::http::HeaderMap;
+use rand::Rng;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::auth::Identity;
+use crate::config::SessionConfig;
+
+pub const SESSION_PATH: &str = "/auth/session";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub identity: Identity,
+    #[serde(default)]
+    pub csrf_token: String,
+    pub created_at: u64,
+    pub expires_at: u64,
+}
+
+/// Browser sessions kept in Redis, so every gateway instance sees the same ones and
+/// backends behind the gateway don't each have to implement their own.
+pub struct SessionStore {
+    config: SessionConfig,
+    redis: redis::Client,
+}
+
+impl SessionStore {
+    pub fn new(config: &SessionConfig, redis_url: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            config: config.clone(),
+            redis: redis::Client::open(redis_url)?,
+        })
+    }
+
+    /// Starts a session for an authenticated caller and returns its id.
+    pub async fn create(&self, identity: Identity) -> anyhow::Result<(String, Session)> {
+        let now = now();
+        let session = Session {
+            identity,
+            csrf_token: random_token(),
+            created_at: now,
+            expires_at: now + self.config.ttl_seconds,
+        };
+
+        let session_id = random_token();
+        let mut conn = self.redis.get_async_connection().await?;
+        conn.set_ex::<_, _, ()>(
+            session_key(&session_id),
+            serde_json::to_string(&session)?,
+            self.config.ttl_seconds,
+        )
+        .await?;
+
+        Ok((session_id, session))
+    }
+
+    /// Looks up the session named by the request's cookie, if any.
+    pub async fn validate(&self, headers: &HeaderMap) -> Option<Session> {
+        let session_id = cookie_value(headers, &self.config.cookie_name)?;
+        let mut conn = self.redis.get_async_connection().await.ok()?;
+        let session: Option<String> = conn.get(session_key(&session_id)).await.ok()?;
+
+        session
+            .and_then(|session| serde_json::from_str::<Session>(&session).ok())
+            .filter(|session| session.expires_at > now())
+    }
+
+    pub async fn destroy(&self, headers: &HeaderMap) -> anyhow::Result<()> {
+        if let Some(session_id) = cookie_value(headers, &self.config.cookie_name) {
+            let mut conn = self.redis.get_async_connection().await?;
+            conn.del::<_, ()>(session_key(&session_id)).await?;
+        }
+        Ok(())
+    }
+
+    pub fn session_cookie(&self, session_id: &str) -> String {
+        self.cookie(session_id, self.config.ttl_seconds)
+    }
+
+    pub fn clear_cookie(&self) -> String {
+        self.cookie("", 0)
+    }
+
+    fn cookie(&self, value: &str, max_age: u64) -> String {
+        let mut cookie = format!(
+            "{}={}; Path=/; HttpOnly; SameSite={}; Max-Age={}",
+            self.config.cookie_name,
+            value,
+            self.config.same_site.as_str(),
+            max_age
+        );
+        if let Some(domain) = &self.config.cookie_domain {
+            cookie.push_str(&format!("; Domain={}", domain));
+        }
+        if self.config.cookie_secure {
+            cookie.push_str("; Secure");
+        }
+        cookie
+    }
+}
+
+/// Value of the named cookie from the request's `Cookie` headers.
+pub fn cookie_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    let prefix = format!("{}=", name);
+
+    headers
+        .get_all("Cookie")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .map(|cookie| cookie.trim())
+        .find_map(|cookie| cookie.strip_prefix(prefix.as_str()))
+        .filter(|value| !value.is_empty())
+        .map(String::from)
+}
+
+fn session_key(session_id: &str) -> String {
+    format!("gateway_session:{}", session_id)
+}
+
+fn random_token() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SameSite;
+
+    #[test]
+    fn test_cookie_attributes() {
+        let store = SessionStore::new(
+            &SessionConfig {
+                cookie_name: "sid".to_string(),
+                ttl_seconds: 3600,
+                cookie_secure: true,
+                same_site: SameSite::Strict,
+                cookie_domain: Some(".example.com".to_string()),
+            },
+            "redis://127.0.0.1:6379",
+        )
+        .unwrap();
+
+        assert_eq!(
+            store.session_cookie("abc"),
+            "sid=abc; Path=/; HttpOnly; SameSite=Strict; Max-Age=3600; Domain=.example.com; Secure"
+        );
+        assert!(store.clear_cookie().starts_with("sid=; "));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Cookie", "theme=dark; sid=abc".parse().unwrap());
+        assert_eq!(cookie_value(&headers, "sid"), Some("abc".to_string()));
+        assert_eq!(cookie_value(&headers, "other"), None);
+    }
+}
@@ -0,0 +1,138 @@
+use reqwest::{Client, Method};
+use std::{collections::HashMap, time::Duration};
+
+use crate::config::{AuthEnforcementMode, Config, SmokeProbe};
+
+#[derive(Debug, Clone)]
+pub struct ProbeFailure {
+    pub probe: String,
+    pub reasons: Vec<String>,
+}
+
+/// Sends every probe to the gateway at `base_url`, returning the ones that failed and
+/// how many were run.
+pub async fn run(config: &Config, base_url: &str) -> anyhow::Result<(Vec<ProbeFailure>, usize)> {
+    let client = Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?;
+    let base_url = base_url.trim_end_matches('/');
+
+    let probes = probes(config);
+    let mut failures = Vec::new();
+
+    for probe in &probes {
+        let reasons = check(&client, base_url, probe).await;
+        if !reasons.is_empty() {
+            failures.push(ProbeFailure {
+                probe: label(probe),
+                reasons,
+            });
+        }
+    }
+
+    Ok((failures, probes.len()))
+}
+
+/// The configured probes, plus a HEAD request for every route none of them reaches.
+/// Generated probes send no credentials, so enforced routes must answer 401.
+pub fn probes(config: &Config) -> Vec<SmokeProbe> {
+    let mut probes = config.smoke_probes.clone();
+
+    for route in &config.routes {
+        let covered = config.smoke_probes.iter().any(|probe| {
+            config.find_route(&probe.path).map(|r| r.path.as_str()) == Some(route.path.as_str())
+        });
+        if covered {
+            continue;
+        }
+
+        let path = route.path.trim_end_matches('*').to_string();
+        // An earlier route shadows this one, so there's nothing to reach
+        if config.find_route(&path).map(|r| r.path.as_str()) != Some(route.path.as_str()) {
+            continue;
+        }
+
+        let expect_status = if config.requires_auth(&path) && route.auth_mode == AuthEnforcementMode::Enforce {
+            vec![401]
+        } else {
+            Vec::new()
+        };
+
+        probes.push(SmokeProbe {
+            name: route.name.clone(),
+            method: "HEAD".to_string(),
+            path,
+            headers: HashMap::new(),
+            expect_status,
+            expect_headers: Vec::new(),
+        });
+    }
+
+    probes
+}
+
+fn label(probe: &SmokeProbe) -> String {
+    probe
+        .name
+        .clone()
+        .unwrap_or_else(|| format!("{} {}", probe.method, probe.path))
+}
+
+async fn check(client: &Client, base_url: &str, probe: &SmokeProbe) -> Vec<String> {
+    let method = match Method::from_bytes(probe.method.as_bytes()) {
+        Ok(method) => method,
+        Err(_) => return vec![format!("invalid method {}", probe.method)],
+    };
+
+    let mut request = client.request(method, format!("{}{}", base_url, probe.path));
+    for (name, value) in &probe.headers {
+        request = request.header(name, value);
+    }
+
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(e) => return vec![format!("request failed: {}", e)],
+    };
+
+    let mut reasons = Vec::new();
+    let status = response.status().as_u16();
+
+    let status_ok = if probe.expect_status.is_empty() {
+        status < 500
+    } else {
+        probe.expect_status.contains(&status)
+    };
+    if !status_ok {
+        reasons.push(format!("unexpected status {}", status));
+    }
+
+    for name in &probe.expect_headers {
+        if !response.headers().contains_key(name.as_str()) {
+            reasons.push(format!("missing header {}", name));
+        }
+    }
+
+    reasons
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_probes() {
+        let mut config = Config::default_config();
+        config.smoke_probes = serde_json::from_value(serde_json::json!([
+            { "method": "GET", "path": "/public/status", "expect_status": [200] }
+        ]))
+        .unwrap();
+
+        let probes = probes(&config);
+        let api = probes.iter().find(|probe| probe.path == "/api/v1/").unwrap();
+        assert_eq!(api.method, "HEAD");
+        assert_eq!(api.expect_status, vec![401]);
+
+        // The configured probe covers the public route
+        assert_eq!(probes.iter().filter(|probe| probe.path.starts_with("/public/")).count(), 1);
+    }
+}
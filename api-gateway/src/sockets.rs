@@ -0,0 +1,170 @@
+use listenfd::ListenFd;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    sync::Mutex,
+};
+use tokio::net::{TcpListener, UdpSocket};
+use tracing::{debug, info, warn};
+
+/// Opens the gateway's listening sockets. Sockets passed in through systemd socket
+/// activation (`LISTEN_FDS`) are used for the addresses they're bound to, so connections
+/// queue on them while the gateway restarts. Others are bound here, with `SO_REUSEPORT`
+/// when configured, so a new process can bind them while the old one drains.
+pub struct SocketBinder {
+    inherited_tcp: Mutex<Vec<std::net::TcpListener>>,
+    inherited_udp: Mutex<Vec<std::net::UdpSocket>>,
+    reuse_port: bool,
+}
+
+impl SocketBinder {
+    pub fn from_env(reuse_port: bool) -> Self {
+        let mut listen_fds = ListenFd::from_env();
+        let mut inherited_tcp = Vec::new();
+        let mut inherited_udp = Vec::new();
+
+        for index in 0..listen_fds.len() {
+            if let Ok(Some(listener)) = listen_fds.take_tcp_listener(index) {
+                inherited_tcp.push(listener);
+            } else if let Ok(Some(socket)) = listen_fds.take_udp_socket(index) {
+                inherited_udp.push(socket);
+            } else {
+                warn!("Ignoring inherited file descriptor {}, which isn't a TCP or UDP socket", index + 3);
+            }
+        }
+        if listen_fds.len() > 0 {
+            info!(
+                "Inherited {} TCP and {} UDP sockets through socket activation",
+                inherited_tcp.len(),
+                inherited_udp.len()
+            );
+        }
+
+        Self {
+            inherited_tcp: Mutex::new(inherited_tcp),
+            inherited_udp: Mutex::new(inherited_udp),
+            reuse_port,
+        }
+    }
+
+    pub fn bind_tcp(&self, addr: impl ToSocketAddrs) -> anyhow::Result<TcpListener> {
+        let addr = resolve(addr)?;
+        let mut inherited = self.inherited_tcp.lock().unwrap();
+        let listener = match inherited.iter().position(|listener| listener.local_addr().ok() == Some(addr)) {
+            Some(index) => inherited.swap_remove(index),
+            None => {
+                let socket = self.socket(addr, Type::STREAM, Protocol::TCP)?;
+                socket.set_reuse_address(true)?;
+                socket.bind(&addr.into())?;
+                socket.listen(1024)?;
+                socket.into()
+            }
+        };
+
+        listener.set_nonblocking(true)?;
+        Ok(TcpListener::from_std(listener)?)
+    }
+
+    pub fn bind_udp(&self, addr: impl ToSocketAddrs) -> anyhow::Result<UdpSocket> {
+        let addr = resolve(addr)?;
+        let mut inherited = self.inherited_udp.lock().unwrap();
+        let socket = match inherited.iter().position(|socket| socket.local_addr().ok() == Some(addr)) {
+            Some(index) => inherited.swap_remove(index),
+            None => {
+                let socket = self.socket(addr, Type::DGRAM, Protocol::UDP)?;
+                socket.bind(&addr.into())?;
+                socket.into()
+            }
+        };
+
+        socket.set_nonblocking(true)?;
+        Ok(UdpSocket::from_std(socket)?)
+    }
+
+    fn socket(&self, addr: SocketAddr, socket_type: Type, protocol: Protocol) -> std::io::Result<Socket> {
+        let socket = Socket::new(Domain::for_address(addr), socket_type, Some(protocol))?;
+        #[cfg(unix)]
+        if self.reuse_port {
+            socket.set_reuse_port(true)?;
+        }
+        Ok(socket)
+    }
+}
+
+fn resolve(addr: impl ToSocketAddrs) -> anyhow::Result<SocketAddr> {
+    addr.to_socket_addrs()?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Listen address didn't resolve"))
+}
+
+/// Tells systemd, for `Type=notify` units, that the gateway is serving.
+pub fn notify_ready() {
+    #[cfg(unix)]
+    notify(&[sd_notify::NotifyState::Ready]);
+}
+
+pub fn notify_stopping() {
+    #[cfg(unix)]
+    notify(&[sd_notify::NotifyState::Stopping]);
+}
+
+#[cfg(unix)]
+fn notify(state: &[sd_notify::NotifyState]) {
+    if let Err(e) = sd_notify::notify(false, state) {
+        debug!("Failed to notify systemd: {}", e);
+    }
+}
+
+/// Resolves on SIGTERM or Ctrl-C.
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reuse_port_allows_a_second_listener() {
+        let binder = SocketBinder {
+            inherited_tcp: Mutex::new(Vec::new()),
+            inherited_udp: Mutex::new(Vec::new()),
+            reuse_port: true,
+        };
+        let first = binder.bind_tcp("127.0.0.1:0").unwrap();
+        let addr = first.local_addr().unwrap();
+        assert!(binder.bind_tcp(addr).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_inherited_listener_is_used_for_its_address() {
+        let inherited = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = inherited.local_addr().unwrap();
+        let binder = SocketBinder {
+            inherited_tcp: Mutex::new(vec![inherited]),
+            inherited_udp: Mutex::new(Vec::new()),
+            reuse_port: false,
+        };
+
+        assert_eq!(binder.bind_tcp(addr).unwrap().local_addr().unwrap(), addr);
+        assert!(binder.inherited_tcp.lock().unwrap().is_empty());
+    }
+}
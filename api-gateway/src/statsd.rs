@@ -0,0 +1,146 @@
+use std::{
+    net::UdpSocket,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::debug;
+
+use crate::config::StatsdConfig;
+
+/// Lines are batched into datagrams no larger than this, which fits a typical MTU.
+const MAX_PACKET_BYTES: usize = 1432;
+
+/// Pushes counters and timers to a StatsD (or DogStatsD) agent. Lines are buffered and
+/// sent when a datagram fills up or on the flush interval; UDP sends never block.
+pub struct StatsdExporter {
+    socket: UdpSocket,
+    prefix: String,
+    dogstatsd: bool,
+    /// Constant tags, already rendered as `key:value` pairs
+    constant_tags: Vec<String>,
+    flush_interval: Duration,
+    buffer: Mutex<String>,
+}
+
+impl StatsdExporter {
+    pub fn new(config: &StatsdConfig) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(&config.address)?;
+        socket.set_nonblocking(true)?;
+
+        let mut constant_tags: Vec<String> = config
+            .tags
+            .iter()
+            .map(|(key, value)| format!("{}:{}", sanitize(key), sanitize(value)))
+            .collect();
+        constant_tags.sort();
+
+        Ok(Self {
+            socket,
+            prefix: config.prefix.trim_end_matches('.').to_string(),
+            dogstatsd: config.dogstatsd,
+            constant_tags,
+            flush_interval: Duration::from_millis(config.flush_interval_ms),
+            buffer: Mutex::new(String::new()),
+        })
+    }
+
+    /// Sends whatever is buffered every flush interval. Runs for the life of the gateway.
+    pub async fn run(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(self.flush_interval);
+        loop {
+            interval.tick().await;
+            self.flush();
+        }
+    }
+
+    pub fn count(&self, name: &str, value: u64, tags: &[(&str, &str)]) {
+        self.push(self.line(name, &value.to_string(), "c", tags));
+    }
+
+    pub fn timing(&self, name: &str, duration: Duration, tags: &[(&str, &str)]) {
+        self.push(self.line(name, &format!("{:.3}", duration.as_secs_f64() * 1000.0), "ms", tags));
+    }
+
+    pub fn flush(&self) {
+        let mut buffer = self.buffer.lock().unwrap();
+        self.send(&buffer);
+        buffer.clear();
+    }
+
+    fn push(&self, line: String) {
+        let mut buffer = self.buffer.lock().unwrap();
+        if !buffer.is_empty() && buffer.len() + 1 + line.len() > MAX_PACKET_BYTES {
+            self.send(&buffer);
+            buffer.clear();
+        }
+        if !buffer.is_empty() {
+            buffer.push('\n');
+        }
+        buffer.push_str(&line);
+    }
+
+    fn send(&self, packet: &str) {
+        if packet.is_empty() {
+            return;
+        }
+        // Metrics are best effort; a full socket buffer or absent agent just loses them
+        if let Err(e) = self.socket.send(packet.as_bytes()) {
+            debug!("Failed to send StatsD metrics: {}", e);
+        }
+    }
+
+    /// `<prefix>.<name>:<value>|<type>[|#tag:value,...]`
+    fn line(&self, name: &str, value: &str, kind: &str, tags: &[(&str, &str)]) -> String {
+        let mut line = format!("{}.{}:{}|{}", self.prefix, name, value, kind);
+
+        if self.dogstatsd && (!self.constant_tags.is_empty() || !tags.is_empty()) {
+            let tags: Vec<String> = self
+                .constant_tags
+                .iter()
+                .cloned()
+                .chain(tags.iter().map(|(key, value)| format!("{}:{}", sanitize(key), sanitize(value))))
+                .collect();
+            line.push_str("|#");
+            line.push_str(&tags.join(","));
+        }
+
+        line
+    }
+}
+
+/// Characters with a meaning in the line protocol can't appear in tags.
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if matches!(c, '|' | ',' | '#' | ':' | '\n') { '_' } else { c })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exporter(dogstatsd: bool) -> StatsdExporter {
+        StatsdExporter::new(&StatsdConfig {
+            address: "127.0.0.1:8125".to_string(),
+            prefix: "gateway.".to_string(),
+            dogstatsd,
+            tags: [("env".to_string(), "prod".to_string())].into_iter().collect(),
+            flush_interval_ms: 1000,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_line_format() {
+        let tags = [("route", "/api/v1/users"), ("status_class", "2xx")];
+
+        assert_eq!(
+            exporter(true).line("requests", "1", "c", &tags),
+            "gateway.requests:1|c|#env:prod,route:/api/v1/users,status_class:2xx"
+        );
+        assert_eq!(exporter(false).line("requests", "1", "c", &tags), "gateway.requests:1|c");
+        assert_eq!(sanitize("a|b,c#d:e"), "a_b_c_d_e");
+    }
+}
@@ -0,0 +1,220 @@
+use axum::body::Bytes;
+use futures::{stream, Stream, StreamExt};
+use std::{
+    pin::Pin,
+    time::Duration,
+};
+use tokio::time::{sleep, sleep_until, Instant};
+use tracing::info;
+
+use crate::config::StreamingConfig;
+
+type ByteStream = Pin<Box<dyn Stream<Item = reqwest::Result<Bytes>> + Send>>;
+
+#[derive(Debug, Clone, Copy)]
+enum Termination {
+    IdleTimeout,
+    MaxDuration,
+}
+
+impl Termination {
+    fn reason(&self) -> &'static str {
+        match self {
+            Termination::IdleTimeout => "idle_timeout",
+            Termination::MaxDuration => "max_duration",
+        }
+    }
+}
+
+struct StreamState {
+    upstream: ByteStream,
+    deadline: Option<Instant>,
+    idle_timeout: Option<Duration>,
+    event_stream: bool,
+    route: String,
+    request_id: String,
+    finished: bool,
+    /// Line breaks at the end of what's been sent, up to the two that end an event
+    trailing_newlines: usize,
+}
+
+enum Next {
+    Chunk(Option<reqwest::Result<Bytes>>),
+    Terminate(Termination),
+}
+
+/// Wraps an upstream body stream so it is closed once it has been idle or open for too long.
+/// Event streams receive a final `gateway_close` event so clients can tell a deliberate
+/// close from a dropped connection.
+pub fn limit_stream(
+    upstream: impl Stream<Item = reqwest::Result<Bytes>> + Send + 'static,
+    limits: &StreamingConfig,
+    event_stream: bool,
+    route: String,
+    request_id: String,
+) -> impl Stream<Item = Result<Bytes, std::io::Error>> + Send {
+    let state = StreamState {
+        upstream: Box::pin(upstream),
+        deadline: limits.max_duration_seconds.map(|secs| Instant::now() + Duration::from_secs(secs)),
+        idle_timeout: limits.idle_timeout_seconds.map(Duration::from_secs),
+        event_stream,
+        route,
+        request_id,
+        finished: false,
+        trailing_newlines: 2,
+    };
+
+    stream::unfold(state, |mut state| async move {
+        if state.finished {
+            return None;
+        }
+
+        let deadline = state.deadline;
+        let idle_timeout = state.idle_timeout;
+
+        let next = tokio::select! {
+            chunk = state.upstream.next() => Next::Chunk(chunk),
+            _ = wait_for(idle_timeout), if idle_timeout.is_some() => Next::Terminate(Termination::IdleTimeout),
+            _ = wait_until(deadline), if deadline.is_some() => Next::Terminate(Termination::MaxDuration),
+        };
+
+        match next {
+            Next::Chunk(Some(Ok(chunk))) => {
+                state.trailing_newlines = trailing_newlines(&chunk, state.trailing_newlines);
+                Some((Ok(chunk), state))
+            }
+            Next::Chunk(Some(Err(e))) => {
+                state.finished = true;
+                Some((Err(std::io::Error::new(std::io::ErrorKind::Other, e)), state))
+            }
+            Next::Chunk(None) => None,
+            Next::Terminate(termination) => {
+                info!(
+                    "Closing stream on route {} ({}, request_id: {})",
+                    state.route,
+                    termination.reason(),
+                    state.request_id
+                );
+                state.finished = true;
+
+                if state.event_stream {
+                    Some((Ok(termination_event(termination, state.trailing_newlines)), state))
+                } else {
+                    None
+                }
+            }
+        }
+    })
+}
+
+async fn wait_for(timeout: Option<Duration>) {
+    if let Some(timeout) = timeout {
+        sleep(timeout).await;
+    }
+}
+
+async fn wait_until(deadline: Option<Instant>) {
+    if let Some(deadline) = deadline {
+        sleep_until(deadline).await;
+    }
+}
+
+/// How many line breaks end the output once `chunk` is sent after output ending in
+/// `before` of them.
+fn trailing_newlines(chunk: &[u8], before: usize) -> usize {
+    let mut count = 0;
+    for &byte in chunk.iter().rev() {
+        match byte {
+            b'\n' => count += 1,
+            b'\r' => {}
+            _ => return count.min(2),
+        }
+    }
+    (before + count).min(2)
+}
+
+/// The upstream may be cut off partway through an event, so that event is ended first;
+/// otherwise clients would read its `data:` lines as part of `gateway_close`.
+fn termination_event(termination: Termination, trailing_newlines: usize) -> Bytes {
+    Bytes::from(format!(
+        "{}event: gateway_close\ndata: {{\"reason\":\"{}\"}}\n\n",
+        "\n".repeat(2 - trailing_newlines.min(2)),
+        termination.reason()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(max_duration_seconds: Option<u64>, idle_timeout_seconds: Option<u64>) -> StreamingConfig {
+        StreamingConfig { max_duration_seconds, idle_timeout_seconds }
+    }
+
+    /// Sends each chunk after its delay, then stays open without sending anything more.
+    fn upstream(chunks: Vec<(u64, &'static str)>) -> impl Stream<Item = reqwest::Result<Bytes>> + Send {
+        stream::iter(chunks)
+            .then(|(delay, chunk)| async move {
+                sleep(Duration::from_secs(delay)).await;
+                Ok(Bytes::from(chunk))
+            })
+            .chain(stream::pending())
+    }
+
+    async fn collect(stream: impl Stream<Item = Result<Bytes, std::io::Error>>) -> Vec<String> {
+        stream
+            .map(|chunk| String::from_utf8(chunk.unwrap().to_vec()).unwrap())
+            .collect()
+            .await
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_timeout_resets_on_each_chunk() {
+        let started = Instant::now();
+        let chunks = vec![(3, "data: a\n\n"), (3, "data: b\n\n")];
+        let stream = limit_stream(upstream(chunks), &limits(None, Some(5)), true, "/events".into(), "r1".into());
+
+        assert_eq!(
+            collect(stream).await,
+            vec![
+                "data: a\n\n",
+                "data: b\n\n",
+                "event: gateway_close\ndata: {\"reason\":\"idle_timeout\"}\n\n",
+            ]
+        );
+        assert_eq!(started.elapsed(), Duration::from_secs(11));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_max_duration_ends_a_partial_event_before_closing() {
+        let started = Instant::now();
+        let chunks = vec![(1, "data: a\n\n"), (1, "data: par")];
+        let stream = limit_stream(upstream(chunks), &limits(Some(10), Some(60)), true, "/events".into(), "r1".into());
+
+        assert_eq!(
+            collect(stream).await,
+            vec![
+                "data: a\n\n",
+                "data: par",
+                "\n\nevent: gateway_close\ndata: {\"reason\":\"max_duration\"}\n\n",
+            ]
+        );
+        assert_eq!(started.elapsed(), Duration::from_secs(10));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_other_streams_end_without_a_close_event() {
+        let chunks = vec![(1, "part one"), (1, "part two")];
+        let stream = limit_stream(upstream(chunks), &limits(Some(10), None), false, "/download".into(), "r1".into());
+
+        assert_eq!(collect(stream).await, vec!["part one", "part two"]);
+    }
+
+    #[test]
+    fn test_trailing_newlines() {
+        assert_eq!(trailing_newlines(b"data: a\r\n\r\n", 0), 2);
+        assert_eq!(trailing_newlines(b"data: a\n", 2), 1);
+        assert_eq!(trailing_newlines(b"\n", 1), 2);
+        assert_eq!(trailing_newlines(b"data: a", 2), 0);
+    }
+}
@@ -0,0 +1,257 @@
+use rand::Rng;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, error, info, warn};
+
+use crate::config::{Config, LoadBalancingStrategy, TcpProxyConfig};
+use crate::health::HealthChecker;
+use crate::metrics::{GaugeGuard, TCP_PROXY_ACTIVE_CONNECTIONS, TCP_PROXY_CONNECTIONS};
+use crate::server::ACCEPT_ERROR_BACKOFF;
+
+/// Forwards connections accepted on one listener to a backend's servers, picking among
+/// those the health checker hasn't ejected.
+pub struct TcpProxy {
+    config: TcpProxyConfig,
+    servers: ServerPool,
+}
+
+/// A backend's servers as the L4 proxies balance across them.
+pub(crate) struct ServerPool {
+    backend: String,
+    servers: Vec<String>,
+    strategy: LoadBalancingStrategy,
+    health_checker: Arc<HealthChecker>,
+    next_index: AtomicUsize,
+    /// Open connections to each server, for `least_connections`
+    connections: HashMap<String, Arc<AtomicUsize>>,
+}
+
+/// Counts a connection against its server until dropped.
+pub(crate) struct ServerConnection(Arc<AtomicUsize>);
+
+impl Drop for ServerConnection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl ServerPool {
+    pub(crate) fn new(
+        backend: &str,
+        strategy: &LoadBalancingStrategy,
+        gateway_config: &Config,
+        health_checker: Arc<HealthChecker>,
+    ) -> anyhow::Result<Self> {
+        let servers = gateway_config
+            .backends
+            .get(backend)
+            .ok_or_else(|| anyhow::anyhow!("Unknown backend {}", backend))?
+            .servers
+            .clone();
+        if servers.is_empty() {
+            return Err(anyhow::anyhow!("Backend {} has no servers", backend));
+        }
+
+        let connections = servers
+            .iter()
+            .map(|server| (server.clone(), Arc::new(AtomicUsize::new(0))))
+            .collect();
+
+        Ok(Self {
+            backend: backend.to_string(),
+            servers,
+            strategy: strategy.clone(),
+            health_checker,
+            next_index: AtomicUsize::new(0),
+            connections,
+        })
+    }
+
+    pub(crate) fn backend(&self) -> &str {
+        &self.backend
+    }
+
+    /// Servers not ejected by health checks, in the order they should be tried.
+    pub(crate) async fn candidates(&self) -> Vec<String> {
+        let unhealthy = self.health_checker.get_unhealthy_servers(&self.backend).await;
+        let mut servers: Vec<String> = self
+            .servers
+            .iter()
+            .filter(|server| !unhealthy.contains(server))
+            .cloned()
+            .collect();
+        if servers.is_empty() {
+            return servers;
+        }
+
+        match self.strategy {
+            LoadBalancingStrategy::RoundRobin | LoadBalancingStrategy::WeightedRoundRobin => {
+                let start = self.next_index.fetch_add(1, Ordering::Relaxed) % servers.len();
+                servers.rotate_left(start);
+            }
+            LoadBalancingStrategy::LeastConnections => {
+                servers.sort_by_key(|server| self.connections[server].load(Ordering::Relaxed));
+            }
+            LoadBalancingStrategy::Random => {
+                let start = rand::thread_rng().gen_range(0..servers.len());
+                servers.rotate_left(start);
+            }
+        }
+        servers
+    }
+
+    pub(crate) fn track(&self, server: &str) -> ServerConnection {
+        let counter = self.connections[server].clone();
+        counter.fetch_add(1, Ordering::Relaxed);
+        ServerConnection(counter)
+    }
+}
+
+impl TcpProxy {
+    pub fn new(
+        config: &TcpProxyConfig,
+        gateway_config: &Config,
+        health_checker: Arc<HealthChecker>,
+    ) -> anyhow::Result<Self> {
+        let servers = ServerPool::new(&config.backend, &config.load_balancing, gateway_config, health_checker)
+            .map_err(|e| anyhow::anyhow!("TCP proxy {}: {}", config.name, e))?;
+
+        Ok(Self {
+            config: config.clone(),
+            servers,
+        })
+    }
+
+    pub fn listen_addr(&self) -> &str {
+        &self.config.listen
+    }
+
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) {
+        info!(
+            "TCP proxy {} listening on {} for backend {}",
+            self.config.name, self.config.listen, self.servers.backend()
+        );
+
+        loop {
+            let (stream, peer_addr) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    error!("TCP proxy {} failed to accept connection: {}", self.config.name, e);
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            };
+
+            let proxy = self.clone();
+            tokio::spawn(async move {
+                let _active = GaugeGuard::new(TCP_PROXY_ACTIVE_CONNECTIONS.with_label_values(&[&proxy.config.name]));
+                let result = match proxy.forward(stream).await {
+                    Ok(()) => "forwarded",
+                    Err(e) => {
+                        debug!("TCP proxy {} connection from {} failed: {}", proxy.config.name, peer_addr, e);
+                        "failed"
+                    }
+                };
+                TCP_PROXY_CONNECTIONS
+                    .with_label_values(&[&proxy.config.name, &proxy.config.backend, result])
+                    .inc();
+            });
+        }
+    }
+
+    async fn forward(&self, mut client: TcpStream) -> anyhow::Result<()> {
+        let (mut upstream, _server_connection) = self.connect().await?;
+        let _ = client.set_nodelay(true);
+        let _ = upstream.set_nodelay(true);
+
+        tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+        Ok(())
+    }
+
+    /// Connects to the server the load balancing strategy picks, moving on to the next
+    /// candidate when a connection can't be made.
+    async fn connect(&self) -> anyhow::Result<(TcpStream, ServerConnection)> {
+        let candidates = self.servers.candidates().await;
+        if candidates.is_empty() {
+            return Err(anyhow::anyhow!("No healthy servers available for backend: {}", self.servers.backend()));
+        }
+
+        let timeout = Duration::from_millis(self.config.connect_timeout_ms);
+        for server in candidates {
+            let server_connection = self.servers.track(&server);
+
+            match tokio::time::timeout(timeout, TcpStream::connect(server_address(&server))).await {
+                Ok(Ok(stream)) => return Ok((stream, server_connection)),
+                Ok(Err(e)) => warn!("TCP proxy {} failed to connect to {}: {}", self.config.name, server, e),
+                Err(_) => warn!("TCP proxy {} timed out connecting to {}", self.config.name, server),
+            }
+        }
+
+        Err(anyhow::anyhow!("No server of backend {} accepted the connection", self.servers.backend()))
+    }
+}
+
+/// The `host:port` of a TCP backend server, which may be written with a `tcp://` scheme.
+pub fn server_address(server: &str) -> &str {
+    server
+        .split_once("://")
+        .map_or(server, |(_, address)| address)
+        .trim_end_matches('/')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_server_address() {
+        assert_eq!(server_address("tcp://db.internal:5432"), "db.internal:5432");
+        assert_eq!(server_address("10.0.0.5:1883"), "10.0.0.5:1883");
+    }
+
+    #[tokio::test]
+    async fn test_forwards_to_a_reachable_server() {
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let mut buf = [0u8; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+        });
+
+        // The first server refuses connections, so the proxy moves on to the second
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let mut config = Config::default_config();
+        let backend = config.backends.get_mut("backend_api").unwrap();
+        backend.servers = vec![format!("tcp://{}", closed), upstream_addr.to_string()];
+        let tcp_config = TcpProxyConfig {
+            name: "echo".to_string(),
+            listen: "127.0.0.1:0".to_string(),
+            backend: "backend_api".to_string(),
+            load_balancing: LoadBalancingStrategy::RoundRobin,
+            connect_timeout_ms: 1000,
+        };
+        let config = Arc::new(config);
+        let health_checker = Arc::new(HealthChecker::new(config.clone()));
+        let proxy = Arc::new(TcpProxy::new(&tcp_config, &config, health_checker).unwrap());
+
+        let listener = TcpListener::bind(proxy.listen_addr()).await.unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        tokio::spawn(proxy.serve(listener));
+
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        client.write_all(b"ping").await.unwrap();
+        let mut reply = [0u8; 4];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"ping");
+    }
+}
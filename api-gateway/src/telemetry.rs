@@ -0,0 +1,238 @@
+use axum::http::HeaderMap;
+use opentelemetry::{
+    global,
+    propagation::{text_map_propagator::FieldIter, Extractor, Injector, TextMapCompositePropagator, TextMapPropagator},
+    trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState, TracerProvider as _},
+    Context, KeyValue,
+};
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{Sampler, TracerProvider},
+    Resource,
+};
+use std::time::Duration;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+use crate::config::TracingConfig;
+
+const LOG_FILTER: &str = "api_gateway=debug,tower_http=debug";
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Headers that carry trace context. When the gateway is tracing, it replaces the
+/// caller's values with its own so the backend's spans hang off the gateway's.
+pub const TRACE_HEADERS: [&str; 8] = [
+    "traceparent",
+    "tracestate",
+    "b3",
+    "x-b3-traceid",
+    "x-b3-spanid",
+    "x-b3-parentspanid",
+    "x-b3-sampled",
+    "x-b3-flags",
+];
+
+/// Installs the log subscriber, plus an OpenTelemetry layer when tracing is configured.
+/// The returned provider must be shut down on exit so buffered spans are flushed.
+pub fn init(config: Option<&TracingConfig>) -> anyhow::Result<Option<TracerProvider>> {
+    let registry = tracing_subscriber::registry()
+        .with(EnvFilter::new(LOG_FILTER))
+        .with(tracing_subscriber::fmt::layer());
+
+    let config = match config {
+        Some(config) => config,
+        None => {
+            registry.init();
+            return Ok(None);
+        }
+    };
+
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(&config.otlp_endpoint)
+        .with_timeout(EXPORT_TIMEOUT)
+        .build()?;
+
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.sampling_ratio.clamp(0.0, 1.0),
+        ))))
+        .with_resource(Resource::new([KeyValue::new("service.name", config.service_name.clone())]))
+        .build();
+
+    // Listed last, traceparent wins when a caller sends both
+    global::set_text_map_propagator(TextMapCompositePropagator::new(vec![
+        Box::new(B3Propagator::new()),
+        Box::new(TraceContextPropagator::new()),
+    ]));
+
+    let tracer = provider.tracer("api-gateway");
+    registry
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .init();
+
+    Ok(Some(provider))
+}
+
+/// Makes `span` a child of the caller's trace, if the request carries one.
+pub fn continue_trace(span: &Span, headers: &HeaderMap) {
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+    span.set_parent(parent);
+}
+
+/// Trace context headers for an upstream request made within the current span, or
+/// `None` when tracing is off and the caller's headers should go through untouched.
+pub fn upstream_trace_headers() -> Option<HeaderMap> {
+    let context = Span::current().context();
+    if !context.span().span_context().is_valid() {
+        return None;
+    }
+
+    let mut headers = HeaderMap::new();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut HeaderInjector(&mut headers)));
+    Some(headers)
+}
+
+/// Id of the current trace if it's being recorded, for linking metrics to it.
+pub fn current_trace_id() -> Option<String> {
+    let context = Span::current().context();
+    let span = context.span();
+    let span_context = span.span_context();
+    (span_context.is_valid() && span_context.is_sampled()).then(|| span_context.trace_id().to_string())
+}
+
+/// Zipkin's B3 propagation. Reads either the single `b3` header or the `X-B3-*` set,
+/// and writes the `X-B3-*` set, which every B3 implementation understands.
+#[derive(Debug)]
+struct B3Propagator {
+    fields: Vec<String>,
+}
+
+impl B3Propagator {
+    fn new() -> Self {
+        Self {
+            fields: ["x-b3-traceid", "x-b3-spanid", "x-b3-sampled"]
+                .iter()
+                .map(|field| field.to_string())
+                .collect(),
+        }
+    }
+
+    /// `{trace id}-{span id}[-{sampling}[-{parent span id}]]`
+    fn extract_single(value: &str) -> Option<SpanContext> {
+        let mut parts = value.split('-');
+        let trace_id = parts.next()?;
+        let span_id = parts.next()?;
+        b3_span_context(trace_id, span_id, parts.next())
+    }
+
+    fn extract_multi(extractor: &dyn Extractor) -> Option<SpanContext> {
+        let sampled = match extractor.get("x-b3-flags") {
+            // Debug implies sampled
+            Some("1") => Some("1"),
+            _ => extractor.get("x-b3-sampled"),
+        };
+        b3_span_context(extractor.get("x-b3-traceid")?, extractor.get("x-b3-spanid")?, sampled)
+    }
+}
+
+impl TextMapPropagator for B3Propagator {
+    fn inject_context(&self, cx: &Context, injector: &mut dyn Injector) {
+        let span = cx.span();
+        let span_context = span.span_context();
+        if !span_context.is_valid() {
+            return;
+        }
+
+        injector.set("x-b3-traceid", span_context.trace_id().to_string());
+        injector.set("x-b3-spanid", span_context.span_id().to_string());
+        injector.set("x-b3-sampled", if span_context.is_sampled() { "1" } else { "0" }.to_string());
+    }
+
+    fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+        let span_context = match extractor.get("b3") {
+            Some(value) => Self::extract_single(value),
+            None => Self::extract_multi(extractor),
+        };
+
+        match span_context {
+            Some(span_context) => cx.with_remote_span_context(span_context),
+            None => cx.clone(),
+        }
+    }
+
+    fn fields(&self) -> FieldIter<'_> {
+        FieldIter::new(&self.fields)
+    }
+}
+
+/// B3 trace ids are 64 or 128 bits. A missing sampling decision is taken as sampled,
+/// since the caller evidently traces this request.
+fn b3_span_context(trace_id: &str, span_id: &str, sampled: Option<&str>) -> Option<SpanContext> {
+    if !matches!(trace_id.len(), 16 | 32) || span_id.len() != 16 {
+        return None;
+    }
+
+    let flags = match sampled {
+        None | Some("1") | Some("d") | Some("true") => TraceFlags::SAMPLED,
+        Some("0") | Some("false") => TraceFlags::NOT_SAMPLED,
+        Some(_) => return None,
+    };
+
+    let span_context = SpanContext::new(
+        TraceId::from_hex(trace_id).ok()?,
+        SpanId::from_hex(span_id).ok()?,
+        flags,
+        true,
+        TraceState::default(),
+    );
+    span_context.is_valid().then_some(span_context)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn extract(headers: &[(&str, &str)]) -> SpanContext {
+        let carrier: HashMap<String, String> = headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        B3Propagator::new()
+            .extract(&carrier)
+            .span()
+            .span_context()
+            .clone()
+    }
+
+    #[test]
+    fn test_b3_extract_and_inject() {
+        let single = extract(&[("b3", "80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-1-05e3ac9a4f6e3b90")]);
+        assert_eq!(single.trace_id().to_string(), "80f198ee56343ba864fe8b2a57d3eff7");
+        assert_eq!(single.span_id().to_string(), "e457b5a2e4d86bd1");
+        assert!(single.is_sampled() && single.is_remote());
+
+        let multi = extract(&[
+            ("x-b3-traceid", "64fe8b2a57d3eff7"),
+            ("x-b3-spanid", "e457b5a2e4d86bd1"),
+            ("x-b3-sampled", "0"),
+        ]);
+        assert_eq!(multi.trace_id().to_string(), "000000000000000064fe8b2a57d3eff7");
+        assert!(!multi.is_sampled());
+
+        assert!(!extract(&[("b3", "0")]).is_valid());
+        assert!(!extract(&[("x-b3-traceid", "not-hex"), ("x-b3-spanid", "e457b5a2e4d86bd1")]).is_valid());
+
+        let mut injected: HashMap<String, String> = HashMap::new();
+        B3Propagator::new().inject_context(&Context::new().with_remote_span_context(single), &mut injected);
+        assert_eq!(injected["x-b3-traceid"], "80f198ee56343ba864fe8b2a57d3eff7");
+        assert_eq!(injected["x-b3-spanid"], "e457b5a2e4d86bd1");
+        assert_eq!(injected["x-b3-sampled"], "1");
+    }
+}
@@ -0,0 +1,337 @@
+use axum::http::{HeaderMap, HeaderValue};
+use openssl::{hash::MessageDigest, x509::X509};
+use rustls::{
+    crypto::CryptoProvider,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier},
+    sign::CertifiedKey,
+    version::{TLS12, TLS13},
+    RootCertStore, ServerConfig, SupportedCipherSuite, SupportedProtocolVersion,
+};
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+use tokio_rustls::TlsAcceptor;
+use tracing::{info, warn};
+
+use crate::config::{ListenerTlsConfig, TlsVersion, XfccConfig, XfccField};
+
+const XFCC_HEADER: &str = "x-forwarded-client-cert";
+
+/// Details of a verified client certificate, attached to each request on the connection.
+#[derive(Debug, Clone)]
+pub struct ClientCertificate {
+    /// Hex SHA-256 of the leaf certificate's DER encoding
+    pub hash: String,
+    pub subject: String,
+    pub uris: Vec<String>,
+    pub dns: Vec<String>,
+    pub cert_pem: String,
+    pub chain_pem: String,
+}
+
+/// Serves the listener's certificate, swapped in place when the files are reloaded so
+/// new handshakes pick it up without restarting the listener.
+#[derive(Debug)]
+pub struct CertificateResolver {
+    cert_file: String,
+    key_file: String,
+    provider: Arc<CryptoProvider>,
+    key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertificateResolver {
+    fn load(tls: &ListenerTlsConfig, provider: Arc<CryptoProvider>) -> anyhow::Result<Self> {
+        let key = load_certified_key(&tls.cert_file, &tls.key_file, &provider)?;
+        Ok(Self {
+            cert_file: tls.cert_file.clone(),
+            key_file: tls.key_file.clone(),
+            provider,
+            key: RwLock::new(Arc::new(key)),
+        })
+    }
+
+    /// Re-reads the cert and key files, keeping the current certificate if they can't
+    /// be loaded (e.g. only one of them has been replaced so far).
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let key = load_certified_key(&self.cert_file, &self.key_file, &self.provider)?;
+        *self.key.write().map_err(|_| anyhow::anyhow!("Certificate lock poisoned"))? = Arc::new(key);
+        Ok(())
+    }
+
+    fn modified(&self) -> Option<(SystemTime, SystemTime)> {
+        let modified = |path: &str| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+        Some((modified(&self.cert_file)?, modified(&self.key_file)?))
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.key.read().ok().map(|key| key.clone())
+    }
+}
+
+/// Polls the cert and key files and reloads them whenever either one changes.
+pub async fn watch_certificates(resolver: Arc<CertificateResolver>, interval: Duration) {
+    let mut last_modified = resolver.modified();
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+        let modified = resolver.modified();
+        if modified.is_none() || modified == last_modified {
+            continue;
+        }
+
+        match resolver.reload() {
+            Ok(()) => {
+                info!("Reloaded TLS certificate from {}", resolver.cert_file);
+                last_modified = modified;
+            }
+            Err(e) => warn!("Failed to reload TLS certificate, keeping the current one: {}", e),
+        }
+    }
+}
+
+fn load_certified_key(cert_file: &str, key_file: &str, provider: &CryptoProvider) -> anyhow::Result<CertifiedKey> {
+    let certs = CertificateDer::pem_file_iter(cert_file)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", cert_file, e))?
+        .collect::<Result<Vec<_>, _>>()?;
+    let key = PrivateKeyDer::from_pem_file(key_file).map_err(|e| anyhow::anyhow!("Failed to read {}: {}", key_file, e))?;
+
+    CertifiedKey::from_der(certs, key, provider)
+        .map_err(|e| anyhow::anyhow!("Invalid certificate {} or key {}: {}", cert_file, key_file, e))
+}
+
+/// The ring provider, restricted to the configured cipher suites.
+fn crypto_provider(tls: &ListenerTlsConfig) -> anyhow::Result<CryptoProvider> {
+    let mut provider = rustls::crypto::ring::default_provider();
+    if tls.cipher_suites.is_empty() {
+        return Ok(provider);
+    }
+
+    let name = |suite: &SupportedCipherSuite| suite.suite().as_str().unwrap_or_default();
+    if let Some(unknown) = tls
+        .cipher_suites
+        .iter()
+        .find(|configured| !provider.cipher_suites.iter().any(|suite| configured.eq_ignore_ascii_case(name(suite))))
+    {
+        return Err(anyhow::anyhow!("Unsupported TLS cipher suite {}", unknown));
+    }
+    provider
+        .cipher_suites
+        .retain(|suite| tls.cipher_suites.iter().any(|configured| configured.eq_ignore_ascii_case(name(suite))));
+    Ok(provider)
+}
+
+/// Builds the listener's acceptor, along with the resolver that reloads its certificate.
+pub fn build_acceptor(tls: &ListenerTlsConfig) -> anyhow::Result<(TlsAcceptor, Arc<CertificateResolver>)> {
+    let provider = Arc::new(crypto_provider(tls)?);
+    let resolver = Arc::new(CertificateResolver::load(tls, provider.clone())?);
+
+    let versions: &[&SupportedProtocolVersion] = match tls.min_version {
+        TlsVersion::Tls12 => &[&TLS13, &TLS12],
+        TlsVersion::Tls13 => &[&TLS13],
+    };
+    let builder = ServerConfig::builder_with_provider(provider.clone()).with_protocol_versions(versions)?;
+
+    let builder = match &tls.client_ca_file {
+        Some(ca_file) => {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(ca_file)
+                .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", ca_file, e))?
+            {
+                roots.add(cert?)?;
+            }
+
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = if tls.require_client_cert {
+                verifier.build()?
+            } else {
+                verifier.allow_unauthenticated().build()?
+            };
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut server_config = builder.with_cert_resolver(resolver.clone());
+    server_config.alpn_protocols = tls.alpn_protocols.iter().map(|protocol| protocol.as_bytes().to_vec()).collect();
+
+    Ok((TlsAcceptor::from(Arc::new(server_config)), resolver))
+}
+
+impl ClientCertificate {
+    /// Parses the peer's chain as presented in the handshake, leaf first.
+    pub fn from_chain(chain: &[CertificateDer<'_>]) -> Option<Self> {
+        let certs = chain
+            .iter()
+            .map(|der| X509::from_der(der.as_ref()))
+            .collect::<Result<Vec<_>, _>>()
+            .ok()?;
+        let leaf = certs.first()?;
+
+        let hash = leaf
+            .digest(MessageDigest::sha256())
+            .ok()?
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        // RFC 2253 lists the most specific component first
+        let subject = leaf
+            .subject_name()
+            .entries()
+            .collect::<Vec<_>>()
+            .iter()
+            .rev()
+            .filter_map(|entry| {
+                let name = entry.object().nid().short_name().ok()?;
+                Some(format!("{}={}", name, String::from_utf8_lossy(entry.data().as_slice())))
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let mut uris = Vec::new();
+        let mut dns = Vec::new();
+        for name in leaf.subject_alt_names().iter().flatten() {
+            if let Some(uri) = name.uri() {
+                uris.push(uri.to_string());
+            } else if let Some(dns_name) = name.dnsname() {
+                dns.push(dns_name.to_string());
+            }
+        }
+
+        let to_pem = |cert: &X509| cert.to_pem().ok().and_then(|pem| String::from_utf8(pem).ok());
+        let cert_pem = to_pem(leaf)?;
+        let chain_pem = certs.iter().filter_map(to_pem).collect();
+
+        Some(Self {
+            hash,
+            subject,
+            uris,
+            dns,
+            cert_pem,
+            chain_pem,
+        })
+    }
+
+    /// Renders the certificate as a single XFCC element.
+    pub fn to_xfcc(&self, fields: &[XfccField]) -> String {
+        let mut parts = Vec::new();
+
+        for field in fields {
+            match field {
+                XfccField::Hash => parts.push(format!("Hash={}", self.hash)),
+                XfccField::Cert => parts.push(format!("Cert=\"{}\"", url_encode(&self.cert_pem))),
+                XfccField::Chain => parts.push(format!("Chain=\"{}\"", url_encode(&self.chain_pem))),
+                XfccField::Subject => parts.push(format!("Subject=\"{}\"", self.subject.replace('"', "\\\""))),
+                XfccField::Uri => parts.extend(self.uris.iter().map(|uri| format!("URI={}", uri))),
+                XfccField::Dns => parts.extend(self.dns.iter().map(|dns| format!("DNS={}", dns))),
+            }
+        }
+
+        parts.join(";")
+    }
+}
+
+/// Sets the XFCC header for the upstream request. Whatever the client sent is replaced
+/// when we have a verified certificate to forward, and otherwise dropped unless the
+/// peer is a trusted proxy that may have set it.
+pub fn set_forwarded_client_cert(
+    headers: &mut HeaderMap,
+    client_cert: Option<&ClientCertificate>,
+    config: Option<&XfccConfig>,
+    peer_trusted: bool,
+) {
+    match (client_cert, config) {
+        (Some(cert), Some(config)) => {
+            if let Ok(value) = HeaderValue::from_str(&cert.to_xfcc(&config.fields)) {
+                headers.insert(XFCC_HEADER, value);
+            } else {
+                headers.remove(XFCC_HEADER);
+            }
+        }
+        _ if !peer_trusted => {
+            headers.remove(XFCC_HEADER);
+        }
+        _ => {}
+    }
+}
+
+fn url_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client_cert() -> ClientCertificate {
+        ClientCertificate {
+            hash: "abc123".to_string(),
+            subject: "CN=billing,O=Example".to_string(),
+            uris: vec!["spiffe://example.com/billing".to_string()],
+            dns: vec!["billing.internal".to_string(), "billing".to_string()],
+            cert_pem: "-----BEGIN CERTIFICATE-----\n".to_string(),
+            chain_pem: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_xfcc_renders_selected_fields() {
+        let xfcc = client_cert().to_xfcc(&[XfccField::Hash, XfccField::Subject, XfccField::Uri, XfccField::Dns]);
+        assert_eq!(
+            xfcc,
+            "Hash=abc123;Subject=\"CN=billing,O=Example\";URI=spiffe://example.com/billing;DNS=billing.internal;DNS=billing"
+        );
+
+        let xfcc = client_cert().to_xfcc(&[XfccField::Cert]);
+        assert_eq!(xfcc, "Cert=\"-----BEGIN%20CERTIFICATE-----%0A\"");
+    }
+
+    #[test]
+    fn test_client_supplied_xfcc_is_dropped_from_untrusted_peers() {
+        let config = XfccConfig { fields: vec![XfccField::Hash] };
+
+        let mut headers = HeaderMap::new();
+        headers.insert(XFCC_HEADER, HeaderValue::from_static("Hash=forged"));
+        set_forwarded_client_cert(&mut headers, None, Some(&config), false);
+        assert!(headers.get(XFCC_HEADER).is_none());
+
+        headers.insert(XFCC_HEADER, HeaderValue::from_static("Hash=forged"));
+        set_forwarded_client_cert(&mut headers, Some(&client_cert()), Some(&config), false);
+        assert_eq!(headers.get(XFCC_HEADER).unwrap(), "Hash=abc123");
+
+        headers.insert(XFCC_HEADER, HeaderValue::from_static("Hash=upstream"));
+        set_forwarded_client_cert(&mut headers, None, Some(&config), true);
+        assert_eq!(headers.get(XFCC_HEADER).unwrap(), "Hash=upstream");
+    }
+    #[test]
+    fn test_cipher_suites_restrict_the_provider() {
+        let tls = |cipher_suites: serde_json::Value| -> ListenerTlsConfig {
+            serde_json::from_value(serde_json::json!({
+                "cert_file": "cert.pem",
+                "key_file": "key.pem",
+                "cipher_suites": cipher_suites
+            }))
+            .unwrap()
+        };
+
+        let suites = serde_json::json!(["TLS13_AES_256_GCM_SHA384", "tls13_chacha20_poly1305_sha256"]);
+        let provider = crypto_provider(&tls(suites)).unwrap();
+        let names: Vec<_> = provider.cipher_suites.iter().map(|suite| suite.suite().as_str().unwrap()).collect();
+        assert_eq!(names, vec!["TLS13_AES_256_GCM_SHA384", "TLS13_CHACHA20_POLY1305_SHA256"]);
+
+        assert!(crypto_provider(&tls(serde_json::json!(["TLS_RSA_WITH_RC4_128_SHA"]))).is_err());
+        assert!(!crypto_provider(&tls(serde_json::json!([]))).unwrap().cipher_suites.is_empty());
+    }
+}
@@ -0,0 +1,134 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::Deserialize;
+use std::time::Duration;
+use tracing::{error, warn};
+
+use crate::auth::AuthError;
+use crate::config::TokenRevocationConfig;
+
+const REDIS_OPERATION_TIMEOUT: Duration = Duration::from_millis(500);
+
+type Claims = serde_json::Map<String, serde_json::Value>;
+
+/// Body of `POST /admin/tokens/revoke`: either the token itself, or its `jti` (with the
+/// token's expiry, if known, so the entry doesn't outlive it).
+#[derive(Debug, Deserialize)]
+pub struct RevokeTokenRequest {
+    pub token: Option<String>,
+    pub jti: Option<String>,
+    pub expires_at: Option<u64>,
+}
+
+/// Deny list of revoked bearer tokens. Tokens are identified by their `jti` claim, or by
+/// a SHA-256 of the whole token when they have none. Entries expire along with the token,
+/// so the list only ever holds tokens that would otherwise still be accepted.
+pub struct TokenDenyList {
+    redis: redis::Client,
+    fail_open: bool,
+}
+
+impl TokenDenyList {
+    pub fn new(config: &TokenRevocationConfig, redis_url: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            redis: redis::Client::open(redis_url)?,
+            fail_open: config.fail_open,
+        })
+    }
+
+    /// Called once the token's signature and claims have been verified.
+    pub async fn check(&self, token: &str, claims: &Claims) -> Result<(), AuthError> {
+        let key = redis_key(&revocation_id(token, claims));
+        let lookup = async {
+            let mut conn = self.redis.get_async_connection().await?;
+            redis::cmd("EXISTS").arg(&key).query_async::<_, bool>(&mut conn).await
+        };
+
+        let failure = match tokio::time::timeout(REDIS_OPERATION_TIMEOUT, lookup).await {
+            Ok(Ok(false)) => return Ok(()),
+            Ok(Ok(true)) => return Err(AuthError::RevokedToken),
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => "timed out".to_string(),
+        };
+
+        if self.fail_open {
+            warn!("Token deny list unavailable, accepting token: {}", failure);
+            Ok(())
+        } else {
+            error!("Token deny list unavailable: {}", failure);
+            Err(AuthError::StoreUnavailable)
+        }
+    }
+
+    /// Adds an entry until `expires_at` (unix seconds), or indefinitely for tokens that
+    /// don't expire. Returns false when the token has already expired and needs no entry.
+    pub async fn revoke(&self, revocation_id: &str, expires_at: Option<u64>) -> anyhow::Result<bool> {
+        let now = chrono::Utc::now().timestamp().max(0) as u64;
+        let ttl = match expires_at {
+            Some(expires_at) if expires_at <= now => return Ok(false),
+            Some(expires_at) => Some(expires_at - now),
+            None => None,
+        };
+
+        let mut conn = self.redis.get_async_connection().await?;
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(redis_key(revocation_id)).arg(now);
+        if let Some(ttl) = ttl {
+            cmd.arg("EX").arg(ttl);
+        }
+        cmd.query_async::<_, ()>(&mut conn).await?;
+
+        Ok(true)
+    }
+}
+
+/// `jti:<jti>` when the token carries one, otherwise `sha256:<hex digest of the token>`.
+pub fn revocation_id(token: &str, claims: &Claims) -> String {
+    match claims.get("jti").and_then(|jti| jti.as_str()) {
+        Some(jti) => format!("jti:{}", jti),
+        None => format!(
+            "sha256:{}",
+            openssl::sha::sha256(token.as_bytes())
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>()
+        ),
+    }
+}
+
+/// Reads a token's claims without checking its signature. Only for working out what to
+/// put on the deny list; never for authenticating.
+pub fn unverified_claims(token: &str) -> Option<Claims> {
+    let payload = token.split('.').nth(1)?;
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+}
+
+fn redis_key(revocation_id: &str) -> String {
+    format!("revoked_token:{}", revocation_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(claims: serde_json::Value) -> String {
+        format!(
+            "eyJhbGciOiJIUzI1NiJ9.{}.signature",
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        )
+    }
+
+    #[test]
+    fn test_revocation_id_prefers_jti() {
+        let with_jti = token(serde_json::json!({ "sub": "u1", "jti": "abc-123" }));
+        let claims = unverified_claims(&with_jti).unwrap();
+        assert_eq!(revocation_id(&with_jti, &claims), "jti:abc-123");
+
+        let without_jti = token(serde_json::json!({ "sub": "u1" }));
+        let claims = unverified_claims(&without_jti).unwrap();
+        let id = revocation_id(&without_jti, &claims);
+        assert!(id.starts_with("sha256:"));
+        assert_eq!(id.len(), "sha256:".len() + 64);
+
+        assert!(unverified_claims("not-a-jwt").is_none());
+    }
+}
@@ -0,0 +1,281 @@
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, HeaderName, HeaderValue, Method, Uri},
+};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::RwLock;
+use tracing::{debug, info};
+
+use crate::config::Config;
+use crate::debug_capture::sensitive_headers;
+
+/// Sampling sessions are always time-boxed; requests for longer are clamped.
+const MAX_SAMPLING_DURATION_SECONDS: u64 = 60 * 60;
+const DEFAULT_SAMPLING_DURATION_SECONDS: u64 = 5 * 60;
+
+#[derive(Clone)]
+pub struct TrafficSampler {
+    client: Client,
+    sessions: Arc<RwLock<HashMap<String, SamplingSession>>>,
+    /// Never copied to the sampling target, which is only as trusted as whoever started
+    /// the session
+    stripped_headers: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SamplingSession {
+    pub route: String,
+    pub target_url: String,
+    pub percentage: f64,
+    pub expires_at: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SamplingRequest {
+    pub route: String,
+    pub target_url: String,
+    pub percentage: f64,
+    pub duration_seconds: Option<u64>,
+}
+
+impl TrafficSampler {
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+
+        let mut stripped_headers = sensitive_headers(config);
+        stripped_headers.extend(["host", "connection", "content-length"].iter().map(|name| name.to_string()));
+
+        Ok(Self {
+            client,
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+            stripped_headers,
+        })
+    }
+
+    pub async fn start(&self, request: SamplingRequest) -> anyhow::Result<SamplingSession> {
+        if !(0.0..=100.0).contains(&request.percentage) {
+            return Err(anyhow::anyhow!("percentage must be between 0 and 100"));
+        }
+
+        reqwest::Url::parse(&request.target_url)
+            .map_err(|e| anyhow::anyhow!("Invalid target_url: {}", e))?;
+
+        let duration = request
+            .duration_seconds
+            .unwrap_or(DEFAULT_SAMPLING_DURATION_SECONDS)
+            .min(MAX_SAMPLING_DURATION_SECONDS);
+
+        let session = SamplingSession {
+            route: request.route.clone(),
+            target_url: request.target_url.trim_end_matches('/').to_string(),
+            percentage: request.percentage,
+            expires_at: now() + duration,
+        };
+
+        info!(
+            "Traffic sampling started for route {} ({}% to {}, expires in {}s)",
+            session.route, session.percentage, session.target_url, duration
+        );
+
+        self.sessions.write().await.insert(request.route, session.clone());
+        Ok(session)
+    }
+
+    pub async fn stop(&self, route: &str) -> Option<SamplingSession> {
+        let session = self.sessions.write().await.remove(route);
+        if session.is_some() {
+            info!("Traffic sampling stopped for route {}", route);
+        }
+        session
+    }
+
+    pub async fn list(&self) -> Vec<SamplingSession> {
+        self.purge_expired().await;
+        self.sessions.read().await.values().cloned().collect()
+    }
+
+    /// Mirrors the request to the route's debug target if a session is active and the
+    /// request is selected. The mirrored response is discarded.
+    pub async fn maybe_sample(
+        &self,
+        route: &str,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        body: &Bytes,
+        request_id: &str,
+    ) {
+        let session = match self.sessions.read().await.get(route) {
+            Some(session) => session.clone(),
+            None => return,
+        };
+
+        if session.expires_at <= now() {
+            self.purge_expired().await;
+            return;
+        }
+
+        if rand::random::<f64>() * 100.0 >= session.percentage {
+            return;
+        }
+
+        let target_url = format!(
+            "{}{}",
+            session.target_url,
+            uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("")
+        );
+
+        let method = match reqwest::Method::from_bytes(method.as_str().as_bytes()) {
+            Ok(method) => method,
+            Err(_) => return,
+        };
+
+        let mut request_builder = self.client.request(method, &target_url);
+        for (name, value) in self.forwarded_headers(headers) {
+            request_builder = request_builder.header(name.as_str(), value.as_bytes());
+        }
+
+        request_builder = request_builder
+            .header("X-Request-ID", request_id)
+            .header("X-Gateway-Sampled", "true");
+
+        if !body.is_empty() {
+            request_builder = request_builder.body(body.clone());
+        }
+
+        let request_id = request_id.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = request_builder.send().await {
+                debug!("Sampled request to {} failed: {} (request_id: {})", target_url, e, request_id);
+            }
+        });
+    }
+
+    fn forwarded_headers<'a>(
+        &'a self,
+        headers: &'a HeaderMap,
+    ) -> impl Iterator<Item = (&'a HeaderName, &'a HeaderValue)> {
+        headers
+            .iter()
+            .filter(|(name, _)| !self.stripped_headers.iter().any(|stripped| stripped == name.as_str()))
+    }
+
+    async fn purge_expired(&self) {
+        let now = now();
+        self.sessions.write().await.retain(|route, session| {
+            let active = session.expires_at > now;
+            if !active {
+                info!("Traffic sampling expired for route {}", route);
+            }
+            active
+        });
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::BackendCredentialsConfig;
+    use axum::{routing::any, Router};
+    use tokio::sync::mpsc;
+
+    fn sampler() -> TrafficSampler {
+        let mut config = Config::default_config();
+        config.backends.get_mut("backend_api").unwrap().credentials = Some(BackendCredentialsConfig {
+            header_name: Some("X-Backend-Token".to_string()),
+            header_value_file: None,
+            client_cert_file: None,
+            client_key_file: None,
+            overlap_seconds: None,
+            watch_interval_seconds: None,
+        });
+        TrafficSampler::new(&config).unwrap()
+    }
+
+    fn request_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in [
+            ("authorization", "Bearer secret"),
+            ("cookie", "session=secret"),
+            ("x-api-key", "ak_secret"),
+            ("x-backend-token", "secret"),
+            ("accept", "application/json"),
+        ] {
+            headers.insert(HeaderName::from_static(name), HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_credential_headers_are_stripped() {
+        let sampler = sampler();
+        let headers = request_headers();
+
+        let forwarded: Vec<&str> = sampler.forwarded_headers(&headers).map(|(name, _)| name.as_str()).collect();
+        assert_eq!(forwarded, vec!["accept"]);
+    }
+
+    #[tokio::test]
+    async fn test_sampled_request_reaches_target_without_credentials() {
+        let (received_tx, mut received) = mpsc::unbounded_channel();
+        let target = Router::new().route(
+            "/*path",
+            any(move |headers: HeaderMap| {
+                let received_tx = received_tx.clone();
+                async move {
+                    let _ = received_tx.send(headers);
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, target).await });
+
+        let sampler = sampler();
+        assert!(sampler
+            .start(SamplingRequest {
+                route: "/api/v1/*".to_string(),
+                target_url: format!("http://{}", addr),
+                percentage: 101.0,
+                duration_seconds: None,
+            })
+            .await
+            .is_err());
+        sampler
+            .start(SamplingRequest {
+                route: "/api/v1/*".to_string(),
+                target_url: format!("http://{}", addr),
+                percentage: 100.0,
+                duration_seconds: None,
+            })
+            .await
+            .unwrap();
+
+        let uri: Uri = "/api/v1/users".parse().unwrap();
+        sampler
+            .maybe_sample("/api/v1/*", &Method::GET, &uri, &request_headers(), &Bytes::new(), "req-1")
+            .await;
+
+        let headers = tokio::time::timeout(Duration::from_secs(5), received.recv()).await.unwrap().unwrap();
+        assert_eq!(headers.get("x-request-id").unwrap(), "req-1");
+        assert_eq!(headers.get("accept").unwrap(), "application/json");
+        for name in ["authorization", "cookie", "x-api-key", "x-backend-token"] {
+            assert!(headers.get(name).is_none(), "{} was forwarded", name);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use axum::{
+    body::Bytes,
+    http::{header, HeaderMap},
+};
+use serde_json::{Map, Value};
+use tracing::debug;
+
+use crate::config::{JsonTransform, RouteConfig};
+
+/// Applies the route's request transform to a body about to be forwarded.
+pub fn request_body(route: &RouteConfig, headers: &HeaderMap, body: Bytes) -> Bytes {
+    match route.transform.as_ref().and_then(|transform| transform.request.as_ref()) {
+        Some(transform) if is_json(headers) => apply(transform, body),
+        _ => body,
+    }
+}
+
+/// Applies the route's response transform to a buffered backend response, dropping its
+/// `Content-Length` when the body changes.
+pub fn response_body(route: &RouteConfig, headers: &mut HeaderMap, body: Bytes) -> Bytes {
+    match route.transform.as_ref().and_then(|transform| transform.response.as_ref()) {
+        Some(transform) if is_json(headers) => {
+            let transformed = apply(transform, body.clone());
+            if transformed != body {
+                headers.remove(header::CONTENT_LENGTH);
+            }
+            transformed
+        }
+        _ => body,
+    }
+}
+
+/// `application/json` and `+json` types such as `application/problem+json`
+pub fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|content_type| {
+            let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+            mime == "application/json" || mime.ends_with("+json")
+        })
+        .unwrap_or(false)
+}
+
+/// Bodies that don't parse are passed through as they are.
+fn apply(transform: &JsonTransform, body: Bytes) -> Bytes {
+    let value = match serde_json::from_slice::<Value>(&body) {
+        Ok(value) => value,
+        Err(e) => {
+            debug!("Not transforming body that isn't valid JSON: {}", e);
+            return body;
+        }
+    };
+
+    match serde_json::to_vec(&transform_value(transform, value)) {
+        Ok(transformed) => Bytes::from(transformed),
+        Err(_) => body,
+    }
+}
+
+fn transform_value(transform: &JsonTransform, mut value: Value) -> Value {
+    if let Some(path) = &transform.unwrap {
+        if let Some(inner) = take(&mut value, path) {
+            value = inner;
+        }
+    }
+
+    for (from, to) in &transform.rename {
+        if let Some(moved) = take(&mut value, from) {
+            set(&mut value, to, moved);
+        }
+    }
+
+    for path in &transform.remove {
+        take(&mut value, path);
+    }
+
+    for (path, default) in &transform.defaults {
+        if get(&value, path).is_none() {
+            set(&mut value, path, default.clone());
+        }
+    }
+
+    match &transform.wrap {
+        Some(key) => {
+            let mut envelope = Map::new();
+            envelope.insert(key.clone(), value);
+            Value::Object(envelope)
+        }
+        None => value,
+    }
+}
+
+fn get<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(value, |value, key| value.as_object()?.get(key))
+}
+
+fn get_mut<'a>(value: &'a mut Value, path: &str) -> Option<&'a mut Value> {
+    path.split('.').try_fold(value, |value, key| value.as_object_mut()?.get_mut(key))
+}
+
+fn take(value: &mut Value, path: &str) -> Option<Value> {
+    let (parent, key) = match path.rsplit_once('.') {
+        Some((parent_path, key)) => (get_mut(value, parent_path)?, key),
+        None => (value, path),
+    };
+    parent.as_object_mut()?.remove(key)
+}
+
+/// Creates intermediate objects as needed. Nothing is set if the path runs through a
+/// value that isn't an object.
+fn set(value: &mut Value, path: &str, new: Value) {
+    let mut current = value;
+    let mut keys = path.split('.').peekable();
+    while let Some(key) = keys.next() {
+        let object = match current.as_object_mut() {
+            Some(object) => object,
+            None => return,
+        };
+        if keys.peek().is_none() {
+            object.insert(key.to_string(), new);
+            return;
+        }
+        current = object
+            .entry(key.to_string())
+            .or_insert_with(|| Value::Object(Map::new()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_transform_steps() {
+        let transform: JsonTransform = serde_json::from_value(json!({
+            "unwrap": "data",
+            "rename": { "user.mail": "user.email", "id": "user.id" },
+            "remove": ["internal", "user.password_hash"],
+            "defaults": { "user.locale": "en", "version": 2 },
+            "wrap": "result"
+        }))
+        .unwrap();
+
+        let body = json!({
+            "data": {
+                "id": 7,
+                "internal": true,
+                "version": 3,
+                "user": { "mail": "jdoe@example.com", "password_hash": "x" }
+            },
+            "meta": {}
+        });
+
+        assert_eq!(
+            transform_value(&transform, body),
+            json!({
+                "result": {
+                    "version": 3,
+                    "user": { "id": 7, "email": "jdoe@example.com", "locale": "en" }
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_only_json_bodies_are_transformed() {
+        let transform = JsonTransform {
+            wrap: Some("data".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(apply(&transform, Bytes::from("[1,2]")), Bytes::from(r#"{"data":[1,2]}"#));
+        assert_eq!(apply(&transform, Bytes::from("not json")), Bytes::from("not json"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, "application/problem+json; charset=utf-8".parse().unwrap());
+        assert!(is_json(&headers));
+        headers.insert(header::CONTENT_TYPE, "text/plain".parse().unwrap());
+        assert!(!is_json(&headers));
+    }
+}
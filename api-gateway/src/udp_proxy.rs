@@ -0,0 +1,222 @@
+use dashmap::DashMap;
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::net::UdpSocket;
+use tracing::{debug, error, info, warn};
+
+use crate::config::{Config, UdpProxyConfig};
+use crate::health::HealthChecker;
+use crate::metrics::{GaugeGuard, UDP_PROXY_DATAGRAMS, UDP_PROXY_SESSIONS};
+use crate::tcp_proxy::{server_address, ServerConnection, ServerPool};
+
+const MAX_DATAGRAM_BYTES: usize = 65535;
+
+/// Relays datagrams between clients and a backend's servers. Each client address has
+/// a session with its own upstream socket, so replies find their way back to it.
+pub struct UdpProxy {
+    config: UdpProxyConfig,
+    servers: ServerPool,
+    sessions: DashMap<SocketAddr, Arc<UdpSession>>,
+}
+
+struct UdpSession {
+    upstream: UdpSocket,
+    last_active: Mutex<Instant>,
+    _server: ServerConnection,
+}
+
+impl UdpSession {
+    fn touch(&self) {
+        *self.last_active.lock().unwrap() = Instant::now();
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_active.lock().unwrap().elapsed()
+    }
+}
+
+impl UdpProxy {
+    pub fn new(
+        config: &UdpProxyConfig,
+        gateway_config: &Config,
+        health_checker: Arc<HealthChecker>,
+    ) -> anyhow::Result<Self> {
+        let servers = ServerPool::new(&config.backend, &config.load_balancing, gateway_config, health_checker)
+            .map_err(|e| anyhow::anyhow!("UDP proxy {}: {}", config.name, e))?;
+
+        Ok(Self {
+            config: config.clone(),
+            servers,
+            sessions: DashMap::new(),
+        })
+    }
+
+    pub fn listen_addr(&self) -> &str {
+        &self.config.listen
+    }
+
+    pub async fn serve(self: Arc<Self>, socket: UdpSocket) {
+        info!(
+            "UDP proxy {} listening on {} for backend {}",
+            self.config.name, self.config.listen, self.servers.backend()
+        );
+
+        let socket = Arc::new(socket);
+        let mut buf = vec![0u8; MAX_DATAGRAM_BYTES];
+        loop {
+            let (len, client) = match socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    error!("UDP proxy {} failed to receive: {}", self.config.name, e);
+                    continue;
+                }
+            };
+
+            let session = match self.session(&socket, client).await {
+                Ok(session) => session,
+                Err(e) => {
+                    debug!("UDP proxy {} dropped a datagram from {}: {}", self.config.name, client, e);
+                    continue;
+                }
+            };
+
+            session.touch();
+            match session.upstream.send(&buf[..len]).await {
+                Ok(_) => UDP_PROXY_DATAGRAMS.with_label_values(&[&self.config.name, "upstream"]).inc(),
+                Err(e) => debug!("UDP proxy {} failed to forward from {}: {}", self.config.name, client, e),
+            }
+        }
+    }
+
+    /// The client's session, opening one to the next server in line if it has none.
+    async fn session(self: &Arc<Self>, socket: &Arc<UdpSocket>, client: SocketAddr) -> anyhow::Result<Arc<UdpSession>> {
+        if let Some(session) = self.sessions.get(&client) {
+            return Ok(session.clone());
+        }
+        if self.sessions.len() >= self.config.max_sessions {
+            return Err(anyhow::anyhow!("Session limit of {} reached", self.config.max_sessions));
+        }
+
+        let session = Arc::new(self.connect().await?);
+        self.sessions.insert(client, session.clone());
+        tokio::spawn(self.clone().relay_replies(socket.clone(), client, session.clone()));
+        Ok(session)
+    }
+
+    async fn connect(&self) -> anyhow::Result<UdpSession> {
+        let candidates = self.servers.candidates().await;
+        if candidates.is_empty() {
+            return Err(anyhow::anyhow!("No healthy servers available for backend: {}", self.servers.backend()));
+        }
+
+        for server in candidates {
+            match connect_upstream(server_address(&server)).await {
+                Ok(upstream) => {
+                    return Ok(UdpSession {
+                        upstream,
+                        last_active: Mutex::new(Instant::now()),
+                        _server: self.servers.track(&server),
+                    })
+                }
+                Err(e) => warn!("UDP proxy {} failed to open a socket to {}: {}", self.config.name, server, e),
+            }
+        }
+
+        Err(anyhow::anyhow!("No server of backend {} could be reached", self.servers.backend()))
+    }
+
+    /// Sends the server's replies to the client until the session goes idle or the
+    /// server's socket fails, e.g. because it's refusing datagrams.
+    async fn relay_replies(self: Arc<Self>, socket: Arc<UdpSocket>, client: SocketAddr, session: Arc<UdpSession>) {
+        let _open = GaugeGuard::new(UDP_PROXY_SESSIONS.with_label_values(&[&self.config.name]));
+        let idle_timeout = Duration::from_secs(self.config.idle_timeout_seconds);
+        let mut buf = vec![0u8; MAX_DATAGRAM_BYTES];
+
+        loop {
+            let wait = idle_timeout.saturating_sub(session.idle_for());
+            match tokio::time::timeout(wait, session.upstream.recv(&mut buf)).await {
+                Ok(Ok(len)) => {
+                    session.touch();
+                    match socket.send_to(&buf[..len], client).await {
+                        Ok(_) => UDP_PROXY_DATAGRAMS.with_label_values(&[&self.config.name, "downstream"]).inc(),
+                        Err(e) => debug!("UDP proxy {} failed to reply to {}: {}", self.config.name, client, e),
+                    }
+                }
+                Ok(Err(e)) => {
+                    debug!("UDP proxy {} session for {} failed: {}", self.config.name, client, e);
+                    break;
+                }
+                // Datagrams from the client count as activity too
+                Err(_) if session.idle_for() < idle_timeout => continue,
+                Err(_) => break,
+            }
+        }
+
+        self.sessions.remove_if(&client, |_, current| Arc::ptr_eq(current, &session));
+    }
+}
+
+async fn connect_upstream(address: &str) -> anyhow::Result<UdpSocket> {
+    let server = tokio::net::lookup_host(address)
+        .await?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("{} didn't resolve to any address", address))?;
+    let local: SocketAddr = if server.is_ipv4() { ([0, 0, 0, 0], 0).into() } else { ([0u16; 8], 0).into() };
+
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(server).await?;
+    Ok(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LoadBalancingStrategy;
+
+    #[tokio::test]
+    async fn test_relays_replies_to_the_sending_client() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            loop {
+                let (len, from) = upstream.recv_from(&mut buf).await.unwrap();
+                upstream.send_to(&buf[..len], from).await.unwrap();
+            }
+        });
+
+        let mut config = Config::default_config();
+        config.backends.get_mut("backend_api").unwrap().servers = vec![upstream_addr.to_string()];
+        let udp_config = UdpProxyConfig {
+            name: "echo".to_string(),
+            listen: "127.0.0.1:0".to_string(),
+            backend: "backend_api".to_string(),
+            load_balancing: LoadBalancingStrategy::RoundRobin,
+            idle_timeout_seconds: 60,
+            max_sessions: 16,
+        };
+        let config = Arc::new(config);
+        let health_checker = Arc::new(HealthChecker::new(config.clone()));
+        let proxy = Arc::new(UdpProxy::new(&udp_config, &config, health_checker).unwrap());
+
+        let socket = UdpSocket::bind(proxy.listen_addr()).await.unwrap();
+        let proxy_addr = socket.local_addr().unwrap();
+        tokio::spawn(proxy.clone().serve(socket));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(proxy_addr).await.unwrap();
+        for message in [&b"one"[..], &b"two"[..]] {
+            client.send(message).await.unwrap();
+            let mut reply = [0u8; 64];
+            let len = tokio::time::timeout(Duration::from_secs(5), client.recv(&mut reply))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(&reply[..len], message);
+        }
+        assert_eq!(proxy.sessions.len(), 1);
+    }
+}
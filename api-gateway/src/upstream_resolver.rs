@@ -0,0 +1,231 @@
+use dashmap::DashMap;
+use hickory_resolver::{
+    config::{ResolverConfig, ResolverOpts},
+    TokioAsyncResolver,
+};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::warn;
+
+use crate::config::{AddressFamilyPreference, UpstreamDnsConfig, UpstreamNetworkConfig};
+use crate::metrics::DNS_RESOLUTION_DURATION;
+
+/// Caches lookups of backend hostnames for their TTL, kept within the configured bounds,
+/// and remembers failed lookups for a while, so requests don't each wait on DNS.
+#[derive(Clone)]
+pub struct DnsCache {
+    resolver: TokioAsyncResolver,
+    min_ttl: Duration,
+    max_ttl: Duration,
+    negative_ttl: Duration,
+    max_entries: usize,
+    entries: Arc<DashMap<String, CachedLookup>>,
+}
+
+#[derive(Clone)]
+struct CachedLookup {
+    result: Result<Vec<IpAddr>, String>,
+    expires: Instant,
+}
+
+impl DnsCache {
+    pub fn new(config: &UpstreamDnsConfig) -> Self {
+        let (resolver_config, mut options) = hickory_resolver::system_conf::read_system_conf().unwrap_or_else(|e| {
+            warn!("Failed to read the system DNS configuration, using public resolvers: {}", e);
+            (ResolverConfig::default(), ResolverOpts::default())
+        });
+        // Answers are cached here instead, where the TTL bounds apply
+        options.cache_size = 0;
+
+        Self {
+            resolver: TokioAsyncResolver::tokio(resolver_config, options),
+            min_ttl: Duration::from_secs(config.min_ttl_seconds),
+            max_ttl: Duration::from_secs(config.max_ttl_seconds.max(config.min_ttl_seconds)),
+            negative_ttl: Duration::from_secs(config.negative_ttl_seconds),
+            max_entries: config.max_entries,
+            entries: Arc::new(DashMap::new()),
+        }
+    }
+
+    pub async fn lookup(&self, host: &str) -> Result<Vec<IpAddr>, String> {
+        if let Some(entry) = self.entries.get(host).filter(|entry| entry.expires > Instant::now()) {
+            return entry.result.clone();
+        }
+
+        let start = Instant::now();
+        let (result, ttl) = match self.resolver.lookup_ip(host).await {
+            Ok(lookup) => {
+                let ttl = lookup.valid_until().saturating_duration_since(Instant::now());
+                (Ok(lookup.iter().collect()), ttl.clamp(self.min_ttl, self.max_ttl))
+            }
+            Err(e) => (Err(format!("Failed to resolve {}: {}", host, e)), self.negative_ttl),
+        };
+        let outcome = if result.is_ok() { "resolved" } else { "failed" };
+        DNS_RESOLUTION_DURATION
+            .with_label_values(&[outcome])
+            .observe(start.elapsed().as_secs_f64());
+
+        if !ttl.is_zero() {
+            if self.entries.len() >= self.max_entries {
+                let now = Instant::now();
+                self.entries.retain(|_, entry| entry.expires > now);
+            }
+            if self.entries.len() < self.max_entries {
+                let expires = Instant::now() + ttl;
+                self.entries.insert(host.to_string(), CachedLookup { result: result.clone(), expires });
+            }
+        }
+        result
+    }
+
+    async fn lookup_socket_addrs(&self, host: &str) -> Result<Vec<SocketAddr>, String> {
+        Ok(self.lookup(host).await?.into_iter().map(|ip| SocketAddr::new(ip, 0)).collect())
+    }
+}
+
+impl Resolve for DnsCache {
+    fn resolve(&self, name: Name) -> Resolving {
+        let cache = self.clone();
+        Box::pin(async move {
+            let addrs = cache.lookup_socket_addrs(name.as_str()).await?;
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// DNS resolver that filters and orders upstream addresses by IP family.
+///
+/// The HTTP connector races the first address family it sees against the rest after a
+/// short delay (happy eyeballs), so putting the preferred family first decides which
+/// one wins when both are reachable. With happy eyeballs disabled only the preferred
+/// family is returned, unless the host has no addresses of that family.
+pub struct AddressFamilyResolver {
+    preference: AddressFamilyPreference,
+    happy_eyeballs: bool,
+    /// Looked up through the system resolver without one
+    dns_cache: Option<DnsCache>,
+}
+
+impl AddressFamilyResolver {
+    pub fn new(network: &UpstreamNetworkConfig, dns_cache: Option<&DnsCache>) -> Self {
+        Self {
+            preference: network.address_family.clone(),
+            happy_eyeballs: network.happy_eyeballs.unwrap_or(true),
+            dns_cache: dns_cache.cloned(),
+        }
+    }
+}
+
+impl Resolve for AddressFamilyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let preference = self.preference.clone();
+        let happy_eyeballs = self.happy_eyeballs;
+        let host = name.as_str().to_string();
+        let dns_cache = self.dns_cache.clone();
+
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = match dns_cache {
+                Some(dns_cache) => dns_cache.lookup_socket_addrs(&host).await?,
+                None => tokio::net::lookup_host((host.as_str(), 0)).await?.collect(),
+            };
+            let ordered = order_addresses(addrs, &preference, happy_eyeballs);
+
+            if ordered.is_empty() {
+                return Err(format!("No addresses matching {:?} for {}", preference, host).into());
+            }
+
+            Ok(Box::new(ordered.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn order_addresses(
+    addrs: Vec<SocketAddr>,
+    preference: &AddressFamilyPreference,
+    happy_eyeballs: bool,
+) -> Vec<SocketAddr> {
+    let (v4, v6): (Vec<_>, Vec<_>) = addrs.iter().copied().partition(|addr| addr.is_ipv4());
+
+    let (preferred, fallback) = match preference {
+        AddressFamilyPreference::Auto => return addrs,
+        AddressFamilyPreference::Ipv4Only => return v4,
+        AddressFamilyPreference::Ipv6Only => return v6,
+        AddressFamilyPreference::PreferIpv4 => (v4, v6),
+        AddressFamilyPreference::PreferIpv6 => (v6, v4),
+    };
+
+    if happy_eyeballs || preferred.is_empty() {
+        preferred.into_iter().chain(fallback).collect()
+    } else {
+        preferred
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs() -> Vec<SocketAddr> {
+        vec![
+            "[2001:db8::1]:0".parse().unwrap(),
+            "192.0.2.1:0".parse().unwrap(),
+        ]
+    }
+
+    #[test]
+    fn test_family_only_filters() {
+        let v4 = order_addresses(addrs(), &AddressFamilyPreference::Ipv4Only, true);
+        assert_eq!(v4, vec!["192.0.2.1:0".parse::<SocketAddr>().unwrap()]);
+
+        let v6 = order_addresses(addrs(), &AddressFamilyPreference::Ipv6Only, true);
+        assert_eq!(v6, vec!["[2001:db8::1]:0".parse::<SocketAddr>().unwrap()]);
+    }
+
+    #[test]
+    fn test_preference_orders_and_respects_happy_eyeballs() {
+        let raced = order_addresses(addrs(), &AddressFamilyPreference::PreferIpv4, true);
+        assert_eq!(raced.len(), 2);
+        assert!(raced[0].is_ipv4());
+
+        let strict = order_addresses(addrs(), &AddressFamilyPreference::PreferIpv6, false);
+        assert_eq!(strict.len(), 1);
+        assert!(strict[0].is_ipv6());
+    }
+
+    #[test]
+    fn test_strict_preference_falls_back_when_family_missing() {
+        let v4_only_host = vec!["192.0.2.1:0".parse().unwrap()];
+        let result = order_addresses(v4_only_host, &AddressFamilyPreference::PreferIpv6, false);
+        assert_eq!(result.len(), 1);
+        assert!(result[0].is_ipv4());
+    }
+
+    #[tokio::test]
+    async fn test_cached_answers_skip_lookups_until_expired() {
+        let cache = DnsCache::new(&UpstreamDnsConfig {
+            min_ttl_seconds: 0,
+            max_ttl_seconds: 60,
+            negative_ttl_seconds: 5,
+            max_entries: 16,
+        });
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        cache.entries.insert(
+            "backend.invalid".to_string(),
+            CachedLookup { result: Ok(vec![ip]), expires: Instant::now() + Duration::from_secs(60) },
+        );
+        assert_eq!(cache.lookup("backend.invalid").await, Ok(vec![ip]));
+
+        cache.entries.insert(
+            "failed.invalid".to_string(),
+            CachedLookup {
+                result: Err("cached failure".to_string()),
+                expires: Instant::now() + Duration::from_secs(5),
+            },
+        );
+        assert_eq!(cache.lookup("failed.invalid").await, Err("cached failure".to_string()));
+    }
+}
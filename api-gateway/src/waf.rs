@@ -0,0 +1,198 @@
+use axum::http::{header, HeaderMap, Uri};
+use regex::Regex;
+use serde::Serialize;
+
+use crate::config::{RouteConfig, WafConfig, WafMode};
+
+/// Where in the request a rule is matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+    Path,
+    Query,
+    Body,
+}
+
+impl Target {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Target::Path => "path",
+            Target::Query => "query",
+            Target::Body => "body",
+        }
+    }
+}
+
+const ALL: &[Target] = &[Target::Path, Target::Query, Target::Body];
+const URI: &[Target] = &[Target::Path, Target::Query];
+
+/// The built-in rule set: id, pattern, and the parts of the request it applies to.
+const RULES: &[(&str, &str, &[Target])] = &[
+    ("sqli-union", r"(?i)\bunion\b[\s(]+(all\s+)?select\b", ALL),
+    ("sqli-tautology", r"(?i)'\s*(or|and)\s+('[^']*'|\d+)\s*=\s*('[^']*'|\d+)", ALL),
+    ("sqli-comment", r"(?i)'\s*(--|#|/\*)", ALL),
+    ("sqli-stacked", r"(?i);\s*(drop|truncate|alter)\s+table\b", ALL),
+    ("sqli-time-based", r"(?i)\b(sleep|benchmark|pg_sleep)\s*\(|\bwaitfor\s+delay\b", ALL),
+    ("xss-script", r"(?i)<\s*/?\s*script\b", ALL),
+    ("xss-event-handler", r"(?i)<[^>]*\bon[a-z]+\s*=", ALL),
+    ("xss-javascript-uri", r"(?i)javascript\s*:", ALL),
+    ("path-traversal", r"(^|[/\\])\.\.([/\\]|$)", URI),
+    ("sensitive-file", r"(?i)(/etc/(passwd|shadow)|\bwin\.ini\b|\bboot\.ini\b)", ALL),
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleHit {
+    pub rule: &'static str,
+    pub target: Target,
+}
+
+pub struct Waf {
+    config: WafConfig,
+    rules: Vec<(&'static str, Regex, &'static [Target])>,
+}
+
+impl Waf {
+    pub fn new(config: &WafConfig) -> anyhow::Result<Self> {
+        let rules = RULES
+            .iter()
+            .filter(|(id, _, _)| !config.disabled_rules.iter().any(|disabled| disabled == id))
+            .map(|(id, pattern, targets)| {
+                Regex::new(pattern)
+                    .map(|regex| (*id, regex, *targets))
+                    .map_err(|e| anyhow::anyhow!("Invalid WAF rule {}: {}", id, e))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            config: config.clone(),
+            rules,
+        })
+    }
+
+    /// The mode the route is inspected in, or `None` when it isn't inspected.
+    pub fn mode(&self, route: Option<&RouteConfig>) -> Option<WafMode> {
+        match route.and_then(|route| route.waf.as_ref()) {
+            Some(route_waf) if !route_waf.enabled => None,
+            Some(route_waf) => Some(route_waf.mode.unwrap_or(self.config.mode)),
+            None => Some(self.config.mode),
+        }
+    }
+
+    pub fn max_body_bytes(&self) -> usize {
+        self.config.max_body_bytes
+    }
+
+    /// Every rule matching the request, at most once per rule. Values are matched after
+    /// percent-decoding, twice over so double-encoded payloads are caught too.
+    pub fn inspect(&self, route: Option<&RouteConfig>, uri: &Uri, headers: &HeaderMap, body: &[u8]) -> Vec<RuleHit> {
+        let route_disabled = route
+            .and_then(|route| route.waf.as_ref())
+            .map(|route_waf| route_waf.disabled_rules.as_slice())
+            .unwrap_or_default();
+
+        let path = decode(&decode(uri.path(), false), false);
+        let query = uri.query().map(|query| decode(&decode(query, true), true)).unwrap_or_default();
+        let form = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map_or(false, |content_type| content_type.starts_with("application/x-www-form-urlencoded"));
+        let body = match std::str::from_utf8(body) {
+            Ok(text) if form => decode(text, true),
+            Ok(text) => text.to_string(),
+            Err(_) => String::new(),
+        };
+
+        let values = [(Target::Path, path), (Target::Query, query), (Target::Body, body)];
+        self.rules
+            .iter()
+            .filter(|(id, _, _)| !route_disabled.iter().any(|disabled| disabled == id))
+            .filter_map(|(id, regex, targets)| {
+                values
+                    .iter()
+                    .find(|(target, value)| targets.contains(target) && !value.is_empty() && regex.is_match(value))
+                    .map(|(target, _)| RuleHit { rule: id, target: *target })
+            })
+            .collect()
+    }
+}
+
+/// Percent-decodes `value`, and `+` as a space in form encoding. Invalid escapes are
+/// kept as they are.
+fn decode(value: &str, form: bool) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() && bytes[i + 1].is_ascii_hexdigit() && bytes[i + 2].is_ascii_hexdigit() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+                decoded.push(u8::from_str_radix(hex, 16).unwrap_or_default());
+                i += 3;
+                continue;
+            }
+            b'+' if form => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn waf(config: serde_json::Value) -> Waf {
+        Waf::new(&serde_json::from_value(config).unwrap()).unwrap()
+    }
+
+    fn rules(hits: Vec<RuleHit>) -> Vec<(&'static str, Target)> {
+        hits.into_iter().map(|hit| (hit.rule, hit.target)).collect()
+    }
+
+    #[test]
+    fn test_rule_hits() {
+        let waf = waf(json!({}));
+        let inspect = |uri: &str, body: &str| rules(waf.inspect(None, &uri.parse().unwrap(), &HeaderMap::new(), body.as_bytes()));
+
+        assert!(inspect("/api/users?name=O%27Brien&page=2", r#"{"bio": "Select a union rep"}"#).is_empty());
+        assert_eq!(inspect("/api/users?id=1%27%20OR%201%3D1", ""), vec![("sqli-tautology", Target::Query)]);
+        assert_eq!(inspect("/api/users?q=1+UNION+SELECT+password", ""), vec![("sqli-union", Target::Query)]);
+        assert_eq!(inspect("/api/files/%252e%252e/secrets", ""), vec![("path-traversal", Target::Path)]);
+        assert_eq!(
+            inspect("/api/comments", r#"{"text": "<img src=x onerror=alert(1)>"}"#),
+            vec![("xss-event-handler", Target::Body)]
+        );
+        assert_eq!(inspect("/api/comments", "<script>alert(1)</script>"), vec![("xss-script", Target::Body)]);
+    }
+
+    #[test]
+    fn test_route_settings() {
+        let waf = waf(json!({ "mode": "block", "disabled_rules": ["xss-script"] }));
+        let route = |route_waf: serde_json::Value| -> RouteConfig {
+            serde_json::from_value(json!({
+                "path": "/api/cms",
+                "backend": "cms",
+                "load_balancing": "round_robin",
+                "auth_required": false,
+                "waf": route_waf
+            }))
+            .unwrap()
+        };
+
+        assert_eq!(waf.mode(None), Some(WafMode::Block));
+        assert_eq!(waf.mode(Some(&route(json!({ "enabled": false })))), None);
+        assert_eq!(waf.mode(Some(&route(json!({ "mode": "detect" })))), Some(WafMode::Detect));
+
+        let cms = route(json!({ "disabled_rules": ["xss-event-handler"] }));
+        let body = br#"<script></script><b onclick="x()">"#;
+        assert!(waf.inspect(Some(&cms), &"/api/cms".parse().unwrap(), &HeaderMap::new(), body).is_empty());
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode("a%20b+c%zz%", false), "a b+c%zz%");
+        assert_eq!(decode("a%20b+c", true), "a b c");
+    }
+}
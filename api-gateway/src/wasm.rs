@@ -0,0 +1,856 @@
+//! A host for proxy-wasm plugins (ABI 0.2.x). Plugins run as route filters: each route
+//! entry naming a plugin gets its own VM, configured with the entry's `settings`, and
+//! every request on the route gets a stream context in it.
+//!
+//! Only the header phases are supported: plugins can read and change request and
+//! response headers (including the `:method`, `:path`, `:authority` and `:status`
+//! pseudo-headers) and answer requests with `proxy_send_local_response`. Body, shared
+//! data, HTTP call and metric hostcalls trap, which fails the request like any other
+//! plugin error.
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use rand::RngCore;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::{debug, error, info, trace, warn};
+use wasmtime::{
+    Caller, Engine, Instance, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, WasmParams, WasmResults,
+};
+
+use crate::config::WasmPluginConfig;
+use crate::filters::{
+    apply_request_pairs, apply_response_pairs, header_map, request_pairs, response_pairs, FilterAction, FilterContext,
+    FilterFuture, HeaderPairs, RequestFilter, ResponseFilter,
+};
+
+const STATUS_OK: i32 = 0;
+const STATUS_NOT_FOUND: i32 = 1;
+const STATUS_BAD_ARGUMENT: i32 = 2;
+const STATUS_INTERNAL_FAILURE: i32 = 10;
+
+const MAP_REQUEST_HEADERS: i32 = 0;
+const MAP_REQUEST_TRAILERS: i32 = 1;
+const MAP_RESPONSE_HEADERS: i32 = 2;
+const MAP_RESPONSE_TRAILERS: i32 = 3;
+
+const BUFFER_VM_CONFIGURATION: i32 = 6;
+const BUFFER_PLUGIN_CONFIGURATION: i32 = 7;
+
+const ACTION_PAUSE: i32 = 1;
+
+const ROOT_CONTEXT_ID: i32 = 1;
+
+/// Streams whose response never came back, e.g. because the client went away, are
+/// forgotten after this long.
+const STREAM_TTL: Duration = Duration::from_secs(300);
+
+/// An engine that meters plugins, so `fuel_per_call` can stop runaway ones.
+pub fn engine() -> anyhow::Result<Engine> {
+    let mut config = wasmtime::Config::new();
+    config.consume_fuel(true);
+    Engine::new(&config)
+}
+
+struct LocalResponse {
+    status: u16,
+    headers: HeaderPairs,
+    body: Vec<u8>,
+}
+
+impl IntoResponse for LocalResponse {
+    fn into_response(self) -> Response {
+        let mut response = Response::new(Body::from(self.body));
+        *response.status_mut() = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        *response.headers_mut() = header_map(&self.headers);
+        response
+    }
+}
+
+/// What hostcalls see and change while the plugin runs.
+struct HostState {
+    plugin: String,
+    limits: StoreLimits,
+    plugin_configuration: Vec<u8>,
+    request_headers: HeaderPairs,
+    request_trailers: HeaderPairs,
+    response_headers: HeaderPairs,
+    response_trailers: HeaderPairs,
+    local_response: Option<LocalResponse>,
+}
+
+impl HostState {
+    fn map(&mut self, map_type: i32) -> Option<&mut HeaderPairs> {
+        match map_type {
+            MAP_REQUEST_HEADERS => Some(&mut self.request_headers),
+            MAP_REQUEST_TRAILERS => Some(&mut self.request_trailers),
+            MAP_RESPONSE_HEADERS => Some(&mut self.response_headers),
+            MAP_RESPONSE_TRAILERS => Some(&mut self.response_trailers),
+            _ => None,
+        }
+    }
+}
+
+/// One instance of the plugin, with its root context configured.
+struct PluginVm {
+    store: Store<HostState>,
+    instance: Instance,
+    fuel_per_call: u64,
+    next_context_id: i32,
+}
+
+impl PluginVm {
+    fn new(plugin: &PluginShared, plugin_configuration: Vec<u8>) -> anyhow::Result<Self> {
+        let state = HostState {
+            plugin: plugin.config.name.clone(),
+            limits: StoreLimitsBuilder::new().memory_size(plugin.config.max_memory_bytes).build(),
+            plugin_configuration,
+            request_headers: Vec::new(),
+            request_trailers: Vec::new(),
+            response_headers: Vec::new(),
+            response_trailers: Vec::new(),
+            local_response: None,
+        };
+        let mut store = Store::new(plugin.module.engine(), state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(plugin.config.fuel_per_call)?;
+        let instance = plugin.linker.instantiate(&mut store, &plugin.module)?;
+
+        let mut vm = Self {
+            store,
+            instance,
+            fuel_per_call: plugin.config.fuel_per_call,
+            next_context_id: ROOT_CONTEXT_ID + 1,
+        };
+        // WASI reactors set up their runtime in `_initialize`
+        if vm.call::<(), ()>("_initialize", ())?.is_none() {
+            vm.call::<(), ()>("_start", ())?;
+        }
+        vm.call::<(i32, i32), ()>("proxy_on_context_create", (ROOT_CONTEXT_ID, 0))?;
+        if vm.call::<(i32, i32), i32>("proxy_on_vm_start", (ROOT_CONTEXT_ID, 0))? == Some(0) {
+            return Err(anyhow::anyhow!("Plugin failed to start"));
+        }
+        let configuration_size = vm.store.data().plugin_configuration.len() as i32;
+        if vm.call::<(i32, i32), i32>("proxy_on_configure", (ROOT_CONTEXT_ID, configuration_size))? == Some(0) {
+            return Err(anyhow::anyhow!("Plugin rejected its configuration"));
+        }
+        Ok(vm)
+    }
+
+    /// Calls an export with a fresh fuel allowance. `None` when the plugin doesn't export
+    /// it, which for the optional callbacks means it has nothing to do.
+    fn call<P: WasmParams, R: WasmResults>(&mut self, name: &str, params: P) -> anyhow::Result<Option<R>> {
+        let func = match self.instance.get_typed_func::<P, R>(&mut self.store, name) {
+            Ok(func) => func,
+            Err(_) => return Ok(None),
+        };
+        self.store.set_fuel(self.fuel_per_call)?;
+        Ok(Some(func.call(&mut self.store, params)?))
+    }
+
+    fn on_request_headers(&mut self, headers: HeaderPairs) -> anyhow::Result<(i32, HeaderPairs, Option<LocalResponse>)> {
+        let context_id = self.next_context_id;
+        self.next_context_id = self.next_context_id.checked_add(1).unwrap_or(ROOT_CONTEXT_ID + 1);
+        self.call::<(i32, i32), ()>("proxy_on_context_create", (context_id, ROOT_CONTEXT_ID))?;
+
+        let state = self.store.data_mut();
+        let num_headers = headers.len() as i32;
+        state.request_headers = headers;
+        state.local_response = None;
+        let action = self.call::<(i32, i32, i32), i32>("proxy_on_request_headers", (context_id, num_headers, 0))?;
+        if action == Some(ACTION_PAUSE) {
+            debug!("Plugin {} paused a request, continuing it", self.store.data().plugin);
+        }
+
+        let state = self.store.data_mut();
+        Ok((context_id, std::mem::take(&mut state.request_headers), state.local_response.take()))
+    }
+
+    fn on_response_headers(
+        &mut self,
+        context_id: i32,
+        request_headers: HeaderPairs,
+        headers: HeaderPairs,
+    ) -> anyhow::Result<(HeaderPairs, Option<LocalResponse>)> {
+        let state = self.store.data_mut();
+        let num_headers = headers.len() as i32;
+        state.request_headers = request_headers;
+        state.response_headers = headers;
+        state.local_response = None;
+        self.call::<(i32, i32, i32), i32>("proxy_on_response_headers", (context_id, num_headers, 0))?;
+
+        let state = self.store.data_mut();
+        Ok((std::mem::take(&mut state.response_headers), state.local_response.take()))
+    }
+
+    fn finish(&mut self, context_id: i32) -> anyhow::Result<()> {
+        self.call::<i32, i32>("proxy_on_done", context_id)?;
+        self.call::<i32, ()>("proxy_on_delete", context_id)?;
+        let state = self.store.data_mut();
+        state.request_headers.clear();
+        state.response_headers.clear();
+        Ok(())
+    }
+}
+
+/// A request between its request and response phase.
+struct Stream {
+    vm: Arc<Mutex<PluginVm>>,
+    context_id: i32,
+    request_headers: HeaderPairs,
+    started: Instant,
+}
+
+struct PluginShared {
+    config: WasmPluginConfig,
+    module: Module,
+    linker: Linker<HostState>,
+    /// One VM per plugin configuration in use
+    vms: Mutex<HashMap<Vec<u8>, Arc<Mutex<PluginVm>>>>,
+    /// Keyed by request ID
+    streams: Mutex<HashMap<String, Stream>>,
+}
+
+impl PluginShared {
+    fn vm(&self, configuration: &[u8]) -> anyhow::Result<Arc<Mutex<PluginVm>>> {
+        let mut vms = self.vms.lock().unwrap();
+        if let Some(vm) = vms.get(configuration) {
+            return Ok(vm.clone());
+        }
+        let vm = Arc::new(Mutex::new(PluginVm::new(self, configuration.to_vec())?));
+        vms.insert(configuration.to_vec(), vm.clone());
+        Ok(vm)
+    }
+
+    /// A VM that trapped may be left in any state, so the next request gets a new one.
+    fn discard(&self, configuration: &[u8]) {
+        self.vms.lock().unwrap().remove(configuration);
+    }
+
+    fn request_headers(
+        &self,
+        configuration: &[u8],
+        request_id: String,
+        headers: HeaderPairs,
+    ) -> anyhow::Result<RequestOutcome> {
+        let vm = self.vm(configuration)?;
+        let mut guard = vm.lock().unwrap();
+        let (context_id, headers, local_response) = guard
+            .on_request_headers(headers)
+            .inspect_err(|_| self.discard(configuration))?;
+
+        if local_response.is_some() || request_id.is_empty() {
+            guard.finish(context_id)?;
+        } else {
+            drop(guard);
+            let mut streams = self.streams.lock().unwrap();
+            streams.retain(|_, stream| stream.started.elapsed() < STREAM_TTL);
+            streams.insert(
+                request_id,
+                Stream {
+                    vm,
+                    context_id,
+                    request_headers: headers.clone(),
+                    started: Instant::now(),
+                },
+            );
+        }
+
+        Ok(match local_response {
+            Some(local_response) => RequestOutcome::Respond(local_response),
+            None => RequestOutcome::Continue(headers),
+        })
+    }
+
+    fn response_headers(
+        &self,
+        configuration: &[u8],
+        request_id: &str,
+        headers: HeaderPairs,
+    ) -> anyhow::Result<Option<(HeaderPairs, Option<LocalResponse>)>> {
+        let stream = match self.streams.lock().unwrap().remove(request_id) {
+            Some(stream) => stream,
+            None => return Ok(None),
+        };
+        let mut vm = stream.vm.lock().unwrap();
+        let result = vm
+            .on_response_headers(stream.context_id, stream.request_headers, headers)
+            .and_then(|result| vm.finish(stream.context_id).map(|_| result));
+        if result.is_err() {
+            self.discard(configuration);
+        }
+        result.map(Some)
+    }
+}
+
+enum RequestOutcome {
+    Continue(HeaderPairs),
+    Respond(LocalResponse),
+}
+
+/// A loaded plugin, usable as both a request and a response filter.
+pub struct WasmPlugin {
+    shared: Arc<PluginShared>,
+}
+
+impl WasmPlugin {
+    pub fn load(engine: &Engine, config: &WasmPluginConfig) -> anyhow::Result<Self> {
+        let module = Module::from_file(engine, &config.path)
+            .map_err(|e| anyhow::anyhow!("Failed to load WASM plugin {} from {}: {}", config.name, config.path, e))?;
+        Self::from_module(module, config)
+    }
+
+    fn from_module(module: Module, config: &WasmPluginConfig) -> anyhow::Result<Self> {
+        let abi_supported = module
+            .exports()
+            .any(|export| matches!(export.name(), "proxy_abi_version_0_2_0" | "proxy_abi_version_0_2_1"));
+        if !abi_supported {
+            return Err(anyhow::anyhow!("WASM plugin {} doesn't implement proxy-wasm ABI 0.2", config.name));
+        }
+
+        let mut linker = Linker::new(module.engine());
+        define_hostcalls(&mut linker)?;
+        define_wasi(&mut linker)?;
+        linker.define_unknown_imports_as_traps(&module)?;
+
+        info!("Loaded WASM plugin {}", config.name);
+        Ok(Self {
+            shared: Arc::new(PluginShared {
+                config: config.clone(),
+                module,
+                linker,
+                vms: Mutex::new(HashMap::new()),
+                streams: Mutex::new(HashMap::new()),
+            }),
+        })
+    }
+
+    fn failed(&self, error: anyhow::Error) -> Option<Response> {
+        error!("WASM plugin {} failed: {}", self.shared.config.name, error);
+        if self.shared.config.fail_open {
+            None
+        } else {
+            Some(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+impl RequestFilter for WasmPlugin {
+    fn on_request<'a>(
+        &'a self,
+        context: &'a FilterContext<'a>,
+        request: &'a mut Request,
+    ) -> FilterFuture<'a, FilterAction> {
+        Box::pin(async move {
+            let shared = self.shared.clone();
+            let configuration = configuration_bytes(context.settings);
+            let request_id = context.request_id.to_string();
+            let headers = request_pairs(request);
+            let outcome =
+                tokio::task::spawn_blocking(move || shared.request_headers(&configuration, request_id, headers))
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|outcome| outcome);
+
+            match outcome {
+                Ok(RequestOutcome::Continue(headers)) => {
+                    apply_request_pairs(request, &headers);
+                    FilterAction::Continue
+                }
+                Ok(RequestOutcome::Respond(local_response)) => FilterAction::Respond(local_response.into_response()),
+                Err(e) => self.failed(e).map_or(FilterAction::Continue, FilterAction::Respond),
+            }
+        })
+    }
+}
+
+impl ResponseFilter for WasmPlugin {
+    fn on_response<'a>(
+        &'a self,
+        context: &'a FilterContext<'a>,
+        response: &'a mut Response,
+    ) -> FilterFuture<'a, ()> {
+        Box::pin(async move {
+            let shared = self.shared.clone();
+            let configuration = configuration_bytes(context.settings);
+            let request_id = context.request_id.to_string();
+            let headers = response_pairs(response);
+            let outcome =
+                tokio::task::spawn_blocking(move || shared.response_headers(&configuration, &request_id, headers))
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|outcome| outcome);
+
+            match outcome {
+                Ok(Some((_, Some(local_response)))) => *response = local_response.into_response(),
+                Ok(Some((headers, None))) => apply_response_pairs(response, &headers),
+                Ok(None) => {}
+                Err(e) => {
+                    if let Some(failure) = self.failed(e) {
+                        *response = failure;
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// A string is passed to the plugin as is, anything else as JSON.
+fn configuration_bytes(settings: &serde_json::Value) -> Vec<u8> {
+    match settings {
+        serde_json::Value::Null => Vec::new(),
+        serde_json::Value::String(configuration) => configuration.clone().into_bytes(),
+        other => other.to_string().into_bytes(),
+    }
+}
+
+/// proxy-wasm's map encoding: the pair count, each pair's key and value lengths, then
+/// the NUL-terminated keys and values.
+fn serialize_map(pairs: &[(String, String)]) -> Vec<u8> {
+    let mut bytes = (pairs.len() as u32).to_le_bytes().to_vec();
+    for (key, value) in pairs {
+        bytes.extend((key.len() as u32).to_le_bytes());
+        bytes.extend((value.len() as u32).to_le_bytes());
+    }
+    for (key, value) in pairs {
+        bytes.extend(key.as_bytes());
+        bytes.push(0);
+        bytes.extend(value.as_bytes());
+        bytes.push(0);
+    }
+    bytes
+}
+
+fn deserialize_map(bytes: &[u8]) -> Option<HeaderPairs> {
+    let u32_at = |offset: usize| -> Option<usize> {
+        Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?) as usize)
+    };
+    let count = u32_at(0)?;
+    let mut data = 4 + count * 8;
+    let mut pairs = Vec::with_capacity(count);
+    for i in 0..count {
+        let key_len = u32_at(4 + i * 8)?;
+        let value_len = u32_at(8 + i * 8)?;
+        let key = bytes.get(data..data + key_len)?;
+        data += key_len + 1;
+        let value = bytes.get(data..data + value_len)?;
+        data += value_len + 1;
+        pairs.push((String::from_utf8_lossy(key).into_owned(), String::from_utf8_lossy(value).into_owned()));
+    }
+    Some(pairs)
+}
+
+fn read(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> Option<Vec<u8>> {
+    let memory = caller.get_export("memory")?.into_memory()?;
+    let mut bytes = vec![0; len as u32 as usize];
+    memory.read(&*caller, ptr as u32 as usize, &mut bytes).ok()?;
+    Some(bytes)
+}
+
+fn read_string(caller: &mut Caller<'_, HostState>, ptr: i32, len: i32) -> Option<String> {
+    read(caller, ptr, len).map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn write(caller: &mut Caller<'_, HostState>, ptr: i32, bytes: &[u8]) -> bool {
+    match caller.get_export("memory").and_then(|export| export.into_memory()) {
+        Some(memory) => memory.write(&mut *caller, ptr as u32 as usize, bytes).is_ok(),
+        None => false,
+    }
+}
+
+/// Copies `data` into memory the plugin allocates and points the return arguments at it.
+fn return_bytes(caller: &mut Caller<'_, HostState>, data: &[u8], return_data: i32, return_size: i32) -> i32 {
+    let ptr = if data.is_empty() {
+        0
+    } else {
+        let allocate = caller
+            .get_export("proxy_on_memory_allocate")
+            .or_else(|| caller.get_export("malloc"))
+            .and_then(|export| export.into_func())
+            .and_then(|func| func.typed::<i32, i32>(&*caller).ok());
+        match allocate.map(|allocate| allocate.call(&mut *caller, data.len() as i32)) {
+            Some(Ok(ptr)) if ptr != 0 => ptr,
+            _ => return STATUS_INTERNAL_FAILURE,
+        }
+    };
+    if !write(caller, ptr, data)
+        || !write(caller, return_data, &(ptr as u32).to_le_bytes())
+        || !write(caller, return_size, &(data.len() as u32).to_le_bytes())
+    {
+        return STATUS_BAD_ARGUMENT;
+    }
+    STATUS_OK
+}
+
+fn define_hostcalls(linker: &mut Linker<HostState>) -> anyhow::Result<()> {
+    linker.func_wrap("env", "proxy_log", |mut caller: Caller<'_, HostState>, level: i32, ptr: i32, len: i32| {
+        let message = match read_string(&mut caller, ptr, len) {
+            Some(message) => message,
+            None => return STATUS_BAD_ARGUMENT,
+        };
+        let plugin = &caller.data().plugin;
+        match level {
+            0 => trace!("[{}] {}", plugin, message),
+            1 => debug!("[{}] {}", plugin, message),
+            2 => info!("[{}] {}", plugin, message),
+            3 => warn!("[{}] {}", plugin, message),
+            _ => error!("[{}] {}", plugin, message),
+        }
+        STATUS_OK
+    })?;
+    linker.func_wrap("env", "proxy_get_log_level", |mut caller: Caller<'_, HostState>, return_level: i32| {
+        // Let the gateway's own filter decide what's kept
+        if write(&mut caller, return_level, &0u32.to_le_bytes()) { STATUS_OK } else { STATUS_BAD_ARGUMENT }
+    })?;
+    linker.func_wrap(
+        "env",
+        "proxy_get_current_time_nanoseconds",
+        |mut caller: Caller<'_, HostState>, return_time: i32| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+            if write(&mut caller, return_time, &now.to_le_bytes()) { STATUS_OK } else { STATUS_BAD_ARGUMENT }
+        },
+    )?;
+    linker.func_wrap("env", "proxy_set_tick_period_milliseconds", |_: Caller<'_, HostState>, _period: i32| STATUS_OK)?;
+    linker.func_wrap("env", "proxy_set_effective_context", |_: Caller<'_, HostState>, _context_id: i32| STATUS_OK)?;
+    linker.func_wrap("env", "proxy_continue_stream", |_: Caller<'_, HostState>, _stream_type: i32| STATUS_OK)?;
+    linker.func_wrap("env", "proxy_continue_request", |_: Caller<'_, HostState>| STATUS_OK)?;
+    linker.func_wrap("env", "proxy_continue_response", |_: Caller<'_, HostState>| STATUS_OK)?;
+    linker.func_wrap("env", "proxy_done", |_: Caller<'_, HostState>| STATUS_OK)?;
+    linker.func_wrap(
+        "env",
+        "proxy_get_property",
+        |_: Caller<'_, HostState>, _path: i32, _path_size: i32, _return_data: i32, _return_size: i32| STATUS_NOT_FOUND,
+    )?;
+    linker.func_wrap(
+        "env",
+        "proxy_get_buffer_bytes",
+        |mut caller: Caller<'_, HostState>,
+         buffer_type: i32,
+         start: i32,
+         max_size: i32,
+         return_data: i32,
+         return_size: i32| {
+            let buffer = match buffer_type {
+                BUFFER_PLUGIN_CONFIGURATION => caller.data().plugin_configuration.clone(),
+                BUFFER_VM_CONFIGURATION => Vec::new(),
+                _ => return STATUS_NOT_FOUND,
+            };
+            let start = (start as u32 as usize).min(buffer.len());
+            let end = start.saturating_add(max_size as u32 as usize).min(buffer.len());
+            return_bytes(&mut caller, &buffer[start..end], return_data, return_size)
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "proxy_get_header_map_pairs",
+        |mut caller: Caller<'_, HostState>, map_type: i32, return_data: i32, return_size: i32| {
+            let bytes = match caller.data_mut().map(map_type) {
+                Some(map) => serialize_map(map),
+                None => return STATUS_BAD_ARGUMENT,
+            };
+            return_bytes(&mut caller, &bytes, return_data, return_size)
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "proxy_set_header_map_pairs",
+        |mut caller: Caller<'_, HostState>, map_type: i32, ptr: i32, len: i32| {
+            let pairs = match read(&mut caller, ptr, len).and_then(|bytes| deserialize_map(&bytes)) {
+                Some(pairs) => pairs,
+                None => return STATUS_BAD_ARGUMENT,
+            };
+            match caller.data_mut().map(map_type) {
+                Some(map) => {
+                    // Pseudo-headers can be changed but not removed
+                    let pseudo: HeaderPairs = map
+                        .iter()
+                        .filter(|(key, _)| key.starts_with(':') && !pairs.iter().any(|(new, _)| new == key))
+                        .cloned()
+                        .collect();
+                    *map = pseudo.into_iter().chain(pairs).collect();
+                    STATUS_OK
+                }
+                None => STATUS_BAD_ARGUMENT,
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "proxy_get_header_map_value",
+        |mut caller: Caller<'_, HostState>,
+         map_type: i32,
+         key: i32,
+         key_size: i32,
+         return_data: i32,
+         return_size: i32| {
+            let key = match read_string(&mut caller, key, key_size) {
+                Some(key) => key,
+                None => return STATUS_BAD_ARGUMENT,
+            };
+            let value = match caller.data_mut().map(map_type) {
+                Some(map) => map
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(&key))
+                    .map(|(_, value)| value.clone()),
+                None => return STATUS_BAD_ARGUMENT,
+            };
+            match value {
+                Some(value) => return_bytes(&mut caller, value.as_bytes(), return_data, return_size),
+                None => STATUS_NOT_FOUND,
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "proxy_add_header_map_value",
+        |mut caller: Caller<'_, HostState>, map_type: i32, key: i32, key_size: i32, value: i32, value_size: i32| {
+            let key = read_string(&mut caller, key, key_size);
+            let (key, value) = match (key, read_string(&mut caller, value, value_size)) {
+                (Some(key), Some(value)) => (key.to_ascii_lowercase(), value),
+                _ => return STATUS_BAD_ARGUMENT,
+            };
+            match caller.data_mut().map(map_type) {
+                Some(map) => {
+                    map.push((key, value));
+                    STATUS_OK
+                }
+                None => STATUS_BAD_ARGUMENT,
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "proxy_replace_header_map_value",
+        |mut caller: Caller<'_, HostState>, map_type: i32, key: i32, key_size: i32, value: i32, value_size: i32| {
+            let key = read_string(&mut caller, key, key_size);
+            let (key, value) = match (key, read_string(&mut caller, value, value_size)) {
+                (Some(key), Some(value)) => (key.to_ascii_lowercase(), value),
+                _ => return STATUS_BAD_ARGUMENT,
+            };
+            match caller.data_mut().map(map_type) {
+                Some(map) => {
+                    map.retain(|(name, _)| !name.eq_ignore_ascii_case(&key));
+                    map.push((key, value));
+                    STATUS_OK
+                }
+                None => STATUS_BAD_ARGUMENT,
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "proxy_remove_header_map_value",
+        |mut caller: Caller<'_, HostState>, map_type: i32, key: i32, key_size: i32| {
+            let key = match read_string(&mut caller, key, key_size) {
+                Some(key) => key,
+                None => return STATUS_BAD_ARGUMENT,
+            };
+            match caller.data_mut().map(map_type) {
+                Some(map) => {
+                    map.retain(|(name, _)| !name.eq_ignore_ascii_case(&key));
+                    STATUS_OK
+                }
+                None => STATUS_BAD_ARGUMENT,
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "proxy_send_local_response",
+        |mut caller: Caller<'_, HostState>,
+         status: i32,
+         _details: i32,
+         _details_size: i32,
+         body: i32,
+         body_size: i32,
+         headers: i32,
+         headers_size: i32,
+         _grpc_status: i32| {
+            let body = read(&mut caller, body, body_size);
+            let headers = read(&mut caller, headers, headers_size).and_then(|bytes| {
+                if bytes.is_empty() { Some(Vec::new()) } else { deserialize_map(&bytes) }
+            });
+            match (body, headers) {
+                (Some(body), Some(headers)) => {
+                    caller.data_mut().local_response = Some(LocalResponse { status: status as u16, headers, body });
+                    STATUS_OK
+                }
+                _ => STATUS_BAD_ARGUMENT,
+            }
+        },
+    )?;
+    Ok(())
+}
+
+/// The WASI calls the Rust and Go proxy-wasm SDKs make. Output goes to the log; plugins
+/// get no environment, arguments or files.
+fn define_wasi(linker: &mut Linker<HostState>) -> anyhow::Result<()> {
+    const WASI: &str = "wasi_snapshot_preview1";
+    const ERRNO_SUCCESS: i32 = 0;
+    const ERRNO_BADF: i32 = 8;
+    const ERRNO_FAULT: i32 = 21;
+
+    linker.func_wrap(
+        WASI,
+        "fd_write",
+        |mut caller: Caller<'_, HostState>, fd: i32, iovs: i32, iovs_len: i32, return_written: i32| {
+            if fd != 1 && fd != 2 {
+                return ERRNO_BADF;
+            }
+            let iovs = match read(&mut caller, iovs, iovs_len.saturating_mul(8)) {
+                Some(iovs) => iovs,
+                None => return ERRNO_FAULT,
+            };
+            let mut output = Vec::new();
+            for iov in iovs.chunks_exact(8) {
+                let ptr = i32::from_le_bytes(iov[0..4].try_into().unwrap());
+                let len = i32::from_le_bytes(iov[4..8].try_into().unwrap());
+                match read(&mut caller, ptr, len) {
+                    Some(bytes) => output.extend(bytes),
+                    None => return ERRNO_FAULT,
+                }
+            }
+            let text = String::from_utf8_lossy(&output);
+            if !text.trim().is_empty() {
+                info!("[{}] {}", caller.data().plugin, text.trim_end());
+            }
+            let written = (output.len() as u32).to_le_bytes();
+            if write(&mut caller, return_written, &written) { ERRNO_SUCCESS } else { ERRNO_FAULT }
+        },
+    )?;
+    for name in ["environ_sizes_get", "args_sizes_get"] {
+        linker.func_wrap(WASI, name, |mut caller: Caller<'_, HostState>, return_count: i32, return_size: i32| {
+            let zero = 0u32.to_le_bytes();
+            if write(&mut caller, return_count, &zero) && write(&mut caller, return_size, &zero) {
+                ERRNO_SUCCESS
+            } else {
+                ERRNO_FAULT
+            }
+        })?;
+    }
+    for name in ["environ_get", "args_get"] {
+        linker.func_wrap(WASI, name, |_: Caller<'_, HostState>, _pointers: i32, _buffer: i32| ERRNO_SUCCESS)?;
+    }
+    linker.func_wrap(
+        WASI,
+        "clock_time_get",
+        |mut caller: Caller<'_, HostState>, _clock_id: i32, _precision: i64, return_time: i32| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+            if write(&mut caller, return_time, &now.to_le_bytes()) { ERRNO_SUCCESS } else { ERRNO_FAULT }
+        },
+    )?;
+    linker.func_wrap(WASI, "random_get", |mut caller: Caller<'_, HostState>, buffer: i32, len: i32| {
+        let mut bytes = vec![0; len as u32 as usize];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        if write(&mut caller, buffer, &bytes) { ERRNO_SUCCESS } else { ERRNO_FAULT }
+    })?;
+    linker.func_wrap(WASI, "sched_yield", |_: Caller<'_, HostState>| ERRNO_SUCCESS)?;
+    linker.func_wrap(WASI, "proc_exit", |_: Caller<'_, HostState>, code: i32| -> anyhow::Result<()> {
+        Err(anyhow::anyhow!("Plugin exited with code {}", code))
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use serde_json::json;
+
+    /// Adds `x-plugin: <configuration>` to requests, and answers 403 when they have no
+    /// `x-allowed` header.
+    const PLUGIN: &str = r#"
+        (module
+          (import "env" "proxy_get_buffer_bytes" (func $get_buffer (param i32 i32 i32 i32 i32) (result i32)))
+          (import "env" "proxy_get_header_map_value" (func $get_header (param i32 i32 i32 i32 i32) (result i32)))
+          (import "env" "proxy_add_header_map_value" (func $add_header (param i32 i32 i32 i32 i32) (result i32)))
+          (import "env" "proxy_send_local_response"
+            (func $respond (param i32 i32 i32 i32 i32 i32 i32 i32) (result i32)))
+          (memory (export "memory") 1)
+          (global $heap (mut i32) (i32.const 1024))
+          (data (i32.const 0) "x-plugin")
+          (data (i32.const 16) "x-allowed")
+          (data (i32.const 32) "denied")
+          (func (export "proxy_abi_version_0_2_1"))
+          (func (export "proxy_on_memory_allocate") (param $size i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $heap))
+            (global.set $heap (i32.add (global.get $heap) (local.get $size)))
+            (local.get $ptr))
+          (func (export "proxy_on_context_create") (param i32 i32))
+          (func (export "proxy_on_vm_start") (param i32 i32) (result i32) (i32.const 1))
+          (func (export "proxy_on_configure") (param i32 i32) (result i32) (i32.const 1))
+          (func (export "proxy_on_request_headers") (param i32 i32 i32) (result i32)
+            (if (i32.ne (call $get_header (i32.const 0) (i32.const 16) (i32.const 9) (i32.const 100) (i32.const 104))
+                        (i32.const 0))
+              (then
+                (drop (call $respond (i32.const 403) (i32.const 0) (i32.const 0) (i32.const 32) (i32.const 6)
+                                     (i32.const 0) (i32.const 0) (i32.const -1)))
+                (return (i32.const 1))))
+            (drop (call $get_buffer (i32.const 7) (i32.const 0) (i32.const 64) (i32.const 100) (i32.const 104)))
+            (drop (call $add_header (i32.const 0) (i32.const 0) (i32.const 8)
+                                    (i32.load (i32.const 100)) (i32.load (i32.const 104))))
+            (i32.const 0)))
+    "#;
+
+    fn plugin(fuel_per_call: u64) -> WasmPlugin {
+        let config: WasmPluginConfig = serde_json::from_value(json!({
+            "name": "test",
+            "path": "test.wasm",
+            "fuel_per_call": fuel_per_call
+        }))
+        .unwrap();
+        WasmPlugin::from_module(Module::new(&engine().unwrap(), PLUGIN).unwrap(), &config).unwrap()
+    }
+
+    #[test]
+    fn test_map_encoding() {
+        let pairs = vec![(":path".to_string(), "/a".to_string()), ("x-b".to_string(), "".to_string())];
+        assert_eq!(deserialize_map(&serialize_map(&pairs)), Some(pairs));
+        assert_eq!(deserialize_map(&[1, 0, 0, 0]), None);
+    }
+
+    #[tokio::test]
+    async fn test_request_headers() {
+        let plugin = plugin(1_000_000);
+        let route = Config::default_config().routes[0].clone();
+        let settings = json!("configured");
+        let context = FilterContext { route: &route, request_id: "req-1", settings: &settings };
+
+        let mut request =
+            Request::builder().uri("/api/v1/orders").header("x-allowed", "1").body(Body::empty()).unwrap();
+        assert!(matches!(plugin.on_request(&context, &mut request).await, FilterAction::Continue));
+        assert_eq!(request.headers()["x-plugin"], "configured");
+        assert_eq!(request.headers()["x-allowed"], "1");
+
+        let mut response = Response::new(Body::empty());
+        plugin.on_response(&context, &mut response).await;
+        assert!(plugin.shared.streams.lock().unwrap().is_empty());
+
+        let mut request = Request::builder().uri("/api/v1/orders").body(Body::empty()).unwrap();
+        match plugin.on_request(&context, &mut request).await {
+            FilterAction::Respond(response) => assert_eq!(response.status(), StatusCode::FORBIDDEN),
+            FilterAction::Continue => panic!("request without x-allowed was let through"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_out_of_fuel_fails_closed() {
+        let plugin = plugin(1);
+        let route = Config::default_config().routes[0].clone();
+        let context = FilterContext { route: &route, request_id: "req-1", settings: &serde_json::Value::Null };
+
+        let mut request = Request::builder().uri("/").header("x-allowed", "1").body(Body::empty()).unwrap();
+        match plugin.on_request(&context, &mut request).await {
+            FilterAction::Respond(response) => assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR),
+            FilterAction::Continue => panic!("failed plugin let the request through"),
+        }
+    }
+}
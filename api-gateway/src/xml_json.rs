@@ -0,0 +1,486 @@
+use axum::{
+    body::Bytes,
+    http::{header, HeaderMap, HeaderValue},
+};
+use serde_json::{Map, Value};
+use tracing::{debug, warn};
+
+use crate::config::{RouteConfig, XmlTranslationConfig};
+use crate::transform;
+
+const SOAP_ENVELOPE_NAMESPACE: &str = "http://schemas.xmlsoap.org/soap/envelope/";
+
+/// Converts a JSON request body to XML for the backend, updating its content type.
+pub fn request_body(route: &RouteConfig, headers: &mut HeaderMap, body: Bytes) -> Bytes {
+    let config = match &route.xml {
+        Some(config) if transform::is_json(headers) => config,
+        _ => return body,
+    };
+
+    let json = match serde_json::from_slice::<Value>(&body) {
+        Ok(json) => json,
+        Err(e) => {
+            debug!("Not translating request body that isn't valid JSON: {}", e);
+            return body;
+        }
+    };
+    let xml = match to_xml(config, json) {
+        Some(xml) => xml,
+        None => {
+            warn!(
+                "Not translating request body for {}: without root_element it must be an object with one field",
+                route.path
+            );
+            return body;
+        }
+    };
+
+    let content_type = if config.soap { "text/xml; charset=utf-8" } else { "application/xml; charset=utf-8" };
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    if let Some(Ok(action)) = config.soap_action.as_deref().map(|action| HeaderValue::from_str(&format!("\"{}\"", action))) {
+        headers.insert("SOAPAction", action);
+    }
+    Bytes::from(xml)
+}
+
+/// Converts an XML backend response to JSON, updating its content type and dropping its
+/// `Content-Length`.
+pub fn response_body(route: &RouteConfig, headers: &mut HeaderMap, body: Bytes) -> Bytes {
+    let config = match &route.xml {
+        Some(config) if is_xml(headers) => config,
+        _ => return body,
+    };
+
+    let json = match std::str::from_utf8(&body).map_err(|e| e.to_string()).and_then(parse) {
+        Ok(root) => to_json(config, root),
+        Err(e) => {
+            warn!("Not translating invalid XML response from {}: {}", route.backend, e);
+            return body;
+        }
+    };
+
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.remove(header::CONTENT_LENGTH);
+    Bytes::from(json.to_string())
+}
+
+fn is_xml(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|content_type| {
+            let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+            mime == "application/xml" || mime == "text/xml" || mime.ends_with("+xml")
+        })
+        .unwrap_or(false)
+}
+
+fn to_xml(config: &XmlTranslationConfig, json: Value) -> Option<String> {
+    let (name, mut value) = match &config.root_element {
+        Some(root) => (root.clone(), json),
+        None => match json {
+            Value::Object(object) if object.len() == 1 => object.into_iter().next()?,
+            _ => return None,
+        },
+    };
+    if let (Some(namespace), Value::Object(object)) = (&config.namespace, &mut value) {
+        object.insert("@xmlns".to_string(), Value::String(namespace.clone()));
+    }
+
+    let mut xml = String::from(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+    if config.soap {
+        xml.push_str(&format!(r#"<soap:Envelope xmlns:soap="{}"><soap:Body>"#, SOAP_ENVELOPE_NAMESPACE));
+        write_element(&mut xml, &name, &value);
+        xml.push_str("</soap:Body></soap:Envelope>");
+    } else {
+        write_element(&mut xml, &name, &value);
+    }
+    Some(xml)
+}
+
+fn write_element(xml: &mut String, name: &str, value: &Value) {
+    let name = element_name(name);
+    match value {
+        Value::Array(items) => items.iter().for_each(|item| write_element(xml, &name, item)),
+        Value::Null => xml.push_str(&format!("<{}/>", name)),
+        Value::Object(object) => {
+            xml.push('<');
+            xml.push_str(&name);
+            for (key, value) in object {
+                if let Some(attribute) = key.strip_prefix('@') {
+                    let value = match value {
+                        Value::String(value) => value.clone(),
+                        value => value.to_string(),
+                    };
+                    xml.push_str(&format!(" {}=\"{}\"", element_name(attribute), escape(&value)));
+                }
+            }
+            xml.push('>');
+            for (key, value) in object {
+                if key == "#text" {
+                    write_text(xml, value);
+                } else if !key.starts_with('@') {
+                    write_element(xml, key, value);
+                }
+            }
+            xml.push_str(&format!("</{}>", name));
+        }
+        value => {
+            xml.push_str(&format!("<{}>", name));
+            write_text(xml, value);
+            xml.push_str(&format!("</{}>", name));
+        }
+    }
+}
+
+fn write_text(xml: &mut String, value: &Value) {
+    match value {
+        Value::String(text) => xml.push_str(&escape(text)),
+        Value::Null => {}
+        value => xml.push_str(&escape(&value.to_string())),
+    }
+}
+
+/// JSON keys can hold characters XML names can't; those become `_`.
+fn element_name(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':') { c } else { '_' })
+        .collect();
+    if !sanitized.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn to_json(config: &XmlTranslationConfig, root: Element) -> Value {
+    // The operation's response is the one element inside the SOAP body
+    let root = if config.soap && local_name(&root.name) == "Envelope" {
+        let body = root.children.into_iter().find_map(|node| match node {
+            Node::Element(element) if local_name(&element.name) == "Body" => Some(element),
+            _ => None,
+        });
+        match body.and_then(|body| {
+            body.children.into_iter().find_map(|node| match node {
+                Node::Element(element) => Some(element),
+                Node::Text(_) => None,
+            })
+        }) {
+            Some(operation) => operation,
+            None => return Value::Object(Map::new()),
+        }
+    } else {
+        root
+    };
+
+    let mut json = Map::new();
+    json.insert(local_name(&root.name).to_string(), element_to_json(root));
+    Value::Object(json)
+}
+
+fn element_to_json(element: Element) -> Value {
+    let attributes: Vec<(String, String)> = element
+        .attributes
+        .into_iter()
+        .filter(|(name, _)| name != "xmlns" && !name.starts_with("xmlns:"))
+        .collect();
+    let mut text = String::new();
+    let mut children = Vec::new();
+    for node in element.children {
+        match node {
+            Node::Text(content) => text.push_str(&content),
+            Node::Element(child) => children.push(child),
+        }
+    }
+    let text = text.trim();
+
+    if attributes.is_empty() && children.is_empty() {
+        return if text.is_empty() { Value::Null } else { Value::String(text.to_string()) };
+    }
+
+    let mut object = Map::new();
+    for (name, value) in attributes {
+        object.insert(format!("@{}", local_name(&name)), Value::String(value));
+    }
+    for child in children {
+        let name = local_name(&child.name).to_string();
+        let value = element_to_json(child);
+        match object.get_mut(&name) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(existing) => {
+                let first = existing.take();
+                *existing = Value::Array(vec![first, value]);
+            }
+            None => {
+                object.insert(name, value);
+            }
+        }
+    }
+    if !text.is_empty() {
+        object.insert("#text".to_string(), Value::String(text.to_string()));
+    }
+    Value::Object(object)
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit_once(':').map_or(name, |(_, local)| local)
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Element(Element),
+    Text(String),
+}
+
+/// Parses the subset of XML backends send in practice: elements, attributes, text,
+/// CDATA and character references. Declarations, comments, processing instructions and
+/// doctypes are skipped.
+fn parse(xml: &str) -> Result<Element, String> {
+    let mut parser = Parser {
+        input: xml.trim_start_matches('\u{feff}'),
+        pos: 0,
+    };
+    parser.skip_misc()?;
+    let root = parser.element()?;
+    parser.skip_misc()?;
+    if parser.pos < parser.input.len() {
+        return Err(format!("unexpected content after the root element at {}", parser.pos));
+    }
+    Ok(root)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Skips past the next `terminator`
+    fn skip_past(&mut self, terminator: &str) -> Result<(), String> {
+        match self.rest().find(terminator) {
+            Some(index) => {
+                self.pos += index + terminator.len();
+                Ok(())
+            }
+            None => Err(format!("unterminated markup, expected '{}'", terminator)),
+        }
+    }
+
+    fn skip_misc(&mut self) -> Result<(), String> {
+        loop {
+            self.skip_whitespace();
+            let rest = self.rest();
+            if rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if rest.starts_with("<!DOCTYPE") {
+                self.skip_past(">")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            Ok(())
+        } else {
+            Err(format!("expected '{}' at {}", token, self.pos))
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let rest = self.rest();
+        let end = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '>' | '/' | '=' | '<'))
+            .unwrap_or(rest.len());
+        if end == 0 {
+            return Err(format!("expected a name at {}", self.pos));
+        }
+        self.pos += end;
+        Ok(rest[..end].to_string())
+    }
+
+    fn element(&mut self) -> Result<Element, String> {
+        self.expect("<")?;
+        let name = self.name()?;
+        let mut element = Element {
+            name,
+            attributes: Vec::new(),
+            children: Vec::new(),
+        };
+
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("/>") {
+                self.pos += 2;
+                return Ok(element);
+            }
+            if self.rest().starts_with('>') {
+                self.pos += 1;
+                break;
+            }
+            let attribute = self.name()?;
+            self.skip_whitespace();
+            self.expect("=")?;
+            self.skip_whitespace();
+            let quote = match self.rest().chars().next() {
+                Some(quote @ ('"' | '\'')) => quote,
+                _ => return Err(format!("expected a quoted value for '{}'", attribute)),
+            };
+            self.pos += 1;
+            let end = self.rest().find(quote).ok_or("unterminated attribute value")?;
+            let value = unescape(&self.rest()[..end])?;
+            self.pos += end + 1;
+            element.attributes.push((attribute, value));
+        }
+
+        loop {
+            let rest = self.rest();
+            if rest.starts_with("</") {
+                self.pos += 2;
+                let closing = self.name()?;
+                self.skip_whitespace();
+                self.expect(">")?;
+                if closing != element.name {
+                    return Err(format!("'{}' closed by '{}'", element.name, closing));
+                }
+                return Ok(element);
+            } else if rest.starts_with("<![CDATA[") {
+                let end = rest.find("]]>").ok_or("unterminated CDATA section")?;
+                element.children.push(Node::Text(rest["<![CDATA[".len()..end].to_string()));
+                self.pos += end + 3;
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if rest.starts_with('<') {
+                element.children.push(Node::Element(self.element()?));
+            } else if rest.is_empty() {
+                return Err(format!("'{}' is never closed", element.name));
+            } else {
+                let end = rest.find('<').unwrap_or(rest.len());
+                element.children.push(Node::Text(unescape(&rest[..end])?));
+                self.pos += end;
+            }
+        }
+    }
+}
+
+fn unescape(text: &str) -> Result<String, String> {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        let end = rest[start..].find(';').ok_or("unterminated entity")? + start;
+        let entity = &rest[start + 1..end];
+        let c = match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(|decimal| decimal.parse::<u32>()))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32)
+                .ok_or_else(|| format!("unknown entity '&{};'", entity))?,
+        };
+        unescaped.push(c);
+        rest = &rest[end + 1..];
+    }
+    unescaped.push_str(rest);
+    Ok(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn config(soap: bool, root_element: Option<&str>) -> XmlTranslationConfig {
+        XmlTranslationConfig {
+            root_element: root_element.map(str::to_string),
+            namespace: Some("urn:example:users".to_string()),
+            soap,
+            soap_action: None,
+        }
+    }
+
+    #[test]
+    fn test_json_to_soap_request() {
+        let json = json!({ "id": 42, "fields": ["name", "email"], "filter": { "@active": true, "#text": "a & b" }, "note": null });
+        let xml = to_xml(&config(true, Some("GetUser")), json).unwrap();
+        assert_eq!(
+            xml,
+            concat!(
+                r#"<?xml version="1.0" encoding="utf-8"?>"#,
+                r#"<soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/"><soap:Body>"#,
+                r#"<GetUser xmlns="urn:example:users"><fields>name</fields><fields>email</fields>"#,
+                r#"<filter active="true">a &amp; b</filter><id>42</id><note/></GetUser>"#,
+                r#"</soap:Body></soap:Envelope>"#
+            )
+        );
+
+        assert!(to_xml(&config(false, None), json!({ "a": 1, "b": 2 })).is_none());
+        assert!(to_xml(&config(false, None), json!({ "GetUser": { "id": 1 } })).unwrap().contains("<GetUser xmlns="));
+    }
+
+    #[test]
+    fn test_soap_response_to_json() {
+        let xml = r#"<?xml version="1.0"?>
+            <!-- generated -->
+            <soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/">
+              <soap:Body>
+                <m:GetUserResponse xmlns:m="urn:example:users">
+                  <m:user id="42"><m:name>J &amp; D</m:name><m:role>admin</m:role><m:role>ops</m:role></m:user>
+                  <m:bio><![CDATA[<b>hi</b>]]></m:bio>
+                  <m:empty/>
+                </m:GetUserResponse>
+              </soap:Body>
+            </soap:Envelope>"#;
+
+        assert_eq!(
+            to_json(&config(true, None), parse(xml).unwrap()),
+            json!({
+                "GetUserResponse": {
+                    "user": { "@id": "42", "name": "J & D", "role": ["admin", "ops"] },
+                    "bio": "<b>hi</b>",
+                    "empty": null
+                }
+            })
+        );
+
+        assert!(parse("<a><b></a>").is_err());
+        assert!(parse("<a>&bogus;</a>").is_err());
+        assert!(parse("<a/><b/>").is_err());
+    }
+}
@@ -53,10 +53,17 @@ CREATE TABLE IF NOT EXISTS order_items (
 CREATE TABLE IF NOT EXISTS api_keys (
     id SERIAL PRIMARY KEY,
     key_name VARCHAR(100) NOT NULL,
-    api_key VARCHAR(255) UNIQUE NOT NULL,
+    -- Plaintext keys are only ever written by this seed script; the gateway hashes
+    -- them into key_prefix/key_salt/key_hash on startup and clears this column
+    api_key VARCHAR(255) UNIQUE,
+    key_prefix VARCHAR(32),
+    key_salt VARCHAR(64),
+    key_hash VARCHAR(64),
     user_id INTEGER REFERENCES users(id),
-    permissions JSONB DEFAULT '{}',
+    permissions JSONB DEFAULT '[]',
     rate_limit INTEGER DEFAULT 1000,
+    plan VARCHAR(50),
+    tenant VARCHAR(100),
     is_active BOOLEAN DEFAULT true,
     expires_at TIMESTAMP WITH TIME ZONE,
     created_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP,
@@ -86,6 +93,7 @@ CREATE INDEX IF NOT EXISTS idx_orders_status ON orders(status);
 CREATE INDEX IF NOT EXISTS idx_order_items_order_id ON order_items(order_id);
 CREATE INDEX IF NOT EXISTS idx_order_items_product_id ON order_items(product_id);
 CREATE INDEX IF NOT EXISTS idx_api_keys_key ON api_keys(api_key);
+CREATE INDEX IF NOT EXISTS idx_api_keys_prefix ON api_keys(key_prefix);
 CREATE INDEX IF NOT EXISTS idx_api_keys_active ON api_keys(is_active);
 CREATE INDEX IF NOT EXISTS idx_audit_logs_user_id ON audit_logs(user_id);
 CREATE INDEX IF NOT EXISTS idx_audit_logs_created_at ON audit_logs(created_at);
@@ -105,10 +113,10 @@ INSERT INTO products (name, description, price, category, stock_quantity, create
 ('Smartphone', 'Latest generation smartphone', 899.99, 'Electronics', 30, 1)
 ON CONFLICT DO NOTHING;
 
-INSERT INTO api_keys (key_name, api_key, user_id, permissions, rate_limit) VALUES
-('Admin API Key', 'ak_admin_12345678901234567890', 1, '{"admin": true, "read": true, "write": true}', 10000),
-('User API Key', 'ak_user_09876543210987654321', 2, '{"read": true, "write": false}', 1000),
-('Service API Key', 'ak_service_11111111111111111111', null, '{"service": true, "read": true, "write": true}', 5000)
+INSERT INTO api_keys (key_name, api_key, user_id, permissions, rate_limit, plan) VALUES
+('Admin API Key', 'ak_admin_12345678901234567890', 1, '["admin", "read", "write", "delete"]', 10000, 'enterprise'),
+('User API Key', 'ak_user_09876543210987654321', 2, '["read"]', 1000, 'free'),
+('Service API Key', 'ak_service_11111111111111111111', null, '["service", "read", "write"]', 5000, 'pro')
 ON CONFLICT (api_key) DO NOTHING;
 
 -- Create updated_at trigger function